@@ -21,6 +21,8 @@ import net.mullvad.talpid.TalpidVpnService
 import net.mullvad.talpid.util.EventNotifier
 
 private const val RELAYS_FILE = "relays.json"
+private const val RELAYS_SIGNATURE_FILE = "relays.json.sig"
+private const val RELAYS_PUBLIC_KEY_FILE = "relays.pub"
 
 class MullvadVpnService : TalpidVpnService() {
     companion object {
@@ -214,6 +216,8 @@ class MullvadVpnService : TalpidVpnService() {
 
         FileResourceExtractor(this).apply {
             extract(RELAYS_FILE, shouldOverwriteRelayList)
+            extract(RELAYS_SIGNATURE_FILE, shouldOverwriteRelayList)
+            extract(RELAYS_PUBLIC_KEY_FILE, true)
         }
     }
 
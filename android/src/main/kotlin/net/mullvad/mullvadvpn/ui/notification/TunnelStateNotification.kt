@@ -64,6 +64,7 @@ class TunnelStateNotification(
             is ErrorStateCause.StartTunnelError -> R.string.start_tunnel_error
             is ErrorStateCause.IsOffline -> R.string.is_offline
             is ErrorStateCause.TapAdapterProblem -> R.string.tap_adapter_problem
+            is ErrorStateCause.AccountExpired -> R.string.account_expired
             is ErrorStateCause.TunnelParameterError -> {
                 when (cause.error) {
                     ParameterGenerationError.NoMatchingRelay -> R.string.no_matching_relay
@@ -9,5 +9,6 @@ sealed class ErrorStateCause {
     class TunnelParameterError(val error: ParameterGenerationError) : ErrorStateCause()
     class IsOffline : ErrorStateCause()
     class TapAdapterProblem : ErrorStateCause()
+    class AccountExpired : ErrorStateCause()
     class VpnPermissionDenied : ErrorStateCause()
 }
@@ -530,7 +530,7 @@ export class DaemonRpc {
   }
 
   public subscribeDaemonEventListener(listener: SubscriptionListener<DaemonEvent>) {
-    const call = this.isConnected && this.client.eventsListen(new Empty());
+    const call = this.isConnected && this.client.eventsListen(new grpcTypes.EventsListenRequest());
     if (!call) {
       throw noConnectionError;
     }
@@ -541,7 +541,9 @@ export class DaemonRpc {
     call.on('data', (data: grpcTypes.DaemonEvent) => {
       try {
         const daemonEvent = convertFromDaemonEvent(data);
-        listener.onEvent(daemonEvent);
+        if (daemonEvent) {
+          listener.onEvent(daemonEvent);
+        }
       } catch (err) {
         listener.onError(err);
       }
@@ -767,6 +769,8 @@ function convertFromTunnelStateErrorCause(
       return { reason: 'start_tunnel_error' };
     case grpcTypes.ErrorState.Cause.TAP_ADAPTER_PROBLEM:
       return { reason: 'tap_adapter_problem' };
+    case grpcTypes.ErrorState.Cause.ACCOUNT_EXPIRED:
+      return { reason: 'account_expired' };
     case grpcTypes.ErrorState.Cause.SET_FIREWALL_POLICY_ERROR:
       return {
         reason: 'set_firewall_policy_error',
@@ -837,6 +841,7 @@ function convertFromProxyEndpoint(proxyEndpoint: grpcTypes.ProxyEndpoint.AsObjec
   const proxyTypeMap: Record<grpcTypes.ProxyType, ProxyType> = {
     [grpcTypes.ProxyType.CUSTOM]: 'custom',
     [grpcTypes.ProxyType.SHADOWSOCKS]: 'shadowsocks',
+    [grpcTypes.ProxyType.SOCKS5]: 'socks5',
   };
 
   return {
@@ -955,6 +960,16 @@ function convertFromBridgeSettings(
     });
   }
 
+  const socks5Settings = bridgeSettings.socks5;
+  if (socks5Settings) {
+    return customSettings({
+      address: socks5Settings.address,
+      auth: socks5Settings.auth && {
+        ...socks5Settings.auth,
+      },
+    });
+  }
+
   const shadowsocksSettings = bridgeSettings.shadowsocks!;
   return customSettings({
     peer: shadowsocksSettings.peer!,
@@ -1032,7 +1047,7 @@ function convertFromTunnelOptions(tunnelOptions: grpcTypes.TunnelOptions.AsObjec
   };
 }
 
-function convertFromDaemonEvent(data: grpcTypes.DaemonEvent): DaemonEvent {
+function convertFromDaemonEvent(data: grpcTypes.DaemonEvent): DaemonEvent | undefined {
   const tunnelState = data.getTunnelState();
   if (tunnelState !== undefined) {
     return { tunnelState: convertFromTunnelState(tunnelState)! };
@@ -1063,9 +1078,13 @@ function convertFromDaemonEvent(data: grpcTypes.DaemonEvent): DaemonEvent {
     };
   }
 
-  return {
-    appVersionInfo: data.getVersionInfo()!.toObject(),
-  };
+  const versionInfo = data.getVersionInfo();
+  if (versionInfo !== undefined) {
+    return { appVersionInfo: versionInfo.toObject() };
+  }
+
+  // Event kinds that the GUI doesn't handle
+  return undefined;
 }
 
 function convertFromKeygenEvent(data: grpcTypes.KeygenEvent): KeygenEvent {
@@ -38,7 +38,8 @@ export type ErrorStateCause =
         | 'set_dns_error'
         | 'start_tunnel_error'
         | 'is_offline'
-        | 'tap_adapter_problem';
+        | 'tap_adapter_problem'
+        | 'account_expired';
     }
   | { reason: 'set_firewall_policy_error'; details: FirewallPolicyError }
   | { reason: 'tunnel_parameter_error'; details: TunnelParameterError }
@@ -67,11 +68,13 @@ export function liftConstraint<T>(constraint: Constraint<T>): LiftedConstraint<T
   return constraint === 'any' ? constraint : constraint.only;
 }
 
-export type ProxyType = 'shadowsocks' | 'custom';
+export type ProxyType = 'shadowsocks' | 'socks5' | 'custom';
 export function proxyTypeToString(proxy: ProxyType): string {
   switch (proxy) {
     case 'shadowsocks':
       return 'Shadowsocks';
+    case 'socks5':
+      return 'SOCKS5 proxy';
     case 'custom':
       return 'custom bridge';
     default:
@@ -131,6 +131,8 @@ function getBlockMessage(blockReason: ErrorStateCause): string {
         'in-app-notifications',
         "Unable to detect a working TAP adapter on this device. If you've disabled it, enable it again. Otherwise, please reinstall the app",
       );
+    case 'account_expired':
+      return messages.pgettext('in-app-notifications', 'This account is out of time');
   }
 }
 
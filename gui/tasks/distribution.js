@@ -21,6 +21,8 @@ const config = {
   extraResources: [
     { from: distAssets('ca.crt'), to: '.' },
     { from: distAssets('relays.json'), to: '.' },
+    { from: distAssets('relays.json.sig'), to: '.' },
+    { from: distAssets('relays.pub'), to: '.' },
     { from: root('CHANGELOG.md'), to: '.' },
   ],
 
@@ -1,6 +1,6 @@
-use crate::{new_rpc_client, Command, Error, Result};
+use crate::{format::format_expiry, new_rpc_client, Command, Error, Result};
 use clap::value_t_or_exit;
-use mullvad_management_interface::{types::Timestamp, Code};
+use mullvad_management_interface::{types::AccountExpiryNotifications, Code};
 use mullvad_types::account::AccountToken;
 
 pub struct Account;
@@ -49,6 +49,27 @@ impl Command for Account {
                             .required(true),
                     ),
             )
+            .subcommand(
+                clap::SubCommand::with_name("expiry-notifications")
+                    .about(
+                        "Control when the daemon notifies about the account running out of time. \
+                         A notification is always sent once the account has expired",
+                    )
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::SubCommand::with_name("get")
+                            .about("Display the hours before expiry at which to notify"),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("set")
+                            .about("Change the hours before expiry at which to notify")
+                            .arg(
+                                clap::Arg::with_name("hours")
+                                    .help("Hours before expiry. Leave out to only notify on expiry")
+                                    .multiple(true),
+                            ),
+                    ),
+            )
     }
 
     async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
@@ -66,6 +87,24 @@ impl Command for Account {
         } else if let Some(matches) = matches.subcommand_matches("redeem") {
             let voucher = value_t_or_exit!(matches.value_of("voucher"), String);
             self.redeem_voucher(voucher).await
+        } else if let Some(matches) = matches.subcommand_matches("expiry-notifications") {
+            match matches.subcommand() {
+                ("get", _) => self.get_expiry_notifications().await,
+                ("set", Some(set_matches)) => {
+                    let hours = match set_matches.values_of("hours") {
+                        Some(values) => values
+                            .map(|value| {
+                                value.parse().map_err(|_| {
+                                    Error::InvalidCommand("hours must be a non-negative integer")
+                                })
+                            })
+                            .collect::<Result<Vec<u32>>>()?,
+                        None => Vec::new(),
+                    };
+                    self.set_expiry_notifications(hours).await
+                }
+                _ => unreachable!("No expiry-notifications command given"),
+            }
         } else {
             unreachable!("No account command given");
         }
@@ -95,7 +134,7 @@ impl Account {
                 .into_inner();
             println!(
                 "Expires at     : {}",
-                Self::format_expiry(&expiry.expiry.unwrap())
+                format_expiry(&expiry.expiry.unwrap())
             );
         } else {
             println!("No account configured");
@@ -123,7 +162,7 @@ impl Account {
                 );
                 println!(
                     "New expiry date: {}",
-                    Self::format_expiry(&submission.new_expiry.unwrap())
+                    format_expiry(&submission.new_expiry.unwrap())
                 );
                 Ok(())
             }
@@ -152,10 +191,32 @@ impl Account {
         }
     }
 
-    fn format_expiry(expiry: &Timestamp) -> String {
-        let ndt = chrono::NaiveDateTime::from_timestamp(expiry.seconds, expiry.nanos as u32);
-        let utc = chrono::DateTime::<chrono::Utc>::from_utc(ndt, chrono::Utc);
-        utc.with_timezone(&chrono::Local).to_string()
+    async fn get_expiry_notifications(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc.get_settings(()).await?.into_inner();
+        Self::print_expiry_notifications(&settings.account_expiry_notifications);
+        Ok(())
+    }
+
+    async fn set_expiry_notifications(&self, hours: Vec<u32>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_account_expiry_notifications(AccountExpiryNotifications { hours })
+            .await?;
+        let settings = rpc.get_settings(()).await?.into_inner();
+        Self::print_expiry_notifications(&settings.account_expiry_notifications);
+        Ok(())
+    }
+
+    fn print_expiry_notifications(hours: &[u32]) {
+        if hours.is_empty() {
+            println!("Notifying when the account has expired");
+        } else {
+            let hours: Vec<String> = hours.iter().map(|hours| hours.to_string()).collect();
+            println!(
+                "Notifying {} hours before the account expires, and when it has expired",
+                hours.join(", ")
+            );
+        }
     }
 
     async fn clear_history(&self) -> Result<()> {
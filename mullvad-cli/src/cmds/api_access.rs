@@ -0,0 +1,198 @@
+use crate::{new_rpc_client, Command, Error, Result};
+use clap::value_t;
+use mullvad_management_interface::types::{
+    api_access_method::{self, CustomProxy, Type as AccessMethodType},
+    bridge_settings::RemoteProxyAuth,
+    ApiAccessMethod, ApiAccessMethods,
+};
+use std::net::{IpAddr, SocketAddr};
+
+pub struct ApiAccess;
+
+#[mullvad_management_interface::async_trait]
+impl Command for ApiAccess {
+    fn name(&self) -> &'static str {
+        "api-access"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about(
+                "Manage how the daemon reaches the API. The methods are tried in order, \
+                 and the next one is used when requests keep failing",
+            )
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("get")
+                    .about("Display the configured methods and the one currently in use"),
+            )
+            .subcommand(create_add_subcommand())
+            .subcommand(
+                clap::SubCommand::with_name("remove")
+                    .about("Remove a method")
+                    .arg(
+                        clap::Arg::with_name("index")
+                            .help("The index of the method, as shown by 'get'")
+                            .required(true),
+                    ),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("get", _) => Self::get().await,
+            ("add", Some(add_matches)) => Self::add(add_matches).await,
+            ("remove", Some(remove_matches)) => {
+                let index =
+                    value_t!(remove_matches.value_of("index"), usize).unwrap_or_else(|e| e.exit());
+                Self::remove(index).await
+            }
+            _ => unreachable!("No api-access command given"),
+        }
+    }
+}
+
+fn create_add_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("add")
+        .about("Add a method to the end of the list")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("direct").about("Connect directly to the API"))
+        .subcommand(
+            clap::SubCommand::with_name("bridges")
+                .about("Connect through a Shadowsocks bridge from the relay list"),
+        )
+        .subcommand(create_proxy_subcommand("socks5").about("Connect through a SOCKS5 proxy"))
+        .subcommand(
+            create_proxy_subcommand("http")
+                .about("Connect through an HTTP proxy that supports the CONNECT method"),
+        )
+}
+
+fn create_proxy_subcommand(name: &'static str) -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name(name)
+        .arg(
+            clap::Arg::with_name("remote-ip")
+                .help("Specifies the IP of the proxy server")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("remote-port")
+                .help("Specifies the port the proxy server is listening on")
+                .required(true)
+                .index(2),
+        )
+        .arg(
+            clap::Arg::with_name("username")
+                .help("Specifies the username for proxy authentication")
+                .long("username")
+                .takes_value(true)
+                .requires("password"),
+        )
+        .arg(
+            clap::Arg::with_name("password")
+                .help("Specifies the password for proxy authentication")
+                .long("password")
+                .takes_value(true)
+                .requires("username"),
+        )
+}
+
+impl ApiAccess {
+    async fn get() -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let methods = rpc.get_settings(()).await?.into_inner().api_access_methods;
+        let current = rpc.get_current_api_access_method(()).await?.into_inner();
+
+        println!("Current method: {}", format_method(&current));
+        println!("Configured methods:");
+        for (index, method) in methods.iter().enumerate() {
+            println!("{:>4}: {}", index, format_method(method));
+        }
+        Ok(())
+    }
+
+    async fn add(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let method = match matches.subcommand() {
+            ("direct", _) => AccessMethodType::Direct(api_access_method::Direct {}),
+            ("bridges", _) => AccessMethodType::Bridges(api_access_method::Bridges {}),
+            ("socks5", Some(proxy_matches)) => {
+                AccessMethodType::Socks5(parse_custom_proxy(proxy_matches))
+            }
+            ("http", Some(proxy_matches)) => {
+                AccessMethodType::Http(parse_custom_proxy(proxy_matches))
+            }
+            _ => unreachable!("No api-access add command given"),
+        };
+
+        let mut methods = Self::get_methods().await?;
+        methods.push(ApiAccessMethod {
+            r#type: Some(method),
+        });
+        Self::set_methods(methods).await?;
+        println!("Added API access method");
+        Ok(())
+    }
+
+    async fn remove(index: usize) -> Result<()> {
+        let mut methods = Self::get_methods().await?;
+        if index >= methods.len() {
+            return Err(Error::InvalidCommand(
+                "No API access method with that index",
+            ));
+        }
+        if methods.len() == 1 {
+            return Err(Error::InvalidCommand(
+                "Cannot remove the only API access method",
+            ));
+        }
+        methods.remove(index);
+        Self::set_methods(methods).await?;
+        println!("Removed API access method");
+        Ok(())
+    }
+
+    async fn get_methods() -> Result<Vec<ApiAccessMethod>> {
+        let mut rpc = new_rpc_client().await?;
+        Ok(rpc.get_settings(()).await?.into_inner().api_access_methods)
+    }
+
+    async fn set_methods(methods: Vec<ApiAccessMethod>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_api_access_methods(ApiAccessMethods { methods })
+            .await?;
+        Ok(())
+    }
+}
+
+fn parse_custom_proxy(matches: &clap::ArgMatches<'_>) -> CustomProxy {
+    let remote_ip = value_t!(matches.value_of("remote-ip"), IpAddr).unwrap_or_else(|e| e.exit());
+    let remote_port = value_t!(matches.value_of("remote-port"), u16).unwrap_or_else(|e| e.exit());
+    let auth = match (matches.value_of("username"), matches.value_of("password")) {
+        (Some(username), Some(password)) => Some(RemoteProxyAuth {
+            username: username.to_string(),
+            password: password.to_string(),
+        }),
+        _ => None,
+    };
+
+    CustomProxy {
+        address: SocketAddr::new(remote_ip, remote_port).to_string(),
+        auth,
+    }
+}
+
+fn format_method(method: &ApiAccessMethod) -> String {
+    let format_proxy = |proxy: &CustomProxy| match &proxy.auth {
+        Some(auth) => format!("{} as {}", proxy.address, auth.username),
+        None => proxy.address.clone(),
+    };
+
+    match &method.r#type {
+        Some(AccessMethodType::Direct(_)) => "direct".to_string(),
+        Some(AccessMethodType::Bridges(_)) => "Shadowsocks bridges".to_string(),
+        Some(AccessMethodType::Socks5(proxy)) => format!("SOCKS5 proxy at {}", format_proxy(proxy)),
+        Some(AccessMethodType::Http(proxy)) => format!("HTTP proxy at {}", format_proxy(proxy)),
+        None => "unknown".to_string(),
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{new_rpc_client, Command, Result};
+use crate::{format, new_rpc_client, Command, Result};
 use clap::value_t_or_exit;
 
 pub struct AutoConnect;
@@ -50,8 +50,12 @@ impl AutoConnect {
 
     async fn get(&self) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
-        let auto_connect = rpc.get_settings(()).await?.into_inner().auto_connect;
-        println!("Autoconnect: {}", if auto_connect { "on" } else { "off" });
+        let settings = rpc.get_settings(()).await?.into_inner();
+        println!(
+            "Autoconnect: {}{}",
+            if settings.auto_connect { "on" } else { "off" },
+            format::lock_marker(&settings, "auto_connect")
+        );
         Ok(())
     }
 }
@@ -1,4 +1,4 @@
-use crate::{new_rpc_client, Command, Error, Result, PRODUCT_VERSION};
+use crate::{format, new_rpc_client, Command, Error, Result, PRODUCT_VERSION};
 use clap::value_t_or_exit;
 
 pub struct BetaProgram;
@@ -35,7 +35,11 @@ impl Command for BetaProgram {
                 } else {
                     "off"
                 };
-                println!("Beta program: {}", enabled_str);
+                println!(
+                    "Beta program: {}{}",
+                    enabled_str,
+                    format::lock_marker(&settings, "show_beta_releases")
+                );
                 Ok(())
             }
             ("set", Some(matches)) => {
@@ -1,4 +1,4 @@
-use crate::{new_rpc_client, Command, Result};
+use crate::{format, new_rpc_client, Command, Result};
 use clap::value_t_or_exit;
 
 pub struct BlockWhenDisconnected;
@@ -51,18 +51,15 @@ impl BlockWhenDisconnected {
 
     async fn get(&self) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
-        let block_when_disconnected = rpc
-            .get_settings(())
-            .await?
-            .into_inner()
-            .block_when_disconnected;
+        let settings = rpc.get_settings(()).await?.into_inner();
         println!(
-            "Network traffic will be {} when the VPN is disconnected",
-            if block_when_disconnected {
+            "Network traffic will be {} when the VPN is disconnected{}",
+            if settings.block_when_disconnected {
                 "blocked"
             } else {
                 "allowed"
-            }
+            },
+            format::lock_marker(&settings, "block_when_disconnected")
         );
         Ok(())
     }
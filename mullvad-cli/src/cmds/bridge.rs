@@ -1,4 +1,4 @@
-use crate::{location, new_rpc_client, Command, Result};
+use crate::{format, location, new_rpc_client, Command, Result};
 use clap::value_t;
 
 use mullvad_management_interface::types::{
@@ -118,6 +118,36 @@ fn create_set_custom_settings_subcommand() -> clap::App<'static, 'static> {
                         .index(4),
                 ),
         )
+        .subcommand(
+            clap::SubCommand::with_name("socks5")
+                .about("Registers a remote SOCKS5 proxy with optional authentication")
+                .arg(
+                    clap::Arg::with_name("remote-ip")
+                        .help("Specifies the IP of the remote SOCKS5 server")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    clap::Arg::with_name("remote-port")
+                        .help("Specifies the port the remote SOCKS5 server is listening on")
+                        .required(true)
+                        .index(2),
+                )
+                .arg(
+                    clap::Arg::with_name("username")
+                        .help("Specifies the username for remote authentication")
+                        .long("username")
+                        .takes_value(true)
+                        .requires("password"),
+                )
+                .arg(
+                    clap::Arg::with_name("password")
+                        .help("Specifies the password for remote authentication")
+                        .long("password")
+                        .takes_value(true)
+                        .requires("username"),
+                ),
+        )
         .subcommand(
             clap::SubCommand::with_name("shadowsocks")
                 .about("Configure bundled Shadowsocks proxy")
@@ -181,13 +211,14 @@ impl Bridge {
     async fn handle_get() -> Result<()> {
         let mut rpc = new_rpc_client().await?;
         let settings = rpc.get_settings(()).await?.into_inner();
-        Self::print_state(settings.bridge_state.unwrap());
-        match settings.bridge_settings.unwrap().r#type.unwrap() {
+        Self::print_state(settings.bridge_state.clone().unwrap());
+        match settings.bridge_settings.clone().unwrap().r#type.unwrap() {
             BridgeSettingsType::Local(local_proxy) => Self::print_local_proxy(&local_proxy),
             BridgeSettingsType::Remote(remote_proxy) => Self::print_remote_proxy(&remote_proxy),
             BridgeSettingsType::Shadowsocks(shadowsocks_proxy) => {
                 Self::print_shadowsocks_proxy(&shadowsocks_proxy)
             }
+            BridgeSettingsType::Socks5(socks5_proxy) => Self::print_socks5_proxy(&socks5_proxy),
             BridgeSettingsType::Normal(constraints) => {
                 println!(
                     "Bridge constraints - {}, {}",
@@ -196,6 +227,8 @@ impl Bridge {
                 );
             }
         };
+        format::print_locked_fields(&settings, "bridge_settings");
+        format::print_locked_fields(&settings, "bridge_state");
         Ok(())
     }
 
@@ -331,6 +364,43 @@ impl Bridge {
                 r#type: Some(BridgeSettingsType::Remote(prost_proxy)),
             })
             .await?;
+        } else if let Some(args) = matches.subcommand_matches("socks5") {
+            let remote_ip =
+                value_t!(args.value_of("remote-ip"), IpAddr).unwrap_or_else(|e| e.exit());
+            let remote_port =
+                value_t!(args.value_of("remote-port"), u16).unwrap_or_else(|e| e.exit());
+
+            let auth = match (args.value_of("username"), args.value_of("password")) {
+                (Some(username), Some(password)) => Some(openvpn::ProxyAuth {
+                    username: username.to_string(),
+                    password: password.to_string(),
+                }),
+                _ => None,
+            };
+            let prost_auth = auth.clone().map(|auth| RemoteProxyAuth {
+                username: auth.username,
+                password: auth.password,
+            });
+
+            let proxy = openvpn::Socks5ProxySettings {
+                address: SocketAddr::new(remote_ip, remote_port),
+                auth,
+            };
+            let prost_proxy = Socks5ProxySettings {
+                address: proxy.address.to_string(),
+                auth: prost_auth,
+            };
+
+            let packed_proxy = openvpn::ProxySettings::Socks5(proxy);
+            if let Err(error) = openvpn::validate_proxy_settings(&packed_proxy) {
+                panic!(error);
+            }
+
+            let mut rpc = new_rpc_client().await?;
+            rpc.set_bridge_settings(BridgeSettings {
+                r#type: Some(BridgeSettingsType::Socks5(prost_proxy)),
+            })
+            .await?;
         } else if let Some(args) = matches.subcommand_matches("shadowsocks") {
             let remote_ip =
                 value_t!(args.value_of("remote-ip"), IpAddr).unwrap_or_else(|e| e.exit());
@@ -395,6 +465,18 @@ impl Bridge {
         }
     }
 
+    fn print_socks5_proxy(proxy: &Socks5ProxySettings) {
+        println!("proxy: SOCKS5");
+        println!("  server address: {}", proxy.address);
+
+        if let Some(ref auth) = proxy.auth {
+            println!("  auth username: {}", auth.username);
+            println!("  auth password: {}", auth.password);
+        } else {
+            println!("  auth: none");
+        }
+    }
+
     fn print_shadowsocks_proxy(proxy: &ShadowsocksProxySettings) {
         println!("proxy: Shadowsocks");
         println!("  peer address: {}", proxy.peer);
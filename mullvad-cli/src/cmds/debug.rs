@@ -0,0 +1,229 @@
+use crate::{new_rpc_client, Command, Error, Result};
+use mullvad_management_interface::types::{LogLevel, LogLevelUpdate, LogLine, StreamLogsRequest};
+use std::fs;
+
+const LOG_LEVELS: &[&str] = &["off", "error", "warn", "info", "debug", "trace"];
+
+/// Number of lines of the daemon log file that are shown before following the log.
+const LOG_TAIL_LINES: usize = 20;
+
+pub struct Debug;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Debug {
+    fn name(&self) -> &'static str {
+        "debug"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Inspect and control the logging of the daemon")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("log")
+                    .about("Show the end of the daemon log")
+                    .arg(
+                        clap::Arg::with_name("follow")
+                            .long("follow")
+                            .short("f")
+                            .help("Keep printing log lines from the daemon as they are logged"),
+                    )
+                    .arg(
+                        clap::Arg::with_name("level")
+                            .long("level")
+                            .takes_value(true)
+                            .possible_values(LOG_LEVELS)
+                            .default_value("info")
+                            .requires("follow")
+                            .help(
+                                "The most verbose level to follow, regardless of the level of \
+                                 the log file",
+                            ),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("log-level")
+                    .about("Get or change the log levels of the daemon while it is running")
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::SubCommand::with_name("get").about("Display the current log levels"),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("set")
+                            .about("Change the default log level, or the level of a module")
+                            .arg(
+                                clap::Arg::with_name("level")
+                                    .required(true)
+                                    .possible_values(LOG_LEVELS),
+                            )
+                            .arg(
+                                clap::Arg::with_name("module")
+                                    .long("module")
+                                    .takes_value(true)
+                                    .help(
+                                        "The module to change the level of, such as \
+                                         talpid_core::firewall. Applies to its submodules too",
+                                    ),
+                            ),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("reset")
+                            .about("Make a module use the default log level again")
+                            .arg(clap::Arg::with_name("module").required(true)),
+                    ),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("log", Some(log_matches)) => self.log(log_matches).await,
+            ("log-level", Some(level_matches)) => match level_matches.subcommand() {
+                ("get", _) => self.get_log_levels().await,
+                ("set", Some(set_matches)) => {
+                    self.set_log_level(
+                        set_matches.value_of("module"),
+                        parse_log_level(set_matches.value_of("level").unwrap()),
+                    )
+                    .await
+                }
+                ("reset", Some(reset_matches)) => {
+                    self.reset_log_level(reset_matches.value_of("module").unwrap())
+                        .await
+                }
+                _ => unreachable!("No log-level command given"),
+            },
+            _ => unreachable!("No debug command given"),
+        }
+    }
+}
+
+impl Debug {
+    async fn log(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let follow = matches.is_present("follow");
+        // Subscribe before printing the log file, so that no lines are missed in between.
+        let mut stream = if follow {
+            let level = parse_log_level(matches.value_of("level").unwrap());
+            let mut rpc = new_rpc_client().await?;
+            Some(
+                rpc.stream_logs(StreamLogsRequest {
+                    level: i32::from(level),
+                })
+                .await?
+                .into_inner(),
+            )
+        } else {
+            None
+        };
+
+        match Self::read_log_tail() {
+            Ok(lines) => {
+                for line in lines {
+                    println!("{}", line);
+                }
+            }
+            // Following the log does not require access to the log file
+            Err(error) if follow => eprintln!("{}", error),
+            Err(error) => return Err(error),
+        }
+
+        if let Some(stream) = &mut stream {
+            while let Some(line) = stream.message().await? {
+                println!("{}", format_log_line(&line));
+            }
+        }
+        Ok(())
+    }
+
+    fn read_log_tail() -> Result<Vec<String>> {
+        let log_dir = mullvad_paths::get_log_dir()
+            .map_err(|_| Error::InvalidCommand("the log directory could not be found"))?;
+        let log_path = log_dir.join("daemon.log");
+        let log = fs::read_to_string(&log_path)
+            .map_err(|error| Error::FileError(log_path.display().to_string(), error))?;
+        let lines: Vec<&str> = log.lines().collect();
+        let start = lines.len().saturating_sub(LOG_TAIL_LINES);
+        Ok(lines[start..].iter().map(|line| line.to_string()).collect())
+    }
+
+    async fn get_log_levels(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let levels = rpc.get_log_levels(()).await?.into_inner();
+        println!("Default level: {}", format_log_level(levels.default_level));
+        let mut modules: Vec<_> = levels.modules.into_iter().collect();
+        modules.sort();
+        for (module, level) in modules {
+            println!("{}: {}", module, format_log_level(level));
+        }
+        Ok(())
+    }
+
+    async fn set_log_level(&self, module: Option<&str>, level: LogLevel) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_log_level(LogLevelUpdate {
+            module: module.unwrap_or_default().to_owned(),
+            level: i32::from(level),
+            reset: false,
+        })
+        .await?;
+        println!("Updated log level");
+        Ok(())
+    }
+
+    async fn reset_log_level(&self, module: &str) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_log_level(LogLevelUpdate {
+            module: module.to_owned(),
+            level: i32::from(LogLevel::Off),
+            reset: true,
+        })
+        .await?;
+        println!("Module {} now uses the default log level", module);
+        Ok(())
+    }
+}
+
+fn parse_log_level(level: &str) -> LogLevel {
+    match level {
+        "off" => LogLevel::Off,
+        "error" => LogLevel::Error,
+        "warn" => LogLevel::Warn,
+        "info" => LogLevel::Info,
+        "debug" => LogLevel::Debug,
+        "trace" => LogLevel::Trace,
+        _ => unreachable!("Invalid log level"),
+    }
+}
+
+fn format_log_level(level: i32) -> &'static str {
+    match LogLevel::from_i32(level) {
+        Some(LogLevel::Off) => "off",
+        Some(LogLevel::Error) => "error",
+        Some(LogLevel::Warn) => "warn",
+        Some(LogLevel::Info) => "info",
+        Some(LogLevel::Debug) => "debug",
+        Some(LogLevel::Trace) => "trace",
+        None => "unknown",
+    }
+}
+
+fn format_log_line(line: &LogLine) -> String {
+    let timestamp = line
+        .timestamp
+        .as_ref()
+        .map(|timestamp| {
+            let ndt =
+                chrono::NaiveDateTime::from_timestamp(timestamp.seconds, timestamp.nanos as u32);
+            let utc = chrono::DateTime::<chrono::Utc>::from_utc(ndt, chrono::Utc);
+            utc.with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M:%S%.3f")
+                .to_string()
+        })
+        .unwrap_or_default();
+    format!(
+        "[{}][{}][{}] {}",
+        timestamp,
+        line.target,
+        format_log_level(line.level).to_uppercase(),
+        line.message
+    )
+}
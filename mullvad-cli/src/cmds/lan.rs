@@ -1,4 +1,4 @@
-use crate::{new_rpc_client, Command, Result};
+use crate::{format, new_rpc_client, Command, Result};
 use clap::value_t_or_exit;
 
 pub struct Lan;
@@ -50,10 +50,11 @@ impl Lan {
 
     async fn get(&self) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
-        let allow_lan = rpc.get_settings(()).await?.into_inner().allow_lan;
+        let settings = rpc.get_settings(()).await?.into_inner();
         println!(
-            "Local network sharing setting: {}",
-            if allow_lan { "allow" } else { "block" }
+            "Local network sharing setting: {}{}",
+            if settings.allow_lan { "allow" } else { "block" },
+            format::lock_marker(&settings, "allow_lan")
         );
         Ok(())
     }
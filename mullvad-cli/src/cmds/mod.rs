@@ -4,6 +4,9 @@ use std::collections::HashMap;
 mod account;
 pub use self::account::Account;
 
+mod api_access;
+pub use self::api_access::ApiAccess;
+
 mod auto_connect;
 pub use self::auto_connect::AutoConnect;
 
@@ -19,6 +22,9 @@ pub use self::bridge::Bridge;
 mod connect;
 pub use self::connect::Connect;
 
+mod debug;
+pub use self::debug::Debug;
+
 mod disconnect;
 pub use self::disconnect::Disconnect;
 
@@ -39,12 +45,21 @@ mod split_tunnel;
 #[cfg(target_os = "linux")]
 pub use self::split_tunnel::SplitTunnel;
 
+mod schedule;
+pub use self::schedule::Schedule;
+
+mod settings;
+pub use self::settings::Settings;
+
 mod status;
 pub use self::status::Status;
 
 mod tunnel;
 pub use self::tunnel::Tunnel;
 
+mod tunnel_proxy;
+pub use self::tunnel_proxy::TunnelProxy;
+
 mod version;
 pub use self::version::Version;
 
@@ -52,20 +67,25 @@ pub use self::version::Version;
 pub fn get_commands() -> HashMap<&'static str, Box<dyn Command>> {
     let commands: Vec<Box<dyn Command>> = vec![
         Box::new(Account),
+        Box::new(ApiAccess),
         Box::new(AutoConnect),
         Box::new(BetaProgram),
         Box::new(BlockWhenDisconnected),
         Box::new(Bridge),
         Box::new(Connect),
+        Box::new(Debug),
         Box::new(Disconnect),
         Box::new(Reconnect),
         Box::new(Lan),
         Box::new(Relay),
         Box::new(Reset),
+        Box::new(Schedule),
+        Box::new(Settings),
         #[cfg(target_os = "linux")]
         Box::new(SplitTunnel),
         Box::new(Status),
         Box::new(Tunnel),
+        Box::new(TunnelProxy),
         Box::new(Version),
     ];
     let mut map = HashMap::new();
@@ -1,4 +1,4 @@
-use crate::{location, new_rpc_client, Command, Error, Result};
+use crate::{format, location, new_rpc_client, Command, Error, Result};
 use clap::{value_t, values_t};
 use std::{
     io::{self, BufRead},
@@ -171,7 +171,26 @@ impl Command for Relay {
             )
             .subcommand(
                 clap::SubCommand::with_name("update")
-                    .about("Update the list of available countries and cities"),
+                    .about(
+                        "Update the list of available countries and cities, and reload the \
+                         custom relay list",
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("rotation")
+                    .about(
+                        "Manage automatic reconnection to a different relay matching the same \
+                         constraints (specified in minutes)",
+                    )
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(clap::SubCommand::with_name("get"))
+                    .subcommand(
+                        clap::SubCommand::with_name("set")
+                            .arg(clap::Arg::with_name("interval").required(true)),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("unset").about("Disable relay rotation"),
+                    ),
             )
     }
 
@@ -184,6 +203,17 @@ impl Command for Relay {
             self.list().await
         } else if matches.subcommand_matches("update").is_some() {
             self.update().await
+        } else if let Some(rotation_matches) = matches.subcommand_matches("rotation") {
+            match rotation_matches.subcommand() {
+                ("get", _) => self.get_rotation_interval().await,
+                ("set", Some(set_matches)) => {
+                    let interval = value_t!(set_matches.value_of("interval"), u32)
+                        .unwrap_or_else(|e| e.exit());
+                    self.set_rotation_interval(interval).await
+                }
+                ("unset", _) => self.unset_rotation_interval().await,
+                _ => unreachable!("No relay rotation command given"),
+            }
         } else {
             unreachable!("No relay command given");
         }
@@ -524,14 +554,44 @@ impl Relay {
         .await
     }
 
-    async fn get(&self) -> Result<()> {
+    async fn get_rotation_interval(&self) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
-        let constraints = rpc
+        let tunnel_options = rpc
             .get_settings(())
             .await?
             .into_inner()
-            .relay_settings
+            .tunnel_options
             .unwrap();
+        match tunnel_options.relay_rotation {
+            Some(rotation) => println!("Relay rotation interval: {} minute(s)", rotation.interval),
+            None => println!("Relay rotation: off"),
+        }
+        Ok(())
+    }
+
+    async fn set_rotation_interval(&self, interval: u32) -> Result<()> {
+        if interval == 0 {
+            return Err(Error::InvalidCommand(
+                "The interval must be at least one minute. Use 'unset' to disable relay rotation",
+            ));
+        }
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_relay_rotation_interval(interval).await?;
+        println!("Set relay rotation interval: {} minute(s)", interval);
+        Ok(())
+    }
+
+    async fn unset_rotation_interval(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.reset_relay_rotation_interval(()).await?;
+        println!("Disabled relay rotation");
+        Ok(())
+    }
+
+    async fn get(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc.get_settings(()).await?.into_inner();
+        let constraints = settings.relay_settings.clone().unwrap();
 
         print!("Current constraints: ");
 
@@ -591,6 +651,7 @@ impl Relay {
                 }
             }
         }
+        format::print_locked_fields(&settings, "relay_settings");
 
         Ok(())
     }
@@ -0,0 +1,170 @@
+use crate::{location, new_rpc_client, Command, Error, Result};
+use chrono::{NaiveTime, Timelike};
+use mullvad_management_interface::{
+    types::{schedule_rule::Action, ScheduleRule},
+    Code,
+};
+
+const WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+pub struct Schedule;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Schedule {
+    fn name(&self) -> &'static str {
+        "schedule"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about(
+                "Connect, disconnect or change location automatically at given times. \
+                 Times are in the local time zone",
+            )
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("add")
+                    .about("Add a rule to the schedule")
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .arg(
+                        clap::Arg::with_name("at")
+                            .help("The time of day to perform the action at, as HH:MM")
+                            .long("at")
+                            .takes_value(true)
+                            .required(true),
+                    )
+                    .arg(
+                        clap::Arg::with_name("days")
+                            .help(
+                                "The days of the week that the rule applies on. Default: every day",
+                            )
+                            .long("days")
+                            .takes_value(true)
+                            .multiple(true)
+                            .use_delimiter(true)
+                            .possible_values(&WEEKDAYS),
+                    )
+                    .subcommand(clap::SubCommand::with_name("connect").about("Connect the tunnel"))
+                    .subcommand(
+                        clap::SubCommand::with_name("disconnect").about("Disconnect the tunnel"),
+                    )
+                    .subcommand(location::get_subcommand().about("Change the location constraint")),
+            )
+            .subcommand(clap::SubCommand::with_name("list").about("List the schedule rules"))
+            .subcommand(
+                clap::SubCommand::with_name("remove")
+                    .about("Remove a rule from the schedule")
+                    .arg(
+                        clap::Arg::with_name("id")
+                            .help("The ID of the rule, as shown by the list command")
+                            .required(true),
+                    ),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("add", Some(add_matches)) => self.add(add_matches).await,
+            ("list", _) => self.list().await,
+            ("remove", Some(remove_matches)) => {
+                let id =
+                    clap::value_t!(remove_matches.value_of("id"), u32).unwrap_or_else(|e| e.exit());
+                self.remove(id).await
+            }
+            _ => unreachable!("No schedule command given"),
+        }
+    }
+}
+
+impl Schedule {
+    async fn add(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let time = NaiveTime::parse_from_str(matches.value_of("at").unwrap(), "%H:%M")
+            .map_err(|_| Error::InvalidCommand("The time must be given as HH:MM"))?;
+        let weekdays = matches
+            .values_of("days")
+            .map(|days| {
+                days.map(|day| WEEKDAYS.iter().position(|weekday| *weekday == day).unwrap() as u32)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let (action, location) = match matches.subcommand() {
+            ("connect", _) => (Action::Connect, None),
+            ("disconnect", _) => (Action::Disconnect, None),
+            ("location", Some(location_matches)) => (
+                Action::SetLocation,
+                Some(location::get_constraint(location_matches)),
+            ),
+            _ => unreachable!("No schedule action given"),
+        };
+
+        let mut rpc = new_rpc_client().await?;
+        let id = rpc
+            .add_schedule_rule(ScheduleRule {
+                id: 0,
+                weekdays,
+                hour: time.hour(),
+                minute: time.minute(),
+                action: i32::from(action),
+                location,
+            })
+            .await?
+            .into_inner();
+        println!("Added schedule rule {}", id);
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let schedule = rpc.get_settings(()).await?.into_inner().schedule;
+        if schedule.is_empty() {
+            println!("No schedule rules");
+        }
+        for rule in &schedule {
+            println!("{}", format_rule(rule));
+        }
+        Ok(())
+    }
+
+    async fn remove(&self, id: u32) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        match rpc.remove_schedule_rule(id).await {
+            Ok(_) => {
+                println!("Removed schedule rule {}", id);
+                Ok(())
+            }
+            Err(status) if status.code() == Code::NotFound => Err(Error::InvalidCommand(
+                "There is no schedule rule with that ID",
+            )),
+            Err(status) => Err(Error::GrpcClientError(status)),
+        }
+    }
+}
+
+fn format_rule(rule: &ScheduleRule) -> String {
+    let action = match Action::from_i32(rule.action) {
+        Some(Action::Connect) => "connect".to_string(),
+        Some(Action::Disconnect) => "disconnect".to_string(),
+        Some(Action::SetLocation) => {
+            format!(
+                "switch to {}",
+                location::format_location(rule.location.as_ref())
+            )
+        }
+        None => "unknown action".to_string(),
+    };
+    let days = if rule.weekdays.is_empty() {
+        "every day".to_string()
+    } else {
+        let days: Vec<&str> = rule
+            .weekdays
+            .iter()
+            .filter_map(|day| WEEKDAYS.get(*day as usize).cloned())
+            .collect();
+        format!("on {}", days.join(", "))
+    };
+    format!(
+        "{}: {} at {:02}:{:02} {}",
+        rule.id, action, rule.hour, rule.minute, days
+    )
+}
@@ -0,0 +1,154 @@
+use crate::{new_rpc_client, Command, Error, Result};
+use clap::value_t_or_exit;
+use mullvad_management_interface::types::{ImportSettingsRequest, SettingsChange, Timestamp};
+use std::fs;
+
+pub struct Settings;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Settings {
+    fn name(&self) -> &'static str {
+        "settings"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Export, import or roll back all settings")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("export")
+                    .about("Write all settings to a file")
+                    .arg(
+                        clap::Arg::with_name("file")
+                            .help("The file to write the settings to")
+                            .required(true),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("import")
+                    .about(
+                        "Replace all settings with the ones in a file. Settings that are locked \
+                         by the settings policy are not changed",
+                    )
+                    .arg(
+                        clap::Arg::with_name("file")
+                            .help("A file written by the export command")
+                            .required(true),
+                    )
+                    .arg(
+                        clap::Arg::with_name("skip account")
+                            .help("Keep the current account number instead of importing it")
+                            .long("skip-account"),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("history")
+                    .about("Show the recent changes to the settings and who made them"),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("rollback")
+                    .about(
+                        "Restore the settings to how they were at a revision in the history. \
+                         Settings that are locked by the settings policy are not changed",
+                    )
+                    .arg(
+                        clap::Arg::with_name("revision")
+                            .help("The revision to restore, as shown by the history command")
+                            .required(true),
+                    ),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("export", Some(export_matches)) => {
+                self.export(export_matches.value_of("file").unwrap()).await
+            }
+            ("import", Some(import_matches)) => {
+                self.import(
+                    import_matches.value_of("file").unwrap(),
+                    import_matches.is_present("skip account"),
+                )
+                .await
+            }
+            ("history", Some(_)) => self.history().await,
+            ("rollback", Some(rollback_matches)) => {
+                let revision = value_t_or_exit!(rollback_matches.value_of("revision"), u64);
+                self.rollback(revision).await
+            }
+            _ => unreachable!("No settings command given"),
+        }
+    }
+}
+
+impl Settings {
+    async fn export(&self, path: &str) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc.export_settings(()).await?.into_inner();
+        fs::write(path, settings).map_err(|error| Error::FileError(path.to_owned(), error))?;
+        println!("Exported settings to {}", path);
+        Ok(())
+    }
+
+    async fn import(&self, path: &str, skip_account: bool) -> Result<()> {
+        let settings =
+            fs::read_to_string(path).map_err(|error| Error::FileError(path.to_owned(), error))?;
+        let mut rpc = new_rpc_client().await?;
+        rpc.import_settings(ImportSettingsRequest {
+            settings,
+            keep_account_token: skip_account,
+        })
+        .await?;
+        println!("Imported settings from {}", path);
+        Ok(())
+    }
+
+    async fn history(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let history = rpc.get_settings_history(()).await?.into_inner();
+        if history.entries.is_empty() {
+            println!("No changes have been recorded");
+        }
+        for entry in history.entries {
+            println!(
+                "Revision {} at {} by {}",
+                entry.revision,
+                Self::format_timestamp(&entry.timestamp.unwrap()),
+                entry.client
+            );
+            for change in entry.changes {
+                Self::print_change(&change);
+            }
+        }
+        Ok(())
+    }
+
+    async fn rollback(&self, revision: u64) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.rollback_settings(revision).await?;
+        println!("Restored settings to revision {}", revision);
+        Ok(())
+    }
+
+    fn print_change(change: &SettingsChange) {
+        let format_value = |value: &str| {
+            if value.is_empty() {
+                "(unset)".to_owned()
+            } else {
+                value.to_owned()
+            }
+        };
+        println!(
+            "    {}: {} -> {}",
+            change.field,
+            format_value(&change.old_value),
+            format_value(&change.new_value)
+        );
+    }
+
+    fn format_timestamp(timestamp: &Timestamp) -> String {
+        let ndt = chrono::NaiveDateTime::from_timestamp(timestamp.seconds, timestamp.nanos as u32);
+        let utc = chrono::DateTime::<chrono::Utc>::from_utc(ndt, chrono::Utc);
+        utc.with_timezone(&chrono::Local).to_string()
+    }
+}
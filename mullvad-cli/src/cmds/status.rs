@@ -1,4 +1,7 @@
-use crate::{format::print_keygen_event, new_rpc_client, Command, Error, Result};
+use crate::{
+    format::{format_expiry, print_keygen_event},
+    new_rpc_client, Command, Error, Result,
+};
 use mullvad_management_interface::{
     types::{
         daemon_event::Event as EventType,
@@ -6,7 +9,8 @@ use mullvad_management_interface::{
             firewall_policy_error::ErrorType as FirewallPolicyErrorType, Cause as ErrorStateCause,
             FirewallPolicyError, GenerationError,
         },
-        ErrorState, ProxyType, TransportProtocol, TunnelEndpoint, TunnelState, TunnelType,
+        ErrorState, EventsListenRequest, ProxyType, TransportProtocol, TunnelEndpoint, TunnelState,
+        TunnelType,
     },
     ManagementServiceClient,
 };
@@ -53,7 +57,10 @@ impl Command for Status {
         if let Some(listen_matches) = matches.subcommand_matches("listen") {
             let verbose = listen_matches.is_present("verbose");
 
-            let mut events = rpc.events_listen(()).await?.into_inner();
+            let mut events = rpc
+                .events_listen(EventsListenRequest { resume_after: 0 })
+                .await?
+                .into_inner();
 
             while let Some(event) = events.message().await? {
                 match event.event.unwrap() {
@@ -79,6 +86,14 @@ impl Command for Status {
                             println!("New relay list: {:#?}", relay_list);
                         }
                     }
+                    EventType::RelayListDiff(diff) => {
+                        if verbose {
+                            println!(
+                                "Relay list changed: added {:?}, removed {:?}, deactivated {:?}",
+                                diff.added, diff.removed, diff.deactivated
+                            );
+                        }
+                    }
                     EventType::VersionInfo(app_version_info) => {
                         if verbose {
                             println!("New app version info: {:#?}", app_version_info);
@@ -90,6 +105,39 @@ impl Command for Status {
                             print_keygen_event(&key_event);
                         }
                     }
+                    EventType::AccountExpiry(event) => {
+                        if let Some(expiry) = event.expiry {
+                            println!("Account expires at {}", format_expiry(&expiry));
+                        }
+                    }
+                    EventType::AccountExpiryNotification(event) => {
+                        if event.threshold_hours == 0 {
+                            println!("The account has run out of time");
+                        } else if let Some(expiry) = event.expiry {
+                            println!(
+                                "The account has less than {} hours left. It expires at {}",
+                                event.threshold_hours,
+                                format_expiry(&expiry)
+                            );
+                        }
+                    }
+                    EventType::ConnectivityCheckFailure(event) => {
+                        if event.relay.is_empty() {
+                            println!("Connectivity check failed");
+                        } else {
+                            println!("Connectivity check failed for {}", event.relay);
+                        }
+                    }
+                    EventType::RetryAttempt(event) => {
+                        if verbose {
+                            println!("Retry attempt {}: {}", event.attempt, event.relay);
+                        }
+                    }
+                    EventType::SplitTunnel(event) => {
+                        if verbose {
+                            println!("Excluded processes: {:?}", event.pids);
+                        }
+                    }
                 }
             }
         }
@@ -146,6 +194,7 @@ fn format_endpoint(endpoint: &TunnelEndpoint) -> String {
             " via {} {} over {}",
             match ProxyType::from_i32(proxy.proxy_type).expect("unknown proxy type") {
                 ProxyType::Shadowsocks => "Shadowsocks",
+                ProxyType::Socks5 => "SOCKS5 proxy",
                 ProxyType::Custom => "custom bridge",
             },
             proxy.address,
@@ -210,6 +259,7 @@ fn error_state_to_string(error_state: &ErrorState) -> String {
         }
         IsOffline => "This device is offline, no tunnels can be established",
         TapAdapterProblem => "A problem with the TAP adapter has been detected",
+        AccountExpired => "The account has run out of time",
         #[cfg(target_os = "android")]
         VpnPermissionDenied => "The Android VPN permission was denied when creating the tunnel",
         #[cfg(not(target_os = "android"))]
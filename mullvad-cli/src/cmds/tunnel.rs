@@ -1,6 +1,12 @@
-use crate::{format::print_keygen_event, new_rpc_client, Command, Error, Result};
+use crate::{
+    format::{self, print_keygen_event},
+    new_rpc_client, Command, Error, Result,
+};
 use clap::value_t;
-use mullvad_management_interface::types::{Timestamp, TunnelOptions};
+use mullvad_management_interface::types::{
+    ConnectivityCheckOptions, PublicKey, Timestamp, TunnelOptions,
+};
+use std::net::Ipv4Addr;
 
 pub struct Tunnel;
 
@@ -37,6 +43,68 @@ fn create_wireguard_subcommand() -> clap::App<'static, 'static> {
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .subcommand(create_wireguard_mtu_subcommand())
         .subcommand(create_wireguard_keys_subcommand())
+        .subcommand(create_wireguard_quantum_resistant_subcommand())
+        .subcommand(create_wireguard_connectivity_check_subcommand())
+}
+
+fn create_wireguard_connectivity_check_subcommand() -> clap::App<'static, 'static> {
+    let timeout_arg = |name: &'static str, help: &'static str| {
+        clap::Arg::with_name(name)
+            .long(name)
+            .takes_value(true)
+            .value_name("SECONDS")
+            .help(help)
+    };
+    clap::SubCommand::with_name("connectivity-check")
+        .about("Configure how the connectivity of the wireguard tunnel is checked")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(
+            clap::SubCommand::with_name("set")
+                .about("Change one or more connectivity check options")
+                .arg(timeout_arg(
+                    "rx-timeout",
+                    "Time to wait for a response to outgoing traffic before pinging",
+                ))
+                .arg(timeout_arg(
+                    "traffic-timeout",
+                    "Time to wait for any traffic before pinging",
+                ))
+                .arg(timeout_arg(
+                    "ping-timeout",
+                    "Time to wait for traffic after the first ping before reconnecting",
+                ))
+                .arg(timeout_arg("ping-interval", "Time to wait between pings"))
+                .arg(
+                    clap::Arg::with_name("ping-target")
+                        .long("ping-target")
+                        .takes_value(true)
+                        .value_name("IPV4 ADDRESS")
+                        .help(
+                            "In-tunnel host to ping, within 10.64.0.0/10 or 100.64.0.0/10. Use \
+                             'gateway' to ping the tunnel gateway",
+                        ),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("reset")
+                .about("Use the default connectivity check options"),
+        )
+}
+
+fn create_wireguard_quantum_resistant_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("quantum-resistant")
+        .about("Negotiate a quantum-resistant pre-shared key with the relay when connecting")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(
+            clap::SubCommand::with_name("set").arg(
+                clap::Arg::with_name("enable")
+                    .required(true)
+                    .takes_value(true)
+                    .possible_values(&["on", "off"]),
+            ),
+        )
 }
 
 fn create_wireguard_mtu_subcommand() -> clap::App<'static, 'static> {
@@ -57,6 +125,29 @@ fn create_wireguard_keys_subcommand() -> clap::App<'static, 'static> {
         .subcommand(clap::SubCommand::with_name("check"))
         .subcommand(clap::SubCommand::with_name("regenerate"))
         .subcommand(create_wireguard_keys_rotation_interval_subcommand())
+        .subcommand(
+            clap::SubCommand::with_name("list").about("List all keys registered on the account"),
+        )
+        .subcommand(create_wireguard_keys_label_subcommand())
+        .subcommand(
+            clap::SubCommand::with_name("revoke")
+                .about("Remove a key used by another device from the account")
+                .arg(
+                    clap::Arg::with_name("key")
+                        .help("The base64 encoded public key to remove")
+                        .required(true),
+                ),
+        )
+}
+
+fn create_wireguard_keys_label_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("label")
+        .about("Manage the local label that identifies the key of this device")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("set").arg(clap::Arg::with_name("label").required(true)),
+        )
+        .subcommand(clap::SubCommand::with_name("unset").about("Remove the label"))
 }
 
 fn create_wireguard_keys_rotation_interval_subcommand() -> clap::App<'static, 'static> {
@@ -142,6 +233,33 @@ impl Tunnel {
                     ("reset", _) => Self::process_wireguard_rotation_interval_reset().await,
                     _ => unreachable!("unhandled command"),
                 },
+                ("list", _) => Self::process_wireguard_key_list().await,
+                ("label", Some(matches)) => match matches.subcommand() {
+                    ("set", Some(matches)) => {
+                        Self::process_wireguard_key_label_set(matches.value_of("label").unwrap())
+                            .await
+                    }
+                    ("unset", _) => Self::process_wireguard_key_label_set("").await,
+                    _ => unreachable!("unhandled command"),
+                },
+                ("revoke", Some(matches)) => Self::process_wireguard_key_revoke(matches).await,
+                _ => unreachable!("unhandled command"),
+            },
+
+            ("connectivity-check", Some(matches)) => match matches.subcommand() {
+                ("get", _) => Self::process_wireguard_connectivity_check_get().await,
+                ("set", Some(matches)) => {
+                    Self::process_wireguard_connectivity_check_set(matches).await
+                }
+                ("reset", _) => Self::process_wireguard_connectivity_check_reset().await,
+                _ => unreachable!("unhandled command"),
+            },
+
+            ("quantum-resistant", Some(matches)) => match matches.subcommand() {
+                ("get", _) => Self::process_wireguard_quantum_resistant_get().await,
+                ("set", Some(matches)) => {
+                    Self::process_wireguard_quantum_resistant_set(matches).await
+                }
                 _ => unreachable!("unhandled command"),
             },
 
@@ -214,6 +332,57 @@ impl Tunnel {
         Ok(())
     }
 
+    async fn process_wireguard_key_list() -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let current_key = match rpc.get_wireguard_key(()).await {
+            Ok(response) => Some(response.into_inner().key),
+            Err(status) if status.code() == mullvad_management_interface::Code::NotFound => None,
+            Err(status) => return Err(Error::GrpcClientError(status)),
+        };
+        let keys = rpc.list_wireguard_keys(()).await?.into_inner().keys;
+        if keys.is_empty() {
+            println!("No keys are registered on the account");
+        }
+        for key in keys {
+            let marker = if Some(&key.key) == current_key.as_ref() {
+                " (this device)"
+            } else {
+                ""
+            };
+            println!("{}{}", base64::encode(&key.key), marker);
+            if !key.label.is_empty() {
+                println!("\tLabel : {}", key.label);
+            }
+            println!("\tIPv4  : {}", key.ipv4_address);
+            println!("\tIPv6  : {}", key.ipv6_address);
+        }
+        Ok(())
+    }
+
+    async fn process_wireguard_key_label_set(label: &str) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_key_label(label.to_string()).await?;
+        if label.is_empty() {
+            println!("Removed the key label");
+        } else {
+            println!("Set the key label to \"{}\"", label);
+        }
+        Ok(())
+    }
+
+    async fn process_wireguard_key_revoke(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let key = base64::decode(matches.value_of("key").unwrap())
+            .ok()
+            .filter(|key| key.len() == 32)
+            .ok_or(Error::InvalidCommand("Invalid public key"))?;
+
+        let mut rpc = new_rpc_client().await?;
+        rpc.revoke_wireguard_key(PublicKey { key, created: None })
+            .await?;
+        println!("Removed the key from the account");
+        Ok(())
+    }
+
     async fn process_wireguard_rotation_interval_get() -> Result<()> {
         let tunnel_options = Self::get_tunnel_options().await?;
         match tunnel_options.wireguard.unwrap().automatic_rotation {
@@ -241,6 +410,111 @@ impl Tunnel {
         Ok(())
     }
 
+    async fn process_wireguard_quantum_resistant_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options().await?;
+        println!(
+            "Quantum-resistant tunnel: {}",
+            if tunnel_options.wireguard.unwrap().quantum_resistant {
+                "on"
+            } else {
+                "off"
+            }
+        );
+        Ok(())
+    }
+
+    async fn process_wireguard_quantum_resistant_set(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let enabled = matches.value_of("enable").unwrap() == "on";
+
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_quantum_resistant(enabled).await?;
+        if enabled {
+            println!("Enabled quantum-resistant tunnels");
+        } else {
+            println!("Disabled quantum-resistant tunnels");
+        }
+        Ok(())
+    }
+
+    async fn process_wireguard_connectivity_check_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options().await?;
+        let options = tunnel_options
+            .wireguard
+            .unwrap()
+            .connectivity_check
+            .unwrap();
+        println!("RX timeout      : {} s", options.rx_timeout);
+        println!("Traffic timeout : {} s", options.traffic_timeout);
+        println!("Ping timeout    : {} s", options.ping_timeout);
+        println!("Ping interval   : {} s", options.ping_interval);
+        println!(
+            "Ping target     : {}",
+            if options.ping_target.is_empty() {
+                "tunnel gateway"
+            } else {
+                &options.ping_target
+            }
+        );
+        Ok(())
+    }
+
+    async fn process_wireguard_connectivity_check_set(
+        matches: &clap::ArgMatches<'_>,
+    ) -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options().await?;
+        let mut options = tunnel_options
+            .wireguard
+            .unwrap()
+            .connectivity_check
+            .unwrap();
+
+        if matches.is_present("rx-timeout") {
+            options.rx_timeout =
+                value_t!(matches.value_of("rx-timeout"), u32).unwrap_or_else(|e| e.exit());
+        }
+        if matches.is_present("traffic-timeout") {
+            options.traffic_timeout =
+                value_t!(matches.value_of("traffic-timeout"), u32).unwrap_or_else(|e| e.exit());
+        }
+        if matches.is_present("ping-timeout") {
+            options.ping_timeout =
+                value_t!(matches.value_of("ping-timeout"), u32).unwrap_or_else(|e| e.exit());
+        }
+        if matches.is_present("ping-interval") {
+            options.ping_interval =
+                value_t!(matches.value_of("ping-interval"), u32).unwrap_or_else(|e| e.exit());
+        }
+        match matches.value_of("ping-target") {
+            Some("gateway") => options.ping_target = String::new(),
+            Some(_) => {
+                let target = value_t!(matches.value_of("ping-target"), Ipv4Addr)
+                    .unwrap_or_else(|e| e.exit());
+                options.ping_target = target.to_string();
+            }
+            None => (),
+        }
+
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_connectivity_check(options).await?;
+        println!("Updated the connectivity check options");
+        Ok(())
+    }
+
+    async fn process_wireguard_connectivity_check_reset() -> Result<()> {
+        let defaults = talpid_types::net::wireguard::ConnectivityCheckOptions::default();
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_connectivity_check(ConnectivityCheckOptions {
+            rx_timeout: defaults.rx_timeout,
+            traffic_timeout: defaults.traffic_timeout,
+            ping_timeout: defaults.ping_timeout,
+            ping_interval: defaults.ping_interval,
+            ping_target: String::new(),
+        })
+        .await?;
+        println!("Reset the connectivity check options to their defaults");
+        Ok(())
+    }
+
     async fn handle_ipv6_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
         if matches.subcommand_matches("get").is_some() {
             Self::process_ipv6_get().await
@@ -291,14 +565,17 @@ impl Tunnel {
     }
 
     async fn process_ipv6_get() -> Result<()> {
-        let tunnel_options = Self::get_tunnel_options().await?;
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc.get_settings(()).await?.into_inner();
+        let tunnel_options = settings.tunnel_options.clone().unwrap();
         println!(
-            "IPv6: {}",
+            "IPv6: {}{}",
             if tunnel_options.generic.unwrap().enable_ipv6 {
                 "on"
             } else {
                 "off"
-            }
+            },
+            format::lock_marker(&settings, "tunnel_options.generic.enable_ipv6")
         );
         Ok(())
     }
@@ -0,0 +1,112 @@
+use crate::{new_rpc_client, Command, Error, Result};
+use clap::value_t;
+use mullvad_management_interface::types::TunnelProxySettings;
+use std::net::{IpAddr, SocketAddr};
+
+pub struct TunnelProxy;
+
+#[mullvad_management_interface::async_trait]
+impl Command for TunnelProxy {
+    fn name(&self) -> &'static str {
+        "tunnel-proxy"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about(
+                "Control the local SOCKS5 proxy that sends traffic through the tunnel. \
+                 The proxy only accepts connections while the tunnel is connected",
+            )
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("set")
+                    .about("Change the tunnel proxy settings")
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::SubCommand::with_name("state")
+                            .about("Enable or disable the tunnel proxy")
+                            .arg(
+                                clap::Arg::with_name("state")
+                                    .required(true)
+                                    .possible_values(&["on", "off"]),
+                            ),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("address")
+                            .about("Set the local address that the tunnel proxy listens on")
+                            .arg(
+                                clap::Arg::with_name("ip")
+                                    .help("Specifies the loopback IP to listen on")
+                                    .required(true)
+                                    .index(1),
+                            )
+                            .arg(
+                                clap::Arg::with_name("port")
+                                    .help("Specifies the port to listen on")
+                                    .required(true)
+                                    .index(2),
+                            ),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("get").about("Display the tunnel proxy settings"),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("set", Some(set_matches)) => self.handle_set(set_matches).await,
+            ("get", _) => self.get().await,
+            _ => unreachable!("No tunnel-proxy command given"),
+        }
+    }
+}
+
+impl TunnelProxy {
+    async fn handle_set(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let mut settings = Self::get_settings().await?;
+        match matches.subcommand() {
+            ("state", Some(state_matches)) => {
+                settings.enabled = state_matches.value_of("state") == Some("on");
+            }
+            ("address", Some(address_matches)) => {
+                let ip =
+                    value_t!(address_matches.value_of("ip"), IpAddr).unwrap_or_else(|e| e.exit());
+                let port =
+                    value_t!(address_matches.value_of("port"), u16).unwrap_or_else(|e| e.exit());
+                if !ip.is_loopback() {
+                    return Err(Error::InvalidCommand(
+                        "The tunnel proxy can only listen on a loopback address",
+                    ));
+                }
+                settings.bind_address = SocketAddr::new(ip, port).to_string();
+            }
+            _ => unreachable!("No tunnel-proxy set command given"),
+        }
+
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_tunnel_proxy_settings(settings).await?;
+        println!("Updated tunnel proxy settings");
+        Ok(())
+    }
+
+    async fn get(&self) -> Result<()> {
+        let settings = Self::get_settings().await?;
+        println!(
+            "Tunnel proxy: {}",
+            if settings.enabled { "on" } else { "off" }
+        );
+        println!("Listening address: {}", settings.bind_address);
+        Ok(())
+    }
+
+    async fn get_settings() -> Result<TunnelProxySettings> {
+        let mut rpc = new_rpc_client().await?;
+        Ok(rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .tunnel_proxy
+            .unwrap())
+    }
+}
@@ -1,4 +1,42 @@
-use mullvad_management_interface::types::KeygenEvent;
+use mullvad_management_interface::types::{KeygenEvent, Settings, Timestamp};
+
+pub fn format_expiry(expiry: &Timestamp) -> String {
+    let ndt = chrono::NaiveDateTime::from_timestamp(expiry.seconds, expiry.nanos as u32);
+    let utc = chrono::DateTime::<chrono::Utc>::from_utc(ndt, chrono::Utc);
+    utc.with_timezone(&chrono::Local).to_string()
+}
+
+/// Returns a marker to print next to a setting if it is locked by the settings policy. Settings
+/// are named by their path in the settings file, e.g. `tunnel_options.generic.enable_ipv6`.
+pub fn lock_marker(settings: &Settings, field: &str) -> &'static str {
+    if settings
+        .locked_fields
+        .iter()
+        .any(|locked_field| contains_field(locked_field, field))
+    {
+        " (locked)"
+    } else {
+        ""
+    }
+}
+
+/// Prints the settings within `section` that are locked by the settings policy, if there are any.
+pub fn print_locked_fields(settings: &Settings, section: &str) {
+    let locked_fields: Vec<&str> = settings
+        .locked_fields
+        .iter()
+        .filter(|field| contains_field(section, field) || contains_field(field, section))
+        .map(String::as_str)
+        .collect();
+    if !locked_fields.is_empty() {
+        println!("Locked by policy: {}", locked_fields.join(", "));
+    }
+}
+
+/// Returns whether `field` is `parent` or one of its children.
+fn contains_field(parent: &str, field: &str) -> bool {
+    field == parent || (field.starts_with(parent) && field[parent.len()..].starts_with('.'))
+}
 
 pub fn print_keygen_event(key_event: &KeygenEvent) {
     use mullvad_management_interface::types::keygen_event::KeygenEvent as EventType;
@@ -30,6 +30,9 @@ pub enum Error {
     /// The given command is not correct in some way
     #[error(display = "Invalid command: {}", _0)]
     InvalidCommand(&'static str),
+
+    #[error(display = "Failed to access {}", _0)]
+    FileError(String, #[error(source)] io::Error),
 }
 
 #[tokio::main]
@@ -0,0 +1,205 @@
+//! Access control for the management interface.
+//!
+//! Every client is assigned a role based on the credentials of the process that connected to the
+//! management interface. The roles are configured in `management-access.json` in the settings
+//! directory:
+//!
+//! ```json
+//! {
+//!     "default_role": "read_only",
+//!     "users": { "1000": "admin" },
+//!     "groups": { "100": "operator" }
+//! }
+//! ```
+//!
+//! A role assigned to the user takes precedence over one assigned to its primary group. Root is
+//! always an admin. If the file does not exist, every client is an admin. Clients whose
+//! credentials can't be read are denied access, except on Windows, where credentials are never
+//! available and every client is given the default role.
+//!
+//! Clients of the [remote management listener](crate::remote_management) are not covered by this
+//! policy. They are given the role that is configured for the listener.
+
+use mullvad_management_interface::{PeerCredentials, Status};
+use serde::Deserialize;
+use std::{collections::HashMap, fmt, fs, io, path::Path};
+
+const ACCESS_POLICY_FILE: &str = "management-access.json";
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Unable to read access policy from {}", _0)]
+    ReadError(String, #[error(source)] io::Error),
+
+    #[error(display = "Unable to parse access policy")]
+    ParseError(#[error(source)] serde_json::Error),
+}
+
+/// Determines which RPCs a client is allowed to call. Each role is allowed to do everything that
+/// the roles below it can do.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// May read the state and settings of the daemon.
+    ReadOnly,
+    /// May also connect, disconnect and select relays.
+    Operator,
+    /// May do anything, including changing settings and the account.
+    Admin,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::ReadOnly => "read-only".fmt(f),
+            Role::Operator => "operator".fmt(f),
+            Role::Admin => "admin".fmt(f),
+        }
+    }
+}
+
+/// Maps the credentials of management interface clients to roles.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct AccessPolicy {
+    default_role: Role,
+    users: HashMap<u32, Role>,
+    groups: HashMap<u32, Role>,
+}
+
+impl Default for AccessPolicy {
+    fn default() -> Self {
+        AccessPolicy {
+            default_role: Role::Admin,
+            users: HashMap::new(),
+            groups: HashMap::new(),
+        }
+    }
+}
+
+impl AccessPolicy {
+    /// Loads the access policy from the settings directory. Returns a policy that gives every
+    /// client full access if no policy file exists.
+    pub fn load(settings_dir: &Path) -> Result<Self, Error> {
+        let path = settings_dir.join(ACCESS_POLICY_FILE);
+        match fs::read(&path) {
+            Ok(contents) => Self::parse(&contents),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(Error::ReadError(path.display().to_string(), error)),
+        }
+    }
+
+    fn parse(contents: &[u8]) -> Result<Self, Error> {
+        serde_json::from_slice(contents).map_err(Error::ParseError)
+    }
+
+    /// Returns the role of a local client with the given credentials, or `None` if the client
+    /// may not use the management interface at all.
+    pub fn role(&self, credentials: Option<PeerCredentials>) -> Option<Role> {
+        let credentials = match credentials {
+            Some(credentials) => credentials,
+            None if cfg!(windows) => return Some(self.default_role),
+            None => return None,
+        };
+        if credentials.uid == 0 {
+            return Some(Role::Admin);
+        }
+        let role = self
+            .users
+            .get(&credentials.uid)
+            .or_else(|| self.groups.get(&credentials.gid))
+            .copied()
+            .unwrap_or(self.default_role);
+        Some(role)
+    }
+}
+
+/// Fails with `PERMISSION_DENIED` unless a client with the given role has at least the `required`
+/// role. A client without a role is always denied.
+pub fn check_role(role: Option<Role>, required: Role) -> Result<(), Status> {
+    match role {
+        Some(role) if role >= required => Ok(()),
+        Some(role) => Err(Status::permission_denied(format!(
+            "This operation requires the {} role, but the client has the {} role",
+            required, role
+        ))),
+        None => Err(Status::permission_denied(
+            "The credentials of the client could not be determined",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_management_interface::Code;
+
+    fn credentials(uid: u32, gid: u32) -> Option<PeerCredentials> {
+        Some(PeerCredentials { uid, gid })
+    }
+
+    #[test]
+    fn test_role_resolution() {
+        let policy = AccessPolicy::parse(
+            br#"{
+                "default_role": "read_only",
+                "users": { "1000": "admin", "1001": "read_only" },
+                "groups": { "100": "operator" }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(policy.role(credentials(0, 0)), Some(Role::Admin));
+        assert_eq!(policy.role(credentials(1000, 1000)), Some(Role::Admin));
+        assert_eq!(policy.role(credentials(1001, 100)), Some(Role::ReadOnly));
+        assert_eq!(policy.role(credentials(1002, 100)), Some(Role::Operator));
+        assert_eq!(policy.role(credentials(1003, 1003)), Some(Role::ReadOnly));
+    }
+
+    #[test]
+    fn test_default_policy_allows_everything() {
+        let policy = AccessPolicy::default();
+        assert!(check_role(policy.role(credentials(1000, 1000)), Role::Admin).is_ok());
+    }
+
+    #[test]
+    fn test_missing_credentials() {
+        let policy = AccessPolicy::default();
+        if cfg!(windows) {
+            assert_eq!(policy.role(None), Some(Role::Admin));
+        } else {
+            assert_eq!(policy.role(None), None);
+            let status = check_role(policy.role(None), Role::ReadOnly).unwrap_err();
+            assert_eq!(status.code(), Code::PermissionDenied);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_socketpair_client_credentials() {
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let client = runtime.block_on(async {
+            let (stream, _peer) = tokio::net::UnixStream::pair().unwrap();
+            PeerCredentials::from_unix_stream(&stream).unwrap()
+        });
+        assert_eq!(client.uid, unsafe { libc::getuid() });
+        assert_eq!(client.gid, unsafe { libc::getgid() });
+    }
+
+    #[test]
+    fn test_operator_permissions() {
+        let mut policy = AccessPolicy::default();
+        policy.users.insert(1000, Role::Operator);
+
+        let role = policy.role(credentials(1000, 1000));
+        assert!(check_role(role, Role::ReadOnly).is_ok());
+        assert!(check_role(role, Role::Operator).is_ok());
+        let status = check_role(role, Role::Admin).unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        assert_eq!(
+            status.message(),
+            "This operation requires the admin role, but the client has the operator role"
+        );
+    }
+}
@@ -80,6 +80,7 @@ impl AccountHistory {
                     .map(|account| AccountEntry {
                         account,
                         wireguard: None,
+                        wireguard_key_label: None,
                     })
                     .collect()
             }
@@ -158,6 +159,7 @@ impl AccountHistory {
             let new_entry = AccountEntry {
                 account: account.to_string(),
                 wireguard: None,
+                wireguard_key_label: None,
             };
             self.insert(new_entry).await?;
         }
@@ -284,4 +286,7 @@ impl AccountHistory {
 pub struct AccountEntry {
     pub account: AccountToken,
     pub wireguard: Option<WireguardData>,
+    /// Label that is applied to every WireGuard key generated for the account on this device.
+    #[serde(default)]
+    pub wireguard_key_label: Option<String>,
 }
@@ -0,0 +1,549 @@
+//! Keeps the expiry of the current account up to date and tells the daemon when the account is
+//! about to run out of time, so that clients can warn the user before the tunnel stops working.
+
+use crate::DaemonEventSender;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use futures::{
+    channel::mpsc,
+    future::{BoxFuture, Fuse, FusedFuture},
+    FutureExt, StreamExt,
+};
+use mullvad_rpc::{rest::MullvadRestHandle, AccountsProxy};
+use mullvad_types::account::AccountToken;
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp, fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use talpid_core::{future_retry::ExponentialBackoff, mpsc::Sender};
+use talpid_types::ErrorExt;
+use tokio::io::AsyncWriteExt;
+
+/// Caches the expiry of the current account. The cache is stored in the settings directory and is
+/// only readable by its owner, since the hash of the account token can be brute forced.
+const EXPIRY_CACHE_FILENAME: &str = "account-expiry.json";
+
+/// The longest time to sleep before looking at the clock again. This keeps notifications on time
+/// if the system clock is changed or the machine wakes up from sleep.
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// How often the expiry is refreshed.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// How often the expiry is refreshed when the account has less than a day left or has expired,
+/// so that time added to the account elsewhere is noticed quickly.
+const EXPIRING_REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to read the account expiry cache")]
+    ReadCache(#[error(source)] io::Error),
+
+    #[error(display = "Failed to write the account expiry cache")]
+    WriteCache(#[error(source)] io::Error),
+
+    #[error(display = "Failed to remove the account expiry cache")]
+    RemoveCache(#[error(source)] io::Error),
+
+    #[error(display = "Failure in serialization of the account expiry cache")]
+    Serialize(#[error(source)] serde_json::Error),
+}
+
+/// Events sent from the account monitor to the daemon.
+pub(crate) enum AccountEvent {
+    /// The API returned an expiry date for the account.
+    Expiry(AccountToken, DateTime<Utc>),
+    /// The time left on the account dropped below the given threshold in hours. The threshold is
+    /// zero once the account has expired.
+    ExpiresSoon(AccountToken, DateTime<Utc>, u32),
+}
+
+enum MonitorCommand {
+    SetAccount(Option<AccountToken>),
+    SetExpiry(AccountToken, DateTime<Utc>),
+    SetThresholds(Vec<u32>),
+    Refresh,
+}
+
+/// A handle for informing a running account monitor about changes. The monitor stops when the
+/// handle is dropped.
+pub(crate) struct AccountMonitorHandle {
+    tx: mpsc::UnboundedSender<MonitorCommand>,
+}
+
+impl AccountMonitorHandle {
+    /// Changes the account that is monitored.
+    pub fn set_account(&self, account_token: Option<AccountToken>) {
+        self.send(MonitorCommand::SetAccount(account_token));
+    }
+
+    /// Updates the expiry of the monitored account, e.g. after a voucher has been submitted.
+    pub fn set_expiry(&self, account_token: AccountToken, expiry: DateTime<Utc>) {
+        self.send(MonitorCommand::SetExpiry(account_token, expiry));
+    }
+
+    /// Changes the hours before expiry at which the daemon is notified.
+    pub fn set_thresholds(&self, thresholds: Vec<u32>) {
+        self.send(MonitorCommand::SetThresholds(thresholds));
+    }
+
+    /// Fetches the expiry from the API as soon as possible.
+    pub fn refresh(&self) {
+        self.send(MonitorCommand::Refresh);
+    }
+
+    fn send(&self, command: MonitorCommand) {
+        if self.tx.unbounded_send(command).is_err() {
+            log::error!("Failed to send command to the account monitor since it is not running");
+        }
+    }
+}
+
+/// Spawns a task that keeps the expiry of the current account up to date. Returns a handle to the
+/// task along with the cached expiry of the account, if any.
+pub(crate) fn spawn(
+    rpc_handle: MullvadRestHandle,
+    settings_dir: &Path,
+    account_token: Option<AccountToken>,
+    thresholds: Vec<u32>,
+    daemon_tx: DaemonEventSender<AccountEvent>,
+) -> (AccountMonitorHandle, Option<DateTime<Utc>>) {
+    let cache_path = settings_dir.join(EXPIRY_CACHE_FILENAME);
+    let mut notifier = ExpiryNotifier::new(thresholds);
+    if let Some(account_token) = &account_token {
+        match load_cache(&cache_path) {
+            Ok(Some(cache)) if cache.account_token_hash == hash_account_token(account_token) => {
+                notifier.expiry = Some(cache.expiry);
+                notifier.notified = cache.notified_threshold;
+            }
+            Ok(_) => (),
+            Err(error) => log::warn!(
+                "{}",
+                error.display_chain_with_msg("Unable to load cached account expiry")
+            ),
+        }
+    }
+    let cached_expiry = notifier.expiry;
+
+    let monitor = AccountMonitor {
+        accounts_proxy: AccountsProxy::new(rpc_handle),
+        cache_path,
+        daemon_tx,
+        account_token,
+        notifier,
+        next_refresh: Utc::now(),
+        // Retry failed refreshes after 1 minute, then 2 minutes and so on
+        retry_backoff: ExponentialBackoff::from_millis(2)
+            .factor(30_000)
+            .max_delay(REFRESH_INTERVAL),
+    };
+    let (tx, rx) = mpsc::unbounded();
+    tokio::spawn(monitor.run(rx));
+    (AccountMonitorHandle { tx }, cached_expiry)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedExpiry {
+    /// Identifies the account that the expiry belongs to. See `hash_account_token`.
+    account_token_hash: String,
+    expiry: DateTime<Utc>,
+    /// The smallest threshold, in hours, that the daemon has been notified about.
+    notified_threshold: Option<u32>,
+}
+
+/// Returns the hex encoded SHA-256 hash of an account token.
+fn hash_account_token(account_token: &str) -> String {
+    digest::digest(&digest::SHA256, account_token.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn load_cache(path: &Path) -> Result<Option<CachedExpiry>, Error> {
+    match fs::File::open(path) {
+        Ok(file) => serde_json::from_reader(io::BufReader::new(file))
+            .map(Some)
+            .map_err(Error::Serialize),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(Error::ReadCache(error)),
+    }
+}
+
+struct AccountMonitor {
+    accounts_proxy: AccountsProxy,
+    cache_path: PathBuf,
+    daemon_tx: DaemonEventSender<AccountEvent>,
+    account_token: Option<AccountToken>,
+    notifier: ExpiryNotifier,
+    next_refresh: DateTime<Utc>,
+    retry_backoff: ExponentialBackoff,
+}
+
+type RefreshFuture = BoxFuture<'static, (AccountToken, Result<DateTime<Utc>, String>)>;
+
+impl AccountMonitor {
+    async fn run(mut self, rx: mpsc::UnboundedReceiver<MonitorCommand>) {
+        let mut rx = rx.fuse();
+        let mut refresh: Fuse<RefreshFuture> = Fuse::terminated();
+        loop {
+            if refresh.is_terminated() {
+                if let Some(future) = self.start_refresh_if_due() {
+                    refresh = future.fuse();
+                }
+            }
+
+            let mut delay =
+                tokio::time::delay_for(self.time_until_next_poll(!refresh.is_terminated())).fuse();
+            futures::select! {
+                command = rx.next() => match command {
+                    Some(MonitorCommand::SetAccount(account_token)) => {
+                        if account_token != self.account_token {
+                            // Results for the previous account are no longer of interest
+                            refresh = Fuse::terminated();
+                            self.set_account(account_token).await;
+                        }
+                    }
+                    Some(MonitorCommand::SetExpiry(account_token, expiry)) => {
+                        self.set_expiry(account_token, expiry).await
+                    }
+                    Some(MonitorCommand::SetThresholds(thresholds)) => {
+                        self.notifier.thresholds = thresholds;
+                    }
+                    Some(MonitorCommand::Refresh) => {
+                        self.next_refresh = Utc::now();
+                    }
+                    None => return,
+                },
+                (account_token, result) = refresh => {
+                    if self.handle_refresh_result(account_token, result).is_err() {
+                        return;
+                    }
+                }
+                _ = delay => (),
+            }
+
+            if self.poll_notification().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    fn time_until_next_poll(&self, refreshing: bool) -> Duration {
+        if self.account_token.is_none() || refreshing {
+            return MAX_SLEEP;
+        }
+        (self.next_refresh - Utc::now())
+            .to_std()
+            .map(|duration| cmp::min(duration, MAX_SLEEP))
+            .unwrap_or(Duration::from_secs(0))
+    }
+
+    fn start_refresh_if_due(&self) -> Option<RefreshFuture> {
+        let account_token = self.account_token.clone()?;
+        if Utc::now() < self.next_refresh {
+            return None;
+        }
+        log::debug!("Refreshing account expiry");
+        let expiry_future = self.accounts_proxy.get_expiry(account_token.clone());
+        Some(Box::pin(async move {
+            let result = expiry_future
+                .await
+                .map_err(|error| error.display_chain_with_msg("Failed to refresh account expiry"));
+            (account_token, result)
+        }))
+    }
+
+    fn handle_refresh_result(
+        &mut self,
+        account_token: AccountToken,
+        result: Result<DateTime<Utc>, String>,
+    ) -> Result<(), ()> {
+        match result {
+            Ok(expiry) => {
+                self.retry_backoff.reset();
+                self.next_refresh = Utc::now() + chrono_duration(refresh_interval(expiry));
+                // The daemon passes the expiry back if it still concerns the current account
+                self.daemon_tx
+                    .send(AccountEvent::Expiry(account_token, expiry))
+            }
+            Err(error) => {
+                let delay = self.retry_backoff.next().unwrap_or(REFRESH_INTERVAL);
+                log::error!("{}. Retrying in {} seconds", error, delay.as_secs());
+                self.next_refresh = Utc::now() + chrono_duration(delay);
+                Ok(())
+            }
+        }
+    }
+
+    async fn set_account(&mut self, account_token: Option<AccountToken>) {
+        self.account_token = account_token;
+        self.notifier = ExpiryNotifier::new(self.notifier.thresholds.clone());
+        self.next_refresh = Utc::now();
+        self.retry_backoff.reset();
+        if self.account_token.is_none() {
+            if let Err(error) = self.remove_cache().await {
+                log::error!("{}", error.display_chain());
+            }
+        }
+    }
+
+    async fn set_expiry(&mut self, account_token: AccountToken, expiry: DateTime<Utc>) {
+        if self.account_token.as_ref() != Some(&account_token) {
+            return;
+        }
+        if self.notifier.set_expiry(expiry, Utc::now()) {
+            self.write_cache().await;
+        }
+    }
+
+    /// Notifies the daemon if the account has passed a threshold since the last poll.
+    async fn poll_notification(&mut self) -> Result<(), ()> {
+        let account_token = match &self.account_token {
+            Some(account_token) => account_token.clone(),
+            None => return Ok(()),
+        };
+        if let (Some(threshold), Some(expiry)) =
+            (self.notifier.poll(Utc::now()), self.notifier.expiry)
+        {
+            if threshold == 0 {
+                log::info!("The account has run out of time");
+            } else {
+                log::info!("The account has less than {} hours left", threshold);
+            }
+            self.daemon_tx
+                .send(AccountEvent::ExpiresSoon(account_token, expiry, threshold))?;
+            self.write_cache().await;
+        }
+        Ok(())
+    }
+
+    async fn write_cache(&self) {
+        if let Err(error) = self.try_write_cache().await {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to save account expiry to disk")
+            );
+        }
+    }
+
+    async fn try_write_cache(&self) -> Result<(), Error> {
+        let (account_token, expiry) = match (&self.account_token, self.notifier.expiry) {
+            (Some(account_token), Some(expiry)) => (account_token, expiry),
+            _ => return Ok(()),
+        };
+        let cache = CachedExpiry {
+            account_token_hash: hash_account_token(account_token),
+            expiry,
+            notified_threshold: self.notifier.notified,
+        };
+        write_cache_file(&self.cache_path, &cache).await
+    }
+
+    async fn remove_cache(&self) -> Result<(), Error> {
+        match tokio::fs::remove_file(&self.cache_path).await {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(Error::RemoveCache(error)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Writes the cache so that it is only readable by its owner.
+async fn write_cache_file(path: &Path, cache: &CachedExpiry) -> Result<(), Error> {
+    let buf = serde_json::to_vec_pretty(cache).map_err(Error::Serialize)?;
+
+    // Write to a temporary file first, so that the cache is never left half written
+    let temp_path = path.with_extension("json.tmp");
+    let mut options = fs::OpenOptions::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = tokio::fs::OpenOptions::from(options)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&temp_path)
+        .await
+        .map_err(Error::WriteCache)?;
+    file.write_all(&buf).await.map_err(Error::WriteCache)?;
+    file.sync_all().await.map_err(Error::WriteCache)?;
+    tokio::fs::rename(&temp_path, path)
+        .await
+        .map_err(Error::WriteCache)
+}
+
+fn refresh_interval(expiry: DateTime<Utc>) -> Duration {
+    if expiry - Utc::now() < ChronoDuration::days(1) {
+        EXPIRING_REFRESH_INTERVAL
+    } else {
+        REFRESH_INTERVAL
+    }
+}
+
+fn chrono_duration(duration: Duration) -> ChronoDuration {
+    ChronoDuration::from_std(duration).unwrap_or_else(|_| ChronoDuration::max_value())
+}
+
+/// Decides when the daemon should be notified about the account running out of time. Every
+/// threshold is notified at most once per expiry, and only the smallest threshold that has been
+/// passed is notified if several are passed at once.
+struct ExpiryNotifier {
+    /// Hours before the expiry at which to notify.
+    thresholds: Vec<u32>,
+    expiry: Option<DateTime<Utc>>,
+    /// The smallest threshold, in hours, that has been notified for the current expiry.
+    notified: Option<u32>,
+}
+
+impl ExpiryNotifier {
+    fn new(thresholds: Vec<u32>) -> Self {
+        ExpiryNotifier {
+            thresholds,
+            expiry: None,
+            notified: None,
+        }
+    }
+
+    /// Updates the expiry and returns whether it changed. If time was added to the account, the
+    /// thresholds that are still passed are not notified again.
+    fn set_expiry(&mut self, expiry: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        if self.expiry == Some(expiry) {
+            return false;
+        }
+        let extended = self.expiry.map(|old| expiry > old).unwrap_or(false);
+        self.notified = if extended {
+            self.passed_threshold(expiry, now)
+        } else {
+            None
+        };
+        self.expiry = Some(expiry);
+        true
+    }
+
+    /// Returns a threshold if it should be notified now.
+    fn poll(&mut self, now: DateTime<Utc>) -> Option<u32> {
+        let threshold = self.passed_threshold(self.expiry?, now)?;
+        match self.notified {
+            Some(notified) if notified <= threshold => None,
+            _ => {
+                self.notified = Some(threshold);
+                Some(threshold)
+            }
+        }
+    }
+
+    /// Returns the smallest threshold that has been passed, which is zero if the account has
+    /// expired.
+    fn passed_threshold(&self, expiry: DateTime<Utc>, now: DateTime<Utc>) -> Option<u32> {
+        let time_left = expiry - now;
+        if time_left <= ChronoDuration::zero() {
+            return Some(0);
+        }
+        self.thresholds
+            .iter()
+            .cloned()
+            .filter(|hours| time_left <= ChronoDuration::hours(i64::from(*hours)))
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn time(hour: u32) -> DateTime<Utc> {
+        Utc.ymd(2020, 6, 1).and_hms(0, 0, 0) + ChronoDuration::hours(i64::from(hour))
+    }
+
+    #[test]
+    fn test_cache_does_not_contain_account_token() {
+        let cache = CachedExpiry {
+            account_token_hash: hash_account_token("1234567890123456"),
+            expiry: time(0),
+            notified_threshold: None,
+        };
+        let serialized = serde_json::to_string(&cache).unwrap();
+        assert!(!serialized.contains("1234567890123456"));
+        assert_eq!(cache.account_token_hash.len(), 64);
+        assert_ne!(
+            cache.account_token_hash,
+            hash_account_token("1234567890123457")
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_cache_is_only_readable_by_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(EXPIRY_CACHE_FILENAME);
+        let cache = CachedExpiry {
+            account_token_hash: hash_account_token("1234567890123456"),
+            expiry: time(0),
+            notified_threshold: Some(24),
+        };
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(write_cache_file(&path, &cache)).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let loaded = load_cache(&path).unwrap().unwrap();
+        assert_eq!(loaded.account_token_hash, cache.account_token_hash);
+        assert_eq!(loaded.notified_threshold, Some(24));
+        assert!(!path.with_extension("json.tmp").exists());
+    }
+
+    #[test]
+    fn test_notifies_passed_thresholds_once() {
+        let mut notifier = ExpiryNotifier::new(vec![72, 24]);
+        assert_eq!(notifier.poll(time(0)), None);
+
+        notifier.set_expiry(time(100), time(0));
+        assert_eq!(notifier.poll(time(0)), None);
+        assert_eq!(notifier.poll(time(28)), Some(72));
+        assert_eq!(notifier.poll(time(29)), None);
+        assert_eq!(notifier.poll(time(76)), Some(24));
+        assert_eq!(notifier.poll(time(99)), None);
+        assert_eq!(notifier.poll(time(100)), Some(0));
+        assert_eq!(notifier.poll(time(101)), None);
+    }
+
+    #[test]
+    fn test_notifies_smallest_passed_threshold() {
+        let mut notifier = ExpiryNotifier::new(vec![72, 24]);
+        notifier.set_expiry(time(10), time(0));
+        assert_eq!(notifier.poll(time(0)), Some(24));
+        assert_eq!(notifier.poll(time(1)), None);
+
+        let mut notifier = ExpiryNotifier::new(vec![72, 24]);
+        notifier.set_expiry(time(10), time(20));
+        assert_eq!(notifier.poll(time(20)), Some(0));
+    }
+
+    #[test]
+    fn test_extended_expiry() {
+        let mut notifier = ExpiryNotifier::new(vec![72, 24]);
+        notifier.set_expiry(time(10), time(0));
+        assert_eq!(notifier.poll(time(10)), Some(0));
+
+        // Still less than a day left, so that threshold is not notified again
+        assert!(notifier.set_expiry(time(30), time(10)));
+        assert_eq!(notifier.poll(time(10)), None);
+        assert_eq!(notifier.poll(time(30)), Some(0));
+
+        // Plenty of time added, so all thresholds are notified again
+        assert!(notifier.set_expiry(time(200), time(30)));
+        assert_eq!(notifier.poll(time(30)), None);
+        assert_eq!(notifier.poll(time(128)), Some(72));
+        assert!(!notifier.set_expiry(time(200), time(128)));
+        assert_eq!(notifier.poll(time(128)), None);
+    }
+}
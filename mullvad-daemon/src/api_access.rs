@@ -0,0 +1,267 @@
+//! Keeps track of how the daemon reaches the API. The configured access methods are tried in
+//! order, and the daemon moves on to the next one when too many requests in a row have failed.
+//!
+//! Since requests to the API have to be possible while all other traffic is blocked, the
+//! endpoint used by the current method is handed to the firewall as an allowed endpoint. On
+//! Windows, the firewall only lets the process that talks to the endpoint reach it, which is the
+//! Shadowsocks client for bridges and the daemon for everything else.
+
+use crate::relays::RelaySelector;
+use mullvad_rpc::{ApiConnectionMode, ApiConnectionModeHandle, ProxyConfig};
+use mullvad_types::api_access::AccessMethod;
+#[cfg(not(target_os = "android"))]
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use talpid_core::firewall::AllowedEndpoint;
+#[cfg(not(target_os = "android"))]
+use talpid_core::proxy::{self, ProxyMonitor, ProxyResourceData};
+use talpid_types::net::{Endpoint, TransportProtocol};
+#[cfg(not(target_os = "android"))]
+use talpid_types::{net::openvpn::ProxySettings, ErrorExt};
+
+/// Number of consecutive failed requests after which the next access method is tried.
+pub const MAX_CONSECUTIVE_FAILURES: u32 = 3;
+
+#[cfg(not(target_os = "android"))]
+const BRIDGE_LOG_DIR: &str = "api-bridge";
+
+/// The Shadowsocks client that connects to the bridge, and is allowed through the firewall.
+#[cfg(windows)]
+const BRIDGE_CLIENT_FILENAME: &str = "sslocal.exe";
+
+/// The daemon executable in the resource directory. Only used if the path of the running
+/// executable cannot be determined.
+#[cfg(windows)]
+const DAEMON_FILENAME: &str = "mullvad-daemon.exe";
+
+/// Sent to the daemon whenever a request to the API fails. Contains the number of requests that
+/// have failed in a row.
+pub(crate) struct ApiAccessFailure(pub u32);
+
+pub struct ApiAccess {
+    methods: Vec<AccessMethod>,
+    current: usize,
+    connection_mode: ApiConnectionModeHandle,
+    #[cfg(not(target_os = "android"))]
+    bridge: Option<Bridge>,
+    #[cfg_attr(target_os = "android", allow(dead_code))]
+    resource_dir: PathBuf,
+    #[cfg_attr(target_os = "android", allow(dead_code))]
+    log_dir: Option<PathBuf>,
+    /// The executable that is allowed to reach the API directly or through a remote proxy.
+    #[cfg(windows)]
+    daemon_path: PathBuf,
+}
+
+/// A local Shadowsocks client that forwards API traffic to a bridge.
+#[cfg(not(target_os = "android"))]
+struct Bridge {
+    monitor: Box<dyn ProxyMonitor>,
+    peer: Endpoint,
+}
+
+impl ApiAccess {
+    pub fn new(
+        connection_mode: ApiConnectionModeHandle,
+        methods: Vec<AccessMethod>,
+        resource_dir: PathBuf,
+        log_dir: Option<PathBuf>,
+    ) -> Self {
+        #[cfg(windows)]
+        let daemon_path = std::env::current_exe().unwrap_or_else(|error| {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to find the path of the daemon executable")
+            );
+            resource_dir.join(DAEMON_FILENAME)
+        });
+
+        ApiAccess {
+            methods,
+            current: 0,
+            connection_mode,
+            #[cfg(not(target_os = "android"))]
+            bridge: None,
+            resource_dir,
+            log_dir,
+            #[cfg(windows)]
+            daemon_path,
+        }
+    }
+
+    /// Returns the access method that is currently in use.
+    pub fn current_method(&self) -> AccessMethod {
+        self.methods
+            .get(self.current)
+            .cloned()
+            .unwrap_or(AccessMethod::Direct)
+    }
+
+    /// Replaces the configured methods and starts over with the first one.
+    pub async fn set_methods(&mut self, methods: Vec<AccessMethod>, relays: &mut RelaySelector) {
+        self.methods = methods;
+        self.current = 0;
+        self.activate(relays).await;
+    }
+
+    /// Moves on to the next configured method.
+    pub async fn rotate(&mut self, relays: &mut RelaySelector) {
+        if !self.methods.is_empty() {
+            self.current = (self.current + 1) % self.methods.len();
+        }
+        self.activate(relays).await;
+    }
+
+    /// Sets up the current method. Methods that cannot be used are skipped. If no method can be
+    /// used, the API is reached directly.
+    pub async fn activate(&mut self, relays: &mut RelaySelector) {
+        #[cfg(not(target_os = "android"))]
+        self.stop_bridge();
+
+        for _ in 0..self.methods.len() {
+            let method = self.current_method();
+            match self.connection_mode_for(&method, relays).await {
+                Some(mode) => {
+                    log::info!("Reaching the API using {}", method);
+                    self.connection_mode.set(mode);
+                    return;
+                }
+                None => {
+                    log::warn!("Unable to reach the API using {}", method);
+                    self.current = (self.current + 1) % self.methods.len();
+                }
+            }
+        }
+
+        self.connection_mode.set(ApiConnectionMode::Direct);
+    }
+
+    /// Returns the endpoint that has to be reachable for the current method to work.
+    pub fn allowed_endpoint(&self) -> AllowedEndpoint {
+        let endpoint = match self.connection_mode.get() {
+            ApiConnectionMode::Socks5(config) | ApiConnectionMode::Http(config) => {
+                #[cfg(not(target_os = "android"))]
+                {
+                    if let Some(bridge) = &self.bridge {
+                        return AllowedEndpoint {
+                            endpoint: bridge.peer,
+                            #[cfg(windows)]
+                            client: self.resource_dir.join(BRIDGE_CLIENT_FILENAME),
+                        };
+                    }
+                }
+                Endpoint {
+                    address: config.address,
+                    protocol: TransportProtocol::Tcp,
+                }
+            }
+            ApiConnectionMode::Direct => Endpoint {
+                address: self.connection_mode.api_address(),
+                protocol: TransportProtocol::Tcp,
+            },
+        };
+        AllowedEndpoint {
+            endpoint,
+            // Proxies and the API are reached by the daemon itself.
+            #[cfg(windows)]
+            client: self.daemon_path.clone(),
+        }
+    }
+
+    async fn connection_mode_for(
+        &mut self,
+        method: &AccessMethod,
+        relays: &mut RelaySelector,
+    ) -> Option<ApiConnectionMode> {
+        match method {
+            AccessMethod::Direct => Some(ApiConnectionMode::Direct),
+            AccessMethod::Socks5(proxy) => Some(ApiConnectionMode::Socks5(ProxyConfig {
+                address: proxy.address,
+                auth: proxy.auth.clone(),
+            })),
+            AccessMethod::Http(proxy) => Some(ApiConnectionMode::Http(ProxyConfig {
+                address: proxy.address,
+                auth: proxy.auth.clone(),
+            })),
+            #[cfg(not(target_os = "android"))]
+            AccessMethod::Bridges => self.start_bridge(relays).await,
+            #[cfg(target_os = "android")]
+            AccessMethod::Bridges => {
+                let _ = relays;
+                None
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "android"))]
+    async fn start_bridge(&mut self, relays: &mut RelaySelector) -> Option<ApiConnectionMode> {
+        let settings = relays.get_api_bridge()?;
+        let peer = match &settings {
+            ProxySettings::Shadowsocks(shadowsocks) => shadowsocks.get_endpoint(),
+            _ => return None,
+        };
+
+        let log_dir = self.log_dir.as_ref().map(|dir| dir.join(BRIDGE_LOG_DIR));
+        if let Some(log_dir) = &log_dir {
+            if let Err(error) = std::fs::create_dir_all(log_dir) {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to create API bridge log directory")
+                );
+            }
+        }
+        let resource_data = ProxyResourceData {
+            resource_dir: self.resource_dir.clone(),
+            log_dir,
+        };
+
+        let result =
+            tokio::task::spawn_blocking(move || proxy::start_proxy(&settings, &resource_data))
+                .await;
+        let monitor = match result {
+            Ok(Ok(monitor)) => monitor,
+            Ok(Err(error)) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to start API bridge client")
+                );
+                return None;
+            }
+            Err(_) => {
+                log::error!("Failed to start API bridge client");
+                return None;
+            }
+        };
+
+        let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), monitor.port());
+        self.bridge = Some(Bridge { monitor, peer });
+        Some(ApiConnectionMode::Socks5(ProxyConfig {
+            address,
+            auth: None,
+        }))
+    }
+
+    #[cfg(not(target_os = "android"))]
+    fn stop_bridge(&mut self) {
+        if let Some(mut bridge) = self.bridge.take() {
+            if let Err(error) = bridge.monitor.close_handle().close() {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to stop API bridge client")
+                );
+            }
+            tokio::task::spawn_blocking(move || {
+                let _ = bridge.monitor.wait();
+            });
+        }
+    }
+}
+
+#[cfg(not(target_os = "android"))]
+impl Drop for ApiAccess {
+    fn drop(&mut self) {
+        if let Some(mut bridge) = self.bridge.take() {
+            let _ = bridge.monitor.close_handle().close();
+        }
+    }
+}
@@ -1,11 +1,13 @@
-use clap::{crate_authors, crate_description, crate_name, App, Arg};
+use clap::{crate_authors, crate_description, crate_name, value_t, App, Arg};
 use log;
+use mullvad_daemon::logging::LogRotation;
 
 use crate::version;
 
 pub struct Config {
     pub log_level: log::LevelFilter,
     pub log_to_file: bool,
+    pub log_rotation: LogRotation,
     pub log_stdout_timestamps: bool,
     pub run_as_service: bool,
     pub register_service: bool,
@@ -28,6 +30,14 @@ pub fn create_config() -> Config {
         _ => log::LevelFilter::Trace,
     };
     let log_to_file = !matches.is_present("disable_log_to_file");
+    let default_rotation = LogRotation::default();
+    let log_rotation = LogRotation {
+        max_size: value_t!(matches, "log_max_size", u64)
+            .ok()
+            .and_then(megabytes_to_bytes)
+            .unwrap_or(default_rotation.max_size),
+        retention: value_t!(matches, "log_retention", usize).unwrap_or(default_rotation.retention),
+    };
     let log_stdout_timestamps = !matches.is_present("disable_stdout_timestamps");
 
     let run_as_service = cfg!(windows) && matches.is_present("run_as_service");
@@ -36,6 +46,7 @@ pub fn create_config() -> Config {
     Config {
         log_level,
         log_to_file,
+        log_rotation,
         log_stdout_timestamps,
         run_as_service,
         register_service,
@@ -80,6 +91,23 @@ fn create_app() -> App<'static, 'static> {
                 .long("disable-log-to-file")
                 .help("Disable logging to file"),
         )
+        .arg(
+            Arg::with_name("log_max_size")
+                .long("log-max-size")
+                .takes_value(true)
+                .value_name("MEGABYTES")
+                .validator(validate_log_max_size)
+                .help("Rotate the log file when it grows beyond this size. 0 disables rotation \
+                       based on size [Default: 10]"),
+        )
+        .arg(
+            Arg::with_name("log_retention")
+                .long("log-retention")
+                .takes_value(true)
+                .value_name("COUNT")
+                .validator(validate_number)
+                .help("Number of rotated log files to keep [Default: 1]"),
+        )
         .arg(
             Arg::with_name("disable_stdout_timestamps")
                 .long("disable-stdout-timestamps")
@@ -99,3 +127,24 @@ fn create_app() -> App<'static, 'static> {
     }
     app
 }
+
+fn validate_number(value: String) -> Result<(), String> {
+    value
+        .parse::<u64>()
+        .map(|_| ())
+        .map_err(|_| format!("'{}' is not a non-negative integer", value))
+}
+
+/// Returns `None` if the size in bytes does not fit in a `u64`.
+fn megabytes_to_bytes(megabytes: u64) -> Option<u64> {
+    megabytes.checked_mul(1024 * 1024)
+}
+
+fn validate_log_max_size(value: String) -> Result<(), String> {
+    let megabytes = value
+        .parse::<u64>()
+        .map_err(|_| format!("'{}' is not a non-negative integer", value))?;
+    megabytes_to_bytes(megabytes)
+        .map(|_| ())
+        .ok_or_else(|| format!("'{}' megabytes is too large", value))
+}
@@ -0,0 +1,253 @@
+//! Collects information about the state of the daemon and the system that is useful when
+//! debugging connectivity problems. None of it is sent anywhere by the daemon itself; it is only
+//! handed to local clients, such as the problem report tool.
+
+use mullvad_types::{settings::Settings, states::TunnelState};
+#[cfg(not(target_os = "linux"))]
+use std::process::Command;
+#[cfg(target_os = "linux")]
+use talpid_types::ErrorExt;
+
+/// Placeholder for the account token in the collected settings.
+const REDACTED_ACCOUNT_TOKEN: &str = "[REDACTED ACCOUNT NUMBER]";
+/// Placeholder for other secrets in the collected settings.
+pub(crate) const REDACTED_SECRET: &str = "[REDACTED]";
+/// Settings fields that hold secrets, such as proxy credentials. They are redacted wherever they
+/// occur in the settings.
+pub(crate) const SECRET_FIELDS: &[&str] = &["auth", "password"];
+
+/// A snapshot of the daemon and system state.
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    /// The current settings as JSON, with the account token and other secrets redacted.
+    pub settings: String,
+    /// The current tunnel state as JSON.
+    pub tunnel_state: String,
+    /// The state of the system, such as routes and firewall rules.
+    pub sections: Vec<Section>,
+    /// The mechanism used to manage DNS, if DNS is currently being managed.
+    pub dns_manager: Option<String>,
+}
+
+/// A named part of the system state. `content` holds the reason if it could not be collected.
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub name: String,
+    pub content: Result<String, String>,
+}
+
+impl Diagnostics {
+    /// Serializes the parts of the snapshot that are known to the daemon. Collecting the system
+    /// state is done separately by `collect_system_state`.
+    pub fn new(settings: &Settings, tunnel_state: &TunnelState) -> Self {
+        Diagnostics {
+            settings: redacted_settings(settings),
+            tunnel_state: serde_json::to_string_pretty(tunnel_state)
+                .unwrap_or_else(|error| format!("Failed to serialize tunnel state: {}", error)),
+            ..Diagnostics::default()
+        }
+    }
+}
+
+/// Reads the routes, firewall rules and other networking state of the system. `dns_manager` is the
+/// DNS monitor in use, as reported by the tunnel state machine.
+#[cfg(target_os = "linux")]
+pub async fn collect_system_state(dns_manager: Option<String>) -> Vec<Section> {
+    talpid_core::diagnostics::collect(dns_manager)
+        .await
+        .into_iter()
+        .map(|section| Section {
+            name: section.name.to_owned(),
+            content: section.content.map_err(|error| error.display_chain()),
+        })
+        .collect()
+}
+
+/// Reads the routes and firewall rules of the system by running external programs.
+#[cfg(not(target_os = "linux"))]
+pub async fn collect_system_state(_dns_manager: Option<String>) -> Vec<Section> {
+    let sections = tokio::task::spawn_blocking(|| {
+        vec![
+            Section {
+                name: "routes".to_owned(),
+                content: Ok(collect_outputs(ROUTE_COMMANDS)),
+            },
+            Section {
+                name: "firewall".to_owned(),
+                content: Ok(collect_outputs(FIREWALL_COMMANDS)),
+            },
+        ]
+    });
+    match sections.await {
+        Ok(sections) => sections,
+        Err(error) => {
+            log::error!("Failed to collect system state: {}", error);
+            Vec::new()
+        }
+    }
+}
+
+fn redacted_settings(settings: &Settings) -> String {
+    serde_json::to_string_pretty(&redacted_settings_value(settings))
+        .unwrap_or_else(|error| format!("Failed to serialize settings: {}", error))
+}
+
+/// Serializes the settings with the account token and all other secrets redacted.
+pub fn redacted_settings_value(settings: &Settings) -> serde_json::Value {
+    let mut value = match serde_json::to_value(settings) {
+        Ok(value) => value,
+        Err(error) => {
+            return serde_json::Value::from(format!("Failed to serialize settings: {}", error))
+        }
+    };
+    if let Some(account_token) = value.get_mut("account_token") {
+        if !account_token.is_null() {
+            *account_token = serde_json::Value::from(REDACTED_ACCOUNT_TOKEN);
+        }
+    }
+    redact_secrets(&mut value);
+    value
+}
+
+fn redact_secrets(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (name, field) in fields.iter_mut() {
+                if SECRET_FIELDS.contains(&name.as_str()) && !field.is_null() {
+                    *field = serde_json::Value::from(REDACTED_SECRET);
+                } else {
+                    redact_secrets(field);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(redact_secrets),
+        _ => (),
+    }
+}
+
+#[cfg(target_os = "macos")]
+const ROUTE_COMMANDS: &[&[&str]] = &[&["netstat", "-rn"]];
+#[cfg(target_os = "macos")]
+const FIREWALL_COMMANDS: &[&[&str]] = &[&["pfctl", "-a", "mullvad", "-s", "rules"]];
+
+#[cfg(windows)]
+const ROUTE_COMMANDS: &[&[&str]] = &[&["route", "print"]];
+#[cfg(windows)]
+const FIREWALL_COMMANDS: &[&[&str]] = &[&["netsh", "wfp", "show", "state", "file=-"]];
+
+#[cfg(target_os = "android")]
+const ROUTE_COMMANDS: &[&[&str]] = &[];
+#[cfg(target_os = "android")]
+const FIREWALL_COMMANDS: &[&[&str]] = &[];
+
+/// Runs each command and concatenates their outputs, each preceded by the command line. Failures
+/// are included in the output rather than returned, so that one missing tool does not hide the
+/// output of the others.
+#[cfg(not(target_os = "linux"))]
+fn collect_outputs(commands: &[&[&str]]) -> String {
+    if commands.is_empty() {
+        return "Not available on this platform".to_owned();
+    }
+    let mut output = String::new();
+    for command in commands {
+        output.push_str(&format!("$ {}\n", command.join(" ")));
+        output.push_str(&run_command(command));
+        output.push('\n');
+    }
+    output
+}
+
+#[cfg(not(target_os = "linux"))]
+fn run_command(command: &[&str]) -> String {
+    match Command::new(command[0]).args(&command[1..]).output() {
+        Ok(output) => {
+            let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+            if !output.status.success() {
+                text.push_str(&format!(
+                    "Command failed ({}): {}",
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+            text
+        }
+        Err(error) => format!("Failed to run command: {}", error),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_types::{
+        api_access::{AccessMethod, CustomProxy},
+        relay_constraints::BridgeSettings,
+    };
+    use talpid_types::net::openvpn::{
+        ProxyAuth, ProxySettings, ShadowsocksProxySettings, Socks5ProxySettings,
+    };
+
+    fn proxy_auth() -> ProxyAuth {
+        ProxyAuth {
+            username: "secret-username".to_owned(),
+            password: "secret-password".to_owned(),
+        }
+    }
+
+    fn assert_secrets_redacted(settings: &Settings) {
+        let diagnostics = Diagnostics::new(settings, &TunnelState::Disconnected);
+        assert!(!diagnostics.settings.contains("secret-"));
+        assert!(diagnostics.settings.contains(REDACTED_SECRET));
+    }
+
+    #[test]
+    fn test_account_token_is_redacted() {
+        let mut settings = Settings::default();
+        settings.set_account_token(Some("1234567890123456".to_owned()));
+
+        let diagnostics = Diagnostics::new(&settings, &TunnelState::Disconnected);
+        assert!(!diagnostics.settings.contains("1234567890123456"));
+        assert!(diagnostics.settings.contains(REDACTED_ACCOUNT_TOKEN));
+
+        let diagnostics = Diagnostics::new(&Settings::default(), &TunnelState::Disconnected);
+        assert!(!diagnostics.settings.contains(REDACTED_ACCOUNT_TOKEN));
+    }
+
+    #[test]
+    fn test_socks5_bridge_auth_is_redacted() {
+        let mut settings = Settings::default();
+        settings.bridge_settings =
+            BridgeSettings::Custom(ProxySettings::Socks5(Socks5ProxySettings {
+                address: "192.0.2.1:1080".parse().unwrap(),
+                auth: Some(proxy_auth()),
+            }));
+        assert_secrets_redacted(&settings);
+    }
+
+    #[test]
+    fn test_shadowsocks_bridge_password_is_redacted() {
+        let mut settings = Settings::default();
+        settings.bridge_settings =
+            BridgeSettings::Custom(ProxySettings::Shadowsocks(ShadowsocksProxySettings {
+                peer: "192.0.2.1:443".parse().unwrap(),
+                password: "secret-password".to_owned(),
+                cipher: "aes-256-gcm".to_owned(),
+            }));
+        assert_secrets_redacted(&settings);
+    }
+
+    #[test]
+    fn test_api_access_method_auth_is_redacted() {
+        let mut settings = Settings::default();
+        settings.api_access_methods = vec![
+            AccessMethod::Socks5(CustomProxy {
+                address: "192.0.2.1:1080".parse().unwrap(),
+                auth: Some(proxy_auth()),
+            }),
+            AccessMethod::Http(CustomProxy {
+                address: "192.0.2.1:8080".parse().unwrap(),
+                auth: Some(proxy_auth()),
+            }),
+        ];
+        assert_secrets_redacted(&settings);
+    }
+}
@@ -5,20 +5,30 @@
 extern crate serde;
 
 
+pub mod access_control;
 mod account_history;
+mod account_monitor;
+mod api_access;
+pub mod diagnostics;
 pub mod exception_logging;
 mod geoip;
 pub mod logging;
 #[cfg(not(target_os = "android"))]
 pub mod management_interface;
+pub mod policy;
 mod relays;
+pub mod remote_management;
 #[cfg(not(target_os = "android"))]
 pub mod rpc_uniqueness_check;
 pub mod runtime;
+mod scheduler;
 mod settings;
+mod settings_history;
+mod tunnel_proxy;
 pub mod version;
 mod version_check;
 
+use chrono::{DateTime, Utc};
 use futures::{
     channel::{mpsc, oneshot},
     future::{abortable, AbortHandle, Future},
@@ -28,14 +38,16 @@ use log::{debug, error, info, warn};
 use mullvad_rpc::AccountsProxy;
 use mullvad_types::{
     account::{AccountData, AccountToken, VoucherSubmission},
+    api_access::AccessMethod,
     endpoint::MullvadEndpoint,
     location::GeoIpLocation,
     relay_constraints::{
         BridgeSettings, BridgeState, Constraint, InternalBridgeConstraints, RelaySettings,
         RelaySettingsUpdate,
     },
-    relay_list::{Relay, RelayList},
-    settings::Settings,
+    relay_list::{Relay, RelayList, RelayListDiff},
+    schedule::ScheduleRule,
+    settings::{Settings, TunnelProxySettings},
     states::{TargetState, TunnelState},
     version::{AppVersion, AppVersionInfo},
     wireguard::KeygenEvent,
@@ -61,7 +73,7 @@ use talpid_core::{
 #[cfg(target_os = "android")]
 use talpid_types::android::AndroidContext;
 use talpid_types::{
-    net::{openvpn, TransportProtocol, TunnelParameters, TunnelType},
+    net::{openvpn, TransportProtocol, TunnelEndpoint, TunnelParameters, TunnelType},
     tunnel::{ErrorStateCause, ParameterGenerationError, TunnelStateTransition},
     ErrorExt,
 };
@@ -79,6 +91,10 @@ const FIRST_KEY_PUSH_TIMEOUT: Duration = Duration::from_secs(5);
 /// Delay between generating a new WireGuard key and reconnecting
 const WG_RECONNECT_DELAY: Duration = Duration::from_secs(30);
 
+/// How long after the expiry of the account the tunnel is blocked, in seconds. This allows for the
+/// system clock being ahead of the API.
+const ACCOUNT_EXPIRY_CLOCK_SKEW_SECS: i64 = 60 * 60;
+
 #[derive(err_derive::Error, Debug)]
 #[error(no_from)]
 pub enum Error {
@@ -173,37 +189,95 @@ pub enum DaemonCommand {
     /// updated.
     UpdateRelayLocations,
     /// Set which account token to use for subsequent connection attempts.
-    SetAccount(oneshot::Sender<()>, Option<AccountToken>),
+    SetAccount(
+        oneshot::Sender<Result<(), settings::Error>>,
+        Option<AccountToken>,
+    ),
     /// Place constraints on the type of tunnel and relay
-    UpdateRelaySettings(oneshot::Sender<()>, RelaySettingsUpdate),
+    UpdateRelaySettings(
+        oneshot::Sender<Result<(), settings::Error>>,
+        RelaySettingsUpdate,
+    ),
     /// Set the allow LAN setting.
-    SetAllowLan(oneshot::Sender<()>, bool),
+    SetAllowLan(oneshot::Sender<Result<(), settings::Error>>, bool),
     /// Set the beta program setting.
-    SetShowBetaReleases(oneshot::Sender<()>, bool),
+    SetShowBetaReleases(oneshot::Sender<Result<(), settings::Error>>, bool),
     /// Set the block_when_disconnected setting.
-    SetBlockWhenDisconnected(oneshot::Sender<()>, bool),
+    SetBlockWhenDisconnected(oneshot::Sender<Result<(), settings::Error>>, bool),
     /// Set the auto-connect setting.
-    SetAutoConnect(oneshot::Sender<()>, bool),
+    SetAutoConnect(oneshot::Sender<Result<(), settings::Error>>, bool),
     /// Set the mssfix argument for OpenVPN
-    SetOpenVpnMssfix(oneshot::Sender<()>, Option<u16>),
+    SetOpenVpnMssfix(oneshot::Sender<Result<(), settings::Error>>, Option<u16>),
     /// Set proxy details for OpenVPN
     SetBridgeSettings(oneshot::Sender<Result<(), settings::Error>>, BridgeSettings),
     /// Set proxy state
     SetBridgeState(oneshot::Sender<Result<(), settings::Error>>, BridgeState),
     /// Set if IPv6 should be enabled in the tunnel
-    SetEnableIpv6(oneshot::Sender<()>, bool),
+    SetEnableIpv6(oneshot::Sender<Result<(), settings::Error>>, bool),
     /// Set MTU for wireguard tunnels
-    SetWireguardMtu(oneshot::Sender<()>, Option<u16>),
+    SetWireguardMtu(oneshot::Sender<Result<(), settings::Error>>, Option<u16>),
     /// Set automatic key rotation interval for wireguard tunnels
-    SetWireguardRotationInterval(oneshot::Sender<()>, Option<u32>),
+    SetWireguardRotationInterval(oneshot::Sender<Result<(), settings::Error>>, Option<u32>),
+    /// Set the interval in minutes after which to reconnect to a different relay
+    SetRelayRotationInterval(oneshot::Sender<Result<(), settings::Error>>, Option<u32>),
+    /// Set whether to negotiate quantum-resistant pre-shared keys for wireguard tunnels
+    SetWireguardQuantumResistant(oneshot::Sender<Result<(), settings::Error>>, bool),
+    /// Set the thresholds and ping target used to check the connectivity of wireguard tunnels
+    SetWireguardConnectivityCheck(
+        oneshot::Sender<Result<(), settings::Error>>,
+        talpid_types::net::wireguard::ConnectivityCheckOptions,
+    ),
+    /// Set the settings of the local SOCKS5 proxy that forwards traffic through the tunnel
+    SetTunnelProxySettings(
+        oneshot::Sender<Result<(), settings::Error>>,
+        TunnelProxySettings,
+    ),
+    /// Set the methods used for reaching the API, in the order they should be tried
+    SetApiAccessMethods(
+        oneshot::Sender<Result<(), settings::Error>>,
+        Vec<AccessMethod>,
+    ),
+    /// Get the method that is currently used for reaching the API
+    GetCurrentApiAccessMethod(oneshot::Sender<AccessMethod>),
+    /// Set the hours before the account expires at which clients are notified
+    SetAccountExpiryNotifications(oneshot::Sender<Result<(), settings::Error>>, Vec<u32>),
+    /// Add a schedule rule and return the ID assigned to it
+    AddScheduleRule(oneshot::Sender<Result<u32, settings::Error>>, ScheduleRule),
+    /// Remove a schedule rule. Returns `false` if there is no rule with the given ID
+    RemoveScheduleRule(oneshot::Sender<Result<bool, settings::Error>>, u32),
     /// Get the daemon settings
     GetSettings(oneshot::Sender<Settings>),
+    /// Serialize the settings in the format of the settings file
+    ExportSettings(oneshot::Sender<Result<String, settings::Error>>),
+    /// Replace all settings with serialized settings. The boolean indicates whether the current
+    /// account token should be kept
+    ImportSettings(oneshot::Sender<Result<(), settings::Error>>, String, bool),
+    /// Get the revisions in the settings history, oldest first
+    GetSettingsHistory(oneshot::Sender<Vec<settings_history::HistoryEntry>>),
+    /// Restore the settings to how they were at the given revision
+    RollbackSettings(oneshot::Sender<Result<(), settings::Error>>, u64),
     /// Generate new wireguard key
     GenerateWireguardKey(oneshot::Sender<wireguard::KeygenEvent>),
     /// Return a public key of the currently set wireguard private key, if there is one
     GetWireguardKey(oneshot::Sender<Option<wireguard::PublicKey>>),
     /// Verify if the currently set wireguard key is valid.
     VerifyWireguardKey(oneshot::Sender<bool>),
+    /// List all wireguard keys registered on the current account
+    ListWireguardKeys(
+        oneshot::Sender<Result<Vec<mullvad_types::wireguard::RegisteredKey>, wireguard::Error>>,
+    ),
+    /// Set the label of the wireguard keys used by this device. The label is only stored locally
+    SetWireguardKeyLabel(
+        oneshot::Sender<Result<(), wireguard::Error>>,
+        Option<String>,
+    ),
+    /// Remove a wireguard key used by another device from the current account
+    RevokeWireguardKey(
+        oneshot::Sender<Result<(), wireguard::Error>>,
+        talpid_types::net::wireguard::PublicKey,
+    ),
+    /// Collect a snapshot of the daemon and system state, for debugging purposes
+    GetDiagnostics(oneshot::Sender<diagnostics::Diagnostics>),
     /// Get information about the currently running and latest app versions
     GetVersionInfo(oneshot::Sender<AppVersionInfo>),
     /// Get current version of the app
@@ -241,6 +315,9 @@ pub(crate) enum InternalDaemonEvent {
     ),
     /// A command sent to the daemon.
     Command(DaemonCommand),
+    /// A command sent to the daemon by the described client. Settings changes made by the command
+    /// are attributed to the client in the settings history.
+    ClientCommand(String, DaemonCommand),
     /// Daemon shutdown triggered by a signal, ctrl-c or similar.
     TriggerShutdown,
     /// Wireguard key generation event
@@ -250,6 +327,8 @@ pub(crate) enum InternalDaemonEvent {
             Result<mullvad_types::wireguard::WireguardData, wireguard::Error>,
         ),
     ),
+    /// The wireguard key of this device is no longer registered on the account
+    WgKeyRevoked(AccountToken, talpid_types::net::wireguard::PublicKey),
     /// New Account created
     NewAccountEvent(
         AccountToken,
@@ -257,6 +336,19 @@ pub(crate) enum InternalDaemonEvent {
     ),
     /// The background job fetching new `AppVersionInfo`s got a new info object.
     NewAppVersionInfo(AppVersionInfo),
+    /// The API returned an expiry date for an account.
+    AccountExpiry(AccountToken, DateTime<Utc>),
+    /// The time left on an account dropped below a notification threshold, given in hours. The
+    /// threshold is zero once the account has expired.
+    AccountExpiresSoon(AccountToken, DateTime<Utc>, u32),
+    /// The connectivity check of the current tunnel failed.
+    ConnectivityCheckFailed,
+    /// The relay rotation interval has elapsed.
+    RotateRelay,
+    /// A request to the API failed. Contains the number of requests that have failed in a row.
+    ApiAccessFailure(u32),
+    /// The relay list was updated and some relays were added, removed or deactivated.
+    RelayListDiff(RelayListDiff),
 }
 
 impl From<TunnelStateTransition> for InternalDaemonEvent {
@@ -277,6 +369,31 @@ impl From<AppVersionInfo> for InternalDaemonEvent {
     }
 }
 
+impl From<RelayListDiff> for InternalDaemonEvent {
+    fn from(diff: RelayListDiff) -> Self {
+        InternalDaemonEvent::RelayListDiff(diff)
+    }
+}
+
+impl From<account_monitor::AccountEvent> for InternalDaemonEvent {
+    fn from(event: account_monitor::AccountEvent) -> Self {
+        match event {
+            account_monitor::AccountEvent::Expiry(account_token, expiry) => {
+                InternalDaemonEvent::AccountExpiry(account_token, expiry)
+            }
+            account_monitor::AccountEvent::ExpiresSoon(account_token, expiry, threshold) => {
+                InternalDaemonEvent::AccountExpiresSoon(account_token, expiry, threshold)
+            }
+        }
+    }
+}
+
+impl From<api_access::ApiAccessFailure> for InternalDaemonEvent {
+    fn from(failure: api_access::ApiAccessFailure) -> Self {
+        InternalDaemonEvent::ApiAccessFailure(failure.0)
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 enum DaemonExecutionState {
     Running,
@@ -358,6 +475,13 @@ impl DaemonCommandSender {
             .unbounded_send(InternalDaemonEvent::Command(command))
             .map_err(|_| Error::DaemonUnavailable)
     }
+
+    /// Sends a command on behalf of the described client.
+    pub fn send_as(&self, client: String, command: DaemonCommand) -> Result<(), Error> {
+        self.0
+            .unbounded_send(InternalDaemonEvent::ClientCommand(client, command))
+            .map_err(|_| Error::DaemonUnavailable)
+    }
 }
 
 pub(crate) struct DaemonEventSender<E = InternalDaemonEvent> {
@@ -434,12 +558,37 @@ pub trait EventListener {
     /// Notify that the relay list changed.
     fn notify_relay_list(&self, relay_list: RelayList);
 
+    /// Notify which relays were added, removed or deactivated when the relay list changed.
+    fn notify_relay_list_diff(&self, _diff: RelayListDiff) {}
+
     /// Notify that info about the latest available app version changed.
     /// Or some flag about the currently running version is changed.
     fn notify_app_version(&self, app_version_info: AppVersionInfo);
 
     /// Notify clients of a key generation event.
     fn notify_key_event(&self, key_event: KeygenEvent);
+
+    /// Notify that the expiry of the current account changed.
+    fn notify_account_expiry(&self, _expiry: DateTime<Utc>) {}
+
+    /// Notify that the time left on the current account dropped below one of the notification
+    /// thresholds, given in hours. The threshold is zero once the account has expired.
+    fn notify_account_expires_soon(&self, _expiry: DateTime<Utc>, _threshold_hours: u32) {}
+
+    /// Notify that the connectivity check failed for the given relay.
+    fn notify_connectivity_check_failure(&self, _relay: Option<String>) {}
+
+    /// Notify that the tunnel is being retried with a new relay.
+    fn notify_retry_attempt(
+        &self,
+        _attempt: u32,
+        _relay: Option<String>,
+        _endpoint: TunnelEndpoint,
+    ) {
+    }
+
+    /// Notify that the set of processes excluded from the tunnel changed.
+    fn notify_split_tunnel_processes(&self, _pids: Vec<i32>) {}
 }
 
 pub struct Daemon<L: EventListener> {
@@ -452,6 +601,10 @@ pub struct Daemon<L: EventListener> {
     rx: mpsc::UnboundedReceiver<InternalDaemonEvent>,
     tx: DaemonEventSender,
     reconnection_job: Option<AbortHandle>,
+    relay_rotation_job: Option<AbortHandle>,
+    /// Hostname of a relay that should not be selected again, if possible, since the daemon is
+    /// rotating away from it.
+    avoided_relay: Option<String>,
     event_listener: L,
     settings: SettingsPersister,
     account_history: account_history::AccountHistory,
@@ -460,9 +613,18 @@ pub struct Daemon<L: EventListener> {
     rpc_handle: mullvad_rpc::rest::MullvadRestHandle,
     wireguard_key_manager: wireguard::KeyManager,
     version_updater_handle: version_check::VersionUpdaterHandle,
+    account_monitor: account_monitor::AccountMonitorHandle,
+    scheduler: scheduler::SchedulerHandle,
+    tunnel_proxy: tunnel_proxy::TunnelProxy,
+    api_access: api_access::ApiAccess,
     relay_selector: relays::RelaySelector,
     last_generated_relay: Option<Relay>,
     last_generated_bridge_relay: Option<Relay>,
+    /// Most recently known expiry of the current account.
+    account_expiry: Option<DateTime<Utc>>,
+    /// Whether `account_expiry` has been returned by the API, rather than read from the cache.
+    /// Only a confirmed expiry blocks the tunnel.
+    account_expiry_confirmed: bool,
     app_version_info: AppVersionInfo,
     shutdown_callbacks: Vec<Box<dyn FnOnce()>>,
     /// oneshot channel that completes once the tunnel state machine has been shut down
@@ -481,6 +643,10 @@ where
         cache_dir: PathBuf,
         event_listener: L,
         command_channel: DaemonCommandChannel,
+        settings_policy: policy::SettingsPolicy,
+        #[cfg(target_os = "linux")] allowed_listener: Option<
+            talpid_core::firewall::AllowedListener,
+        >,
         #[cfg(target_os = "android")] android_context: AndroidContext,
     ) -> Result<Self, Error> {
         let (tunnel_state_machine_shutdown_tx, tunnel_state_machine_shutdown_signal) =
@@ -494,21 +660,26 @@ where
         .map_err(Error::InitRpcFactory)?;
         let rpc_handle = rpc_runtime.mullvad_rest_handle();
 
+        let (internal_event_tx, internal_event_rx) = command_channel.destructure();
+
         let relay_list_listener = event_listener.clone();
-        let on_relay_list_update = move |relay_list: &RelayList| {
+        let relay_list_diff_tx = internal_event_tx.to_specialized_sender();
+        let on_relay_list_update = move |relay_list: &RelayList, diff: &RelayListDiff| {
             relay_list_listener.notify_relay_list(relay_list.clone());
+            if !diff.is_empty() {
+                let _ = relay_list_diff_tx.send(diff.clone());
+            }
         };
         let mut relay_selector = relays::RelaySelector::new(
             rpc_handle.clone(),
             on_relay_list_update,
             &resource_dir,
             &cache_dir,
+            &settings_dir,
         );
 
-        let (internal_event_tx, internal_event_rx) = command_channel.destructure();
-
 
-        let mut settings = SettingsPersister::load(&settings_dir);
+        let mut settings = SettingsPersister::load(&settings_dir, settings_policy);
 
         if version::is_beta_version() {
             let _ = settings.set_show_beta_releases(true);
@@ -523,6 +694,31 @@ where
             settings.show_beta_releases,
         );
         tokio::spawn(version_updater.run());
+        let (account_monitor, cached_account_expiry) = account_monitor::spawn(
+            rpc_handle.clone(),
+            &settings_dir,
+            settings.get_account_token(),
+            settings.account_expiry_notifications.clone(),
+            internal_event_tx.to_specialized_sender(),
+        );
+        let scheduler = scheduler::spawn(
+            scheduler::LocalClock,
+            settings.schedule.clone(),
+            internal_event_tx.to_specialized_sender(),
+        );
+        let tunnel_proxy = tunnel_proxy::TunnelProxy::new(settings.tunnel_proxy);
+
+        let connection_mode = rpc_runtime.connection_mode_handle();
+        let failure_tx = internal_event_tx.to_specialized_sender();
+        connection_mode.set_failure_listener(move |failures| {
+            let _ = failure_tx.send(api_access::ApiAccessFailure(failures));
+        });
+        let mut api_access = api_access::ApiAccess::new(
+            connection_mode,
+            settings.api_access_methods.clone(),
+            resource_dir.clone(),
+            log_dir.clone(),
+        );
         let account_history =
             account_history::AccountHistory::new(&cache_dir, &settings_dir, rpc_handle.clone())
                 .await
@@ -571,6 +767,9 @@ where
             TargetState::Unsecured
         };
 
+        // The exclusion cgroup must exist before the firewall rules that match it are applied
+        #[cfg(target_os = "linux")]
+        let exclude_pids = split_tunnel::PidManager::new().map_err(Error::InitSplitTunneling)?;
 
         let tunnel_command_tx = tunnel_state_machine::spawn(
             settings.allow_lan,
@@ -582,6 +781,8 @@ where
             internal_event_tx.to_specialized_sender(),
             tunnel_state_machine_shutdown_tx,
             initial_target_state != TargetState::Secured,
+            #[cfg(target_os = "linux")]
+            allowed_listener,
             #[cfg(target_os = "android")]
             android_context,
         )
@@ -594,16 +795,23 @@ where
         // Attempt to download a fresh relay list
         relay_selector.update().await;
 
+        api_access.activate(&mut relay_selector).await;
+        let _ = tunnel_command_tx.unbounded_send(TunnelCommand::AllowEndpoint(Some(
+            api_access.allowed_endpoint(),
+        )));
+
         let mut daemon = Daemon {
             tunnel_command_tx,
             tunnel_state: TunnelState::Disconnected,
             target_state: initial_target_state,
             state: DaemonExecutionState::Running,
             #[cfg(target_os = "linux")]
-            exclude_pids: split_tunnel::PidManager::new().map_err(Error::InitSplitTunneling)?,
+            exclude_pids,
             rx: internal_event_rx,
             tx: internal_event_tx,
             reconnection_job: None,
+            relay_rotation_job: None,
+            avoided_relay: None,
             event_listener,
             settings,
             account_history,
@@ -612,9 +820,15 @@ where
             rpc_handle,
             wireguard_key_manager,
             version_updater_handle,
+            account_monitor,
+            scheduler,
+            tunnel_proxy,
+            api_access,
             relay_selector,
             last_generated_relay: None,
             last_generated_bridge_relay: None,
+            account_expiry: cached_account_expiry,
+            account_expiry_confirmed: false,
             app_version_info,
             shutdown_callbacks: vec![],
             tunnel_state_machine_shutdown_signal,
@@ -701,14 +915,32 @@ where
                     .await
             }
             Command(command) => self.handle_command(command).await,
+            ClientCommand(client, command) => {
+                self.settings.set_current_client(Some(client));
+                self.handle_command(command).await;
+                self.settings.set_current_client(None);
+            }
             TriggerShutdown => self.trigger_shutdown_event(),
             WgKeyEvent(key_event) => self.handle_wireguard_key_event(key_event).await,
+            WgKeyRevoked(account, public_key) => {
+                self.handle_wireguard_key_revoked(account, public_key).await
+            }
             NewAccountEvent(account_token, tx) => {
                 self.handle_new_account_event(account_token, tx).await
             }
             NewAppVersionInfo(app_version_info) => {
                 self.handle_new_app_version_info(app_version_info)
             }
+            AccountExpiry(account_token, expiry) => {
+                self.handle_account_expiry(account_token, expiry)
+            }
+            AccountExpiresSoon(account_token, expiry, threshold) => {
+                self.handle_account_expires_soon(account_token, expiry, threshold)
+            }
+            ConnectivityCheckFailed => self.handle_connectivity_check_failed(),
+            RotateRelay => self.handle_relay_rotation(),
+            ApiAccessFailure(failures) => self.handle_api_access_failure(failures).await,
+            InternalDaemonEvent::RelayListDiff(diff) => self.handle_relay_list_diff(diff),
         }
     }
 
@@ -718,7 +950,7 @@ where
     ) {
         match &tunnel_state_transition {
             TunnelStateTransition::Disconnected
-            | TunnelStateTransition::Connected(_)
+            | TunnelStateTransition::Connected(..)
             | TunnelStateTransition::Error(_) => {
                 // Reset the RPCs so that they fail immediately after the underlying socket gets
                 // invalidated due to the tunnel either coming up or breaking.
@@ -727,13 +959,17 @@ where
             _ => (),
         };
 
+        self.tunnel_proxy
+            .handle_tunnel_state_transition(&tunnel_state_transition)
+            .await;
+
         let tunnel_state = match tunnel_state_transition {
             TunnelStateTransition::Disconnected => TunnelState::Disconnected,
             TunnelStateTransition::Connecting(endpoint) => TunnelState::Connecting {
                 endpoint,
                 location: self.build_location_from_relay(),
             },
-            TunnelStateTransition::Connected(endpoint) => TunnelState::Connected {
+            TunnelStateTransition::Connected(endpoint, _) => TunnelState::Connected {
                 endpoint,
                 location: self.build_location_from_relay(),
             },
@@ -745,10 +981,15 @@ where
 
 
         self.unschedule_reconnect();
+        self.unschedule_relay_rotation();
 
         debug!("New tunnel state: {:?}", tunnel_state);
         match tunnel_state {
             TunnelState::Disconnected => self.state.disconnected(),
+            TunnelState::Connected { .. } => {
+                self.avoided_relay = None;
+                self.schedule_relay_rotation();
+            }
             TunnelState::Error(ref error_state) => {
                 if error_state.is_blocking() {
                     info!(
@@ -763,6 +1004,9 @@ where
                 }
 
                 if let ErrorStateCause::AuthFailed(_) = error_state.cause() {
+                    // The account may have run out of time, in which case the next attempt
+                    // blocks with a more specific cause
+                    self.account_monitor.refresh();
                     self.schedule_reconnect(Duration::from_secs(60)).await
                 }
             }
@@ -805,6 +1049,7 @@ where
                                 .unwrap_or(None)
                                 .and_then(|entry| entry.wireguard)
                                 .is_some(),
+                            self.avoided_relay.as_deref(),
                         )
                         .ok();
                     if let Some((relay, endpoint)) = endpoint {
@@ -840,6 +1085,17 @@ where
                     }
                 }
             };
+            if retry_attempt > 0 {
+                if let Ok(ref parameters) = result {
+                    self.event_listener.notify_retry_attempt(
+                        retry_attempt,
+                        self.last_generated_relay
+                            .as_ref()
+                            .map(|relay| relay.hostname.clone()),
+                        parameters.get_tunnel_endpoint(),
+                    );
+                }
+            }
             if tunnel_parameters_tx.send(result).is_err() {
                 log::error!("Failed to send tunnel parameters");
             }
@@ -973,6 +1229,33 @@ where
         }
     }
 
+    /// Schedules a reconnect to a different relay, if relay rotation is enabled.
+    fn schedule_relay_rotation(&mut self) {
+        self.unschedule_relay_rotation();
+        let interval = match self.settings.tunnel_options.relay_rotation_interval {
+            Some(interval) => interval,
+            None => return,
+        };
+        if let RelaySettings::CustomTunnelEndpoint(_) = self.settings.get_relay_settings() {
+            return;
+        }
+
+        let daemon_tx = self.tx.clone();
+        let (future, abort_handle) = abortable(Box::pin(async move {
+            tokio::time::delay_for(Duration::from_secs(u64::from(interval) * 60)).await;
+            let _ = daemon_tx.send(InternalDaemonEvent::RotateRelay);
+        }));
+
+        tokio::spawn(future);
+        self.relay_rotation_job = Some(abort_handle);
+    }
+
+    fn unschedule_relay_rotation(&mut self) {
+        if let Some(job) = self.relay_rotation_job.take() {
+            job.abort();
+        }
+    }
+
 
     async fn handle_command(&mut self, command: DaemonCommand) {
         use self::DaemonCommand::*;
@@ -1014,10 +1297,42 @@ where
             SetWireguardRotationInterval(tx, interval) => {
                 self.on_set_wireguard_rotation_interval(tx, interval).await
             }
+            SetRelayRotationInterval(tx, interval) => {
+                self.on_set_relay_rotation_interval(tx, interval)
+            }
+            SetWireguardQuantumResistant(tx, quantum_resistant) => {
+                self.on_set_wireguard_quantum_resistant(tx, quantum_resistant)
+            }
+            SetWireguardConnectivityCheck(tx, options) => {
+                self.on_set_wireguard_connectivity_check(tx, options)
+            }
+            SetTunnelProxySettings(tx, tunnel_proxy) => {
+                self.on_set_tunnel_proxy_settings(tx, tunnel_proxy).await
+            }
+            SetApiAccessMethods(tx, methods) => self.on_set_api_access_methods(tx, methods).await,
+            GetCurrentApiAccessMethod(tx) => self.on_get_current_api_access_method(tx),
+            SetAccountExpiryNotifications(tx, hours) => {
+                self.on_set_account_expiry_notifications(tx, hours)
+            }
+            AddScheduleRule(tx, rule) => self.on_add_schedule_rule(tx, rule),
+            RemoveScheduleRule(tx, id) => self.on_remove_schedule_rule(tx, id),
             GetSettings(tx) => self.on_get_settings(tx),
+            ExportSettings(tx) => self.on_export_settings(tx),
+            ImportSettings(tx, settings, keep_account_token) => {
+                self.on_import_settings(tx, settings, keep_account_token)
+                    .await
+            }
+            GetSettingsHistory(tx) => self.on_get_settings_history(tx),
+            RollbackSettings(tx, revision) => self.on_rollback_settings(tx, revision).await,
             GenerateWireguardKey(tx) => self.on_generate_wireguard_key(tx).await,
             GetWireguardKey(tx) => self.on_get_wireguard_key(tx).await,
             VerifyWireguardKey(tx) => self.on_verify_wireguard_key(tx).await,
+            ListWireguardKeys(tx) => self.on_list_wireguard_keys(tx).await,
+            SetWireguardKeyLabel(tx, label) => self.on_set_wireguard_key_label(tx, label).await,
+            RevokeWireguardKey(tx, public_key) => {
+                self.on_revoke_wireguard_key(tx, public_key).await
+            }
+            GetDiagnostics(tx) => self.on_get_diagnostics(tx),
             GetVersionInfo(tx) => self.on_get_version_info(tx),
             GetCurrentVersion(tx) => self.on_get_current_version(tx),
             #[cfg(not(target_os = "android"))]
@@ -1067,20 +1382,24 @@ where
                     .unwrap_or_else(|| account_history::AccountEntry {
                         account: account.clone(),
                         wireguard: None,
+                        wireguard_key_label: None,
                     });
                 // if no key existed before
                 let first_key_for_account_on_host = account_entry.wireguard.is_none();
                 account_entry.wireguard = Some(data);
-                match self.account_history.insert(account_entry).await {
+                match self.account_history.insert(account_entry.clone()).await {
                     Ok(_) => {
                         if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
                             self.schedule_reconnect(WG_RECONNECT_DELAY).await;
                         }
-                        self.event_listener
-                            .notify_key_event(KeygenEvent::NewKey(public_key));
                         if first_key_for_account_on_host {
                             self.ensure_key_rotation().await;
+                        } else {
+                            self.wireguard_key_manager
+                                .run_revocation_check(account, public_key.key.clone());
                         }
+                        self.event_listener
+                            .notify_key_event(KeygenEvent::NewKey(public_key));
                     }
                     Err(e) => {
                         log::error!(
@@ -1109,6 +1428,48 @@ where
         }
     }
 
+    async fn handle_wireguard_key_revoked(
+        &mut self,
+        account: AccountToken,
+        public_key: talpid_types::net::wireguard::PublicKey,
+    ) {
+        if self.settings.get_account_token().as_ref() != Some(&account) {
+            return;
+        }
+        let mut account_entry = match self.account_history.get(&account).await {
+            Ok(Some(entry)) => entry,
+            Ok(None) => return,
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to read account entry from history")
+                );
+                return;
+            }
+        };
+        // The key may have been replaced since the revocation was detected
+        if account_entry
+            .wireguard
+            .as_ref()
+            .map(|wg_data| wg_data.private_key.public_key() != public_key)
+            .unwrap_or(true)
+        {
+            return;
+        }
+
+        log::warn!("The wireguard key of this device was revoked. Generating a new key");
+        account_entry.wireguard = None;
+        if let Err(error) = self.account_history.insert(account_entry).await {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to remove revoked wireguard key")
+            );
+        }
+        self.wireguard_key_manager
+            .spawn_key_generation_task(account, None)
+            .await;
+    }
+
     async fn ensure_key_rotation(&mut self) {
         if let Some(token) = self.settings.get_account_token() {
             let rotation_interval = self
@@ -1145,6 +1506,116 @@ where
         self.event_listener.notify_app_version(app_version_info);
     }
 
+    fn handle_account_expiry(&mut self, account_token: AccountToken, expiry: DateTime<Utc>) {
+        if self.settings.get_account_token().as_ref() != Some(&account_token) {
+            return;
+        }
+        self.account_monitor.set_expiry(account_token, expiry);
+        self.account_expiry_confirmed = true;
+        if self.account_expiry != Some(expiry) {
+            self.account_expiry = Some(expiry);
+            self.event_listener.notify_account_expiry(expiry);
+        }
+
+        let has_expired = self.account_has_expired();
+        let is_blocked = self.is_blocked_by_account_expiry();
+        if has_expired != is_blocked {
+            if is_blocked {
+                info!("Reconnecting since time was added to the account");
+            }
+            self.reconnect_tunnel();
+        }
+    }
+
+    fn handle_account_expires_soon(
+        &mut self,
+        account_token: AccountToken,
+        expiry: DateTime<Utc>,
+        threshold: u32,
+    ) {
+        if self.settings.get_account_token().as_ref() != Some(&account_token) {
+            return;
+        }
+        self.event_listener
+            .notify_account_expires_soon(expiry, threshold);
+        if threshold == 0 && !self.is_blocked_by_account_expiry() {
+            // Don't wait for the relay to reject the tunnel. The tunnel is blocked once the API
+            // confirms the expiry.
+            self.account_monitor.refresh();
+        }
+    }
+
+    /// Returns whether the API has confirmed that the account has run out of time. The expiry
+    /// has to have passed by more than `ACCOUNT_EXPIRY_CLOCK_SKEW`, so that a clock that runs
+    /// slightly fast does not lock the user out.
+    fn account_has_expired(&self) -> bool {
+        self.account_expiry_confirmed
+            && self
+                .account_expiry
+                .map(|expiry| {
+                    expiry + chrono::Duration::seconds(ACCOUNT_EXPIRY_CLOCK_SKEW_SECS) <= Utc::now()
+                })
+                .unwrap_or(false)
+    }
+
+    fn is_blocked_by_account_expiry(&self) -> bool {
+        match &self.tunnel_state {
+            TunnelState::Error(error_state) => {
+                *error_state.cause() == ErrorStateCause::AccountExpired
+            }
+            _ => false,
+        }
+    }
+
+    fn handle_relay_rotation(&mut self) {
+        self.relay_rotation_job = None;
+        if let TunnelState::Connected { .. } = self.tunnel_state {
+            self.avoided_relay = self
+                .last_generated_relay
+                .as_ref()
+                .map(|relay| relay.hostname.clone());
+            info!("Rotating relay");
+            self.connect_tunnel();
+        }
+    }
+
+    fn handle_relay_list_diff(&mut self, diff: RelayListDiff) {
+        if let Some(relay) = &self.last_generated_relay {
+            if diff.is_unavailable(&relay.hostname) {
+                warn!(
+                    "The relay in use, {}, was removed or deactivated in the relay list",
+                    relay.hostname
+                );
+            }
+        }
+        self.event_listener.notify_relay_list_diff(diff);
+    }
+
+    fn handle_connectivity_check_failed(&mut self) {
+        self.event_listener.notify_connectivity_check_failure(
+            self.last_generated_relay
+                .as_ref()
+                .map(|relay| relay.hostname.clone()),
+        );
+    }
+
+    async fn handle_api_access_failure(&mut self, failures: u32) {
+        if failures % api_access::MAX_CONSECUTIVE_FAILURES == 0 {
+            info!(
+                "{} requests to the API failed in a row, trying the next access method",
+                failures
+            );
+            self.api_access.rotate(&mut self.relay_selector).await;
+        }
+        // The address of the API may have changed even if the access method did not.
+        self.update_api_allowed_endpoint();
+    }
+
+    fn update_api_allowed_endpoint(&mut self) {
+        let endpoint = self.api_access.allowed_endpoint();
+        self.send_tunnel_command(TunnelCommand::AllowEndpoint(Some(endpoint)));
+    }
+
     fn on_set_target_state(&mut self, tx: oneshot::Sender<()>, new_target_state: TargetState) {
         if self.state.is_running() {
             self.set_target_state(new_target_state);
@@ -1263,9 +1734,13 @@ where
         tx: oneshot::Sender<Result<AccountData, mullvad_rpc::rest::Error>>,
         account_token: AccountToken,
     ) {
-        let expiry_fut = self.accounts_proxy.get_expiry(account_token);
-        let rpc_call = async {
-            let result = expiry_fut.await.map(|expiry| AccountData { expiry });
+        let expiry_fut = self.accounts_proxy.get_expiry(account_token.clone());
+        let daemon_tx = self.tx.clone();
+        let rpc_call = async move {
+            let result = expiry_fut.await.map(|expiry| {
+                let _ = daemon_tx.send(InternalDaemonEvent::AccountExpiry(account_token, expiry));
+                AccountData { expiry }
+            });
             Self::oneshot_send(tx, result, "account data");
         };
         tokio::spawn(rpc_call);
@@ -1290,9 +1765,19 @@ where
         voucher: String,
     ) {
         if let Some(account_token) = self.settings.get_account_token() {
-            let future = self.accounts_proxy.submit_voucher(account_token, voucher);
-            let rpc_call = async {
-                Self::oneshot_send(tx, future.await, "submit_voucher response");
+            let future = self
+                .accounts_proxy
+                .submit_voucher(account_token.clone(), voucher);
+            let daemon_tx = self.tx.clone();
+            let rpc_call = async move {
+                let result = future.await;
+                if let Ok(ref submission) = result {
+                    let _ = daemon_tx.send(InternalDaemonEvent::AccountExpiry(
+                        account_token,
+                        submission.new_expiry,
+                    ));
+                }
+                Self::oneshot_send(tx, result, "submit_voucher response");
             };
             tokio::spawn(rpc_call);
         }
@@ -1306,7 +1791,11 @@ where
         self.relay_selector.update().await;
     }
 
-    async fn on_set_account(&mut self, tx: oneshot::Sender<()>, account_token: Option<String>) {
+    async fn on_set_account(
+        &mut self,
+        tx: oneshot::Sender<Result<(), settings::Error>>,
+        account_token: Option<String>,
+    ) {
         match self.set_account(account_token.clone()).await {
             Ok(account_changed) => {
                 if account_changed {
@@ -1321,10 +1810,11 @@ where
                         }
                     };
                 }
-                Self::oneshot_send(tx, (), "set_account response");
+                Self::oneshot_send(tx, Ok(()), "set_account response");
             }
             Err(e) => {
                 log::error!("Failed to set account - {}", e);
+                Self::oneshot_send(tx, Err(e), "set_account response");
             }
         }
     }
@@ -1337,6 +1827,11 @@ where
         if account_changed {
             self.event_listener
                 .notify_settings(self.settings.to_settings());
+            self.account_expiry = None;
+            self.account_expiry_confirmed = false;
+            self.account_monitor.set_account(account_token.clone());
+            // The check polls the API with the previous account token
+            self.wireguard_key_manager.stop_revocation_check();
 
             // Bump account history if a token was set
             if let Some(token) = account_token.clone() {
@@ -1450,10 +1945,21 @@ where
         }
     }
 
+    #[cfg(target_os = "linux")]
+    fn notify_split_tunnel_processes(&self) {
+        match self.exclude_pids.list() {
+            Ok(pids) => self.event_listener.notify_split_tunnel_processes(pids),
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to obtain PIDs")),
+        }
+    }
+
     #[cfg(target_os = "linux")]
     fn on_add_split_tunnel_process(&mut self, tx: oneshot::Sender<()>, pid: i32) {
         match self.exclude_pids.add(pid) {
-            Ok(()) => Self::oneshot_send(tx, (), "add_split_tunnel_process response"),
+            Ok(()) => {
+                Self::oneshot_send(tx, (), "add_split_tunnel_process response");
+                self.notify_split_tunnel_processes();
+            }
             Err(e) => error!("{}", e.display_chain_with_msg("Unable to add PID")),
         }
     }
@@ -1461,7 +1967,10 @@ where
     #[cfg(target_os = "linux")]
     fn on_remove_split_tunnel_process(&mut self, tx: oneshot::Sender<()>, pid: i32) {
         match self.exclude_pids.remove(pid) {
-            Ok(()) => Self::oneshot_send(tx, (), "remove_split_tunnel_process response"),
+            Ok(()) => {
+                Self::oneshot_send(tx, (), "remove_split_tunnel_process response");
+                self.notify_split_tunnel_processes();
+            }
             Err(e) => error!("{}", e.display_chain_with_msg("Unable to remove PID")),
         }
     }
@@ -1469,16 +1978,23 @@ where
     #[cfg(target_os = "linux")]
     fn on_clear_split_tunnel_processes(&mut self, tx: oneshot::Sender<()>) {
         match self.exclude_pids.clear() {
-            Ok(()) => Self::oneshot_send(tx, (), "clear_split_tunnel_processes response"),
+            Ok(()) => {
+                Self::oneshot_send(tx, (), "clear_split_tunnel_processes response");
+                self.notify_split_tunnel_processes();
+            }
             Err(e) => error!("{}", e.display_chain_with_msg("Unable to clear PIDs")),
         }
     }
 
-    fn on_update_relay_settings(&mut self, tx: oneshot::Sender<()>, update: RelaySettingsUpdate) {
+    fn on_update_relay_settings(
+        &mut self,
+        tx: oneshot::Sender<Result<(), settings::Error>>,
+        update: RelaySettingsUpdate,
+    ) {
         let save_result = self.settings.update_relay_settings(update);
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, (), "update_relay_settings response");
+                Self::oneshot_send(tx, Ok(()), "update_relay_settings response");
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
@@ -1486,30 +2002,44 @@ where
                     self.reconnect_tunnel();
                 }
             }
-            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "update_relay_settings response");
+            }
         }
     }
 
-    fn on_set_allow_lan(&mut self, tx: oneshot::Sender<()>, allow_lan: bool) {
+    fn on_set_allow_lan(
+        &mut self,
+        tx: oneshot::Sender<Result<(), settings::Error>>,
+        allow_lan: bool,
+    ) {
         let save_result = self.settings.set_allow_lan(allow_lan);
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, (), "set_allow_lan response");
+                Self::oneshot_send(tx, Ok(()), "set_allow_lan response");
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
                     self.send_tunnel_command(TunnelCommand::AllowLan(allow_lan));
                 }
             }
-            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_allow_lan response");
+            }
         }
     }
 
-    async fn on_set_show_beta_releases(&mut self, tx: oneshot::Sender<()>, enabled: bool) {
+    async fn on_set_show_beta_releases(
+        &mut self,
+        tx: oneshot::Sender<Result<(), settings::Error>>,
+        enabled: bool,
+    ) {
         let save_result = self.settings.set_show_beta_releases(enabled);
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, (), "set_show_beta_releases response");
+                Self::oneshot_send(tx, Ok(()), "set_show_beta_releases response");
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
@@ -1517,13 +2047,16 @@ where
                     handle.set_show_beta_releases(enabled).await;
                 }
             }
-            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_show_beta_releases response");
+            }
         }
     }
 
     fn on_set_block_when_disconnected(
         &mut self,
-        tx: oneshot::Sender<()>,
+        tx: oneshot::Sender<Result<(), settings::Error>>,
         block_when_disconnected: bool,
     ) {
         let save_result = self
@@ -1531,7 +2064,7 @@ where
             .set_block_when_disconnected(block_when_disconnected);
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, (), "set_block_when_disconnected response");
+                Self::oneshot_send(tx, Ok(()), "set_block_when_disconnected response");
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
@@ -1540,29 +2073,43 @@ where
                     ));
                 }
             }
-            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_block_when_disconnected response");
+            }
         }
     }
 
-    fn on_set_auto_connect(&mut self, tx: oneshot::Sender<()>, auto_connect: bool) {
+    fn on_set_auto_connect(
+        &mut self,
+        tx: oneshot::Sender<Result<(), settings::Error>>,
+        auto_connect: bool,
+    ) {
         let save_result = self.settings.set_auto_connect(auto_connect);
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, (), "set auto-connect response");
+                Self::oneshot_send(tx, Ok(()), "set auto-connect response");
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
                 }
             }
-            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set auto-connect response");
+            }
         }
     }
 
-    fn on_set_openvpn_mssfix(&mut self, tx: oneshot::Sender<()>, mssfix_arg: Option<u16>) {
+    fn on_set_openvpn_mssfix(
+        &mut self,
+        tx: oneshot::Sender<Result<(), settings::Error>>,
+        mssfix_arg: Option<u16>,
+    ) {
         let save_result = self.settings.set_openvpn_mssfix(mssfix_arg);
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, (), "set_openvpn_mssfix response");
+                Self::oneshot_send(tx, Ok(()), "set_openvpn_mssfix response");
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
@@ -1574,7 +2121,10 @@ where
                     }
                 }
             }
-            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_openvpn_mssfix response");
+            }
         }
     }
 
@@ -1630,11 +2180,15 @@ where
     }
 
 
-    fn on_set_enable_ipv6(&mut self, tx: oneshot::Sender<()>, enable_ipv6: bool) {
+    fn on_set_enable_ipv6(
+        &mut self,
+        tx: oneshot::Sender<Result<(), settings::Error>>,
+        enable_ipv6: bool,
+    ) {
         let save_result = self.settings.set_enable_ipv6(enable_ipv6);
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, (), "set_enable_ipv6 response");
+                Self::oneshot_send(tx, Ok(()), "set_enable_ipv6 response");
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
@@ -1642,15 +2196,22 @@ where
                     self.reconnect_tunnel();
                 }
             }
-            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_enable_ipv6 response");
+            }
         }
     }
 
-    fn on_set_wireguard_mtu(&mut self, tx: oneshot::Sender<()>, mtu: Option<u16>) {
+    fn on_set_wireguard_mtu(
+        &mut self,
+        tx: oneshot::Sender<Result<(), settings::Error>>,
+        mtu: Option<u16>,
+    ) {
         let save_result = self.settings.set_wireguard_mtu(mtu);
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, (), "set_wireguard_mtu response");
+                Self::oneshot_send(tx, Ok(()), "set_wireguard_mtu response");
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
@@ -1662,29 +2223,243 @@ where
                     }
                 }
             }
-            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_wireguard_mtu response");
+            }
+        }
+    }
+
+    fn on_set_wireguard_quantum_resistant(
+        &mut self,
+        tx: oneshot::Sender<Result<(), settings::Error>>,
+        quantum_resistant: bool,
+    ) {
+        let save_result = self
+            .settings
+            .set_wireguard_quantum_resistant(quantum_resistant);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_wireguard_quantum_resistant response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
+                        info!(
+                            "Initiating tunnel restart because the quantum-resistant tunnel \
+                             setting changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_wireguard_quantum_resistant response");
+            }
+        }
+    }
+
+    fn on_set_wireguard_connectivity_check(
+        &mut self,
+        tx: oneshot::Sender<Result<(), settings::Error>>,
+        options: talpid_types::net::wireguard::ConnectivityCheckOptions,
+    ) {
+        let save_result = self.settings.set_wireguard_connectivity_check(options);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_wireguard_connectivity_check response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
+                        info!(
+                            "Initiating tunnel restart because the connectivity check options \
+                             changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_wireguard_connectivity_check response");
+            }
         }
     }
 
     async fn on_set_wireguard_rotation_interval(
         &mut self,
-        tx: oneshot::Sender<()>,
+        tx: oneshot::Sender<Result<(), settings::Error>>,
         interval: Option<u32>,
     ) {
         let save_result = self.settings.set_wireguard_rotation_interval(interval);
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, (), "set_wireguard_rotation_interval response");
+                Self::oneshot_send(tx, Ok(()), "set_wireguard_rotation_interval response");
                 if settings_changed {
                     self.ensure_key_rotation().await;
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
                 }
             }
-            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_wireguard_rotation_interval response");
+            }
         }
     }
 
+    fn on_set_relay_rotation_interval(
+        &mut self,
+        tx: oneshot::Sender<Result<(), settings::Error>>,
+        interval: Option<u32>,
+    ) {
+        let save_result = self.settings.set_relay_rotation_interval(interval);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_relay_rotation_interval response");
+                if settings_changed {
+                    if let TunnelState::Connected { .. } = self.tunnel_state {
+                        self.schedule_relay_rotation();
+                    }
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_relay_rotation_interval response");
+            }
+        }
+    }
+
+    async fn on_set_tunnel_proxy_settings(
+        &mut self,
+        tx: oneshot::Sender<Result<(), settings::Error>>,
+        tunnel_proxy: TunnelProxySettings,
+    ) {
+        let result = match self.settings.set_tunnel_proxy_settings(tunnel_proxy) {
+            Ok(settings_changed) => {
+                if settings_changed {
+                    self.tunnel_proxy.set_settings(tunnel_proxy).await;
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+                Ok(())
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to set tunnel proxy settings")
+                );
+                Err(error)
+            }
+        };
+        Self::oneshot_send(tx, result, "set_tunnel_proxy_settings response");
+    }
+
+    async fn on_set_api_access_methods(
+        &mut self,
+        tx: oneshot::Sender<Result<(), settings::Error>>,
+        methods: Vec<AccessMethod>,
+    ) {
+        let result = match self.settings.set_api_access_methods(methods.clone()) {
+            Ok(settings_changed) => {
+                if settings_changed {
+                    self.api_access
+                        .set_methods(methods, &mut self.relay_selector)
+                        .await;
+                    self.update_api_allowed_endpoint();
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+                Ok(())
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to set API access methods")
+                );
+                Err(error)
+            }
+        };
+        Self::oneshot_send(tx, result, "set_api_access_methods response");
+    }
+
+    fn on_get_current_api_access_method(&self, tx: oneshot::Sender<AccessMethod>) {
+        Self::oneshot_send(
+            tx,
+            self.api_access.current_method(),
+            "get_current_api_access_method response",
+        );
+    }
+
+    fn on_set_account_expiry_notifications(
+        &mut self,
+        tx: oneshot::Sender<Result<(), settings::Error>>,
+        hours: Vec<u32>,
+    ) {
+        let result = match self.settings.set_account_expiry_notifications(hours) {
+            Ok(settings_changed) => {
+                if settings_changed {
+                    self.account_monitor
+                        .set_thresholds(self.settings.account_expiry_notifications.clone());
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+                Ok(())
+            }
+            Err(error) => {
+                error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to set account expiry notifications")
+                );
+                Err(error)
+            }
+        };
+        Self::oneshot_send(tx, result, "set_account_expiry_notifications response");
+    }
+
+    fn on_add_schedule_rule(
+        &mut self,
+        tx: oneshot::Sender<Result<u32, settings::Error>>,
+        rule: ScheduleRule,
+    ) {
+        let result = self.settings.add_schedule_rule(rule);
+        match result {
+            Ok(_) => self.on_schedule_changed(),
+            Err(ref error) => log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to add schedule rule")
+            ),
+        }
+        Self::oneshot_send(tx, result, "add_schedule_rule response");
+    }
+
+    fn on_remove_schedule_rule(
+        &mut self,
+        tx: oneshot::Sender<Result<bool, settings::Error>>,
+        id: u32,
+    ) {
+        let result = self.settings.remove_schedule_rule(id);
+        match result {
+            Ok(true) => self.on_schedule_changed(),
+            Ok(false) => (),
+            Err(ref error) => log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to remove schedule rule")
+            ),
+        }
+        Self::oneshot_send(tx, result, "remove_schedule_rule response");
+    }
+
+    fn on_schedule_changed(&mut self) {
+        self.scheduler.set_rules(self.settings.schedule.clone());
+        self.event_listener
+            .notify_settings(self.settings.to_settings());
+    }
+
     async fn ensure_wireguard_keys_for_current_account(&mut self) {
         if let Some(account) = self.settings.get_account_token() {
             if self
@@ -1733,6 +2508,7 @@ where
                     account_history::AccountEntry {
                         account: account_token.clone(),
                         wireguard: None,
+                        wireguard_key_label: None,
                     }
                 })
             })?;
@@ -1755,7 +2531,7 @@ where
                 let public_key = new_data.get_public_key();
                 account_entry.wireguard = Some(new_data);
                 self.account_history
-                    .insert(account_entry)
+                    .insert(account_entry.clone())
                     .await
                     .map_err(|e| {
                         format!("Failed to add new wireguard key to account data: {}", e)
@@ -1843,10 +2619,283 @@ where
         });
     }
 
+    async fn on_list_wireguard_keys(
+        &mut self,
+        tx: oneshot::Sender<Result<Vec<mullvad_types::wireguard::RegisteredKey>, wireguard::Error>>,
+    ) {
+        let account = match self.settings.get_account_token() {
+            Some(account) => account,
+            None => {
+                Self::oneshot_send(
+                    tx,
+                    Err(wireguard::Error::NoAccountToken),
+                    "list_wireguard_keys response",
+                );
+                return;
+            }
+        };
+        let (current_key, label) = match self.account_history.get(&account).await {
+            Ok(Some(entry)) => (
+                entry.wireguard.map(|wg| wg.private_key.public_key()),
+                entry.wireguard_key_label,
+            ),
+            Ok(None) => (None, None),
+            Err(error) => {
+                Self::oneshot_send(
+                    tx,
+                    Err(wireguard::Error::AccountHistoryError(error)),
+                    "list_wireguard_keys response",
+                );
+                return;
+            }
+        };
+
+        let list_future = self
+            .wireguard_key_manager
+            .list_keys(account, current_key, label);
+        tokio::spawn(async move {
+            Self::oneshot_send(tx, list_future.await, "list_wireguard_keys response");
+        });
+    }
+
+    async fn on_set_wireguard_key_label(
+        &mut self,
+        tx: oneshot::Sender<Result<(), wireguard::Error>>,
+        label: Option<String>,
+    ) {
+        let account = match self.settings.get_account_token() {
+            Some(account) => account,
+            None => {
+                Self::oneshot_send(
+                    tx,
+                    Err(wireguard::Error::NoAccountToken),
+                    "set_wireguard_key_label response",
+                );
+                return;
+            }
+        };
+        // Labels are only known to this device, as the API has no notion of them
+        let result = async {
+            let mut account_entry =
+                self.account_history
+                    .get(&account)
+                    .await?
+                    .unwrap_or_else(|| account_history::AccountEntry {
+                        account: account.clone(),
+                        wireguard: None,
+                        wireguard_key_label: None,
+                    });
+            account_entry.wireguard_key_label = label;
+            self.account_history.insert(account_entry).await
+        }
+        .await
+        .map_err(wireguard::Error::AccountHistoryError);
+        Self::oneshot_send(tx, result, "set_wireguard_key_label response");
+    }
+
+    async fn on_revoke_wireguard_key(
+        &mut self,
+        tx: oneshot::Sender<Result<(), wireguard::Error>>,
+        public_key: talpid_types::net::wireguard::PublicKey,
+    ) {
+        let account = match self.settings.get_account_token() {
+            Some(account) => account,
+            None => {
+                Self::oneshot_send(
+                    tx,
+                    Err(wireguard::Error::NoAccountToken),
+                    "revoke_wireguard_key response",
+                );
+                return;
+            }
+        };
+        let current_key = self
+            .account_history
+            .get(&account)
+            .await
+            .map(|entry| entry.and_then(|e| e.wireguard.map(|wg| wg.private_key.public_key())));
+        match current_key {
+            Ok(Some(current_key)) if current_key == public_key => {
+                Self::oneshot_send(
+                    tx,
+                    Err(wireguard::Error::RevokeCurrentKey),
+                    "revoke_wireguard_key response",
+                );
+                return;
+            }
+            Ok(_) => (),
+            Err(error) => {
+                Self::oneshot_send(
+                    tx,
+                    Err(wireguard::Error::AccountHistoryError(error)),
+                    "revoke_wireguard_key response",
+                );
+                return;
+            }
+        }
+
+        let revoke_future = self.wireguard_key_manager.revoke_key(account, public_key);
+        tokio::spawn(async move {
+            Self::oneshot_send(tx, revoke_future.await, "revoke_wireguard_key response");
+        });
+    }
+
     fn on_get_settings(&self, tx: oneshot::Sender<Settings>) {
         Self::oneshot_send(tx, self.settings.to_settings(), "get_settings response");
     }
 
+    fn on_get_diagnostics(&mut self, tx: oneshot::Sender<diagnostics::Diagnostics>) {
+        let mut diagnostics =
+            diagnostics::Diagnostics::new(&self.settings.to_settings(), &self.tunnel_state);
+        let (dns_manager_tx, dns_manager_rx) = oneshot::channel();
+        self.send_tunnel_command(TunnelCommand::GetDnsManager(dns_manager_tx));
+        tokio::spawn(async move {
+            let dns_manager = dns_manager_rx.await.unwrap_or(None);
+            diagnostics.sections = diagnostics::collect_system_state(dns_manager.clone()).await;
+            diagnostics.dns_manager = dns_manager;
+            Self::oneshot_send(tx, diagnostics, "get_diagnostics response");
+        });
+    }
+
+    fn on_export_settings(&self, tx: oneshot::Sender<Result<String, settings::Error>>) {
+        Self::oneshot_send(tx, self.settings.export(), "export_settings response");
+    }
+
+    async fn on_import_settings(
+        &mut self,
+        tx: oneshot::Sender<Result<(), settings::Error>>,
+        settings: String,
+        keep_account_token: bool,
+    ) {
+        let old_settings = self.settings.to_settings();
+        let result = match self
+            .settings
+            .import(settings.as_bytes(), keep_account_token)
+        {
+            Ok(settings_changed) => {
+                if settings_changed {
+                    self.apply_replaced_settings(old_settings).await;
+                }
+                Ok(())
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to import settings")
+                );
+                Err(error)
+            }
+        };
+        Self::oneshot_send(tx, result, "import_settings response");
+    }
+
+    fn on_get_settings_history(&self, tx: oneshot::Sender<Vec<settings_history::HistoryEntry>>) {
+        Self::oneshot_send(tx, self.settings.history(), "get_settings_history response");
+    }
+
+    async fn on_rollback_settings(
+        &mut self,
+        tx: oneshot::Sender<Result<(), settings::Error>>,
+        revision: u64,
+    ) {
+        let old_settings = self.settings.to_settings();
+        let result = match self.settings.rollback(revision) {
+            Ok(settings_changed) => {
+                if settings_changed {
+                    self.apply_replaced_settings(old_settings).await;
+                }
+                Ok(())
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to roll back settings")
+                );
+                Err(error)
+            }
+        };
+        Self::oneshot_send(tx, result, "rollback_settings response");
+    }
+
+    /// Propagates the differences between `old_settings` and the current settings to the rest of
+    /// the daemon, and reconnects if any settings that affect the tunnel have changed.
+    async fn apply_replaced_settings(&mut self, old_settings: Settings) {
+        let new_settings = self.settings.to_settings();
+        self.event_listener.notify_settings(new_settings.clone());
+
+        if new_settings.allow_lan != old_settings.allow_lan {
+            self.send_tunnel_command(TunnelCommand::AllowLan(new_settings.allow_lan));
+        }
+        if new_settings.block_when_disconnected != old_settings.block_when_disconnected {
+            self.send_tunnel_command(TunnelCommand::BlockWhenDisconnected(
+                new_settings.block_when_disconnected,
+            ));
+        }
+        if new_settings.show_beta_releases != old_settings.show_beta_releases {
+            let mut handle = self.version_updater_handle.clone();
+            handle
+                .set_show_beta_releases(new_settings.show_beta_releases)
+                .await;
+        }
+        if new_settings.tunnel_proxy != old_settings.tunnel_proxy {
+            self.tunnel_proxy
+                .set_settings(new_settings.tunnel_proxy)
+                .await;
+        }
+        if new_settings.schedule != old_settings.schedule {
+            self.scheduler.set_rules(new_settings.schedule.clone());
+        }
+        if new_settings.api_access_methods != old_settings.api_access_methods {
+            self.api_access
+                .set_methods(
+                    new_settings.api_access_methods.clone(),
+                    &mut self.relay_selector,
+                )
+                .await;
+            self.update_api_allowed_endpoint();
+        }
+
+        if new_settings.account_expiry_notifications != old_settings.account_expiry_notifications {
+            self.account_monitor
+                .set_thresholds(new_settings.account_expiry_notifications.clone());
+        }
+
+        let account_token = new_settings.get_account_token();
+        if account_token != old_settings.get_account_token() {
+            self.account_expiry = None;
+            self.account_expiry_confirmed = false;
+            self.account_monitor.set_account(account_token.clone());
+            // The check polls the API with the previous account token
+            self.wireguard_key_manager.stop_revocation_check();
+            if let Some(token) = account_token.as_ref() {
+                if let Err(e) = self.account_history.bump_history(token).await {
+                    log::error!("Failed to bump account history: {}", e);
+                }
+            }
+            self.ensure_wireguard_keys_for_current_account().await;
+        }
+        if new_settings.tunnel_options.wireguard.automatic_rotation
+            != old_settings.tunnel_options.wireguard.automatic_rotation
+        {
+            self.ensure_key_rotation().await;
+        }
+
+        if account_token.is_none() {
+            if old_settings.get_account_token().is_some() {
+                info!("Disconnecting because account token was cleared");
+                self.set_target_state(TargetState::Unsecured);
+            }
+        } else if account_token != old_settings.get_account_token()
+            || new_settings.get_relay_settings() != old_settings.get_relay_settings()
+            || new_settings.bridge_settings != old_settings.bridge_settings
+            || new_settings.get_bridge_state() != old_settings.get_bridge_state()
+            || new_settings.tunnel_options != old_settings.tunnel_options
+        {
+            info!("Initiating tunnel restart because the settings were replaced");
+            self.reconnect_tunnel();
+        }
+    }
+
     fn oneshot_send<T>(tx: oneshot::Sender<T>, t: T, msg: &'static str) {
         if tx.send(t).is_err() {
             warn!("Unable to send {} to the daemon command sender", msg);
@@ -1898,7 +2947,14 @@ where
     }
 
     fn connect_tunnel(&mut self) {
-        self.send_tunnel_command(TunnelCommand::Connect);
+        if self.account_has_expired() {
+            info!("Blocking all network connections since the account has run out of time");
+            // Check whether time has been added since the expiry was last fetched
+            self.account_monitor.refresh();
+            self.send_tunnel_command(TunnelCommand::Block(ErrorStateCause::AccountExpired));
+        } else {
+            self.send_tunnel_command(TunnelCommand::Connect);
+        }
     }
 
     fn disconnect_tunnel(&mut self) {
@@ -2027,4 +3083,8 @@ impl TunnelParametersGenerator for MullvadTunnelParametersGenerator {
             }
         }
     }
+
+    fn connectivity_check_failed(&mut self) {
+        let _ = self.tx.send(InternalDaemonEvent::ConnectivityCheckFailed);
+    }
 }
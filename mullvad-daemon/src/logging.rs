@@ -2,9 +2,18 @@ use fern::{
     colors::{Color, ColoredLevelConfig},
     Output,
 };
-use log;
-use std::{fmt, io, path::PathBuf};
-use talpid_core::logging::rotate_log;
+use log::{self, LevelFilter};
+use parking_lot::{Mutex, RwLock};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use talpid_core::logging::rotate_log_with_retention;
+use tokio::sync::broadcast;
 
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -59,43 +68,303 @@ const LINE_SEPARATOR: &str = "\r\n";
 
 const DATE_TIME_FORMAT_STR: &str = "[%Y-%m-%d %H:%M:%S%.3f]";
 
+/// Number of log lines that can be buffered for each log stream subscriber before lines are
+/// dropped.
+const LOG_STREAM_CAPACITY: usize = 1024;
+
+const LEVEL_FILTERS: [LevelFilter; 6] = [
+    LevelFilter::Off,
+    LevelFilter::Error,
+    LevelFilter::Warn,
+    LevelFilter::Info,
+    LevelFilter::Debug,
+    LevelFilter::Trace,
+];
+
+lazy_static::lazy_static! {
+    static ref LOG_LEVELS: RwLock<LogLevels> = RwLock::new(LogLevels::new(LevelFilter::Info));
+    static ref LOG_STREAM: LogStream = LogStream::new();
+}
+
+/// When and how the daemon log file is rotated while the daemon is running. It is always rotated
+/// when the daemon starts.
+#[derive(Debug, Clone, Copy)]
+pub struct LogRotation {
+    /// The log file is rotated when it would grow beyond this many bytes. Zero disables rotation
+    /// based on size.
+    pub max_size: u64,
+    /// The number of old log files to keep.
+    pub retention: usize,
+}
+
+impl Default for LogRotation {
+    fn default() -> Self {
+        LogRotation {
+            max_size: 10 * 1024 * 1024,
+            retention: 1,
+        }
+    }
+}
+
+/// The log levels of the daemon. They can be changed while the daemon is running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogLevels {
+    /// Level of modules that have no specific level.
+    pub default: LevelFilter,
+    /// Levels of specific modules and their submodules.
+    pub modules: BTreeMap<String, LevelFilter>,
+}
+
+impl LogLevels {
+    fn new(default: LevelFilter) -> Self {
+        LogLevels {
+            default,
+            modules: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the level of `target` if the default level was `default`. The most specific module
+    /// level set for the target takes precedence over the silenced crates.
+    fn level_for(&self, target: &str, default: LevelFilter) -> LevelFilter {
+        let module_level = self
+            .modules
+            .iter()
+            .filter(|(module, _)| is_module_or_submodule(target, module))
+            .max_by_key(|(module, _)| module.len())
+            .map(|(_, level)| *level);
+        if let Some(level) = module_level {
+            return level;
+        }
+
+        let crate_name = target.split("::").next().unwrap_or(target);
+        if SILENCED_CRATES.contains(&crate_name) {
+            LevelFilter::Warn
+        } else if SLIGHTLY_SILENCED_CRATES.contains(&crate_name) {
+            one_level_quieter(default)
+        } else {
+            default
+        }
+    }
+
+    /// Returns the most verbose level that any module may log at.
+    fn max_level(&self, default: LevelFilter) -> LevelFilter {
+        self.modules
+            .values()
+            .copied()
+            .chain(std::iter::once(default))
+            .chain(std::iter::once(LevelFilter::Warn))
+            .max()
+            .unwrap_or(default)
+    }
+}
+
+fn is_module_or_submodule(target: &str, module: &str) -> bool {
+    target == module || (target.starts_with(module) && target[module.len()..].starts_with("::"))
+}
+
+/// Returns the current log levels.
+pub fn log_levels() -> LogLevels {
+    LOG_LEVELS.read().clone()
+}
+
+/// Sets the level of `module`, or the default level if `module` is `None`. If `level` is `None`,
+/// the level of `module` is removed, so that it uses the default level again.
+pub fn set_log_level(module: Option<&str>, level: Option<LevelFilter>) {
+    {
+        let mut levels = LOG_LEVELS.write();
+        match (module, level) {
+            (Some(module), Some(level)) => {
+                levels.modules.insert(module.to_owned(), level);
+            }
+            (Some(module), None) => {
+                levels.modules.remove(module);
+            }
+            (None, Some(level)) => levels.default = level,
+            (None, None) => (),
+        }
+    }
+    update_max_level();
+    log::info!(
+        "Changed log level of {} to {}",
+        module.unwrap_or("all modules"),
+        level
+            .map(|level| level.to_string())
+            .unwrap_or_else(|| "default".to_owned())
+    );
+}
+
+/// Lets `log` skip records that no output or log stream subscriber is interested in.
+fn update_max_level() {
+    let levels = LOG_LEVELS.read();
+    let max_level = levels
+        .max_level(levels.default)
+        .max(levels.max_level(LOG_STREAM.max_level()));
+    log::set_max_level(max_level);
+}
+
+/// Returns whether the log file and stdout should include records with the given metadata.
+fn is_enabled(metadata: &log::Metadata<'_>) -> bool {
+    let levels = LOG_LEVELS.read();
+    metadata.level() <= levels.level_for(metadata.target(), levels.default)
+}
+
+/// Returns whether any output or log stream subscriber is interested in the record.
+fn is_enabled_for_any(metadata: &log::Metadata<'_>) -> bool {
+    if is_enabled(metadata) {
+        return true;
+    }
+    let stream_level = LOG_STREAM.max_level();
+    stream_level != LevelFilter::Off
+        && metadata.level() <= LOG_LEVELS.read().level_for(metadata.target(), stream_level)
+}
+
+/// A log record, as sent to log stream subscribers.
+#[derive(Debug, Clone)]
+pub struct LogLine {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub level: log::Level,
+    pub target: String,
+    pub message: String,
+}
+
+struct LogStream {
+    sender: broadcast::Sender<LogLine>,
+    /// Number of subscribers for each level, indexed by `LevelFilter as usize`.
+    subscribers: Mutex<[usize; 6]>,
+    /// The most verbose level of any subscriber, as `LevelFilter as usize`.
+    max_level: AtomicUsize,
+}
+
+impl LogStream {
+    fn new() -> Self {
+        let (sender, _) = broadcast::channel(LOG_STREAM_CAPACITY);
+        LogStream {
+            sender,
+            subscribers: Mutex::new([0; 6]),
+            max_level: AtomicUsize::new(LevelFilter::Off as usize),
+        }
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        LEVEL_FILTERS[self.max_level.load(Ordering::Relaxed)]
+    }
+
+    fn update_subscribers(&self, level: LevelFilter, add: bool) {
+        let mut subscribers = self.subscribers.lock();
+        if add {
+            subscribers[level as usize] += 1;
+        } else {
+            subscribers[level as usize] -= 1;
+        }
+        let max_level = subscribers
+            .iter()
+            .rposition(|count| *count > 0)
+            .unwrap_or(LevelFilter::Off as usize);
+        self.max_level.store(max_level, Ordering::Relaxed);
+    }
+
+    fn publish(&self, record: &log::Record<'_>) {
+        if self.max_level() == LevelFilter::Off {
+            return;
+        }
+        let _ = self.sender.send(LogLine {
+            timestamp: chrono::Utc::now(),
+            level: record.level(),
+            target: record.target().to_owned(),
+            message: record.args().to_string(),
+        });
+    }
+}
+
+/// Receives log lines from the daemon, up to a given level.
+pub struct LogSubscription {
+    receiver: broadcast::Receiver<LogLine>,
+    level: LevelFilter,
+}
+
+/// Starts streaming log lines. Only lines at `level` or less verbose are received, but levels set
+/// for specific modules still apply.
+pub fn subscribe(level: LevelFilter) -> LogSubscription {
+    let receiver = LOG_STREAM.sender.subscribe();
+    LOG_STREAM.update_subscribers(level, true);
+    update_max_level();
+    LogSubscription { receiver, level }
+}
+
+impl LogSubscription {
+    /// Returns the next log line, or `None` if the logger is gone. If the subscriber has fallen
+    /// behind, a line saying how many lines were dropped is returned in their place.
+    pub async fn next(&mut self) -> Option<LogLine> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(line) => {
+                    let level = LOG_LEVELS.read().level_for(&line.target, self.level);
+                    if line.level <= level {
+                        return Some(line);
+                    }
+                }
+                Err(broadcast::RecvError::Lagged(count)) => {
+                    return Some(LogLine {
+                        timestamp: chrono::Utc::now(),
+                        level: log::Level::Warn,
+                        target: module_path!().to_owned(),
+                        message: format!("Dropped {} log lines", count),
+                    });
+                }
+                Err(broadcast::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+impl Drop for LogSubscription {
+    fn drop(&mut self) {
+        LOG_STREAM.update_subscribers(self.level, false);
+        update_max_level();
+    }
+}
+
 pub fn init_logger(
-    log_level: log::LevelFilter,
+    log_level: LevelFilter,
     log_file: Option<&PathBuf>,
     output_timestamp: bool,
+    log_rotation: LogRotation,
 ) -> Result<(), Error> {
-    let mut top_dispatcher = fern::Dispatch::new().level(log_level);
-    for silenced_crate in SILENCED_CRATES {
-        top_dispatcher = top_dispatcher.level_for(*silenced_crate, log::LevelFilter::Warn);
-    }
-    for silenced_crate in SLIGHTLY_SILENCED_CRATES {
-        top_dispatcher = top_dispatcher.level_for(*silenced_crate, one_level_quieter(log_level));
-    }
+    *LOG_LEVELS.write() = LogLevels::new(log_level);
+
+    let mut top_dispatcher = fern::Dispatch::new()
+        .level(LevelFilter::Trace)
+        .filter(is_enabled_for_any);
 
     let stdout_formatter = Formatter {
         output_timestamp,
         output_color: true,
     };
     let stdout_dispatcher = fern::Dispatch::new()
+        .filter(is_enabled)
         .format(move |out, message, record| stdout_formatter.output_msg(out, message, record))
         .chain(io::stdout());
     top_dispatcher = top_dispatcher.chain(stdout_dispatcher);
 
     if let Some(ref log_file) = log_file {
-        rotate_log(log_file).map_err(Error::RotateLog)?;
+        rotate_log_with_retention(log_file, log_rotation.retention).map_err(Error::RotateLog)?;
         let file_formatter = Formatter {
             output_timestamp: true,
             output_color: false,
         };
-        let f = fern::log_file(log_file).map_err(|source| Error::WriteFile {
-            path: log_file.display().to_string(),
-            source,
-        })?;
+        let f =
+            RotatingLogFile::open(log_file, log_rotation).map_err(|source| Error::WriteFile {
+                path: log_file.display().to_string(),
+                source,
+            })?;
         let file_dispatcher = fern::Dispatch::new()
+            .filter(is_enabled)
             .format(move |out, message, record| file_formatter.output_msg(out, message, record))
-            .chain(Output::file(f, LINE_SEPARATOR));
+            .chain(Output::writer(Box::new(f), LINE_SEPARATOR));
         top_dispatcher = top_dispatcher.chain(file_dispatcher);
     }
+
+    top_dispatcher = top_dispatcher.chain(Output::call(|record| LOG_STREAM.publish(record)));
     #[cfg(all(target_os = "android", debug_assertions))]
     {
         use android_logger::{AndroidLogger, Config};
@@ -105,10 +374,69 @@ pub fn init_logger(
         top_dispatcher = top_dispatcher.chain(logger);
     }
     top_dispatcher.apply().map_err(Error::SetLoggerError)?;
+    update_max_level();
     Ok(())
 }
 
-fn one_level_quieter(level: log::LevelFilter) -> log::LevelFilter {
+/// A log file that is rotated when it grows beyond a maximum size. It is only rotated between
+/// lines.
+struct RotatingLogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    at_line_start: bool,
+    rotation: LogRotation,
+}
+
+impl RotatingLogFile {
+    fn open(path: &Path, rotation: LogRotation) -> io::Result<Self> {
+        let file = Self::open_file(path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingLogFile {
+            path: path.to_owned(),
+            file,
+            size,
+            at_line_start: true,
+            rotation,
+        })
+    }
+
+    fn open_file(path: &Path) -> io::Result<File> {
+        fs::OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        rotate_log_with_retention(&self.path, self.rotation.retention)
+            .map_err(|error| io::Error::new(io::ErrorKind::Other, error))?;
+        self.file = Self::open_file(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingLogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.rotation.max_size > 0
+            && self.at_line_start
+            && self.size > 0
+            && self.size + buf.len() as u64 > self.rotation.max_size
+        {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        if written > 0 {
+            self.at_line_start = buf[written - 1] == b'\n';
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn one_level_quieter(level: LevelFilter) -> LevelFilter {
     use log::LevelFilter::*;
     match level {
         Off => Off,
@@ -170,3 +498,79 @@ fn escape_newlines(text: String) -> String {
 fn escape_newlines(text: String) -> String {
     text.replace("\n", LINE_SEPARATOR)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_level_for() {
+        let mut levels = LogLevels::new(LevelFilter::Info);
+        assert_eq!(
+            levels.level_for("mullvad_daemon", LevelFilter::Info),
+            LevelFilter::Info
+        );
+        assert_eq!(
+            levels.level_for("hyper::client", LevelFilter::Trace),
+            LevelFilter::Warn
+        );
+        assert_eq!(
+            levels.level_for("nftnl", LevelFilter::Debug),
+            LevelFilter::Info
+        );
+
+        levels
+            .modules
+            .insert("talpid_core".to_owned(), LevelFilter::Debug);
+        levels
+            .modules
+            .insert("talpid_core::firewall".to_owned(), LevelFilter::Trace);
+        levels
+            .modules
+            .insert("hyper".to_owned(), LevelFilter::Debug);
+
+        assert_eq!(
+            levels.level_for("talpid_core::firewall::imp", LevelFilter::Info),
+            LevelFilter::Trace
+        );
+        assert_eq!(
+            levels.level_for("talpid_core::dns", LevelFilter::Info),
+            LevelFilter::Debug
+        );
+        assert_eq!(
+            levels.level_for("talpid_core_extra", LevelFilter::Info),
+            LevelFilter::Info
+        );
+        assert_eq!(
+            levels.level_for("hyper", LevelFilter::Info),
+            LevelFilter::Debug
+        );
+        assert_eq!(levels.max_level(LevelFilter::Info), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn test_rotating_log_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("daemon.log");
+        let rotation = LogRotation {
+            max_size: 10,
+            retention: 2,
+        };
+        let mut file = RotatingLogFile::open(&path, rotation).unwrap();
+
+        file.write_all(b"first").unwrap();
+        file.write_all(b" line\n").unwrap();
+        file.write_all(b"second\n").unwrap();
+        file.write_all(b"third\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "third\n");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("daemon.old.log")).unwrap(),
+            "second\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("daemon.old.2.log")).unwrap(),
+            "first line\n"
+        );
+    }
+}
@@ -2,8 +2,11 @@
 
 use log::{debug, error, info, warn};
 use mullvad_daemon::{
+    access_control::{AccessPolicy, Role},
     logging,
     management_interface::{ManagementInterfaceEventBroadcaster, ManagementInterfaceServer},
+    policy::SettingsPolicy,
+    remote_management::RemoteManagementConfig,
     rpc_uniqueness_check,
     runtime::new_runtime_builder,
     version, Daemon, DaemonCommandChannel, DaemonCommandSender,
@@ -50,6 +53,7 @@ fn init_logging(config: &cli::Config) -> Result<Option<PathBuf>, String> {
         config.log_level,
         log_file.as_ref(),
         config.log_stdout_timestamps,
+        config.log_rotation,
     )
     .map_err(|e| e.display_chain_with_msg("Unable to initialize logger"))?;
     log_panics::init();
@@ -125,7 +129,29 @@ async fn create_daemon(
         .map_err(|e| e.display_chain_with_msg("Unable to get cache dir"))?;
 
     let command_channel = DaemonCommandChannel::new();
-    let event_listener = spawn_management_interface(command_channel.sender()).await?;
+    let access_policy = AccessPolicy::load(&settings_dir).map_err(|e| {
+        e.display_chain_with_msg("Unable to load management interface access policy")
+    })?;
+    let settings_policy = SettingsPolicy::load(&settings_dir)
+        .map_err(|e| e.display_chain_with_msg("Unable to load settings policy"))?;
+    let remote_management = RemoteManagementConfig::load(&settings_dir)
+        .map_err(|e| e.display_chain_with_msg("Unable to load remote management config"))?;
+    let remote_server = remote_management
+        .as_ref()
+        .map(|config| {
+            config
+                .server_config()
+                .map(|server_config| (server_config, config.role()))
+        })
+        .transpose()
+        .map_err(|e| e.display_chain_with_msg("Invalid remote management config"))?;
+    let event_listener = spawn_management_interface(
+        command_channel.sender(),
+        access_policy,
+        settings_policy.locked_fields().map(String::from).collect(),
+        remote_server,
+    )
+    .await?;
 
     Daemon::start(
         log_dir,
@@ -134,6 +160,11 @@ async fn create_daemon(
         cache_dir,
         event_listener,
         command_channel,
+        settings_policy,
+        #[cfg(target_os = "linux")]
+        remote_management
+            .as_ref()
+            .map(RemoteManagementConfig::allowed_listener),
     )
     .await
     .map_err(|e| e.display_chain_with_msg("Unable to initialize daemon"))
@@ -141,12 +172,18 @@ async fn create_daemon(
 
 async fn spawn_management_interface(
     command_sender: DaemonCommandSender,
+    access_policy: AccessPolicy,
+    locked_settings: Vec<String>,
+    remote_server: Option<(mullvad_management_interface::RemoteServerConfig, Role)>,
 ) -> Result<ManagementInterfaceEventBroadcaster, String> {
-    let server = ManagementInterfaceServer::start(command_sender)
-        .await
-        .map_err(|error| {
-            error.display_chain_with_msg("Unable to start management interface server")
-        })?;
+    let server = ManagementInterfaceServer::start(
+        command_sender,
+        access_policy,
+        locked_settings,
+        remote_server,
+    )
+    .await
+    .map_err(|error| error.display_chain_with_msg("Unable to start management interface server"))?;
     let event_broadcaster = server.event_broadcaster();
 
     info!("Management interface listening on {}", server.socket_path());
@@ -1,28 +1,43 @@
-use crate::{DaemonCommand, DaemonCommandSender, EventListener};
-use futures::channel::oneshot;
+use crate::{
+    access_control::{self, AccessPolicy, Role},
+    diagnostics, logging,
+    settings_history::HistoryEntry,
+    DaemonCommand, DaemonCommandSender, EventListener,
+};
+use chrono::{DateTime, NaiveTime, Timelike, Utc, Weekday};
+use futures::{
+    channel::oneshot,
+    future::{AbortHandle, Abortable},
+    Stream,
+};
 use mullvad_management_interface::{
     types::{self, daemon_event, management_service_server::ManagementService},
-    Code, Request, Response, Status,
+    Code, PeerCredentials, RemoteServerConfig, Request, Response, Status,
 };
 use mullvad_paths;
 use mullvad_rpc::{rest::Error as RestError, StatusCode};
 use mullvad_types::{
     account::AccountToken,
+    api_access::{AccessMethod, CustomProxy},
     location::GeoIpLocation,
     relay_constraints::{
         BridgeConstraints, BridgeSettings, BridgeState, Constraint, LocationConstraint,
         OpenVpnConstraints, Provider, RelayConstraintsUpdate, RelaySettings, RelaySettingsUpdate,
         WireguardConstraints,
     },
-    relay_list::{Relay, RelayList, RelayListCountry},
-    settings::{Settings, TunnelOptions},
+    relay_list::{Relay, RelayList, RelayListCountry, RelayListDiff},
+    schedule::{ScheduleAction, ScheduleRule},
+    settings::{Settings, TunnelOptions, TunnelProxySettings},
     states::{TargetState, TunnelState},
     version, wireguard, ConnectionConfig,
 };
 use parking_lot::RwLock;
 use std::{
     cmp,
+    collections::VecDeque,
+    pin::Pin,
     sync::{mpsc, Arc},
+    task::{Context, Poll},
 };
 use talpid_types::{
     net::{TransportProtocol, TunnelType},
@@ -37,9 +52,24 @@ pub enum Error {
     SetupError(#[error(source)] mullvad_management_interface::Error),
 }
 
+#[derive(Clone)]
 struct ManagementServiceImpl {
     daemon_tx: DaemonCommandSender,
-    subscriptions: Arc<RwLock<Vec<EventsListenerSender>>>,
+    subscriptions: Arc<RwLock<EventSubscriptions>>,
+    access_policy: AccessPolicy,
+    locked_settings: Arc<Vec<String>>,
+    peer: Peer,
+}
+
+/// The client served by a `ManagementServiceImpl`. Every connection to the local socket gets its
+/// own instance, so this is determined by the listener and never by what the client sends.
+#[derive(Debug, Clone, Copy)]
+enum Peer {
+    /// A client connected to the local socket or pipe. The credentials are `None` if they could
+    /// not be read.
+    Local(Option<PeerCredentials>),
+    /// A client authenticated by the remote listener. It is given the configured role.
+    Remote(Role),
 }
 
 pub type ServiceResult<T> = std::result::Result<Response<T>, Status>;
@@ -47,20 +77,135 @@ type EventsListenerReceiver =
     tokio::sync::mpsc::UnboundedReceiver<Result<types::DaemonEvent, Status>>;
 type EventsListenerSender = tokio::sync::mpsc::UnboundedSender<Result<types::DaemonEvent, Status>>;
 
+/// Log lines streamed to a client. The log subscription that feeds the stream ends as soon as the
+/// stream is dropped, rather than when the next log line fails to be sent.
+pub struct LogLineStream {
+    receiver: tokio::sync::mpsc::UnboundedReceiver<Result<types::LogLine, Status>>,
+    forwarder: AbortHandle,
+}
+
+impl Stream for LogLineStream {
+    type Item = Result<types::LogLine, Status>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+}
+
+impl Drop for LogLineStream {
+    fn drop(&mut self) {
+        self.forwarder.abort();
+    }
+}
+
+/// Number of recent events that are kept around so that clients can resume an event stream.
+const RECENT_EVENTS_CAPACITY: usize = 100;
+
 const INVALID_VOUCHER_MESSAGE: &str = "This voucher code is invalid";
 const USED_VOUCHER_MESSAGE: &str = "This voucher code has already been used";
 
+/// Event listeners, along with a buffer of the most recent events sent to them.
+struct EventSubscriptions {
+    listeners: Vec<EventsListener>,
+    recent_events: VecDeque<RecentEvent>,
+    next_sequence_number: u64,
+    /// Identifies this instance of the event stream, as sequence numbers restart with the daemon.
+    stream_id: u64,
+}
+
+/// A client listening for events. Secrets in settings events are redacted unless
+/// `include_secrets` is set.
+struct EventsListener {
+    tx: EventsListenerSender,
+    include_secrets: bool,
+}
+
+impl EventsListener {
+    fn send(&self, event: &types::DaemonEvent) -> bool {
+        let mut event = event.clone();
+        if !self.include_secrets {
+            if let Some(daemon_event::Event::Settings(settings)) = &mut event.event {
+                redact_settings_secrets(settings);
+            }
+        }
+        self.tx.send(Ok(event)).is_ok()
+    }
+}
+
+/// An event that was sent to listeners, and the same event as JSON for diagnostics.
+struct RecentEvent {
+    event: types::DaemonEvent,
+    /// Secrets, such as the account token in settings, are redacted.
+    details: serde_json::Value,
+}
+
+impl Default for EventSubscriptions {
+    fn default() -> Self {
+        EventSubscriptions {
+            listeners: Vec::new(),
+            recent_events: VecDeque::with_capacity(RECENT_EVENTS_CAPACITY),
+            next_sequence_number: 1,
+            stream_id: rand::random(),
+        }
+    }
+}
+
+impl EventSubscriptions {
+    /// Adds a listener. If `resume_after` is non-zero, buffered events with a greater sequence
+    /// number are sent to it first. Resuming a stream other than the current one fails, since
+    /// its events cannot be told apart from the ones in the current stream.
+    fn subscribe(
+        &mut self,
+        listener: EventsListener,
+        stream_id: u64,
+        resume_after: u64,
+    ) -> Result<(), Status> {
+        if resume_after != 0 {
+            if stream_id != self.stream_id {
+                return Err(Status::failed_precondition(
+                    "Cannot resume events from a previous daemon instance",
+                ));
+            }
+            for RecentEvent { event, .. } in &self.recent_events {
+                if event.sequence_number > resume_after && !listener.send(event) {
+                    return Ok(());
+                }
+            }
+        }
+        self.listeners.push(listener);
+        Ok(())
+    }
+
+    fn broadcast(&mut self, mut event: types::DaemonEvent, details: serde_json::Value) {
+        event.sequence_number = self.next_sequence_number;
+        event.stream_id = self.stream_id;
+        self.next_sequence_number += 1;
+
+        if self.recent_events.len() >= RECENT_EVENTS_CAPACITY {
+            self.recent_events.pop_front();
+        }
+        self.recent_events.push_back(RecentEvent {
+            event: event.clone(),
+            details,
+        });
+
+        self.listeners.retain(|listener| listener.send(&event));
+    }
+}
+
 #[mullvad_management_interface::async_trait]
 impl ManagementService for ManagementServiceImpl {
     type GetRelayLocationsStream =
         tokio::sync::mpsc::Receiver<Result<types::RelayListCountry, Status>>;
     type GetSplitTunnelProcessesStream = tokio::sync::mpsc::UnboundedReceiver<Result<i32, Status>>;
     type EventsListenStream = EventsListenerReceiver;
+    type StreamLogsStream = LogLineStream;
 
     // Control and get the tunnel state
     //
 
-    async fn connect_tunnel(&self, _: Request<()>) -> ServiceResult<()> {
+    async fn connect_tunnel(&self, request: Request<()>) -> ServiceResult<()> {
+        self.check_role(Role::Operator)?;
         log::debug!("connect_tunnel");
 
         let (tx, rx) = oneshot::channel();
@@ -69,7 +214,8 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(()))
     }
 
-    async fn disconnect_tunnel(&self, _: Request<()>) -> ServiceResult<()> {
+    async fn disconnect_tunnel(&self, request: Request<()>) -> ServiceResult<()> {
+        self.check_role(Role::Operator)?;
         log::debug!("disconnect_tunnel");
 
         let (tx, _) = oneshot::channel();
@@ -77,13 +223,15 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(()))
     }
 
-    async fn reconnect_tunnel(&self, _: Request<()>) -> ServiceResult<()> {
+    async fn reconnect_tunnel(&self, request: Request<()>) -> ServiceResult<()> {
+        self.check_role(Role::Operator)?;
         log::debug!("reconnect_tunnel");
         self.send_command_to_daemon(DaemonCommand::Reconnect)?;
         Ok(Response::new(()))
     }
 
-    async fn get_tunnel_state(&self, _: Request<()>) -> ServiceResult<types::TunnelState> {
+    async fn get_tunnel_state(&self, request: Request<()>) -> ServiceResult<types::TunnelState> {
+        self.check_role(Role::ReadOnly)?;
         log::debug!("get_tunnel_state");
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::GetState(tx))?;
@@ -94,28 +242,41 @@ impl ManagementService for ManagementServiceImpl {
     // Control the daemon and receive events
     //
 
-    async fn events_listen(&self, _: Request<()>) -> ServiceResult<Self::EventsListenStream> {
+    async fn events_listen(
+        &self,
+        request: Request<types::EventsListenRequest>,
+    ) -> ServiceResult<Self::EventsListenStream> {
+        self.check_role(Role::ReadOnly)?;
+        let request = request.into_inner();
         let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
 
+        // Replaying and subscribing under the same lock ensures that no events are lost in between
+        let listener = EventsListener {
+            tx,
+            include_secrets: self.may_see_secrets(),
+        };
         let mut subscriptions = self.subscriptions.write();
-        subscriptions.push(tx);
+        subscriptions.subscribe(listener, request.stream_id, request.resume_after)?;
 
         Ok(Response::new(rx))
     }
 
-    async fn prepare_restart(&self, _: Request<()>) -> ServiceResult<()> {
+    async fn prepare_restart(&self, request: Request<()>) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
         log::debug!("prepare_restart");
         self.send_command_to_daemon(DaemonCommand::PrepareRestart)?;
         Ok(Response::new(()))
     }
 
-    async fn shutdown(&self, _: Request<()>) -> ServiceResult<()> {
+    async fn shutdown(&self, request: Request<()>) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
         log::debug!("shutdown");
         self.send_command_to_daemon(DaemonCommand::Shutdown)?;
         Ok(Response::new(()))
     }
 
-    async fn factory_reset(&self, _: Request<()>) -> ServiceResult<()> {
+    async fn factory_reset(&self, request: Request<()>) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
         #[cfg(not(target_os = "android"))]
         {
             log::debug!("factory_reset");
@@ -131,7 +292,50 @@ impl ManagementService for ManagementServiceImpl {
         }
     }
 
-    async fn get_current_version(&self, _: Request<()>) -> ServiceResult<String> {
+    async fn get_diagnostics(&self, request: Request<()>) -> ServiceResult<types::Diagnostics> {
+        self.check_role(Role::Admin)?;
+        log::debug!("get_diagnostics");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetDiagnostics(tx))?;
+        let diagnostics = rx.await.map_err(|_| Status::internal("internal error"))?;
+        let recent_events = self
+            .subscriptions
+            .read()
+            .recent_events
+            .iter()
+            .map(|recent| {
+                serde_json::json!({
+                    "sequence_number": recent.event.sequence_number,
+                    "event": recent.details,
+                })
+                .to_string()
+            })
+            .collect();
+        Ok(Response::new(types::Diagnostics {
+            settings: diagnostics.settings,
+            tunnel_state: diagnostics.tunnel_state,
+            recent_events,
+            dns_manager: diagnostics.dns_manager.unwrap_or_default(),
+            sections: diagnostics
+                .sections
+                .into_iter()
+                .map(|section| {
+                    let (content, error) = match section.content {
+                        Ok(content) => (content, String::new()),
+                        Err(error) => (String::new(), error),
+                    };
+                    types::DiagnosticsSection {
+                        name: section.name,
+                        content,
+                        error,
+                    }
+                })
+                .collect(),
+        }))
+    }
+
+    async fn get_current_version(&self, request: Request<()>) -> ServiceResult<String> {
+        self.check_role(Role::ReadOnly)?;
         log::debug!("get_current_version");
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::GetCurrentVersion(tx))?;
@@ -140,7 +344,8 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(|_| Status::internal("internal error"))
     }
 
-    async fn get_version_info(&self, _: Request<()>) -> ServiceResult<types::AppVersionInfo> {
+    async fn get_version_info(&self, request: Request<()>) -> ServiceResult<types::AppVersionInfo> {
+        self.check_role(Role::ReadOnly)?;
         log::debug!("get_version_info");
 
         let (tx, rx) = oneshot::channel();
@@ -154,7 +359,8 @@ impl ManagementService for ManagementServiceImpl {
     // Relays and tunnel constraints
     //
 
-    async fn update_relay_locations(&self, _: Request<()>) -> ServiceResult<()> {
+    async fn update_relay_locations(&self, request: Request<()>) -> ServiceResult<()> {
+        self.check_role(Role::Operator)?;
         log::debug!("update_relay_locations");
         self.send_command_to_daemon(DaemonCommand::UpdateRelayLocations)?;
         Ok(Response::new(()))
@@ -164,21 +370,25 @@ impl ManagementService for ManagementServiceImpl {
         &self,
         request: Request<types::RelaySettingsUpdate>,
     ) -> ServiceResult<()> {
+        self.check_role(Role::Operator)?;
+        let client = self.client_description(&request);
         log::debug!("update_relay_settings");
         let (tx, rx) = oneshot::channel();
         let constraints_update = convert_relay_settings_update(&request.into_inner())?;
 
         let message = DaemonCommand::UpdateRelaySettings(tx, constraints_update);
-        self.send_command_to_daemon(message)?;
-        rx.await
+        self.send_command_as(client, message)?;
+        let settings_result = rx.await.map_err(|_| Status::internal("internal error"))?;
+        settings_result
             .map(Response::new)
-            .map_err(|_| Status::internal("internal error"))
+            .map_err(map_settings_error)
     }
 
     async fn get_relay_locations(
         &self,
-        _: Request<()>,
+        request: Request<()>,
     ) -> ServiceResult<Self::GetRelayLocationsStream> {
+        self.check_role(Role::ReadOnly)?;
         log::debug!("get_relay_locations");
 
         let (tx, rx) = oneshot::channel();
@@ -205,7 +415,11 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(stream_rx))
     }
 
-    async fn get_current_location(&self, _: Request<()>) -> ServiceResult<types::GeoIpLocation> {
+    async fn get_current_location(
+        &self,
+        request: Request<()>,
+    ) -> ServiceResult<types::GeoIpLocation> {
+        self.check_role(Role::ReadOnly)?;
         log::debug!("get_current_location");
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::GetCurrentLocation(tx))?;
@@ -220,6 +434,8 @@ impl ManagementService for ManagementServiceImpl {
         &self,
         request: Request<types::BridgeSettings>,
     ) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
+        let client = self.client_description(&request);
         use talpid_types::net;
         use types::bridge_settings::Type as BridgeSettingType;
 
@@ -283,19 +499,37 @@ impl ManagementService for ManagementServiceImpl {
                 );
                 BridgeSettings::Custom(proxy_settings)
             }
+            BridgeSettingType::Socks5(proxy_settings) => {
+                let address = proxy_settings
+                    .address
+                    .parse()
+                    .map_err(|_| Status::invalid_argument("failed to parse IP address"))?;
+                let auth = proxy_settings.auth.map(|auth| net::openvpn::ProxyAuth {
+                    username: auth.username,
+                    password: auth.password,
+                });
+                let proxy_settings =
+                    net::openvpn::ProxySettings::Socks5(net::openvpn::Socks5ProxySettings {
+                        address,
+                        auth,
+                    });
+                BridgeSettings::Custom(proxy_settings)
+            }
         };
 
         log::debug!("set_bridge_settings({:?})", settings);
 
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::SetBridgeSettings(tx, settings))?;
+        self.send_command_as(client, DaemonCommand::SetBridgeSettings(tx, settings))?;
         let settings_result = rx.await.map_err(|_| Status::internal("internal error"))?;
         settings_result
             .map(Response::new)
-            .map_err(|_| Status::internal("internal error"))
+            .map_err(map_settings_error)
     }
 
     async fn set_bridge_state(&self, request: Request<types::BridgeState>) -> ServiceResult<()> {
+        self.check_role(Role::Operator)?;
+        let client = self.client_description(&request);
         use types::bridge_state::State;
 
         let bridge_state = match State::from_i32(request.into_inner().state) {
@@ -307,69 +541,118 @@ impl ManagementService for ManagementServiceImpl {
 
         log::debug!("set_bridge_state({:?})", bridge_state);
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::SetBridgeState(tx, bridge_state))?;
+        self.send_command_as(client, DaemonCommand::SetBridgeState(tx, bridge_state))?;
         let settings_result = rx.await.map_err(|_| Status::internal("internal error"))?;
         settings_result
             .map(Response::new)
-            .map_err(|_| Status::internal("internal error"))
+            .map_err(map_settings_error)
+    }
+
+    async fn set_relay_rotation_interval(&self, request: Request<u32>) -> ServiceResult<()> {
+        self.check_role(Role::Operator)?;
+        let client = self.client_description(&request);
+        let interval = request.into_inner();
+        if interval == 0 {
+            return Err(Status::invalid_argument(
+                "the interval must be at least one minute",
+            ));
+        }
+
+        log::debug!("set_relay_rotation_interval({})", interval);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_as(
+            client,
+            DaemonCommand::SetRelayRotationInterval(tx, Some(interval)),
+        )?;
+        let settings_result = rx.await.map_err(|_| Status::internal("internal error"))?;
+        settings_result
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn reset_relay_rotation_interval(&self, request: Request<()>) -> ServiceResult<()> {
+        self.check_role(Role::Operator)?;
+        let client = self.client_description(&request);
+        log::debug!("reset_relay_rotation_interval");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_as(client, DaemonCommand::SetRelayRotationInterval(tx, None))?;
+        let settings_result = rx.await.map_err(|_| Status::internal("internal error"))?;
+        settings_result
+            .map(Response::new)
+            .map_err(map_settings_error)
     }
 
     // Settings
     //
 
-    async fn get_settings(&self, _: Request<()>) -> ServiceResult<types::Settings> {
+    async fn get_settings(&self, request: Request<()>) -> ServiceResult<types::Settings> {
+        self.check_role(Role::ReadOnly)?;
         log::debug!("get_settings");
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::GetSettings(tx))?;
         rx.await
-            .map(|settings| Response::new(convert_settings(&settings)))
+            .map(|settings| Response::new(self.convert_settings_for_peer(&settings)))
             .map_err(|_| Status::internal("internal error"))
     }
 
     async fn set_allow_lan(&self, request: Request<bool>) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
+        let client = self.client_description(&request);
         let allow_lan = request.into_inner();
         log::debug!("set_allow_lan({})", allow_lan);
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::SetAllowLan(tx, allow_lan))?;
-        rx.await
+        self.send_command_as(client, DaemonCommand::SetAllowLan(tx, allow_lan))?;
+        let settings_result = rx.await.map_err(|_| Status::internal("internal error"))?;
+        settings_result
             .map(Response::new)
-            .map_err(|_| Status::internal("internal error"))
+            .map_err(map_settings_error)
     }
 
     async fn set_show_beta_releases(&self, request: Request<bool>) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
+        let client = self.client_description(&request);
         let enabled = request.into_inner();
         log::debug!("set_show_beta_releases({})", enabled);
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::SetShowBetaReleases(tx, enabled))?;
-        rx.await
+        self.send_command_as(client, DaemonCommand::SetShowBetaReleases(tx, enabled))?;
+        let settings_result = rx.await.map_err(|_| Status::internal("internal error"))?;
+        settings_result
             .map(Response::new)
-            .map_err(|_| Status::internal("internal error"))
+            .map_err(map_settings_error)
     }
 
     async fn set_block_when_disconnected(&self, request: Request<bool>) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
+        let client = self.client_description(&request);
         let block_when_disconnected = request.into_inner();
         log::debug!("set_block_when_disconnected({})", block_when_disconnected);
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::SetBlockWhenDisconnected(
-            tx,
-            block_when_disconnected,
-        ))?;
-        rx.await
+        self.send_command_as(
+            client,
+            DaemonCommand::SetBlockWhenDisconnected(tx, block_when_disconnected),
+        )?;
+        let settings_result = rx.await.map_err(|_| Status::internal("internal error"))?;
+        settings_result
             .map(Response::new)
-            .map_err(|_| Status::internal("internal error"))
+            .map_err(map_settings_error)
     }
 
     async fn set_auto_connect(&self, request: Request<bool>) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
+        let client = self.client_description(&request);
         let auto_connect = request.into_inner();
         log::debug!("set_auto_connect({})", auto_connect);
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::SetAutoConnect(tx, auto_connect))?;
-        rx.await
+        self.send_command_as(client, DaemonCommand::SetAutoConnect(tx, auto_connect))?;
+        let settings_result = rx.await.map_err(|_| Status::internal("internal error"))?;
+        settings_result
             .map(Response::new)
-            .map_err(|_| Status::internal("internal error"))
+            .map_err(map_settings_error)
     }
 
     async fn set_openvpn_mssfix(&self, request: Request<u32>) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
+        let client = self.client_description(&request);
         let mssfix = request.into_inner();
         let mssfix = if mssfix != 0 {
             Some(mssfix as u16)
@@ -378,37 +661,281 @@ impl ManagementService for ManagementServiceImpl {
         };
         log::debug!("set_openvpn_mssfix({:?})", mssfix);
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::SetOpenVpnMssfix(tx, mssfix))?;
-        rx.await
+        self.send_command_as(client, DaemonCommand::SetOpenVpnMssfix(tx, mssfix))?;
+        let settings_result = rx.await.map_err(|_| Status::internal("internal error"))?;
+        settings_result
             .map(Response::new)
-            .map_err(|_| Status::internal("internal error"))
+            .map_err(map_settings_error)
     }
 
     async fn set_wireguard_mtu(&self, request: Request<u32>) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
+        let client = self.client_description(&request);
         let mtu = request.into_inner();
         let mtu = if mtu != 0 { Some(mtu as u16) } else { None };
         log::debug!("set_wireguard_mtu({:?})", mtu);
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::SetWireguardMtu(tx, mtu))?;
-        rx.await
+        self.send_command_as(client, DaemonCommand::SetWireguardMtu(tx, mtu))?;
+        let settings_result = rx.await.map_err(|_| Status::internal("internal error"))?;
+        settings_result
             .map(Response::new)
-            .map_err(|_| Status::internal("internal error"))
+            .map_err(map_settings_error)
+    }
+
+    async fn set_wireguard_quantum_resistant(&self, request: Request<bool>) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
+        let client = self.client_description(&request);
+        let quantum_resistant = request.into_inner();
+        log::debug!("set_wireguard_quantum_resistant({})", quantum_resistant);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_as(
+            client,
+            DaemonCommand::SetWireguardQuantumResistant(tx, quantum_resistant),
+        )?;
+        let settings_result = rx.await.map_err(|_| Status::internal("internal error"))?;
+        settings_result
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_wireguard_connectivity_check(
+        &self,
+        request: Request<types::ConnectivityCheckOptions>,
+    ) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
+        let client = self.client_description(&request);
+        let options = request.into_inner();
+        log::debug!("set_wireguard_connectivity_check({:?})", options);
+        let ping_target = if options.ping_target.is_empty() {
+            None
+        } else {
+            Some(
+                options
+                    .ping_target
+                    .parse()
+                    .map_err(|_| Status::invalid_argument("invalid ping target"))?,
+            )
+        };
+        let options = talpid_types::net::wireguard::ConnectivityCheckOptions {
+            rx_timeout: options.rx_timeout,
+            traffic_timeout: options.traffic_timeout,
+            ping_timeout: options.ping_timeout,
+            ping_interval: options.ping_interval,
+            ping_target,
+        };
+        if !options.is_valid() {
+            return Err(Status::invalid_argument(
+                "timeouts must be non-zero, the ping interval must be shorter than the ping \
+                 timeout, and the ping target must be an in-tunnel address",
+            ));
+        }
+
+        let (tx, rx) = oneshot::channel();
+        self.send_command_as(
+            client,
+            DaemonCommand::SetWireguardConnectivityCheck(tx, options),
+        )?;
+        let settings_result = rx.await.map_err(|_| Status::internal("internal error"))?;
+        settings_result
+            .map(Response::new)
+            .map_err(map_settings_error)
     }
 
     async fn set_enable_ipv6(&self, request: Request<bool>) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
+        let client = self.client_description(&request);
         let enable_ipv6 = request.into_inner();
         log::debug!("set_enable_ipv6({})", enable_ipv6);
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::SetEnableIpv6(tx, enable_ipv6))?;
-        rx.await
+        self.send_command_as(client, DaemonCommand::SetEnableIpv6(tx, enable_ipv6))?;
+        let settings_result = rx.await.map_err(|_| Status::internal("internal error"))?;
+        settings_result
             .map(Response::new)
-            .map_err(|_| Status::internal("internal error"))
+            .map_err(map_settings_error)
+    }
+
+    async fn set_tunnel_proxy_settings(
+        &self,
+        request: Request<types::TunnelProxySettings>,
+    ) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
+        let client = self.client_description(&request);
+        let settings = request.into_inner();
+        let tunnel_proxy = TunnelProxySettings {
+            enabled: settings.enabled,
+            bind_address: settings
+                .bind_address
+                .parse()
+                .map_err(|_| Status::invalid_argument("failed to parse bind address"))?,
+        };
+        if !tunnel_proxy.is_valid() {
+            return Err(Status::invalid_argument(
+                "bind address must be a loopback address with a non-zero port",
+            ));
+        }
+
+        log::debug!("set_tunnel_proxy_settings({:?})", tunnel_proxy);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_as(
+            client,
+            DaemonCommand::SetTunnelProxySettings(tx, tunnel_proxy),
+        )?;
+        let settings_result = rx.await.map_err(|_| Status::internal("internal error"))?;
+        settings_result
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn add_schedule_rule(&self, request: Request<types::ScheduleRule>) -> ServiceResult<u32> {
+        self.check_role(Role::Admin)?;
+        let client = self.client_description(&request);
+        let rule = convert_proto_schedule_rule(request.into_inner())?;
+        log::debug!("add_schedule_rule({})", rule);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_as(client, DaemonCommand::AddScheduleRule(tx, rule))?;
+        let settings_result = rx.await.map_err(|_| Status::internal("internal error"))?;
+        settings_result
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn remove_schedule_rule(&self, request: Request<u32>) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
+        let client = self.client_description(&request);
+        let id = request.into_inner();
+        log::debug!("remove_schedule_rule({})", id);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_as(client, DaemonCommand::RemoveScheduleRule(tx, id))?;
+        match rx.await.map_err(|_| Status::internal("internal error"))? {
+            Ok(true) => Ok(Response::new(())),
+            Ok(false) => Err(Status::not_found("no schedule rule with the given ID")),
+            Err(error) => Err(map_settings_error(error)),
+        }
+    }
+
+    async fn export_settings(&self, request: Request<()>) -> ServiceResult<String> {
+        self.check_role(Role::Admin)?;
+        log::debug!("export_settings");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ExportSettings(tx))?;
+        let settings_result = rx.await.map_err(|_| Status::internal("internal error"))?;
+        settings_result
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn import_settings(
+        &self,
+        request: Request<types::ImportSettingsRequest>,
+    ) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
+        let client = self.client_description(&request);
+        let request = request.into_inner();
+        log::debug!(
+            "import_settings(keep_account_token: {})",
+            request.keep_account_token
+        );
+        let (tx, rx) = oneshot::channel();
+        self.send_command_as(
+            client,
+            DaemonCommand::ImportSettings(tx, request.settings, request.keep_account_token),
+        )?;
+        let settings_result = rx.await.map_err(|_| Status::internal("internal error"))?;
+        settings_result
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn get_settings_history(
+        &self,
+        request: Request<()>,
+    ) -> ServiceResult<types::SettingsHistory> {
+        self.check_role(Role::ReadOnly)?;
+        log::debug!("get_settings_history");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetSettingsHistory(tx))?;
+        let entries = rx.await.map_err(|_| Status::internal("internal error"))?;
+        Ok(Response::new(types::SettingsHistory {
+            entries: entries.iter().map(convert_history_entry).collect(),
+        }))
+    }
+
+    async fn rollback_settings(&self, request: Request<u64>) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
+        let client = self.client_description(&request);
+        let revision = request.into_inner();
+        log::debug!("rollback_settings({})", revision);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_as(client, DaemonCommand::RollbackSettings(tx, revision))?;
+        let settings_result = rx.await.map_err(|_| Status::internal("internal error"))?;
+        settings_result
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_api_access_methods(
+        &self,
+        request: Request<types::ApiAccessMethods>,
+    ) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
+        let client = self.client_description(&request);
+        let methods = request
+            .into_inner()
+            .methods
+            .iter()
+            .map(convert_proto_api_access_method)
+            .collect::<Result<Vec<_>, _>>()?;
+        if methods.is_empty() {
+            return Err(Status::invalid_argument(
+                "at least one API access method is required",
+            ));
+        }
+
+        log::debug!("set_api_access_methods({:?})", methods);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_as(client, DaemonCommand::SetApiAccessMethods(tx, methods))?;
+        let settings_result = rx.await.map_err(|_| Status::internal("internal error"))?;
+        settings_result
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn get_current_api_access_method(
+        &self,
+        request: Request<()>,
+    ) -> ServiceResult<types::ApiAccessMethod> {
+        self.check_role(Role::ReadOnly)?;
+        log::debug!("get_current_api_access_method");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetCurrentApiAccessMethod(tx))?;
+        let method = rx.await.map_err(|_| Status::internal("internal error"))?;
+        Ok(Response::new(convert_api_access_method(&method)))
+    }
+
+    async fn set_account_expiry_notifications(
+        &self,
+        request: Request<types::AccountExpiryNotifications>,
+    ) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
+        let client = self.client_description(&request);
+        let hours = request.into_inner().hours;
+        log::debug!("set_account_expiry_notifications({:?})", hours);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_as(
+            client,
+            DaemonCommand::SetAccountExpiryNotifications(tx, hours),
+        )?;
+        let settings_result = rx.await.map_err(|_| Status::internal("internal error"))?;
+        settings_result
+            .map(Response::new)
+            .map_err(map_settings_error)
     }
 
     // Account management
     //
 
-    async fn create_new_account(&self, _: Request<()>) -> ServiceResult<String> {
+    async fn create_new_account(&self, request: Request<()>) -> ServiceResult<String> {
+        self.check_role(Role::Admin)?;
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::CreateNewAccount(tx))?;
         let result = rx.await.map_err(|_| Status::internal("internal error"))?;
@@ -419,6 +946,8 @@ impl ManagementService for ManagementServiceImpl {
     }
 
     async fn set_account(&self, request: Request<AccountToken>) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
+        let client = self.client_description(&request);
         log::debug!("set_account");
         let account_token = request.into_inner();
         let account_token = if account_token == "" {
@@ -427,16 +956,18 @@ impl ManagementService for ManagementServiceImpl {
             Some(account_token)
         };
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::SetAccount(tx, account_token))?;
-        rx.await
+        self.send_command_as(client, DaemonCommand::SetAccount(tx, account_token))?;
+        let settings_result = rx.await.map_err(|_| Status::internal("internal error"))?;
+        settings_result
             .map(Response::new)
-            .map_err(|_| Status::internal("internal error"))
+            .map_err(map_settings_error)
     }
 
     async fn get_account_data(
         &self,
         request: Request<AccountToken>,
     ) -> ServiceResult<types::AccountData> {
+        self.check_role(Role::Operator)?;
         log::debug!("get_account_data");
         let account_token = request.into_inner();
         let (tx, rx) = oneshot::channel();
@@ -460,7 +991,11 @@ impl ManagementService for ManagementServiceImpl {
             })
     }
 
-    async fn get_account_history(&self, _: Request<()>) -> ServiceResult<types::AccountHistory> {
+    async fn get_account_history(
+        &self,
+        request: Request<()>,
+    ) -> ServiceResult<types::AccountHistory> {
+        self.check_role(Role::Admin)?;
         // TODO: this might be a stream
         log::debug!("get_account_history");
         let (tx, rx) = oneshot::channel();
@@ -474,6 +1009,7 @@ impl ManagementService for ManagementServiceImpl {
         &self,
         request: Request<AccountToken>,
     ) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
         log::debug!("remove_account_from_history");
         let account_token = request.into_inner();
         let (tx, rx) = oneshot::channel();
@@ -483,7 +1019,8 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(|_| Status::internal("internal error"))
     }
 
-    async fn clear_account_history(&self, _: Request<()>) -> ServiceResult<()> {
+    async fn clear_account_history(&self, request: Request<()>) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
         log::debug!("clear_account_history");
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::ClearAccountHistory(tx))?;
@@ -492,7 +1029,8 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(|_| Status::internal("internal error"))
     }
 
-    async fn get_www_auth_token(&self, _: Request<()>) -> ServiceResult<String> {
+    async fn get_www_auth_token(&self, request: Request<()>) -> ServiceResult<String> {
+        self.check_role(Role::Admin)?;
         log::debug!("get_www_auth_token");
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::GetWwwAuthToken(tx))?;
@@ -512,6 +1050,7 @@ impl ManagementService for ManagementServiceImpl {
         &self,
         request: Request<String>,
     ) -> ServiceResult<types::VoucherSubmission> {
+        self.check_role(Role::Admin)?;
         log::debug!("submit_voucher");
         let voucher = request.into_inner();
         let (tx, rx) = oneshot::channel();
@@ -547,29 +1086,42 @@ impl ManagementService for ManagementServiceImpl {
     //
 
     async fn set_wireguard_rotation_interval(&self, request: Request<u32>) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
+        let client = self.client_description(&request);
         let interval = request.into_inner();
 
         log::debug!("set_wireguard_rotation_interval({:?})", interval);
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::SetWireguardRotationInterval(
-            tx,
-            Some(interval),
-        ))?;
-        rx.await
-            .map_err(|_| Status::internal("internal error"))
+        self.send_command_as(
+            client,
+            DaemonCommand::SetWireguardRotationInterval(tx, Some(interval)),
+        )?;
+        let settings_result = rx.await.map_err(|_| Status::internal("internal error"))?;
+        settings_result
             .map(Response::new)
+            .map_err(map_settings_error)
     }
 
-    async fn reset_wireguard_rotation_interval(&self, _: Request<()>) -> ServiceResult<()> {
+    async fn reset_wireguard_rotation_interval(&self, request: Request<()>) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
+        let client = self.client_description(&request);
         log::debug!("reset_wireguard_rotation_interval");
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::SetWireguardRotationInterval(tx, None))?;
-        rx.await
-            .map_err(|_| Status::internal("internal error"))
+        self.send_command_as(
+            client,
+            DaemonCommand::SetWireguardRotationInterval(tx, None),
+        )?;
+        let settings_result = rx.await.map_err(|_| Status::internal("internal error"))?;
+        settings_result
             .map(Response::new)
+            .map_err(map_settings_error)
     }
 
-    async fn generate_wireguard_key(&self, _: Request<()>) -> ServiceResult<types::KeygenEvent> {
+    async fn generate_wireguard_key(
+        &self,
+        request: Request<()>,
+    ) -> ServiceResult<types::KeygenEvent> {
+        self.check_role(Role::Admin)?;
         // TODO: return error for TooManyKeys, GenerationFailure
         // on success, simply return the new key or nil
         log::debug!("generate_wireguard_key");
@@ -580,7 +1132,8 @@ impl ManagementService for ManagementServiceImpl {
             .map(|event| Response::new(convert_wireguard_key_event(&event)))
     }
 
-    async fn get_wireguard_key(&self, _: Request<()>) -> ServiceResult<types::PublicKey> {
+    async fn get_wireguard_key(&self, request: Request<()>) -> ServiceResult<types::PublicKey> {
+        self.check_role(Role::ReadOnly)?;
         log::debug!("get_wireguard_key");
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::GetWireguardKey(tx))?;
@@ -592,7 +1145,8 @@ impl ManagementService for ManagementServiceImpl {
         }
     }
 
-    async fn verify_wireguard_key(&self, _: Request<()>) -> ServiceResult<bool> {
+    async fn verify_wireguard_key(&self, request: Request<()>) -> ServiceResult<bool> {
+        self.check_role(Role::ReadOnly)?;
         log::debug!("verify_wireguard_key");
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::VerifyWireguardKey(tx))?;
@@ -601,13 +1155,63 @@ impl ManagementService for ManagementServiceImpl {
             .map(Response::new)
     }
 
+    async fn list_wireguard_keys(
+        &self,
+        request: Request<()>,
+    ) -> ServiceResult<types::WireguardKeyList> {
+        self.check_role(Role::Operator)?;
+        log::debug!("list_wireguard_keys");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ListWireguardKeys(tx))?;
+        let keys = rx
+            .await
+            .map_err(|_| Status::internal("internal error"))?
+            .map_err(map_wireguard_key_error)?;
+        Ok(Response::new(types::WireguardKeyList {
+            keys: keys.iter().map(convert_registered_key).collect(),
+        }))
+    }
+
+    async fn set_wireguard_key_label(&self, request: Request<String>) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
+        let label = request.into_inner();
+        log::debug!("set_wireguard_key_label({})", label);
+        let label = if label.is_empty() { None } else { Some(label) };
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetWireguardKeyLabel(tx, label))?;
+        rx.await
+            .map_err(|_| Status::internal("internal error"))?
+            .map(Response::new)
+            .map_err(map_wireguard_key_error)
+    }
+
+    async fn revoke_wireguard_key(&self, request: Request<types::PublicKey>) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
+        let public_key = request.into_inner();
+        if public_key.key.len() != 32 {
+            return Err(Status::invalid_argument("invalid public key"));
+        }
+        let mut key = [0; 32];
+        key.copy_from_slice(&public_key.key);
+        let key = talpid_types::net::wireguard::PublicKey::from(key);
+        log::debug!("revoke_wireguard_key({})", key);
+
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::RevokeWireguardKey(tx, key))?;
+        rx.await
+            .map_err(|_| Status::internal("internal error"))?
+            .map(Response::new)
+            .map_err(map_wireguard_key_error)
+    }
+
     // Split tunneling
     //
 
     async fn get_split_tunnel_processes(
         &self,
-        _: Request<()>,
+        request: Request<()>,
     ) -> ServiceResult<Self::GetSplitTunnelProcessesStream> {
+        self.check_role(Role::ReadOnly)?;
         #[cfg(target_os = "linux")]
         {
             log::debug!("get_split_tunnel_processes");
@@ -633,6 +1237,7 @@ impl ManagementService for ManagementServiceImpl {
 
     #[cfg(target_os = "linux")]
     async fn add_split_tunnel_process(&self, request: Request<i32>) -> ServiceResult<()> {
+        self.check_role(Role::Operator)?;
         let pid = request.into_inner();
         log::debug!("add_split_tunnel_process");
         let (tx, rx) = oneshot::channel();
@@ -642,12 +1247,14 @@ impl ManagementService for ManagementServiceImpl {
             .map(Response::new)
     }
     #[cfg(not(target_os = "linux"))]
-    async fn add_split_tunnel_process(&self, _: Request<i32>) -> ServiceResult<()> {
+    async fn add_split_tunnel_process(&self, request: Request<i32>) -> ServiceResult<()> {
+        self.check_role(Role::Operator)?;
         Ok(Response::new(()))
     }
 
     #[cfg(target_os = "linux")]
     async fn remove_split_tunnel_process(&self, request: Request<i32>) -> ServiceResult<()> {
+        self.check_role(Role::Operator)?;
         let pid = request.into_inner();
         log::debug!("remove_split_tunnel_process");
         let (tx, rx) = oneshot::channel();
@@ -657,11 +1264,13 @@ impl ManagementService for ManagementServiceImpl {
             .map(Response::new)
     }
     #[cfg(not(target_os = "linux"))]
-    async fn remove_split_tunnel_process(&self, _: Request<i32>) -> ServiceResult<()> {
+    async fn remove_split_tunnel_process(&self, request: Request<i32>) -> ServiceResult<()> {
+        self.check_role(Role::Operator)?;
         Ok(Response::new(()))
     }
 
-    async fn clear_split_tunnel_processes(&self, _: Request<()>) -> ServiceResult<()> {
+    async fn clear_split_tunnel_processes(&self, request: Request<()>) -> ServiceResult<()> {
+        self.check_role(Role::Operator)?;
         #[cfg(target_os = "linux")]
         {
             log::debug!("clear_split_tunnel_processes");
@@ -676,18 +1285,136 @@ impl ManagementService for ManagementServiceImpl {
             Ok(Response::new(()))
         }
     }
+
+    // Daemon logging
+    //
+
+    async fn get_log_levels(&self, request: Request<()>) -> ServiceResult<types::LogLevels> {
+        self.check_role(Role::ReadOnly)?;
+        log::debug!("get_log_levels");
+        let levels = logging::log_levels();
+        Ok(Response::new(types::LogLevels {
+            default_level: i32::from(convert_log_level(levels.default)),
+            modules: levels
+                .modules
+                .into_iter()
+                .map(|(module, level)| (module, i32::from(convert_log_level(level))))
+                .collect(),
+        }))
+    }
+
+    async fn set_log_level(&self, request: Request<types::LogLevelUpdate>) -> ServiceResult<()> {
+        self.check_role(Role::Admin)?;
+        log::debug!("set_log_level");
+        let update = request.into_inner();
+        let module = Some(update.module.as_str()).filter(|module| !module.is_empty());
+        if update.reset {
+            if module.is_none() {
+                return Err(Status::invalid_argument(
+                    "only the level of a specific module can be reset",
+                ));
+            }
+            logging::set_log_level(module, None);
+        } else {
+            let level = convert_proto_log_level(update.level)?;
+            logging::set_log_level(module, Some(level));
+        }
+        Ok(Response::new(()))
+    }
+
+    async fn stream_logs(
+        &self,
+        request: Request<types::StreamLogsRequest>,
+    ) -> ServiceResult<Self::StreamLogsStream> {
+        self.check_role(Role::Admin)?;
+        log::debug!("stream_logs");
+        let level = convert_proto_log_level(request.into_inner().level)?;
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let mut subscription = logging::subscribe(level);
+        let (forwarder, abort_registration) = AbortHandle::new_pair();
+        let forward_lines = async move {
+            while let Some(line) = subscription.next().await {
+                let line = types::LogLine {
+                    timestamp: Some(types::Timestamp {
+                        seconds: line.timestamp.timestamp(),
+                        nanos: line.timestamp.timestamp_subsec_nanos() as i32,
+                    }),
+                    level: i32::from(convert_log_level(line.level.to_level_filter())),
+                    target: line.target,
+                    message: line.message,
+                };
+                if tx.send(Ok(line)).is_err() {
+                    break;
+                }
+            }
+        };
+        tokio::spawn(Abortable::new(forward_lines, abort_registration));
+
+        Ok(Response::new(LogLineStream {
+            receiver: rx,
+            forwarder,
+        }))
+    }
 }
 
 impl ManagementServiceImpl {
-    /// Sends a command to the daemon and maps the error to an RPC error.
+    /// Fails unless the client has at least the `required` role.
+    fn role(&self) -> Option<Role> {
+        match self.peer {
+            Peer::Local(credentials) => self.access_policy.role(credentials),
+            Peer::Remote(role) => Some(role),
+        }
+    }
+
+    fn check_role(&self, required: Role) -> Result<(), Status> {
+        access_control::check_role(self.role(), required).map_err(|status| {
+            log::warn!("Denied request from {:?}: {}", self.peer, status.message());
+            status
+        })
+    }
+
+    /// Only admins, who may read the account history anyway, get to see the account token and
+    /// proxy credentials in the settings.
+    fn may_see_secrets(&self) -> bool {
+        self.role() == Some(Role::Admin)
+    }
+
+    fn convert_settings_for_peer(&self, settings: &Settings) -> types::Settings {
+        let mut settings = convert_settings(settings, &self.locked_settings);
+        if !self.may_see_secrets() {
+            redact_settings_secrets(&mut settings);
+        }
+        settings
+    }
+
+    /// Describes the client that sent a request, for the settings history.
+    fn client_description<T>(&self, request: &Request<T>) -> String {
+        match self.peer {
+            Peer::Local(Some(credentials)) => format!("uid {}", credentials.uid),
+            Peer::Local(None) => "local client".to_owned(),
+            Peer::Remote(_) => match request.remote_addr() {
+                Some(address) => format!("remote client {}", address),
+                None => "remote client".to_owned(),
+            },
+        }
+    }
+
     fn send_command_to_daemon(&self, command: DaemonCommand) -> Result<(), Status> {
         self.daemon_tx
             .send(command)
             .map_err(|_| Status::internal("internal error"))
     }
+
+    /// Sends a command that changes the settings on behalf of the described client.
+    fn send_command_as(&self, client: String, command: DaemonCommand) -> Result<(), Status> {
+        self.daemon_tx
+            .send_as(client, command)
+            .map_err(|_| Status::internal("internal error"))
+    }
 }
 
-fn convert_settings(settings: &Settings) -> types::Settings {
+fn convert_settings(settings: &Settings, locked_settings: &[String]) -> types::Settings {
     types::Settings {
         account_token: settings.get_account_token().unwrap_or_default(),
         relay_settings: Some(convert_relay_settings(&settings.get_relay_settings())),
@@ -698,6 +1425,46 @@ fn convert_settings(settings: &Settings) -> types::Settings {
         auto_connect: settings.auto_connect,
         tunnel_options: Some(convert_tunnel_options(&settings.tunnel_options)),
         show_beta_releases: settings.show_beta_releases,
+        tunnel_proxy: Some(convert_tunnel_proxy_settings(&settings.tunnel_proxy)),
+        schedule: settings
+            .schedule
+            .iter()
+            .map(convert_schedule_rule)
+            .collect(),
+        locked_fields: locked_settings.to_vec(),
+        api_access_methods: settings
+            .api_access_methods
+            .iter()
+            .map(convert_api_access_method)
+            .collect(),
+        account_expiry_notifications: settings.account_expiry_notifications.clone(),
+    }
+}
+
+/// Blanks the account token and removes all proxy credentials from `settings`.
+fn redact_settings_secrets(settings: &mut types::Settings) {
+    use types::{api_access_method::Type as AccessMethodType, bridge_settings::Type as BridgeType};
+
+    settings.account_token.clear();
+    if let Some(bridge_type) = settings
+        .bridge_settings
+        .as_mut()
+        .and_then(|bridge_settings| bridge_settings.r#type.as_mut())
+    {
+        match bridge_type {
+            BridgeType::Normal(_) | BridgeType::Local(_) => (),
+            BridgeType::Remote(proxy) => proxy.auth = None,
+            BridgeType::Socks5(proxy) => proxy.auth = None,
+            BridgeType::Shadowsocks(proxy) => proxy.password.clear(),
+        }
+    }
+    for method in &mut settings.api_access_methods {
+        match &mut method.r#type {
+            Some(AccessMethodType::Socks5(proxy)) | Some(AccessMethodType::Http(proxy)) => {
+                proxy.auth = None
+            }
+            Some(AccessMethodType::Direct(_)) | Some(AccessMethodType::Bridges(_)) | None => (),
+        }
     }
 }
 
@@ -819,6 +1586,7 @@ fn convert_relay_settings_update(
                             public_key: wireguard::PublicKey::from(public_key),
                             allowed_ips,
                             endpoint,
+                            psk: None,
                         },
                         ipv4_gateway,
                         ipv6_gateway,
@@ -1044,6 +1812,17 @@ fn convert_bridge_settings(settings: &BridgeSettings) -> types::BridgeSettings {
                     cipher: proxy_settings.cipher.clone(),
                 })
             }
+            net::openvpn::ProxySettings::Socks5(proxy_settings) => {
+                BridgeSettingType::Socks5(bridge_settings::Socks5ProxySettings {
+                    address: proxy_settings.address.to_string(),
+                    auth: proxy_settings.auth.as_ref().map(|auth| {
+                        bridge_settings::RemoteProxyAuth {
+                            username: auth.username.clone(),
+                            password: auth.password.clone(),
+                        }
+                    }),
+                })
+            }
         },
     };
 
@@ -1072,6 +1851,32 @@ fn convert_wireguard_key_event(
     }
 }
 
+fn convert_history_entry(entry: &HistoryEntry) -> types::SettingsHistoryEntry {
+    let encode_value = |value: &Option<serde_json::Value>| {
+        value
+            .as_ref()
+            .map(|value| value.to_string())
+            .unwrap_or_default()
+    };
+    types::SettingsHistoryEntry {
+        revision: entry.revision,
+        timestamp: Some(types::Timestamp {
+            seconds: entry.timestamp.timestamp(),
+            nanos: 0,
+        }),
+        client: entry.client.clone(),
+        changes: entry
+            .changes
+            .iter()
+            .map(|change| types::SettingsChange {
+                field: change.field.clone(),
+                old_value: encode_value(&change.old_value),
+                new_value: encode_value(&change.new_value),
+            })
+            .collect(),
+    }
+}
+
 fn convert_public_key(public_key: &wireguard::PublicKey) -> types::PublicKey {
     types::PublicKey {
         key: public_key.key.as_bytes().to_vec(),
@@ -1082,6 +1887,15 @@ fn convert_public_key(public_key: &wireguard::PublicKey) -> types::PublicKey {
     }
 }
 
+fn convert_registered_key(key: &wireguard::RegisteredKey) -> types::RegisteredWireguardKey {
+    types::RegisteredWireguardKey {
+        key: key.pubkey.as_bytes().to_vec(),
+        ipv4_address: key.ipv4_address.to_string(),
+        ipv6_address: key.ipv6_address.to_string(),
+        label: key.label.clone().unwrap_or_default(),
+    }
+}
+
 fn convert_location_constraint(
     location: &Constraint<LocationConstraint>,
 ) -> Option<types::RelayLocation> {
@@ -1134,13 +1948,189 @@ fn convert_tunnel_options(options: &TunnelOptions) -> types::TunnelOptions {
                 .wireguard
                 .automatic_rotation
                 .map(|interval| RotationInterval { interval }),
+            quantum_resistant: options.wireguard.quantum_resistant,
+            connectivity_check: Some(convert_connectivity_check_options(
+                &options.wireguard.connectivity_check,
+            )),
         }),
         generic: Some(types::tunnel_options::GenericOptions {
             enable_ipv6: options.generic.enable_ipv6,
         }),
+        relay_rotation: options
+            .relay_rotation_interval
+            .map(|interval| types::tunnel_options::RelayRotationInterval { interval }),
+    }
+}
+
+fn convert_connectivity_check_options(
+    options: &talpid_types::net::wireguard::ConnectivityCheckOptions,
+) -> types::ConnectivityCheckOptions {
+    types::ConnectivityCheckOptions {
+        rx_timeout: options.rx_timeout,
+        traffic_timeout: options.traffic_timeout,
+        ping_timeout: options.ping_timeout,
+        ping_interval: options.ping_interval,
+        ping_target: options
+            .ping_target
+            .map(|target| target.to_string())
+            .unwrap_or_default(),
+    }
+}
+
+fn convert_log_level(level: log::LevelFilter) -> types::LogLevel {
+    match level {
+        log::LevelFilter::Off => types::LogLevel::Off,
+        log::LevelFilter::Error => types::LogLevel::Error,
+        log::LevelFilter::Warn => types::LogLevel::Warn,
+        log::LevelFilter::Info => types::LogLevel::Info,
+        log::LevelFilter::Debug => types::LogLevel::Debug,
+        log::LevelFilter::Trace => types::LogLevel::Trace,
+    }
+}
+
+fn convert_proto_log_level(level: i32) -> Result<log::LevelFilter, Status> {
+    match types::LogLevel::from_i32(level) {
+        Some(types::LogLevel::Off) => Ok(log::LevelFilter::Off),
+        Some(types::LogLevel::Error) => Ok(log::LevelFilter::Error),
+        Some(types::LogLevel::Warn) => Ok(log::LevelFilter::Warn),
+        Some(types::LogLevel::Info) => Ok(log::LevelFilter::Info),
+        Some(types::LogLevel::Debug) => Ok(log::LevelFilter::Debug),
+        Some(types::LogLevel::Trace) => Ok(log::LevelFilter::Trace),
+        None => Err(Status::invalid_argument("unknown log level")),
+    }
+}
+
+fn convert_tunnel_proxy_settings(settings: &TunnelProxySettings) -> types::TunnelProxySettings {
+    types::TunnelProxySettings {
+        enabled: settings.enabled,
+        bind_address: settings.bind_address.to_string(),
+    }
+}
+
+fn convert_api_access_method(method: &AccessMethod) -> types::ApiAccessMethod {
+    use types::api_access_method::{self, Type as AccessMethodType};
+
+    let convert_proxy = |proxy: &CustomProxy| api_access_method::CustomProxy {
+        address: proxy.address.to_string(),
+        auth: proxy
+            .auth
+            .as_ref()
+            .map(|auth| types::bridge_settings::RemoteProxyAuth {
+                username: auth.username.clone(),
+                password: auth.password.clone(),
+            }),
+    };
+
+    let method = match method {
+        AccessMethod::Direct => AccessMethodType::Direct(api_access_method::Direct {}),
+        AccessMethod::Bridges => AccessMethodType::Bridges(api_access_method::Bridges {}),
+        AccessMethod::Socks5(proxy) => AccessMethodType::Socks5(convert_proxy(proxy)),
+        AccessMethod::Http(proxy) => AccessMethodType::Http(convert_proxy(proxy)),
+    };
+
+    types::ApiAccessMethod {
+        r#type: Some(method),
+    }
+}
+
+fn convert_proto_api_access_method(
+    method: &types::ApiAccessMethod,
+) -> Result<AccessMethod, Status> {
+    use talpid_types::net::openvpn::ProxyAuth;
+    use types::api_access_method::{CustomProxy as ProtoCustomProxy, Type as AccessMethodType};
+
+    let convert_proxy = |proxy: &ProtoCustomProxy| -> Result<CustomProxy, Status> {
+        Ok(CustomProxy {
+            address: proxy
+                .address
+                .parse()
+                .map_err(|_| Status::invalid_argument("failed to parse proxy address"))?,
+            auth: proxy.auth.as_ref().map(|auth| ProxyAuth {
+                username: auth.username.clone(),
+                password: auth.password.clone(),
+            }),
+        })
+    };
+
+    match method
+        .r#type
+        .as_ref()
+        .ok_or(Status::invalid_argument("missing API access method"))?
+    {
+        AccessMethodType::Direct(_) => Ok(AccessMethod::Direct),
+        AccessMethodType::Bridges(_) => Ok(AccessMethod::Bridges),
+        AccessMethodType::Socks5(proxy) => Ok(AccessMethod::Socks5(convert_proxy(proxy)?)),
+        AccessMethodType::Http(proxy) => Ok(AccessMethod::Http(convert_proxy(proxy)?)),
+    }
+}
+
+const WEEKDAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+fn convert_schedule_rule(rule: &ScheduleRule) -> types::ScheduleRule {
+    use types::schedule_rule::Action;
+
+    let (action, location) = match &rule.action {
+        ScheduleAction::Connect => (Action::Connect, None),
+        ScheduleAction::Disconnect => (Action::Disconnect, None),
+        ScheduleAction::SetLocation(location) => (
+            Action::SetLocation,
+            convert_location_constraint(&Constraint::Only(location.clone())),
+        ),
+    };
+    types::ScheduleRule {
+        id: rule.id,
+        weekdays: rule
+            .weekdays
+            .iter()
+            .map(|day| day.num_days_from_monday())
+            .collect(),
+        hour: rule.time.hour(),
+        minute: rule.time.minute(),
+        action: i32::from(action),
+        location,
     }
 }
 
+fn convert_proto_schedule_rule(rule: types::ScheduleRule) -> Result<ScheduleRule, Status> {
+    use types::schedule_rule::Action;
+
+    let weekdays = rule
+        .weekdays
+        .iter()
+        .map(|day| {
+            WEEKDAYS
+                .get(*day as usize)
+                .cloned()
+                .ok_or_else(|| Status::invalid_argument("invalid day of the week"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let time = NaiveTime::from_hms_opt(rule.hour, rule.minute, 0)
+        .ok_or_else(|| Status::invalid_argument("invalid time of day"))?;
+    let action = match Action::from_i32(rule.action) {
+        Some(Action::Connect) => ScheduleAction::Connect,
+        Some(Action::Disconnect) => ScheduleAction::Disconnect,
+        Some(Action::SetLocation) => match rule.location.map(convert_proto_location) {
+            Some(Constraint::Only(location)) => ScheduleAction::SetLocation(location),
+            _ => return Err(Status::invalid_argument("a location must be specified")),
+        },
+        None => return Err(Status::invalid_argument("unknown action")),
+    };
+    Ok(ScheduleRule {
+        id: rule.id,
+        weekdays,
+        time,
+        action,
+    })
+}
+
 fn convert_relay_list_country(country: &RelayListCountry) -> types::RelayListCountry {
     let mut proto_country = types::RelayListCountry {
         name: country.name.clone(),
@@ -1320,6 +2310,7 @@ fn convert_state(state: TunnelState) -> types::TunnelState {
                     ErrorStateCause::TapAdapterProblem => {
                         i32::from(ProtoErrorCause::TapAdapterProblem)
                     }
+                    ErrorStateCause::AccountExpired => i32::from(ProtoErrorCause::AccountExpired),
                     #[cfg(target_os = "android")]
                     ErrorStateCause::VpnPermissionDenied => {
                         i32::from(ProtoErrorCause::VpnPermissionDenied)
@@ -1386,6 +2377,7 @@ fn convert_endpoint(endpoint: talpid_types::net::TunnelEndpoint) -> types::Tunne
             },
             proxy_type: match proxy_ep.proxy_type {
                 net::proxy::ProxyType::Shadowsocks => i32::from(types::ProxyType::Shadowsocks),
+                net::proxy::ProxyType::Socks5 => i32::from(types::ProxyType::Socks5),
                 net::proxy::ProxyType::Custom => i32::from(types::ProxyType::Custom),
             },
         }),
@@ -1432,7 +2424,8 @@ fn convert_proto_location(location: types::RelayLocation) -> Constraint<Location
 }
 
 pub struct ManagementInterfaceServer {
-    subscriptions: Arc<RwLock<Vec<EventsListenerSender>>>,
+    subscriptions: Arc<RwLock<EventSubscriptions>>,
+    locked_settings: Arc<Vec<String>>,
     socket_path: String,
     server_abort_tx: triggered::Trigger,
     server_join_handle: Option<
@@ -1441,8 +2434,14 @@ pub struct ManagementInterfaceServer {
 }
 
 impl ManagementInterfaceServer {
-    pub async fn start(tunnel_tx: DaemonCommandSender) -> Result<Self, Error> {
-        let subscriptions = Arc::<RwLock<Vec<EventsListenerSender>>>::default();
+    pub async fn start(
+        tunnel_tx: DaemonCommandSender,
+        access_policy: AccessPolicy,
+        locked_settings: Vec<String>,
+        remote_server: Option<(RemoteServerConfig, Role)>,
+    ) -> Result<Self, Error> {
+        let subscriptions = Arc::<RwLock<EventSubscriptions>>::default();
+        let locked_settings = Arc::new(locked_settings);
 
         let socket_path = mullvad_paths::get_rpc_socket_path()
             .to_string_lossy()
@@ -1453,9 +2452,37 @@ impl ManagementInterfaceServer {
         let server = ManagementServiceImpl {
             daemon_tx: tunnel_tx,
             subscriptions: subscriptions.clone(),
+            access_policy,
+            locked_settings: locked_settings.clone(),
+            peer: Peer::Local(None),
         };
+        if let Some((remote_config, remote_role)) = remote_server {
+            log::info!(
+                "Remote management interface listening on {}",
+                remote_config.address
+            );
+            let remote_server = mullvad_management_interface::spawn_remote_rpc_server(
+                ManagementServiceImpl {
+                    peer: Peer::Remote(remote_role),
+                    ..server.clone()
+                },
+                remote_config,
+                server_abort_rx.clone(),
+            );
+            tokio::spawn(async move {
+                if let Err(error) = remote_server.await {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Remote management interface failed")
+                    );
+                }
+            });
+        }
         let server_join_handle = tokio::spawn(mullvad_management_interface::spawn_rpc_server(
-            server,
+            move |credentials| ManagementServiceImpl {
+                peer: Peer::Local(credentials),
+                ..server.clone()
+            },
             start_tx,
             server_abort_rx,
         ));
@@ -1470,6 +2497,7 @@ impl ManagementInterfaceServer {
 
         Ok(ManagementInterfaceServer {
             subscriptions,
+            locked_settings,
             socket_path,
             server_abort_tx,
             server_join_handle: Some(server_join_handle),
@@ -1483,6 +2511,7 @@ impl ManagementInterfaceServer {
     pub fn event_broadcaster(&self) -> ManagementInterfaceEventBroadcaster {
         ManagementInterfaceEventBroadcaster {
             subscriptions: self.subscriptions.clone(),
+            locked_settings: self.locked_settings.clone(),
             close_handle: self.server_abort_tx.clone(),
         }
     }
@@ -1501,24 +2530,39 @@ impl ManagementInterfaceServer {
 /// A handle that allows broadcasting messages to all subscribers of the management interface.
 #[derive(Clone)]
 pub struct ManagementInterfaceEventBroadcaster {
-    subscriptions: Arc<RwLock<Vec<EventsListenerSender>>>,
+    subscriptions: Arc<RwLock<EventSubscriptions>>,
+    locked_settings: Arc<Vec<String>>,
     close_handle: triggered::Trigger,
 }
 
 impl EventListener for ManagementInterfaceEventBroadcaster {
     /// Sends a new state update to all `new_state` subscribers of the management interface.
     fn notify_new_state(&self, new_state: TunnelState) {
-        self.notify(types::DaemonEvent {
-            event: Some(daemon_event::Event::TunnelState(convert_state(new_state))),
-        })
+        let details = serde_json::json!({ "tunnel_state": new_state });
+        self.notify(
+            types::DaemonEvent {
+                sequence_number: 0,
+                stream_id: 0,
+                event: Some(daemon_event::Event::TunnelState(convert_state(new_state))),
+            },
+            details,
+        )
     }
 
     /// Sends settings to all `settings` subscribers of the management interface.
     fn notify_settings(&self, settings: Settings) {
         log::debug!("Broadcasting new settings");
-        self.notify(types::DaemonEvent {
-            event: Some(daemon_event::Event::Settings(convert_settings(&settings))),
-        })
+        self.notify(
+            types::DaemonEvent {
+                sequence_number: 0,
+                stream_id: 0,
+                event: Some(daemon_event::Event::Settings(convert_settings(
+                    &settings,
+                    &self.locked_settings,
+                ))),
+            },
+            serde_json::json!({ "settings": diagnostics::redacted_settings_value(&settings) }),
+        )
     }
 
     /// Sends relays to all subscribers of the management interface.
@@ -1531,33 +2575,171 @@ impl EventListener for ManagementInterfaceEventBroadcaster {
         for country in &relay_list.countries {
             new_list.countries.push(convert_relay_list_country(country));
         }
-        self.notify(types::DaemonEvent {
-            event: Some(daemon_event::Event::RelayList(new_list)),
-        })
+        // The relay list itself is large, and its changes are described by relay list diff events
+        self.notify(
+            types::DaemonEvent {
+                sequence_number: 0,
+                stream_id: 0,
+                event: Some(daemon_event::Event::RelayList(new_list)),
+            },
+            serde_json::json!({ "relay_list": { "relays": relay_list.relays().count() } }),
+        )
+    }
+
+    fn notify_relay_list_diff(&self, diff: RelayListDiff) {
+        log::debug!("Broadcasting relay list diff");
+        let details = serde_json::json!({ "relay_list_diff": diff });
+        self.notify(
+            types::DaemonEvent {
+                sequence_number: 0,
+                stream_id: 0,
+                event: Some(daemon_event::Event::RelayListDiff(types::RelayListDiff {
+                    added: diff.added,
+                    removed: diff.removed,
+                    deactivated: diff.deactivated,
+                })),
+            },
+            details,
+        )
     }
 
     fn notify_app_version(&self, app_version_info: version::AppVersionInfo) {
         log::debug!("Broadcasting new app version info");
         let new_info = convert_version_info(&app_version_info);
-        self.notify(types::DaemonEvent {
-            event: Some(daemon_event::Event::VersionInfo(new_info)),
-        })
+        self.notify(
+            types::DaemonEvent {
+                sequence_number: 0,
+                stream_id: 0,
+                event: Some(daemon_event::Event::VersionInfo(new_info)),
+            },
+            serde_json::json!({ "version_info": app_version_info }),
+        )
     }
 
     fn notify_key_event(&self, key_event: mullvad_types::wireguard::KeygenEvent) {
         log::debug!("Broadcasting new wireguard key event");
         let new_event = convert_wireguard_key_event(&key_event);
-        self.notify(types::DaemonEvent {
-            event: Some(daemon_event::Event::KeyEvent(new_event)),
-        })
+        self.notify(
+            types::DaemonEvent {
+                sequence_number: 0,
+                stream_id: 0,
+                event: Some(daemon_event::Event::KeyEvent(new_event)),
+            },
+            serde_json::json!({ "key_event": key_event }),
+        )
+    }
+
+    fn notify_account_expiry(&self, expiry: DateTime<Utc>) {
+        log::debug!("Broadcasting new account expiry");
+        self.notify(
+            types::DaemonEvent {
+                sequence_number: 0,
+                stream_id: 0,
+                event: Some(daemon_event::Event::AccountExpiry(
+                    types::AccountExpiryEvent {
+                        expiry: Some(types::Timestamp {
+                            seconds: expiry.timestamp(),
+                            nanos: 0,
+                        }),
+                    },
+                )),
+            },
+            serde_json::json!({ "account_expiry": { "expiry": expiry } }),
+        )
+    }
+
+    fn notify_account_expires_soon(&self, expiry: DateTime<Utc>, threshold_hours: u32) {
+        log::debug!("Broadcasting account expiry notification");
+        self.notify(
+            types::DaemonEvent {
+                sequence_number: 0,
+                stream_id: 0,
+                event: Some(daemon_event::Event::AccountExpiryNotification(
+                    types::AccountExpiryNotification {
+                        expiry: Some(types::Timestamp {
+                            seconds: expiry.timestamp(),
+                            nanos: 0,
+                        }),
+                        threshold_hours,
+                    },
+                )),
+            },
+            serde_json::json!({
+                "account_expiry_notification": {
+                    "expiry": expiry,
+                    "threshold_hours": threshold_hours,
+                }
+            }),
+        )
+    }
+
+    fn notify_connectivity_check_failure(&self, relay: Option<String>) {
+        log::debug!("Broadcasting connectivity check failure");
+        let details = serde_json::json!({ "connectivity_check_failure": { "relay": relay } });
+        self.notify(
+            types::DaemonEvent {
+                sequence_number: 0,
+                stream_id: 0,
+                event: Some(daemon_event::Event::ConnectivityCheckFailure(
+                    types::ConnectivityCheckFailure {
+                        relay: relay.unwrap_or_default(),
+                    },
+                )),
+            },
+            details,
+        )
+    }
+
+    fn notify_retry_attempt(
+        &self,
+        attempt: u32,
+        relay: Option<String>,
+        endpoint: talpid_types::net::TunnelEndpoint,
+    ) {
+        log::debug!("Broadcasting retry attempt {}", attempt);
+        let details = serde_json::json!({
+            "retry_attempt": {
+                "attempt": attempt,
+                "relay": relay,
+                "endpoint": endpoint,
+            }
+        });
+        self.notify(
+            types::DaemonEvent {
+                sequence_number: 0,
+                stream_id: 0,
+                event: Some(daemon_event::Event::RetryAttempt(types::RetryAttempt {
+                    attempt,
+                    relay: relay.unwrap_or_default(),
+                    endpoint: Some(convert_endpoint(endpoint)),
+                })),
+            },
+            details,
+        )
+    }
+
+    fn notify_split_tunnel_processes(&self, pids: Vec<i32>) {
+        log::debug!("Broadcasting split tunnel processes");
+        let details = serde_json::json!({ "split_tunnel": { "pids": pids } });
+        self.notify(
+            types::DaemonEvent {
+                sequence_number: 0,
+                stream_id: 0,
+                event: Some(daemon_event::Event::SplitTunnel(
+                    types::SplitTunnelProcesses { pids },
+                )),
+            },
+            details,
+        )
     }
 }
 
 impl ManagementInterfaceEventBroadcaster {
-    fn notify(&self, value: types::DaemonEvent) {
+    /// Sends an event to all listeners. `details` describes the event in diagnostics.
+    fn notify(&self, value: types::DaemonEvent, details: serde_json::Value) {
         let mut subscriptions = self.subscriptions.write();
         // TODO: using write-lock everywhere. use a mutex instead?
-        subscriptions.retain(|tx| tx.send(Ok(value.clone())).is_ok());
+        subscriptions.broadcast(value, details);
     }
 }
 
@@ -1567,7 +2749,45 @@ impl Drop for ManagementInterfaceEventBroadcaster {
     }
 }
 
-// Converts a REST API error for an account into a tonic status.
+fn map_settings_error(error: crate::settings::Error) -> Status {
+    use crate::settings::Error;
+    match error {
+        Error::InvalidSettings(_) => Status::invalid_argument("invalid settings"),
+        Error::LockedSetting(field) => Status::failed_precondition(format!(
+            "the setting \"{}\" is locked by the settings policy",
+            field
+        )),
+        Error::QuantumResistanceUnsupported => {
+            Status::unimplemented("quantum-resistant tunnels are not supported on this platform")
+        }
+        Error::HistoryError(crate::settings_history::Error::UnknownRevision(revision)) => {
+            Status::not_found(format!(
+                "revision {} is not in the settings history",
+                revision
+            ))
+        }
+        Error::HistoryError(_) => Status::failed_precondition("unable to roll back settings"),
+        _ => Status::internal("internal error"),
+    }
+}
+
+// Converts an error from managing WireGuard keys into a tonic status.
+fn map_wireguard_key_error(error: crate::wireguard::Error) -> Status {
+    use crate::wireguard::Error;
+    match error {
+        Error::NoAccountToken => Status::failed_precondition("no account token is set"),
+        Error::RevokeCurrentKey => {
+            Status::invalid_argument("the key is in use by this device; regenerate it instead")
+        }
+        Error::TooManyKeys => Status::resource_exhausted("too many keys"),
+        Error::RestError(RestError::ApiError(status, _)) if status == StatusCode::NOT_FOUND => {
+            Status::not_found("the key is not registered on the account")
+        }
+        Error::RestError(error) => map_rest_account_error(error),
+        Error::AccountHistoryError(_) => Status::internal("internal error"),
+    }
+}
+
 fn map_rest_account_error(error: RestError) -> Status {
     match error {
         RestError::ApiError(status, message)
@@ -1578,3 +2798,198 @@ fn map_rest_account_error(error: RestError) -> Status {
         _ => Status::internal("internal error"),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn service(peer: Peer) -> ManagementServiceImpl {
+        let (daemon_tx, _) = futures::channel::mpsc::unbounded();
+        ManagementServiceImpl {
+            daemon_tx: DaemonCommandSender(Arc::new(daemon_tx)),
+            subscriptions: Default::default(),
+            access_policy: AccessPolicy::default(),
+            locked_settings: Default::default(),
+            peer,
+        }
+    }
+
+    #[test]
+    fn test_remote_client_gets_configured_role() {
+        // The default policy makes every local client an admin. Remote clients used to be treated
+        // as local ones with the credentials encoded in their address, so a remote client
+        // connecting from fd6d:756c:7661:6400::/64 could claim to be root.
+        let remote = service(Peer::Remote(Role::ReadOnly));
+        assert!(remote.check_role(Role::ReadOnly).is_ok());
+        let status = remote.check_role(Role::Admin).unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+
+        let root = service(Peer::Local(Some(PeerCredentials { uid: 0, gid: 0 })));
+        assert!(root.check_role(Role::Admin).is_ok());
+    }
+
+    fn version_event() -> types::DaemonEvent {
+        types::DaemonEvent {
+            sequence_number: 0,
+            stream_id: 0,
+            event: Some(daemon_event::Event::VersionInfo(
+                types::AppVersionInfo::default(),
+            )),
+        }
+    }
+
+    #[test]
+    fn test_resume_event_stream() {
+        let mut subscriptions = EventSubscriptions::default();
+        for _ in 0..RECENT_EVENTS_CAPACITY + 5 {
+            subscriptions.broadcast(version_event(), serde_json::Value::Null);
+        }
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let listener = EventsListener {
+            tx,
+            include_secrets: false,
+        };
+        let stream_id = subscriptions.stream_id;
+        subscriptions
+            .subscribe(listener, stream_id, RECENT_EVENTS_CAPACITY as u64 + 2)
+            .unwrap();
+        subscriptions.broadcast(version_event(), serde_json::Value::Null);
+
+        let mut received = Vec::new();
+        while let Ok(event) = rx.try_recv() {
+            received.push(event.unwrap().sequence_number);
+        }
+        let expected: Vec<u64> =
+            (RECENT_EVENTS_CAPACITY as u64 + 3..=RECENT_EVENTS_CAPACITY as u64 + 6).collect();
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn test_resume_requires_matching_stream() {
+        let mut subscriptions = EventSubscriptions::default();
+        subscriptions.broadcast(version_event(), serde_json::Value::Null);
+        let stream_id = subscriptions.stream_id;
+
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let listener = EventsListener {
+            tx,
+            include_secrets: false,
+        };
+        let status = subscriptions
+            .subscribe(listener, stream_id.wrapping_add(1), 1)
+            .unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert!(subscriptions.listeners.is_empty());
+
+        // Listeners that do not resume do not need to know the stream
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let listener = EventsListener {
+            tx,
+            include_secrets: false,
+        };
+        subscriptions.subscribe(listener, 0, 0).unwrap();
+        subscriptions.broadcast(version_event(), serde_json::Value::Null);
+        assert_eq!(rx.try_recv().unwrap().unwrap().stream_id, stream_id);
+    }
+
+    #[test]
+    fn test_ring_buffer_drops_oldest_events() {
+        let mut subscriptions = EventSubscriptions::default();
+        for _ in 0..RECENT_EVENTS_CAPACITY + 1 {
+            subscriptions.broadcast(version_event(), serde_json::Value::Null);
+        }
+        assert_eq!(subscriptions.recent_events.len(), RECENT_EVENTS_CAPACITY);
+        assert_eq!(subscriptions.recent_events[0].event.sequence_number, 2);
+    }
+
+    fn settings_with_secrets() -> Settings {
+        use talpid_types::net::openvpn::{ProxyAuth, ProxySettings, Socks5ProxySettings};
+
+        let auth = ProxyAuth {
+            username: "secret-username".to_owned(),
+            password: "secret-password".to_owned(),
+        };
+        let mut settings = Settings::default();
+        settings.set_account_token(Some("1234567890123456".to_owned()));
+        settings.bridge_settings =
+            BridgeSettings::Custom(ProxySettings::Socks5(Socks5ProxySettings {
+                address: "192.0.2.1:1080".parse().unwrap(),
+                auth: Some(auth.clone()),
+            }));
+        settings.api_access_methods = vec![AccessMethod::Http(CustomProxy {
+            address: "192.0.2.1:8080".parse().unwrap(),
+            auth: Some(auth),
+        })];
+        settings
+    }
+
+    fn contains_secrets(settings: &types::Settings) -> bool {
+        let settings = format!("{:?}", settings);
+        settings.contains("1234567890123456") || settings.contains("secret-")
+    }
+
+    #[test]
+    fn test_read_only_client_does_not_get_secrets() {
+        let settings = settings_with_secrets();
+
+        let read_only = service(Peer::Remote(Role::ReadOnly));
+        assert!(!contains_secrets(
+            &read_only.convert_settings_for_peer(&settings)
+        ));
+        let operator = service(Peer::Remote(Role::Operator));
+        assert!(!contains_secrets(
+            &operator.convert_settings_for_peer(&settings)
+        ));
+        let admin = service(Peer::Remote(Role::Admin));
+        assert!(contains_secrets(
+            &admin.convert_settings_for_peer(&settings)
+        ));
+    }
+
+    #[test]
+    fn test_read_only_listener_does_not_get_secrets() {
+        let settings_event = types::DaemonEvent {
+            sequence_number: 0,
+            stream_id: 0,
+            event: Some(daemon_event::Event::Settings(convert_settings(
+                &settings_with_secrets(),
+                &[],
+            ))),
+        };
+        let received_secrets = |rx: &mut EventsListenerReceiver| {
+            let mut events = Vec::new();
+            while let Ok(event) = rx.try_recv() {
+                events.push(event.unwrap());
+            }
+            assert_eq!(events.len(), 2);
+            events.iter().any(|event| match &event.event {
+                Some(daemon_event::Event::Settings(settings)) => contains_secrets(settings),
+                _ => panic!("unexpected event"),
+            })
+        };
+
+        let mut subscriptions = EventSubscriptions::default();
+        let stream_id = subscriptions.stream_id;
+        subscriptions.broadcast(settings_event.clone(), serde_json::Value::Null);
+        subscriptions.broadcast(settings_event.clone(), serde_json::Value::Null);
+
+        // Both replayed and new events are redacted
+        let (tx, mut read_only_rx) = tokio::sync::mpsc::unbounded_channel();
+        let listener = EventsListener {
+            tx,
+            include_secrets: false,
+        };
+        subscriptions.subscribe(listener, stream_id, 1).unwrap();
+        let (tx, mut admin_rx) = tokio::sync::mpsc::unbounded_channel();
+        let listener = EventsListener {
+            tx,
+            include_secrets: true,
+        };
+        subscriptions.subscribe(listener, stream_id, 1).unwrap();
+        subscriptions.broadcast(settings_event, serde_json::Value::Null);
+
+        assert!(!received_secrets(&mut read_only_rx));
+        assert!(received_secrets(&mut admin_rx));
+    }
+}
@@ -0,0 +1,231 @@
+//! Settings that are enforced by an administrator.
+//!
+//! The policy file is a JSON object that maps fields of the settings to the values they are
+//! forced to. Fields are identified by their dot separated path in `settings.json`, for instance
+//! `tunnel_options.generic.enable_ipv6`. The values are applied when the daemon starts, and the
+//! fields are locked so that clients can't change them. The daemon never writes to the file.
+//!
+//! ```json
+//! {
+//!     "block_when_disconnected": true,
+//!     "allow_lan": false,
+//!     "relay_settings.normal.tunnel_protocol": { "only": "wireguard" },
+//!     "relay_settings.normal.location": { "only": { "country": "se" } }
+//! }
+//! ```
+//!
+//! Every field must exist in the settings and its value must be valid for it, otherwise the
+//! whole policy is rejected.
+//!
+//! On Unix, the file must be owned by root and must not be writable by anyone else. On Windows,
+//! it must be owned by SYSTEM or Built-in Administrators and must not be writable by anyone else.
+
+use mullvad_types::settings::Settings;
+use serde_json::{Map, Value};
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+use talpid_types::ErrorExt;
+
+const POLICY_FILE: &str = "policy.json";
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Unable to read policy file")]
+    ReadError(#[error(source)] io::Error),
+
+    #[cfg(unix)]
+    #[error(display = "The policy file must be owned by root and only be writable by root")]
+    InsecurePermissions,
+
+    #[cfg(windows)]
+    #[error(
+        display = "The policy file must be owned by SYSTEM or Administrators and only be writable by them"
+    )]
+    InsecurePermissions,
+
+    #[error(display = "Unable to parse policy file")]
+    ParseError(#[error(source)] serde_json::Error),
+
+    #[error(display = "Unable to serialize settings")]
+    SerializeError(#[error(source)] serde_json::Error),
+
+    #[error(display = "There is no setting called \"{}\"", _0)]
+    UnknownField(String),
+
+    #[error(display = "Invalid policy value for \"{}\"", _0)]
+    InvalidValue(String, #[error(source)] serde_json::Error),
+}
+
+/// Settings fields that are locked to the values given by the administrator.
+#[derive(Debug, Default, Clone)]
+pub struct SettingsPolicy {
+    fields: Vec<(String, Value)>,
+}
+
+impl SettingsPolicy {
+    /// Loads the policy from the settings directory. If there is no policy file, nothing is
+    /// locked.
+    pub fn load(settings_dir: &Path) -> Result<Self, Error> {
+        let path = settings_dir.join(POLICY_FILE);
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(error) => return Err(Error::ReadError(error)),
+        };
+        log::info!("Loading settings policy from {}", path.display());
+
+        #[cfg(unix)]
+        {
+            let metadata = file.metadata().map_err(Error::ReadError)?;
+            if metadata.uid() != 0 || metadata.mode() & 0o022 != 0 {
+                return Err(Error::InsecurePermissions);
+            }
+        }
+        #[cfg(windows)]
+        {
+            if !crate::settings::windows::is_admin_only_writable(&path).map_err(Error::ReadError)? {
+                return Err(Error::InsecurePermissions);
+            }
+        }
+
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).map_err(Error::ReadError)?;
+        Self::from_bytes(&bytes)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let fields: Map<String, Value> =
+            serde_json::from_slice(bytes).map_err(Error::ParseError)?;
+        let policy = SettingsPolicy {
+            fields: fields.into_iter().collect(),
+        };
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    /// Checks that every field exists in the settings and that its value is valid, by applying
+    /// the policy to the default settings.
+    fn validate(&self) -> Result<(), Error> {
+        let mut settings = Settings::default();
+        for (path, value) in &self.fields {
+            settings = Self::apply_field(&settings, path, value)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the paths of the locked fields.
+    pub fn locked_fields(&self) -> impl Iterator<Item = &str> {
+        self.fields.iter().map(|(path, _)| path.as_str())
+    }
+
+    /// Returns `settings` with the locked fields set to their policy values. Fields that can't
+    /// be applied are logged and skipped.
+    pub fn apply(&self, mut settings: Settings) -> Settings {
+        for (path, value) in &self.fields {
+            match Self::apply_field(&settings, path, value) {
+                Ok(new_settings) => settings = new_settings,
+                Err(error) => log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to apply settings policy")
+                ),
+            }
+        }
+        settings
+    }
+
+    fn apply_field(settings: &Settings, path: &str, value: &Value) -> Result<Settings, Error> {
+        let mut settings_value = serde_json::to_value(settings).map_err(Error::SerializeError)?;
+        let field = settings_value
+            .pointer_mut(&json_pointer(path))
+            .ok_or_else(|| Error::UnknownField(path.to_owned()))?;
+        *field = value.clone();
+        serde_json::from_value(settings_value)
+            .map_err(|error| Error::InvalidValue(path.to_owned(), error))
+    }
+
+    /// Returns the first locked field that differs between `old_settings` and `new_settings`.
+    pub fn changed_locked_field(
+        &self,
+        old_settings: &Settings,
+        new_settings: &Settings,
+    ) -> Option<&str> {
+        if self.fields.is_empty() {
+            return None;
+        }
+        let old_value = serde_json::to_value(old_settings).ok()?;
+        let new_value = serde_json::to_value(new_settings).ok()?;
+        self.locked_fields().find(|path| {
+            let pointer = json_pointer(path);
+            old_value.pointer(&pointer) != new_value.pointer(&pointer)
+        })
+    }
+}
+
+/// Converts a dot separated field path to a JSON pointer.
+fn json_pointer(path: &str) -> String {
+    path.split('.').map(|key| format!("/{}", key)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const POLICY: &[u8] = br#"{
+        "allow_lan": true,
+        "tunnel_options.generic.enable_ipv6": false
+    }"#;
+
+    #[test]
+    fn test_apply_policy() {
+        let policy = SettingsPolicy::from_bytes(POLICY).unwrap();
+        let mut settings = Settings::default();
+        settings.tunnel_options.generic.enable_ipv6 = true;
+
+        let settings = policy.apply(settings);
+        assert!(settings.allow_lan);
+        assert!(!settings.tunnel_options.generic.enable_ipv6);
+    }
+
+    #[test]
+    fn test_changed_locked_field() {
+        let policy = SettingsPolicy::from_bytes(POLICY).unwrap();
+        let old_settings = policy.apply(Settings::default());
+
+        let mut new_settings = old_settings.clone();
+        new_settings.auto_connect = !new_settings.auto_connect;
+        assert_eq!(
+            policy.changed_locked_field(&old_settings, &new_settings),
+            None
+        );
+
+        new_settings.tunnel_options.generic.enable_ipv6 = true;
+        assert_eq!(
+            policy.changed_locked_field(&old_settings, &new_settings),
+            Some("tunnel_options.generic.enable_ipv6")
+        );
+    }
+
+    #[test]
+    fn test_reject_unknown_field() {
+        let policy = br#"{ "allow_lan": true, "no_such_setting": 1 }"#;
+        match SettingsPolicy::from_bytes(policy) {
+            Err(Error::UnknownField(field)) => assert_eq!(field, "no_such_setting"),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_reject_invalid_value() {
+        let policy = br#"{ "allow_lan": "yes" }"#;
+        match SettingsPolicy::from_bytes(policy) {
+            Err(Error::InvalidValue(field, _)) => assert_eq!(field, "allow_lan"),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+}
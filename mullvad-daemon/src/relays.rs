@@ -8,7 +8,10 @@ use futures::{
     FutureExt, SinkExt, StreamExt,
 };
 use log::{debug, error, info, warn};
-use mullvad_rpc::{rest::MullvadRestHandle, RelayListProxy};
+use mullvad_rpc::{
+    relay_list::Error as RelayListError, rest::MullvadRestHandle, RelayListDocument,
+    RelayListProxy, RelayListPublicKey,
+};
 use mullvad_types::{
     endpoint::MullvadEndpoint,
     location::Location,
@@ -16,11 +19,14 @@ use mullvad_types::{
         BridgeState, Constraint, InternalBridgeConstraints, LocationConstraint, Match,
         OpenVpnConstraints, Provider, RelayConstraints, WireguardConstraints,
     },
-    relay_list::{OpenVpnEndpointData, Relay, RelayList, RelayTunnels, WireguardEndpointData},
+    relay_list::{
+        OpenVpnEndpointData, Relay, RelayList, RelayListDiff, RelayTunnels, WireguardEndpointData,
+    },
 };
 use parking_lot::Mutex;
 use rand::{self, rngs::ThreadRng, seq::SliceRandom, Rng};
 use std::{
+    collections::HashSet,
     future::Future,
     io,
     net::{IpAddr, SocketAddr},
@@ -33,10 +39,22 @@ use talpid_types::{
     net::{all_of_the_internet, openvpn::ProxySettings, wireguard, TransportProtocol, TunnelType},
     ErrorExt,
 };
-use tokio::fs::File;
+use tokio::{fs::File, io::AsyncWriteExt};
 
 const DATE_TIME_FORMAT_STR: &str = "%Y-%m-%d %H:%M:%S%.3f";
 const RELAYS_FILENAME: &str = "relays.json";
+/// Stores the ETag of the cached relay list, so that the list is only downloaded when it changed.
+const RELAYS_ETAG_FILENAME: &str = "relays.etag";
+/// Detached signature of a relay list file, stored next to it.
+const RELAYS_SIGNATURE_FILENAME: &str = "relays.json.sig";
+/// The key that relay lists are signed with, shipped in the resource directory. No relay list is
+/// used if it is missing.
+const RELAYS_PUBLIC_KEY_FILENAME: &str = "relays.pub";
+/// Optional, user-supplied relay list in the settings directory. Its relays are added to the
+/// downloaded ones. The file is not signed, since only root can write to the settings directory.
+const CUSTOM_RELAYS_FILENAME: &str = "custom-relays.json";
+/// Provider set on all relays from the custom relay list.
+const CUSTOM_RELAY_PROVIDER: &str = "custom";
 /// How often the updater should wake up to check the cache of the in-memory cache of relays.
 /// This check is very cheap. The only reason to not have it very often is because if downloading
 /// constantly fails it will try very often and fill the logs etc.
@@ -63,26 +81,123 @@ pub enum Error {
     #[error(display = "Failure in serialization of the relay list")]
     Serialize(#[error(source)] serde_json::Error),
 
+    #[error(display = "The relay list is not signed")]
+    UnsignedRelayList,
+
+    #[error(display = "The relay list signature is invalid")]
+    InvalidSignature,
+
+    #[error(display = "The relay list public key is invalid")]
+    InvalidPublicKey,
+
+    #[error(display = "The relay list public key could not be loaded")]
+    MissingPublicKey,
+
+    #[error(display = "Failed to read the relay list public key")]
+    ReadPublicKey(#[error(source)] io::Error),
+
+    #[error(display = "Failed to read the custom relay list")]
+    ReadCustomRelays(#[error(source)] io::Error),
+
+    #[error(display = "Failed to parse the custom relay list")]
+    ParseCustomRelays(#[error(source)] serde_json::Error),
+
+    #[error(display = "Invalid custom relay {}: {}", _0, _1)]
+    InvalidCustomRelay(String, &'static str),
+
     #[error(display = "Downloader already shut down")]
     DownloaderShutDown,
 }
 
+impl From<RelayListError> for Error {
+    fn from(error: RelayListError) -> Self {
+        match error {
+            RelayListError::MissingSignature => Error::UnsignedRelayList,
+            RelayListError::InvalidSignature => Error::InvalidSignature,
+            RelayListError::InvalidPublicKey => Error::InvalidPublicKey,
+            RelayListError::Parse(error) => Error::Serialize(error),
+        }
+    }
+}
+
+/// The key that relay lists have to be signed with.
+#[derive(Debug, Clone)]
+enum RelayListVerification {
+    /// Relay lists have to be signed with this key.
+    Enabled(RelayListPublicKey),
+    /// The public key is missing or could not be loaded, so no relay list is trusted.
+    MissingKey,
+}
+
+impl RelayListVerification {
+    fn load(path: &Path) -> Self {
+        match Self::read_public_key(path) {
+            Ok(public_key) => RelayListVerification::Enabled(public_key),
+            Err(error) => {
+                error!(
+                    "{}",
+                    error.display_chain_with_msg(
+                        "Unable to load relay list public key. No relay list will be used"
+                    )
+                );
+                RelayListVerification::MissingKey
+            }
+        }
+    }
+
+    fn read_public_key(path: &Path) -> Result<RelayListPublicKey, Error> {
+        let encoded_key = std::fs::read_to_string(path).map_err(Error::ReadPublicKey)?;
+        Ok(RelayListPublicKey::from_base64(&encoded_key)?)
+    }
+
+    /// Parses a relay list, after checking its signature.
+    fn parse(&self, document: &RelayListDocument) -> Result<RelayList, Error> {
+        match self {
+            RelayListVerification::Enabled(public_key) => Ok(document.verify(public_key)?),
+            RelayListVerification::MissingKey => Err(Error::MissingPublicKey),
+        }
+    }
+}
+
 struct ParsedRelays {
     last_updated: SystemTime,
+    etag: Option<String>,
+    /// The relay list from the API, or the bundled one.
+    downloaded_relays: RelayList,
+    /// The relays from the custom relay list file.
+    custom_relays: RelayList,
     locations: RelayList,
     relays: Vec<Relay>,
 }
 
 impl ParsedRelays {
     pub fn empty() -> Self {
-        ParsedRelays {
-            last_updated: time::UNIX_EPOCH,
+        Self::from_relay_list(RelayList::empty(), time::UNIX_EPOCH)
+    }
+
+    pub fn from_relay_list(relay_list: RelayList, last_updated: SystemTime) -> Self {
+        let mut parsed_relays = ParsedRelays {
+            last_updated,
+            etag: None,
+            downloaded_relays: relay_list,
+            custom_relays: RelayList::empty(),
             locations: RelayList::empty(),
             relays: Vec::new(),
-        }
+        };
+        parsed_relays.update_locations();
+        parsed_relays
     }
 
-    pub fn from_relay_list(relay_list: RelayList, last_updated: SystemTime) -> Self {
+    /// Replaces the custom relays that are added to the downloaded relay list.
+    pub fn set_custom_relays(&mut self, custom_relays: RelayList) {
+        self.custom_relays = custom_relays;
+        self.update_locations();
+    }
+
+    fn update_locations(&mut self) {
+        let mut relay_list = self.downloaded_relays.clone();
+        relay_list.merge(self.custom_relays_without_official());
+
         let mut relays = Vec::new();
         for country in &relay_list.countries {
             let country_name = country.name.clone();
@@ -106,27 +221,125 @@ impl ParsedRelays {
                 }
             }
         }
-        ParsedRelays {
-            last_updated,
-            locations: relay_list,
-            relays,
-        }
+        self.locations = relay_list;
+        self.relays = relays;
     }
 
-    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+    /// Reads a relay list and its detached signature from disk. The relay list is only used if
+    /// the signature is valid.
+    pub fn from_file(
+        path: impl AsRef<Path>,
+        verification: &RelayListVerification,
+    ) -> Result<Self, Error> {
         debug!("Reading relays from {}", path.as_ref().display());
-        let (last_modified, file) =
-            Self::open_file(path.as_ref()).map_err(Error::OpenRelayCache)?;
-        let relay_list =
-            serde_json::from_reader(io::BufReader::new(file)).map_err(Error::Serialize)?;
+        let (last_modified, document) =
+            Self::read_file(path.as_ref()).map_err(Error::OpenRelayCache)?;
+        let signature_path = path.as_ref().with_file_name(RELAYS_SIGNATURE_FILENAME);
+        let signature = match std::fs::read_to_string(signature_path) {
+            Ok(signature) => Some(signature),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => None,
+            Err(error) => return Err(Error::OpenRelayCache(error)),
+        };
+
+        let relay_list = verification.parse(&RelayListDocument {
+            document,
+            signature,
+            etag: None,
+        })?;
 
         Ok(Self::from_relay_list(relay_list, last_modified))
     }
 
-    fn open_file(path: &Path) -> io::Result<(SystemTime, std::fs::File)> {
-        let file = std::fs::File::open(path)?;
-        let last_modified = file.metadata()?.modified()?;
-        Ok((last_modified, file))
+    fn read_file(path: &Path) -> io::Result<(SystemTime, Vec<u8>)> {
+        let last_modified = std::fs::metadata(path)?.modified()?;
+        let document = std::fs::read(path)?;
+        Ok((last_modified, document))
+    }
+
+    /// Reads the custom relay list. All its relays are tagged with `CUSTOM_RELAY_PROVIDER`. A
+    /// missing file is the same as an empty list. The whole list is rejected if any relay in it
+    /// is invalid.
+    fn read_custom_relays(path: &Path) -> Result<RelayList, Error> {
+        let document = match std::fs::read(path) {
+            Ok(document) => document,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(RelayList::empty()),
+            Err(error) => return Err(Error::ReadCustomRelays(error)),
+        };
+        let mut relay_list: RelayList =
+            serde_json::from_slice(&document).map_err(Error::ParseCustomRelays)?;
+        Self::validate_custom_relays(&relay_list)?;
+        for city in relay_list
+            .countries
+            .iter_mut()
+            .flat_map(|country| country.cities.iter_mut())
+        {
+            for relay in &mut city.relays {
+                relay.provider = CUSTOM_RELAY_PROVIDER.to_string();
+            }
+        }
+        Ok(relay_list)
+    }
+
+    /// Checks that every custom relay has a unique hostname and at least one usable tunnel
+    /// endpoint.
+    fn validate_custom_relays(relay_list: &RelayList) -> Result<(), Error> {
+        let mut hostnames = HashSet::new();
+        for country in &relay_list.countries {
+            for city in &country.cities {
+                for relay in &city.relays {
+                    let invalid =
+                        |reason| Err(Error::InvalidCustomRelay(relay.hostname.clone(), reason));
+                    if relay.hostname.is_empty() {
+                        return invalid("the hostname is empty");
+                    }
+                    if country.code.is_empty() || city.code.is_empty() {
+                        return invalid("the country or city code is empty");
+                    }
+                    if !hostnames.insert(relay.hostname.as_str()) {
+                        return invalid("the hostname is used by another custom relay");
+                    }
+                    if relay.tunnels.openvpn.is_empty() && relay.tunnels.wireguard.is_empty() {
+                        return invalid("there are no tunnel endpoints");
+                    }
+                    let invalid_port_range = relay.tunnels.wireguard.iter().any(|endpoint| {
+                        endpoint.port_ranges.is_empty()
+                            || endpoint.port_ranges.iter().any(|(start, end)| start > end)
+                    });
+                    if invalid_port_range {
+                        return invalid("a WireGuard port range is empty or invalid");
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the custom relays, without the ones that have the hostname of a downloaded relay.
+    /// Those would otherwise hide the official relay.
+    fn custom_relays_without_official(&self) -> RelayList {
+        let official_hostnames: HashSet<&str> = self
+            .downloaded_relays
+            .relays()
+            .map(|relay| relay.hostname.as_str())
+            .collect();
+        let mut custom_relays = self.custom_relays.clone();
+        for city in custom_relays
+            .countries
+            .iter_mut()
+            .flat_map(|country| country.cities.iter_mut())
+        {
+            city.relays.retain(|relay| {
+                let is_official = official_hostnames.contains(relay.hostname.as_str());
+                if is_official {
+                    warn!(
+                        "Ignoring custom relay {}, since it has the hostname of a Mullvad relay",
+                        relay.hostname
+                    );
+                }
+                !is_official
+            });
+        }
+        custom_relays
     }
 
     pub fn last_updated(&self) -> SystemTime {
@@ -153,20 +366,33 @@ impl RelaySelector {
     /// to refresh the relay list from the internet.
     pub fn new(
         rpc_handle: MullvadRestHandle,
-        on_update: impl Fn(&RelayList) + Send + 'static,
+        on_update: impl Fn(&RelayList, &RelayListDiff) + Send + 'static,
         resource_dir: &Path,
         cache_dir: &Path,
+        settings_dir: &Path,
     ) -> Self {
         let cache_path = cache_dir.join(RELAYS_FILENAME);
+        let custom_relays_path = settings_dir.join(CUSTOM_RELAYS_FILENAME);
+        let etag_path = cache_dir.join(RELAYS_ETAG_FILENAME);
         let resource_path = resource_dir.join(RELAYS_FILENAME);
-        let unsynchronized_parsed_relays = Self::read_relays_from_disk(&cache_path, &resource_path)
-            .unwrap_or_else(|error| {
-                error!(
-                    "{}",
-                    error.display_chain_with_msg("Unable to load cached relays")
-                );
-                ParsedRelays::empty()
-            });
+        let verification =
+            RelayListVerification::load(&resource_dir.join(RELAYS_PUBLIC_KEY_FILENAME));
+        let mut unsynchronized_parsed_relays =
+            Self::read_relays_from_disk(&cache_path, &etag_path, &resource_path, &verification)
+                .unwrap_or_else(|error| {
+                    error!(
+                        "{}",
+                        error.display_chain_with_msg("Unable to load cached relays")
+                    );
+                    ParsedRelays::empty()
+                });
+        match ParsedRelays::read_custom_relays(&custom_relays_path) {
+            Ok(custom_relays) => unsynchronized_parsed_relays.set_custom_relays(custom_relays),
+            Err(error) => error!(
+                "{}",
+                error.display_chain_with_msg("Unable to load custom relays")
+            ),
+        }
         info!(
             "Initialized with {} cached relays from {}",
             unsynchronized_parsed_relays.relays().len(),
@@ -178,6 +404,9 @@ impl RelaySelector {
         let updater = RelayListUpdater::new(
             rpc_handle,
             cache_path,
+            etag_path,
+            custom_relays_path,
+            verification,
             parsed_relays.clone(),
             Box::new(on_update),
         );
@@ -190,7 +419,7 @@ impl RelaySelector {
         }
     }
 
-    /// Download the newest relay list.
+    /// Reload the custom relays and download the newest relay list.
     pub fn update(&mut self) -> impl Future<Output = ()> {
         let mut updater = self.updater.clone();
         async move {
@@ -208,13 +437,15 @@ impl RelaySelector {
     }
 
     /// Returns a random relay and relay endpoint matching the given constraints and with
-    /// preferences applied.
+    /// preferences applied. The relay with the hostname `avoided_relay` is only returned if no
+    /// other relay matches the constraints.
     pub fn get_tunnel_endpoint(
         &mut self,
         relay_constraints: &RelayConstraints,
         bridge_state: &BridgeState,
         retry_attempt: u32,
         wg_key_exists: bool,
+        avoided_relay: Option<&str>,
     ) -> Result<(Relay, MullvadEndpoint), Error> {
         let preferred_constraints = self.preferred_constraints(
             relay_constraints,
@@ -222,13 +453,16 @@ impl RelaySelector {
             retry_attempt,
             wg_key_exists,
         );
-        if let Some((relay, endpoint)) = self.get_tunnel_endpoint_internal(&preferred_constraints) {
+        if let Some((relay, endpoint)) =
+            self.get_tunnel_endpoint_internal(&preferred_constraints, avoided_relay)
+        {
             debug!(
                 "Relay matched on highest preference for retry attempt {}",
                 retry_attempt
             );
             Ok((relay, endpoint))
-        } else if let Some((relay, endpoint)) = self.get_tunnel_endpoint_internal(relay_constraints)
+        } else if let Some((relay, endpoint)) =
+            self.get_tunnel_endpoint_internal(relay_constraints, avoided_relay)
         {
             debug!(
                 "Relay matched on second preference for retry attempt {}",
@@ -372,6 +606,26 @@ impl RelaySelector {
         })
     }
 
+    /// Returns a random Shadowsocks bridge that can be used to reach the API.
+    pub fn get_api_bridge(&mut self) -> Option<ProxySettings> {
+        let constraints = InternalBridgeConstraints {
+            location: Constraint::Any,
+            provider: Constraint::Any,
+            transport_protocol: Constraint::Only(TransportProtocol::Tcp),
+        };
+        let matching_relays: Vec<Relay> = self
+            .parsed_relays
+            .lock()
+            .relays()
+            .iter()
+            .filter(|relay| relay.active)
+            .filter_map(|relay| Self::matching_bridge_relay(relay, &constraints))
+            .collect();
+
+        let relay = self.pick_random_relay(&matching_relays)?.clone();
+        self.pick_random_bridge(&relay)
+    }
+
     /// Returns preferred constraints
     #[allow(unused_variables)]
     fn preferred_tunnel_constraints(
@@ -448,8 +702,9 @@ impl RelaySelector {
     fn get_tunnel_endpoint_internal(
         &mut self,
         constraints: &RelayConstraints,
+        avoided_relay: Option<&str>,
     ) -> Option<(Relay, MullvadEndpoint)> {
-        let matching_relays: Vec<Relay> = self
+        let mut matching_relays: Vec<Relay> = self
             .parsed_relays
             .lock()
             .relays()
@@ -458,6 +713,10 @@ impl RelaySelector {
             .filter_map(|relay| Self::matching_relay(relay, constraints))
             .collect();
 
+        if let Some(avoided_relay) = avoided_relay {
+            matching_relays = Self::avoid_relay(matching_relays, avoided_relay);
+        }
+
         self.pick_random_relay(&matching_relays)
             .and_then(|selected_relay| {
                 info!(
@@ -469,6 +728,25 @@ impl RelaySelector {
             })
     }
 
+    /// Removes the relay with the hostname `avoided_relay` from `relays`, unless no other relay
+    /// could be selected.
+    fn avoid_relay(relays: Vec<Relay>, avoided_relay: &str) -> Vec<Relay> {
+        let other_relays: Vec<Relay> = relays
+            .iter()
+            .filter(|relay| relay.hostname != avoided_relay)
+            .cloned()
+            .collect();
+        if other_relays.iter().any(|relay| relay.weight > 0) {
+            other_relays
+        } else {
+            debug!(
+                "No relay other than {} matches the constraints",
+                avoided_relay
+            );
+            relays
+        }
+    }
+
     /// Takes a `Relay` and a corresponding `RelayConstraints` and returns a new `Relay` if the
     /// given relay matches the constraints.
     fn matching_relay(relay: &Relay, constraints: &RelayConstraints) -> Option<Relay> {
@@ -685,6 +963,7 @@ impl RelaySelector {
             public_key: data.public_key,
             endpoint: SocketAddr::new(host, port),
             allowed_ips: all_of_the_internet(),
+            psk: None,
         };
         Some(MullvadEndpoint::Wireguard {
             peer: peer_config,
@@ -733,15 +1012,22 @@ impl RelaySelector {
         }
     }
 
-    /// Try to read the relays from disk, preferring the newer ones.
+    /// Try to read the relays from disk, preferring the newer ones. The stored ETag is only used
+    /// along with the cached relays, since it describes those.
     fn read_relays_from_disk(
         cache_path: &Path,
+        etag_path: &Path,
         resource_path: &Path,
+        verification: &RelayListVerification,
     ) -> Result<ParsedRelays, Error> {
         // prefer the resource path's relay list if the cached one doesn't exist or was modified
         // before the resource one was created.
-        let cached_relays = ParsedRelays::from_file(cache_path);
-        let bundled_relays = match ParsedRelays::from_file(resource_path) {
+        let cached_relays =
+            ParsedRelays::from_file(cache_path, verification).map(|mut cached_relays| {
+                cached_relays.etag = Self::read_etag(etag_path);
+                cached_relays
+            });
+        let bundled_relays = match ParsedRelays::from_file(resource_path, verification) {
             Ok(bundled_relays) => bundled_relays,
             Err(e) => {
                 log::error!("Failed to load bundled relays - {}", e);
@@ -759,6 +1045,21 @@ impl RelaySelector {
             Ok(bundled_relays)
         }
     }
+
+    fn read_etag(etag_path: &Path) -> Option<String> {
+        match std::fs::read_to_string(etag_path) {
+            Ok(etag) => Some(etag.trim().to_string()).filter(|etag| !etag.is_empty()),
+            Err(error) => {
+                if error.kind() != io::ErrorKind::NotFound {
+                    error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to read relay list ETag")
+                    );
+                }
+                None
+            }
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -778,8 +1079,11 @@ impl RelayListUpdaterHandle {
 struct RelayListUpdater {
     rpc_client: RelayListProxy,
     cache_path: PathBuf,
+    etag_path: PathBuf,
+    custom_relays_path: PathBuf,
+    verification: RelayListVerification,
     parsed_relays: Arc<Mutex<ParsedRelays>>,
-    on_update: Box<dyn Fn(&RelayList) + Send + 'static>,
+    on_update: Box<dyn Fn(&RelayList, &RelayListDiff) + Send + 'static>,
     earliest_next_try: Instant,
 }
 
@@ -787,8 +1091,11 @@ impl RelayListUpdater {
     pub fn new(
         rpc_handle: MullvadRestHandle,
         cache_path: PathBuf,
+        etag_path: PathBuf,
+        custom_relays_path: PathBuf,
+        verification: RelayListVerification,
         parsed_relays: Arc<Mutex<ParsedRelays>>,
-        on_update: Box<dyn Fn(&RelayList) + Send + 'static>,
+        on_update: Box<dyn Fn(&RelayList, &RelayListDiff) + Send + 'static>,
     ) -> RelayListUpdaterHandle {
         let (tx, cmd_rx) = mpsc::channel(1);
         let service = rpc_handle.service();
@@ -796,6 +1103,9 @@ impl RelayListUpdater {
         let updater = RelayListUpdater {
             rpc_client,
             cache_path,
+            etag_path,
+            custom_relays_path,
+            verification,
             parsed_relays,
             on_update,
             earliest_next_try: Instant::now() + UPDATE_INTERVAL,
@@ -813,7 +1123,7 @@ impl RelayListUpdater {
             futures::select! {
                 _check_update = check_interval.next() => {
                     if download_future.is_terminated() && self.should_update() {
-                        download_future = Box::pin(Self::download_relay_list(self.rpc_client.clone()).fuse());
+                        download_future = Box::pin(Self::download_relay_list(self.rpc_client.clone(), self.etag()).fuse());
                         self.earliest_next_try = Instant::now() + UPDATE_INTERVAL;
                     }
                 },
@@ -826,7 +1136,9 @@ impl RelayListUpdater {
                 cmd = cmd_rx.next() => {
                     match cmd {
                         Some(_) => {
-                            self.consume_new_relay_list(self.rpc_client.relay_list().await).await;
+                            self.reload_custom_relays();
+                            let etag = self.etag();
+                            self.consume_new_relay_list(self.rpc_client.relay_list(etag).await).await;
                         },
                         None => {
                             log::error!("Relay list updater shutting down");
@@ -841,14 +1153,22 @@ impl RelayListUpdater {
 
     async fn consume_new_relay_list(
         &mut self,
-        result: Result<RelayList, mullvad_rpc::rest::Error>,
+        result: Result<Option<RelayListDocument>, mullvad_rpc::rest::Error>,
     ) {
         match result {
-            Ok(relay_list) => {
-                if let Err(err) = self.update_cache(relay_list).await {
-                    log::error!("Failed to update relay list cache: {}", err);
+            Ok(Some(document)) => {
+                if let Err(err) = self.update_cache(document).await {
+                    log::error!(
+                        "{}",
+                        err.display_chain_with_msg("Failed to update relay list")
+                    );
                 }
             }
+            Ok(None) => {
+                debug!("Relay list is up to date");
+                // The cache file is left as is, so the list is checked again after a restart.
+                self.parsed_relays.lock().last_updated = SystemTime::now();
+            }
             Err(err) => {
                 log::error!(
                     "Failed to fetch new relay list: {}. Will retry in {} minutes",
@@ -862,6 +1182,26 @@ impl RelayListUpdater {
         }
     }
 
+    fn reload_custom_relays(&mut self) {
+        let custom_relays = match ParsedRelays::read_custom_relays(&self.custom_relays_path) {
+            Ok(custom_relays) => custom_relays,
+            Err(error) => {
+                error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to reload custom relays")
+                );
+                return;
+            }
+        };
+        info!("Loaded {} custom relays", custom_relays.relays().count());
+
+        let mut parsed_relays = self.parsed_relays.lock();
+        let old_locations = parsed_relays.locations().clone();
+        parsed_relays.set_custom_relays(custom_relays);
+        let diff = RelayListDiff::new(&old_locations, parsed_relays.locations());
+        (self.on_update)(parsed_relays.locations(), &diff);
+    }
+
     /// Returns true if the current parsed_relays is older than UPDATE_INTERVAL
     fn should_update(&mut self) -> bool {
         match SystemTime::now().duration_since(self.parsed_relays.lock().last_updated()) {
@@ -873,10 +1213,17 @@ impl RelayListUpdater {
         }
     }
 
+    /// Returns the ETag of the relay list currently in use, if it is known.
+    fn etag(&self) -> Option<String> {
+        self.parsed_relays.lock().etag.clone()
+    }
+
     fn download_relay_list(
         rpc_handle: RelayListProxy,
-    ) -> impl Future<Output = Result<RelayList, mullvad_rpc::rest::Error>> + 'static {
-        let download_futures = move || rpc_handle.relay_list();
+        etag: Option<String>,
+    ) -> impl Future<Output = Result<Option<RelayListDocument>, mullvad_rpc::rest::Error>> + 'static
+    {
+        let download_futures = move || rpc_handle.relay_list(etag.clone());
 
         let exponential_backoff = ExponentialBackoff::from_millis(EXPONENTIAL_BACKOFF_DELAY_MS)
             .factor(EXPONENTIAL_BACKOFF_FACTOR)
@@ -890,37 +1237,348 @@ impl RelayListUpdater {
         download_future
     }
 
-    async fn update_cache(&mut self, new_relay_list: RelayList) -> Result<(), Error> {
-        if let Err(error) = Self::cache_relays(&self.cache_path, &new_relay_list).await {
-            error!(
-                "{}",
-                error.display_chain_with_msg("Failed to update relay cache on disk")
-            );
-        }
-
-        let new_parsed_relays = ParsedRelays::from_relay_list(new_relay_list, SystemTime::now());
+    async fn update_cache(&mut self, document: RelayListDocument) -> Result<(), Error> {
+        let mut new_parsed_relays = Self::verify_and_cache(
+            &self.cache_path,
+            &self.etag_path,
+            &self.verification,
+            &document,
+        )
+        .await?;
         info!(
             "Downloaded relay inventory has {} relays",
             new_parsed_relays.relays().len()
         );
 
         let mut parsed_relays = self.parsed_relays.lock();
+        new_parsed_relays.set_custom_relays(parsed_relays.custom_relays.clone());
+        let diff = RelayListDiff::new(parsed_relays.locations(), new_parsed_relays.locations());
+        if !diff.is_empty() {
+            info!(
+                "Relay list changed: {} added, {} removed, {} deactivated",
+                diff.added.len(),
+                diff.removed.len(),
+                diff.deactivated.len()
+            );
+        }
         *parsed_relays = new_parsed_relays;
-        (self.on_update)(parsed_relays.locations());
+        (self.on_update)(parsed_relays.locations(), &diff);
         Ok(())
     }
 
-    /// Write a `RelayList` to the cache file.
-    async fn cache_relays(cache_path: &Path, relays: &RelayList) -> Result<(), Error> {
+    /// Checks the signature of a downloaded relay list, and writes it to the cache. Nothing is
+    /// written to disk if the signature is missing or invalid.
+    async fn verify_and_cache(
+        cache_path: &Path,
+        etag_path: &Path,
+        verification: &RelayListVerification,
+        document: &RelayListDocument,
+    ) -> Result<ParsedRelays, Error> {
+        let new_relay_list = verification.parse(document)?;
+        let etag = document.etag.clone();
+
+        // The ETag on disk must not be used with an outdated cache file.
+        let cached_etag = if let Err(error) = Self::cache_relays(cache_path, document).await {
+            error!(
+                "{}",
+                error.display_chain_with_msg("Failed to update relay cache on disk")
+            );
+            None
+        } else {
+            etag.as_deref()
+        };
+        if let Err(error) = Self::cache_etag(etag_path, cached_etag).await {
+            error!(
+                "{}",
+                error.display_chain_with_msg("Failed to update relay list ETag on disk")
+            );
+        }
+
+        let mut new_parsed_relays =
+            ParsedRelays::from_relay_list(new_relay_list, SystemTime::now());
+        new_parsed_relays.etag = etag;
+        Ok(new_parsed_relays)
+    }
+
+    /// Write the ETag of the cached relay list to disk, or remove it if there is none.
+    async fn cache_etag(etag_path: &Path, etag: Option<&str>) -> io::Result<()> {
+        match etag {
+            Some(etag) => tokio::fs::write(etag_path, etag).await,
+            None => match tokio::fs::remove_file(etag_path).await {
+                Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+                result => result,
+            },
+        }
+    }
+
+    /// Write a verified relay list document and its signature to the cache. Both are
+    /// written atomically. If the daemon stops between the two writes, the signature does not
+    /// match the cached relay list, which is then not used.
+    async fn cache_relays(cache_path: &Path, relays: &RelayListDocument) -> Result<(), Error> {
         debug!("Writing relays cache to {}", cache_path.display());
-        let mut file = File::create(cache_path)
-            .await
-            .map_err(Error::OpenRelayCache)?;
-        let bytes = serde_json::to_vec_pretty(relays).map_err(Error::Serialize)?;
-        let mut slice: &[u8] = bytes.as_slice();
-        let _ = tokio::io::copy(&mut slice, &mut file)
+        let signature_path = cache_path.with_file_name(RELAYS_SIGNATURE_FILENAME);
+        match &relays.signature {
+            Some(signature) => write_atomically(&signature_path, signature.as_bytes()).await,
+            None => match tokio::fs::remove_file(&signature_path).await {
+                Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+                result => result,
+            },
+        }
+        .map_err(Error::WriteRelayCache)?;
+        write_atomically(cache_path, &relays.document)
             .await
-            .map_err(Error::WriteRelayCache)?;
-        Ok(())
+            .map_err(Error::WriteRelayCache)
+    }
+}
+
+/// Writes `contents` to a temporary file next to `path`, and renames it to `path`.
+async fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut temp_filename = path.file_name().unwrap_or_default().to_owned();
+    temp_filename.push(".tmp");
+    let temp_path = path.with_file_name(temp_filename);
+
+    let mut file = File::create(&temp_path).await?;
+    file.write_all(contents).await?;
+    file.sync_all().await?;
+    tokio::fs::rename(&temp_path, path).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_types::relay_list::test_support::{self, relay_list};
+    use ring::{
+        rand::SystemRandom,
+        signature::{Ed25519KeyPair, KeyPair},
+    };
+
+    const DOCUMENT: &[u8] = include_bytes!("../../mullvad-rpc/test-relay-list/relays.json");
+
+    fn relay(hostname: &str, weight: u64) -> Relay {
+        Relay {
+            weight,
+            ..test_support::relay(hostname)
+        }
+    }
+
+    /// Generates a new signing key, and returns it along with a verification using its public
+    /// key.
+    fn generate_key() -> (Ed25519KeyPair, RelayListVerification) {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let public_key =
+            RelayListPublicKey::from_base64(&base64::encode(key_pair.public_key().as_ref()))
+                .unwrap();
+        (key_pair, RelayListVerification::Enabled(public_key))
+    }
+
+    fn sign(key_pair: &Ed25519KeyPair, document: &[u8]) -> String {
+        base64::encode(key_pair.sign(document).as_ref())
+    }
+
+    fn tampered_document() -> Vec<u8> {
+        let mut document = DOCUMENT.to_vec();
+        let position = document.iter().position(|byte| *byte == b'a').unwrap();
+        document[position] = b'b';
+        document
+    }
+
+    /// Writes a relay list and, if given, its signature to a new directory, and returns the
+    /// directory along with the path to the relay list.
+    fn write_relay_list(document: &[u8], signature: Option<&str>) -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(RELAYS_FILENAME);
+        std::fs::write(&path, document).unwrap();
+        if let Some(signature) = signature {
+            std::fs::write(dir.path().join(RELAYS_SIGNATURE_FILENAME), signature).unwrap();
+        }
+        (dir, path)
+    }
+
+    /// Verifies and caches a downloaded relay list in a new directory, and returns the
+    /// directory along with the result.
+    fn verify_and_cache(
+        verification: &RelayListVerification,
+        document: Vec<u8>,
+        signature: Option<String>,
+    ) -> (tempfile::TempDir, Result<ParsedRelays, Error>) {
+        let dir = tempfile::tempdir().unwrap();
+        let document = RelayListDocument {
+            document,
+            signature,
+            etag: Some("\"1\"".to_string()),
+        };
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let result = runtime.block_on(RelayListUpdater::verify_and_cache(
+            &dir.path().join(RELAYS_FILENAME),
+            &dir.path().join(RELAYS_ETAG_FILENAME),
+            verification,
+            &document,
+        ));
+        (dir, result)
+    }
+
+    fn hostnames(relays: &[Relay]) -> Vec<&str> {
+        relays.iter().map(|relay| relay.hostname.as_str()).collect()
+    }
+
+    fn custom_relay(hostname: &str) -> Relay {
+        let mut relay = relay(hostname, 1);
+        relay.tunnels.openvpn.push(OpenVpnEndpointData {
+            port: 1194,
+            protocol: TransportProtocol::Udp,
+        });
+        relay
+    }
+
+    #[test]
+    fn test_avoid_previous_relay() {
+        let relays = vec![relay("se1-wireguard", 1), relay("se2-wireguard", 1)];
+        let remaining = RelaySelector::avoid_relay(relays, "se1-wireguard");
+        assert_eq!(hostnames(&remaining), vec!["se2-wireguard"]);
+    }
+
+    #[test]
+    fn test_reuse_previous_relay_without_alternative() {
+        let relays = vec![relay("se1-wireguard", 1)];
+        let remaining = RelaySelector::avoid_relay(relays, "se1-wireguard");
+        assert_eq!(hostnames(&remaining), vec!["se1-wireguard"]);
+
+        // Relays that can't be selected are not alternatives
+        let relays = vec![relay("se1-wireguard", 1), relay("se2-wireguard", 0)];
+        let remaining = RelaySelector::avoid_relay(relays, "se1-wireguard");
+        assert_eq!(
+            hostnames(&remaining),
+            vec!["se1-wireguard", "se2-wireguard"]
+        );
+    }
+
+    #[test]
+    fn test_validate_custom_relays() {
+        let relays = relay_list(vec![custom_relay("custom1"), custom_relay("custom2")]);
+        assert!(ParsedRelays::validate_custom_relays(&relays).is_ok());
+
+        let relays = relay_list(vec![custom_relay("custom1"), custom_relay("custom1")]);
+        assert!(matches!(
+            ParsedRelays::validate_custom_relays(&relays),
+            Err(Error::InvalidCustomRelay(..))
+        ));
+
+        let relays = relay_list(vec![custom_relay("")]);
+        assert!(matches!(
+            ParsedRelays::validate_custom_relays(&relays),
+            Err(Error::InvalidCustomRelay(..))
+        ));
+
+        let relays = relay_list(vec![relay("custom1", 1)]);
+        assert!(matches!(
+            ParsedRelays::validate_custom_relays(&relays),
+            Err(Error::InvalidCustomRelay(..))
+        ));
+    }
+
+    #[test]
+    fn test_custom_relay_cannot_hide_official_relay() {
+        let mut parsed_relays = ParsedRelays::from_relay_list(
+            relay_list(vec![custom_relay("se1-wireguard")]),
+            time::UNIX_EPOCH,
+        );
+        let mut hidden_relay = custom_relay("se1-wireguard");
+        hidden_relay.provider = CUSTOM_RELAY_PROVIDER.to_string();
+        let mut added_relay = custom_relay("custom1");
+        added_relay.provider = CUSTOM_RELAY_PROVIDER.to_string();
+        parsed_relays.set_custom_relays(relay_list(vec![hidden_relay, added_relay]));
+
+        let mut relays = parsed_relays.relays().clone();
+        relays.sort_by(|a, b| a.hostname.cmp(&b.hostname));
+        assert_eq!(hostnames(&relays), vec!["custom1", "se1-wireguard"]);
+        let official_relay = relays
+            .iter()
+            .find(|relay| relay.hostname == "se1-wireguard")
+            .unwrap();
+        assert_ne!(official_relay.provider, CUSTOM_RELAY_PROVIDER);
+    }
+    #[test]
+    fn test_read_signed_relay_list() {
+        let (key_pair, verification) = generate_key();
+        let (_dir, path) = write_relay_list(DOCUMENT, Some(&sign(&key_pair, DOCUMENT)));
+        let parsed_relays = ParsedRelays::from_file(&path, &verification).unwrap();
+        assert!(!parsed_relays.relays().is_empty());
+    }
+
+    #[test]
+    fn test_read_unsigned_relay_list() {
+        let (_key_pair, verification) = generate_key();
+        let (_dir, path) = write_relay_list(DOCUMENT, None);
+        assert!(matches!(
+            ParsedRelays::from_file(&path, &verification),
+            Err(Error::UnsignedRelayList)
+        ));
+    }
+
+    #[test]
+    fn test_read_tampered_relay_list() {
+        let (key_pair, verification) = generate_key();
+        let (_dir, path) = write_relay_list(&tampered_document(), Some(&sign(&key_pair, DOCUMENT)));
+        assert!(matches!(
+            ParsedRelays::from_file(&path, &verification),
+            Err(Error::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn test_missing_public_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let verification =
+            RelayListVerification::load(&dir.path().join(RELAYS_PUBLIC_KEY_FILENAME));
+        assert!(matches!(verification, RelayListVerification::MissingKey));
+
+        let (key_pair, _verification) = generate_key();
+        let (_dir, path) = write_relay_list(DOCUMENT, Some(&sign(&key_pair, DOCUMENT)));
+        assert!(matches!(
+            ParsedRelays::from_file(&path, &verification),
+            Err(Error::MissingPublicKey)
+        ));
+    }
+
+    #[test]
+    fn test_cache_signed_relay_list() {
+        let (key_pair, verification) = generate_key();
+        let signature = sign(&key_pair, DOCUMENT);
+        let (dir, result) =
+            verify_and_cache(&verification, DOCUMENT.to_vec(), Some(signature.clone()));
+        let parsed_relays = result.unwrap();
+        assert!(!parsed_relays.relays().is_empty());
+        assert_eq!(parsed_relays.etag.as_deref(), Some("\"1\""));
+
+        let cache_path = dir.path().join(RELAYS_FILENAME);
+        assert_eq!(std::fs::read(&cache_path).unwrap(), DOCUMENT);
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join(RELAYS_SIGNATURE_FILENAME)).unwrap(),
+            signature
+        );
+        assert!(ParsedRelays::from_file(&cache_path, &verification).is_ok());
+    }
+
+    #[test]
+    fn test_unsigned_relay_list_is_not_cached() {
+        let (_key_pair, verification) = generate_key();
+        let (dir, result) = verify_and_cache(&verification, DOCUMENT.to_vec(), None);
+        assert!(matches!(result, Err(Error::UnsignedRelayList)));
+        assert!(!dir.path().join(RELAYS_FILENAME).exists());
+        assert!(!dir.path().join(RELAYS_ETAG_FILENAME).exists());
+    }
+
+    #[test]
+    fn test_tampered_relay_list_is_not_cached() {
+        let (key_pair, verification) = generate_key();
+        let (dir, result) = verify_and_cache(
+            &verification,
+            tampered_document(),
+            Some(sign(&key_pair, DOCUMENT)),
+        );
+        assert!(matches!(result, Err(Error::InvalidSignature)));
+        assert!(!dir.path().join(RELAYS_FILENAME).exists());
+        assert!(!dir.path().join(RELAYS_ETAG_FILENAME).exists());
     }
 }
@@ -0,0 +1,169 @@
+//! Configuration of the optional remote management listener.
+//!
+//! The management interface can also be served over TCP with mutual TLS, which allows headless
+//! machines to be managed remotely. The listener is disabled unless `remote-management.json`
+//! exists in the settings directory and has `enabled` set:
+//!
+//! ```json
+//! {
+//!     "enabled": true,
+//!     "address": "0.0.0.0:7443",
+//!     "certificate": "/etc/mullvad-vpn/remote/server.pem",
+//!     "private_key": "/etc/mullvad-vpn/remote/server.key",
+//!     "client_ca": "/etc/mullvad-vpn/remote/ca.pem",
+//!     "allowed_clients": ["<SHA-256 fingerprint of a client certificate>"],
+//!     "role": "operator",
+//!     "management_networks": ["10.10.0.0/16"]
+//! }
+//! ```
+//!
+//! Only clients that present a certificate issued by `client_ca` and whose fingerprint is listed
+//! in `allowed_clients` may connect. Remote clients are given `role`, which must be set
+//! explicitly. The [access policy](crate::access_control) only applies to local clients.
+//!
+//! On Linux, the firewall lets the networks in `management_networks` reach the listener in every
+//! tunnel state, including the blocked state.
+
+use crate::access_control::Role;
+use ipnetwork::IpNetwork;
+use mullvad_management_interface::RemoteServerConfig;
+use serde::Deserialize;
+use std::{
+    fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+#[cfg(target_os = "linux")]
+use talpid_core::firewall::AllowedListener;
+
+const REMOTE_MANAGEMENT_FILE: &str = "remote-management.json";
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Unable to read {}", _0)]
+    ReadError(String, #[error(source)] io::Error),
+
+    #[error(display = "Unable to parse remote management config")]
+    ParseError(#[error(source)] serde_json::Error),
+
+    #[error(display = "Invalid client certificate fingerprint: {}", _0)]
+    InvalidFingerprint(String),
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct RemoteManagementConfig {
+    #[serde(default)]
+    enabled: bool,
+    address: SocketAddr,
+    certificate: PathBuf,
+    private_key: PathBuf,
+    client_ca: PathBuf,
+    allowed_clients: Vec<String>,
+    role: Role,
+    #[serde(default)]
+    management_networks: Vec<IpNetwork>,
+}
+
+impl RemoteManagementConfig {
+    /// Loads the config from the settings directory. Returns `None` if the remote management
+    /// listener is not enabled.
+    pub fn load(settings_dir: &Path) -> Result<Option<Self>, Error> {
+        let path = settings_dir.join(REMOTE_MANAGEMENT_FILE);
+        let contents = match fs::read(&path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(Error::ReadError(path.display().to_string(), error)),
+        };
+        let config: Self = serde_json::from_slice(&contents).map_err(Error::ParseError)?;
+        Ok(if config.enabled { Some(config) } else { None })
+    }
+
+    /// Reads the certificates and keys referenced by the config.
+    pub fn server_config(&self) -> Result<RemoteServerConfig, Error> {
+        let read = |path: &Path| {
+            fs::read(path).map_err(|error| Error::ReadError(path.display().to_string(), error))
+        };
+        Ok(RemoteServerConfig {
+            address: self.address,
+            certificate: read(&self.certificate)?,
+            private_key: read(&self.private_key)?,
+            client_ca: read(&self.client_ca)?,
+            allowed_clients: self
+                .allowed_clients
+                .iter()
+                .map(|fingerprint| parse_fingerprint(fingerprint))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Returns the role that remote clients are given.
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// Returns the listener that the firewall should let the management networks reach.
+    #[cfg(target_os = "linux")]
+    pub fn allowed_listener(&self) -> AllowedListener {
+        AllowedListener {
+            port: self.address.port(),
+            networks: self.management_networks.clone(),
+        }
+    }
+}
+
+/// Parses a hex encoded SHA-256 fingerprint. Bytes may be separated by colons, as printed by
+/// `openssl x509 -fingerprint -sha256`.
+fn parse_fingerprint(fingerprint: &str) -> Result<[u8; 32], Error> {
+    let digits: String = fingerprint.chars().filter(|c| *c != ':').collect();
+    let invalid = || Error::InvalidFingerprint(fingerprint.to_owned());
+    if digits.len() != 64 || !digits.is_ascii() {
+        return Err(invalid());
+    }
+    let mut bytes = [0u8; 32];
+    for (byte, pair) in bytes.iter_mut().zip(digits.as_bytes().chunks(2)) {
+        let pair = std::str::from_utf8(pair).map_err(|_| invalid())?;
+        *byte = u8::from_str_radix(pair, 16).map_err(|_| invalid())?;
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_fingerprint() {
+        let expected = {
+            let mut bytes = [0u8; 32];
+            bytes[0] = 0xab;
+            bytes[31] = 0x01;
+            bytes
+        };
+        let plain = format!("ab{}01", "00".repeat(30));
+        assert_eq!(parse_fingerprint(&plain).unwrap(), expected);
+
+        let separated = format!("AB:{}01", "00:".repeat(30));
+        assert_eq!(parse_fingerprint(&separated).unwrap(), expected);
+
+        assert!(parse_fingerprint("abcd").is_err());
+        assert!(parse_fingerprint(&"zz".repeat(32)).is_err());
+    }
+
+    #[test]
+    fn test_disabled_by_default() {
+        let config: RemoteManagementConfig = serde_json::from_str(
+            r#"{
+                "address": "0.0.0.0:7443",
+                "certificate": "server.pem",
+                "private_key": "server.key",
+                "client_ca": "ca.pem",
+                "allowed_clients": [],
+                "role": "read_only"
+            }"#,
+        )
+        .unwrap();
+        assert!(!config.enabled);
+        assert!(config.management_networks.is_empty());
+    }
+}
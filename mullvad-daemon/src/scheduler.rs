@@ -0,0 +1,334 @@
+//! Performs the actions of the schedule rules in the settings at their configured times.
+
+use crate::{DaemonCommand, DaemonEventSender};
+use chrono::{Datelike, Duration as ChronoDuration, Local, NaiveDateTime};
+use futures::{
+    channel::{mpsc, oneshot},
+    FutureExt, StreamExt,
+};
+use mullvad_types::{
+    relay_constraints::{Constraint, RelayConstraintsUpdate, RelaySettingsUpdate},
+    schedule::{ScheduleAction, ScheduleRule},
+    states::TargetState,
+};
+use std::{cmp, time::Duration};
+use talpid_core::mpsc::Sender;
+use talpid_types::ErrorExt;
+
+/// The longest time to sleep before looking at the clock again. This keeps the scheduler on time
+/// if the system clock is changed or the machine wakes up from sleep.
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// Source of the current local time.
+pub trait Clock: Send + 'static {
+    fn now(&self) -> NaiveDateTime;
+}
+
+/// Reads the local time from the system clock.
+pub struct LocalClock;
+
+impl Clock for LocalClock {
+    fn now(&self) -> NaiveDateTime {
+        Local::now().naive_local()
+    }
+}
+
+/// Keeps track of which schedule rules have been triggered.
+pub struct Schedule<C: Clock> {
+    clock: C,
+    rules: Vec<ScheduleRule>,
+    last_poll: NaiveDateTime,
+}
+
+impl<C: Clock> Schedule<C> {
+    pub fn new(clock: C, rules: Vec<ScheduleRule>) -> Self {
+        let last_poll = clock.now();
+        Schedule {
+            clock,
+            rules,
+            last_poll,
+        }
+    }
+
+    /// Replaces the rules. Rules are only triggered at times after the previous poll.
+    pub fn set_rules(&mut self, rules: Vec<ScheduleRule>) {
+        self.rules = rules;
+    }
+
+    /// Returns the actions of the rules that have been triggered since the previous poll, in the
+    /// order that they were triggered. A rule that was triggered several times, e.g. while the
+    /// machine was asleep, is only returned once.
+    pub fn poll(&mut self) -> Vec<ScheduleAction> {
+        let now = self.clock.now();
+        let last_poll = self.last_poll;
+        self.last_poll = now;
+
+        let mut triggered: Vec<_> = self
+            .rules
+            .iter()
+            .filter_map(|rule| {
+                last_occurrence(rule, now)
+                    .filter(|time| *time > last_poll)
+                    .map(|time| (time, rule))
+            })
+            .collect();
+        triggered.sort_by_key(|(time, _)| *time);
+        triggered
+            .into_iter()
+            .map(|(_, rule)| rule.action.clone())
+            .collect()
+    }
+
+    /// Returns how long to wait before polling again.
+    pub fn time_until_next_poll(&self) -> Duration {
+        let now = self.clock.now();
+        self.rules
+            .iter()
+            .filter_map(|rule| next_occurrence(rule, now))
+            .min()
+            .and_then(|next| (next - now).to_std().ok())
+            .map(|duration| cmp::min(duration, MAX_SLEEP))
+            .unwrap_or(MAX_SLEEP)
+    }
+}
+
+/// Returns the latest time at or before `time` at which `rule` is triggered.
+fn last_occurrence(rule: &ScheduleRule, time: NaiveDateTime) -> Option<NaiveDateTime> {
+    (0..=7)
+        .map(|days| time.date() - ChronoDuration::days(days))
+        .filter(|date| rule.applies_on(date.weekday()))
+        .map(|date| date.and_time(rule.time))
+        .find(|occurrence| *occurrence <= time)
+}
+
+/// Returns the earliest time after `time` at which `rule` is triggered.
+fn next_occurrence(rule: &ScheduleRule, time: NaiveDateTime) -> Option<NaiveDateTime> {
+    (0..=7)
+        .map(|days| time.date() + ChronoDuration::days(days))
+        .filter(|date| rule.applies_on(date.weekday()))
+        .map(|date| date.and_time(rule.time))
+        .find(|occurrence| *occurrence > time)
+}
+
+/// A handle for updating the rules of a running scheduler. The scheduler stops when the handle
+/// is dropped.
+pub struct SchedulerHandle {
+    tx: mpsc::UnboundedSender<Vec<ScheduleRule>>,
+}
+
+impl SchedulerHandle {
+    pub fn set_rules(&self, rules: Vec<ScheduleRule>) {
+        if self.tx.unbounded_send(rules).is_err() {
+            log::error!("Failed to update schedule rules since the scheduler is not running");
+        }
+    }
+}
+
+/// Spawns a task that sends commands to the daemon when schedule rules are triggered.
+pub(crate) fn spawn(
+    clock: impl Clock,
+    rules: Vec<ScheduleRule>,
+    daemon_tx: DaemonEventSender<DaemonCommand>,
+) -> SchedulerHandle {
+    let (tx, rx) = mpsc::unbounded();
+    tokio::spawn(run(Schedule::new(clock, rules), rx, daemon_tx));
+    SchedulerHandle { tx }
+}
+
+async fn run<C: Clock>(
+    mut schedule: Schedule<C>,
+    rx: mpsc::UnboundedReceiver<Vec<ScheduleRule>>,
+    daemon_tx: DaemonEventSender<DaemonCommand>,
+) {
+    let mut rx = rx.fuse();
+    loop {
+        let mut delay = tokio::time::delay_for(schedule.time_until_next_poll()).fuse();
+        futures::select! {
+            rules = rx.next() => match rules {
+                Some(rules) => {
+                    // Perform anything that is due first, so that new rules aren't triggered
+                    // retroactively
+                    for action in schedule.poll() {
+                        if perform_action(action, &daemon_tx).await.is_err() {
+                            return;
+                        }
+                    }
+                    schedule.set_rules(rules);
+                }
+                None => return,
+            },
+            _ = delay => {
+                for action in schedule.poll() {
+                    if perform_action(action, &daemon_tx).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn perform_action(
+    action: ScheduleAction,
+    daemon_tx: &DaemonEventSender<DaemonCommand>,
+) -> Result<(), ()> {
+    log::info!("Performing scheduled action: {}", action);
+    match action {
+        ScheduleAction::Connect => {
+            send_command(daemon_tx, |tx| {
+                DaemonCommand::SetTargetState(tx, TargetState::Secured)
+            })
+            .await
+        }
+        ScheduleAction::Disconnect => {
+            send_command(daemon_tx, |tx| {
+                DaemonCommand::SetTargetState(tx, TargetState::Unsecured)
+            })
+            .await
+        }
+        ScheduleAction::SetLocation(location) => {
+            let update = RelaySettingsUpdate::Normal(RelayConstraintsUpdate {
+                location: Some(Constraint::Only(location)),
+                ..Default::default()
+            });
+            let result = send_command(daemon_tx, |tx| {
+                DaemonCommand::UpdateRelaySettings(tx, update)
+            })
+            .await?;
+            if let Err(error) = result {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to perform scheduled action")
+                );
+            }
+            Ok(())
+        }
+    }
+}
+
+async fn send_command<T>(
+    daemon_tx: &DaemonEventSender<DaemonCommand>,
+    make_command: impl FnOnce(oneshot::Sender<T>) -> DaemonCommand,
+) -> Result<T, ()> {
+    let (tx, rx) = oneshot::channel();
+    daemon_tx.send(make_command(tx))?;
+    rx.await.map_err(|_| ())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{NaiveDate, NaiveTime, Weekday};
+    use mullvad_types::relay_constraints::LocationConstraint;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone)]
+    struct MockClock(Arc<Mutex<NaiveDateTime>>);
+
+    impl MockClock {
+        fn new(time: NaiveDateTime) -> Self {
+            MockClock(Arc::new(Mutex::new(time)))
+        }
+
+        fn set(&self, time: NaiveDateTime) {
+            *self.0.lock().unwrap() = time;
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> NaiveDateTime {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    fn rule(weekdays: Vec<Weekday>, hour: u32, action: ScheduleAction) -> ScheduleRule {
+        ScheduleRule {
+            id: 0,
+            weekdays,
+            time: NaiveTime::from_hms(hour, 0, 0),
+            action,
+        }
+    }
+
+    fn weekdays() -> Vec<Weekday> {
+        vec![
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+        ]
+    }
+
+    // 2020-10-02 is a Friday
+    fn date(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(2020, 10, day).and_hms(hour, minute, 0)
+    }
+
+    #[test]
+    fn test_weekday_rule() {
+        let clock = MockClock::new(date(2, 7, 0));
+        let mut schedule = Schedule::new(
+            clock.clone(),
+            vec![rule(weekdays(), 8, ScheduleAction::Connect)],
+        );
+
+        assert_eq!(schedule.time_until_next_poll(), MAX_SLEEP);
+        clock.set(date(2, 7, 59));
+        assert_eq!(schedule.poll(), vec![]);
+        assert_eq!(schedule.time_until_next_poll(), Duration::from_secs(60));
+
+        clock.set(date(2, 8, 0));
+        assert_eq!(schedule.poll(), vec![ScheduleAction::Connect]);
+        assert_eq!(schedule.poll(), vec![]);
+
+        // Saturday and Sunday are skipped
+        clock.set(date(3, 8, 0));
+        assert_eq!(schedule.poll(), vec![]);
+        clock.set(date(4, 9, 0));
+        assert_eq!(schedule.poll(), vec![]);
+        assert_eq!(
+            next_occurrence(&schedule.rules[0], clock.now()),
+            Some(date(5, 8, 0))
+        );
+    }
+
+    #[test]
+    fn test_missed_rules_are_performed_in_order() {
+        let clock = MockClock::new(date(2, 7, 0));
+        let location = ScheduleAction::SetLocation(LocationConstraint::Country("se".to_owned()));
+        let mut schedule = Schedule::new(
+            clock.clone(),
+            vec![
+                rule(vec![], 23, ScheduleAction::Disconnect),
+                rule(weekdays(), 8, ScheduleAction::Connect),
+                rule(weekdays(), 18, location.clone()),
+            ],
+        );
+
+        // Asleep until Monday afternoon. Each rule is only performed once.
+        clock.set(date(5, 12, 0));
+        assert_eq!(
+            schedule.poll(),
+            vec![
+                location.clone(),
+                ScheduleAction::Disconnect,
+                ScheduleAction::Connect
+            ]
+        );
+
+        clock.set(date(5, 23, 30));
+        assert_eq!(schedule.poll(), vec![location, ScheduleAction::Disconnect]);
+    }
+
+    #[test]
+    fn test_new_rules_are_not_retroactive() {
+        let clock = MockClock::new(date(2, 7, 0));
+        let mut schedule = Schedule::new(clock.clone(), vec![]);
+
+        clock.set(date(2, 9, 0));
+        assert_eq!(schedule.poll(), vec![]);
+        schedule.set_rules(vec![rule(vec![], 8, ScheduleAction::Connect)]);
+        assert_eq!(schedule.poll(), vec![]);
+    }
+}
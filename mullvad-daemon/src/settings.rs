@@ -1,7 +1,13 @@
+use crate::{
+    policy::SettingsPolicy,
+    settings_history::{self, HistoryEntry, SettingsHistory},
+};
 use log::{debug, error, info};
 use mullvad_types::{
+    api_access::AccessMethod,
     relay_constraints::{BridgeSettings, BridgeState, RelaySettingsUpdate},
-    settings::Settings,
+    schedule::ScheduleRule,
+    settings::{Settings, TunnelProxySettings},
 };
 use std::{
     fs::{self, File},
@@ -9,11 +15,15 @@ use std::{
     ops::Deref,
     path::{Path, PathBuf},
 };
-use talpid_types::ErrorExt;
+use talpid_core::tunnel::wireguard::config::QUANTUM_RESISTANCE_SUPPORTED;
+use talpid_types::{net::wireguard, ErrorExt};
 
 
 const SETTINGS_FILE: &str = "settings.json";
 
+/// The client that changes are attributed to in the settings history when no client is set.
+const DEFAULT_CLIENT: &str = "daemon";
+
 
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -26,6 +36,18 @@ pub enum Error {
 
     #[error(display = "Unable to write settings to {}", _0)]
     WriteError(String, #[error(source)] io::Error),
+
+    #[error(display = "Invalid settings")]
+    InvalidSettings(#[error(source)] mullvad_types::settings::Error),
+
+    #[error(display = "The setting \"{}\" is locked by the settings policy", _0)]
+    LockedSetting(String),
+
+    #[error(display = "Quantum-resistant tunnels are not supported on this platform")]
+    QuantumResistanceUnsupported,
+
+    #[error(display = "Settings history error")]
+    HistoryError(#[error(source)] settings_history::Error),
 }
 
 #[derive(err_derive::Error, Debug)]
@@ -49,21 +71,35 @@ enum LoadSettingsError {
 pub struct SettingsPersister {
     settings: Settings,
     path: PathBuf,
+    policy: SettingsPolicy,
+    history: SettingsHistory,
+    current_client: Option<String>,
 }
 
 impl SettingsPersister {
-    /// Loads user settings from file. If no file is present it returns the defaults.
-    pub fn load(settings_dir: &Path) -> Self {
+    /// Loads user settings from file. If no file is present it returns the defaults. The values
+    /// in `policy` are applied on top of the loaded settings.
+    pub fn load(settings_dir: &Path, policy: SettingsPolicy) -> Self {
         let path = settings_dir.join(SETTINGS_FILE);
         let (mut settings, mut should_save) = Self::load_settings(&path);
 
+        let enforced_settings = policy.apply(settings.clone());
+        should_save |= enforced_settings != settings;
+        settings = enforced_settings;
+
         // Force IPv6 to be enabled on Android
         if cfg!(target_os = "android") {
             should_save |=
                 Self::update_field(&mut settings.tunnel_options.generic.enable_ipv6, true);
         }
 
-        let mut persister = SettingsPersister { settings, path };
+        let mut persister = SettingsPersister {
+            settings,
+            path,
+            policy,
+            history: SettingsHistory::load(settings_dir),
+            current_client: None,
+        };
 
         if should_save {
             if let Err(error) = persister.save() {
@@ -123,29 +159,44 @@ impl SettingsPersister {
             }
         })?;
 
-        Settings::load_from_bytes(&settings_bytes)
+        Self::parse_settings(&settings_bytes).map_err(LoadSettingsError::ParseError)
+    }
+
+    /// Parses settings of any version. The boolean indicates whether they were migrated.
+    fn parse_settings(bytes: &[u8]) -> Result<(Settings, bool), mullvad_types::settings::Error> {
+        Settings::load_from_bytes(bytes)
             .map(|settings| (settings, false))
-            .or_else(|_| {
-                Settings::migrate_from_bytes(&settings_bytes).map(|settings| (settings, true))
-            })
-            .map_err(LoadSettingsError::ParseError)
+            .or_else(|_| Settings::migrate_from_bytes(bytes).map(|settings| (settings, true)))
     }
 
     /// Serializes the settings and saves them to the file it was loaded from.
-    fn save(&mut self) -> Result<(), Error> {
+    fn save(&self) -> Result<(), Error> {
+        self.write_settings(&self.settings)
+    }
+
+    /// Writes `settings` to a temporary file and renames it over the settings file, so that the
+    /// settings file is never left partially written.
+    fn write_settings(&self, settings: &Settings) -> Result<(), Error> {
         debug!("Writing settings to {}", self.path.display());
-        let mut file = File::create(&self.path)
-            .map_err(|e| Error::WriteError(self.path.display().to_string(), e))?;
+        let temp_path = self.path.with_extension("json.tmp");
+        let write_error = |e| Error::WriteError(self.path.display().to_string(), e);
 
-        serde_json::to_writer_pretty(&mut file, &self.settings).map_err(Error::SerializeError)?;
-        file.sync_all()
-            .map_err(|e| Error::WriteError(self.path.display().to_string(), e))
+        let mut file = File::create(&temp_path).map_err(write_error)?;
+        serde_json::to_writer_pretty(&mut file, settings).map_err(Error::SerializeError)?;
+        file.sync_all().map_err(write_error)?;
+        fs::rename(&temp_path, &self.path).map_err(write_error)
     }
 
     /// Resets default settings
     #[cfg(not(target_os = "android"))]
     pub fn reset(&mut self) -> Result<(), Error> {
-        self.settings = Settings::default();
+        self.settings = self.policy.apply(Settings::default());
+        if let Err(error) = self.history.clear() {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Unable to clear settings history")
+            );
+        }
         self.save().or_else(|e| {
             log::error!(
                 "{}",
@@ -161,85 +212,221 @@ impl SettingsPersister {
         self.settings.clone()
     }
 
+    /// Serializes the settings in the same format as the settings file.
+    pub fn export(&self) -> Result<String, Error> {
+        serde_json::to_string_pretty(&self.settings).map_err(Error::SerializeError)
+    }
+
+    /// Replaces all settings with the ones in `settings_bytes`, which may be of an older version.
+    /// If `keep_account_token` is set, the current account token is kept. Fields locked by the
+    /// settings policy are set to their policy values. Nothing is changed if an error occurs.
+    pub fn import(
+        &mut self,
+        settings_bytes: &[u8],
+        keep_account_token: bool,
+    ) -> Result<bool, Error> {
+        let (mut imported_settings, _) =
+            Self::parse_settings(settings_bytes).map_err(Error::InvalidSettings)?;
+        if keep_account_token {
+            imported_settings.set_account_token(self.settings.get_account_token());
+        }
+        let imported_settings = self.policy.apply(imported_settings);
+        self.update(|settings| {
+            if *settings != imported_settings {
+                *settings = imported_settings;
+                true
+            } else {
+                false
+            }
+        })
+    }
+
+    /// Sets the client that subsequent changes are attributed to in the settings history.
+    pub fn set_current_client(&mut self, client: Option<String>) {
+        self.current_client = client;
+    }
+
+    /// Returns the revisions in the settings history, oldest first.
+    pub fn history(&self) -> Vec<HistoryEntry> {
+        self.history.entries().cloned().collect()
+    }
+
+    /// Restores the settings to how they were at `revision` in the settings history. The
+    /// rollback is itself recorded as a new revision, and locked fields can't be rolled back.
+    pub fn rollback(&mut self, revision: u64) -> Result<bool, Error> {
+        let rolled_back_settings = self
+            .history
+            .settings_at(&self.settings, revision)
+            .map_err(Error::HistoryError)?;
+        self.update(|settings| {
+            if *settings != rolled_back_settings {
+                *settings = rolled_back_settings;
+                true
+            } else {
+                false
+            }
+        })
+    }
+
     /// Changes account number to the one given. Also saves the new settings to disk.
     /// The boolean in the Result indicates if the account token changed or not
     pub fn set_account_token(&mut self, account_token: Option<String>) -> Result<bool, Error> {
-        let should_save = self.settings.set_account_token(account_token);
-        self.update(should_save)
+        self.update(|settings| settings.set_account_token(account_token))
     }
 
     pub fn update_relay_settings(&mut self, update: RelaySettingsUpdate) -> Result<bool, Error> {
-        let should_save = self.settings.update_relay_settings(update);
-        self.update(should_save)
+        self.update(|settings| settings.update_relay_settings(update))
     }
 
     pub fn set_allow_lan(&mut self, allow_lan: bool) -> Result<bool, Error> {
-        let should_save = Self::update_field(&mut self.settings.allow_lan, allow_lan);
-        self.update(should_save)
+        self.update(|settings| Self::update_field(&mut settings.allow_lan, allow_lan))
     }
 
     pub fn set_block_when_disconnected(
         &mut self,
         block_when_disconnected: bool,
     ) -> Result<bool, Error> {
-        let should_save = Self::update_field(
-            &mut self.settings.block_when_disconnected,
-            block_when_disconnected,
-        );
-        self.update(should_save)
+        self.update(|settings| {
+            Self::update_field(
+                &mut settings.block_when_disconnected,
+                block_when_disconnected,
+            )
+        })
     }
 
     pub fn set_auto_connect(&mut self, auto_connect: bool) -> Result<bool, Error> {
-        let should_save = Self::update_field(&mut self.settings.auto_connect, auto_connect);
-        self.update(should_save)
+        self.update(|settings| Self::update_field(&mut settings.auto_connect, auto_connect))
     }
 
     pub fn set_openvpn_mssfix(&mut self, openvpn_mssfix: Option<u16>) -> Result<bool, Error> {
-        let should_save = Self::update_field(
-            &mut self.settings.tunnel_options.openvpn.mssfix,
-            openvpn_mssfix,
-        );
-        self.update(should_save)
+        self.update(|settings| {
+            Self::update_field(&mut settings.tunnel_options.openvpn.mssfix, openvpn_mssfix)
+        })
     }
 
     pub fn set_enable_ipv6(&mut self, enable_ipv6: bool) -> Result<bool, Error> {
-        let should_save = Self::update_field(
-            &mut self.settings.tunnel_options.generic.enable_ipv6,
-            enable_ipv6,
-        );
-        self.update(should_save)
+        self.update(|settings| {
+            Self::update_field(
+                &mut settings.tunnel_options.generic.enable_ipv6,
+                enable_ipv6,
+            )
+        })
     }
 
     pub fn set_wireguard_mtu(&mut self, mtu: Option<u16>) -> Result<bool, Error> {
-        let should_save = Self::update_field(&mut self.settings.tunnel_options.wireguard.mtu, mtu);
-        self.update(should_save)
+        self.update(|settings| Self::update_field(&mut settings.tunnel_options.wireguard.mtu, mtu))
     }
 
     pub fn set_wireguard_rotation_interval(
         &mut self,
         automatic_rotation: Option<u32>,
     ) -> Result<bool, Error> {
-        let should_save = Self::update_field(
-            &mut self.settings.tunnel_options.wireguard.automatic_rotation,
-            automatic_rotation,
-        );
-        self.update(should_save)
+        self.update(|settings| {
+            Self::update_field(
+                &mut settings.tunnel_options.wireguard.automatic_rotation,
+                automatic_rotation,
+            )
+        })
+    }
+
+    pub fn set_relay_rotation_interval(&mut self, interval: Option<u32>) -> Result<bool, Error> {
+        self.update(|settings| {
+            Self::update_field(
+                &mut settings.tunnel_options.relay_rotation_interval,
+                interval,
+            )
+        })
+    }
+
+    pub fn set_wireguard_quantum_resistant(
+        &mut self,
+        quantum_resistant: bool,
+    ) -> Result<bool, Error> {
+        if quantum_resistant && !QUANTUM_RESISTANCE_SUPPORTED {
+            return Err(Error::QuantumResistanceUnsupported);
+        }
+        self.update(|settings| {
+            Self::update_field(
+                &mut settings.tunnel_options.wireguard.quantum_resistant,
+                quantum_resistant,
+            )
+        })
+    }
+
+    pub fn set_wireguard_connectivity_check(
+        &mut self,
+        connectivity_check: wireguard::ConnectivityCheckOptions,
+    ) -> Result<bool, Error> {
+        self.update(|settings| {
+            Self::update_field(
+                &mut settings.tunnel_options.wireguard.connectivity_check,
+                connectivity_check,
+            )
+        })
     }
 
     pub fn set_show_beta_releases(&mut self, show_beta_releases: bool) -> Result<bool, Error> {
-        let should_save =
-            Self::update_field(&mut self.settings.show_beta_releases, show_beta_releases);
-        self.update(should_save)
+        self.update(|settings| {
+            Self::update_field(&mut settings.show_beta_releases, show_beta_releases)
+        })
+    }
+
+    pub fn set_tunnel_proxy_settings(
+        &mut self,
+        tunnel_proxy: TunnelProxySettings,
+    ) -> Result<bool, Error> {
+        self.update(|settings| Self::update_field(&mut settings.tunnel_proxy, tunnel_proxy))
+    }
+
+    pub fn set_api_access_methods(&mut self, methods: Vec<AccessMethod>) -> Result<bool, Error> {
+        self.update(|settings| Self::update_field(&mut settings.api_access_methods, methods))
+    }
+
+    /// Sets the hours before expiry at which clients are notified. They are stored in descending
+    /// order without duplicates or zero, since clients are always notified once the account has
+    /// expired.
+    pub fn set_account_expiry_notifications(&mut self, mut hours: Vec<u32>) -> Result<bool, Error> {
+        hours.retain(|hours| *hours > 0);
+        hours.sort_unstable_by(|a, b| b.cmp(a));
+        hours.dedup();
+        self.update(|settings| {
+            Self::update_field(&mut settings.account_expiry_notifications, hours)
+        })
+    }
+
+    /// Adds a schedule rule and returns the ID assigned to it.
+    pub fn add_schedule_rule(&mut self, mut rule: ScheduleRule) -> Result<u32, Error> {
+        rule.id = self
+            .settings
+            .schedule
+            .iter()
+            .map(|rule| rule.id)
+            .max()
+            .map(|id| id + 1)
+            .unwrap_or(1);
+        let id = rule.id;
+        self.update(|settings| {
+            settings.schedule.push(rule);
+            true
+        })
+        .map(|_| id)
+    }
+
+    /// Removes the schedule rule with the given ID. Returns `false` if there is no such rule.
+    pub fn remove_schedule_rule(&mut self, id: u32) -> Result<bool, Error> {
+        self.update(|settings| {
+            let rule_count = settings.schedule.len();
+            settings.schedule.retain(|rule| rule.id != id);
+            settings.schedule.len() != rule_count
+        })
     }
 
     pub fn set_bridge_settings(&mut self, bridge_settings: BridgeSettings) -> Result<bool, Error> {
-        let should_save = Self::update_field(&mut self.settings.bridge_settings, bridge_settings);
-        self.update(should_save)
+        self.update(|settings| Self::update_field(&mut settings.bridge_settings, bridge_settings))
     }
 
     pub fn set_bridge_state(&mut self, bridge_state: BridgeState) -> Result<bool, Error> {
-        let should_save = self.settings.set_bridge_state(bridge_state);
-        self.update(should_save)
+        self.update(|settings| settings.set_bridge_state(bridge_state))
     }
 
     fn update_field<T: Eq>(field: &mut T, new_value: T) -> bool {
@@ -251,12 +438,32 @@ impl SettingsPersister {
         }
     }
 
-    fn update(&mut self, should_save: bool) -> Result<bool, Error> {
-        if should_save {
-            self.save().map(|_| true)
-        } else {
-            Ok(false)
+    /// Applies `update_fn` to a copy of the settings, which returns whether it changed anything.
+    /// The change is rejected if it touches a field that is locked by the settings policy.
+    /// Otherwise the new settings replace the current ones, are saved and are recorded in the
+    /// settings history.
+    fn update(&mut self, update_fn: impl FnOnce(&mut Settings) -> bool) -> Result<bool, Error> {
+        let mut new_settings = self.settings.clone();
+        if !update_fn(&mut new_settings) {
+            return Ok(false);
         }
+        if let Some(field) = self
+            .policy
+            .changed_locked_field(&self.settings, &new_settings)
+        {
+            return Err(Error::LockedSetting(field.to_owned()));
+        }
+        self.write_settings(&new_settings)?;
+        let old_settings = std::mem::replace(&mut self.settings, new_settings);
+
+        let client = self.current_client.as_deref().unwrap_or(DEFAULT_CLIENT);
+        if let Err(error) = self.history.record(&old_settings, &self.settings, client) {
+            error!(
+                "{}",
+                error.display_chain_with_msg("Failed to record settings history")
+            );
+        }
+        Ok(true)
     }
 }
 
@@ -270,24 +477,41 @@ impl Deref for SettingsPersister {
 
 
 #[cfg(windows)]
-mod windows {
+pub(crate) mod windows {
     use std::{ffi::OsStr, fs, io, os::windows::ffi::OsStrExt, path::Path, ptr};
     use talpid_types::ErrorExt;
     use winapi::{
-        shared::{minwindef::TRUE, winerror::ERROR_SUCCESS},
+        shared::{
+            minwindef::{DWORD, FALSE, TRUE},
+            winerror::ERROR_SUCCESS,
+        },
         um::{
             accctrl::{SE_FILE_OBJECT, SE_OBJECT_TYPE},
             aclapi::GetNamedSecurityInfoW,
-            securitybaseapi::IsWellKnownSid,
+            securitybaseapi::{GetAce, IsWellKnownSid},
             winbase::LocalFree,
             winnt::{
-                WinBuiltinAdministratorsSid, WinLocalSystemSid, OWNER_SECURITY_INFORMATION, PSID,
-                SECURITY_DESCRIPTOR, SECURITY_INFORMATION, SID, WELL_KNOWN_SID_TYPE,
+                WinBuiltinAdministratorsSid, WinLocalSystemSid, ACCESS_ALLOWED_ACE,
+                ACCESS_ALLOWED_ACE_TYPE, ACCESS_DENIED_ACE_TYPE, ACE_HEADER, ACL,
+                DACL_SECURITY_INFORMATION, DELETE, FILE_APPEND_DATA, FILE_WRITE_ATTRIBUTES,
+                FILE_WRITE_DATA, FILE_WRITE_EA, GENERIC_ALL, GENERIC_WRITE, INHERIT_ONLY_ACE,
+                OWNER_SECURITY_INFORMATION, PACL, PSID, SECURITY_DESCRIPTOR, SECURITY_INFORMATION,
+                SID, WELL_KNOWN_SID_TYPE, WRITE_DAC, WRITE_OWNER,
             },
         },
     };
 
     const MIGRATION_DIRNAME: &str = "windows.old";
+    /// Access rights that allow a file, or its permissions, to be modified.
+    const WRITE_ACCESS_MASK: DWORD = FILE_WRITE_DATA
+        | FILE_APPEND_DATA
+        | FILE_WRITE_EA
+        | FILE_WRITE_ATTRIBUTES
+        | DELETE
+        | WRITE_DAC
+        | WRITE_OWNER
+        | GENERIC_WRITE
+        | GENERIC_ALL;
     const MIGRATE_FILES: [(&str, bool); 2] =
         [("settings.json", true), ("account-history.json", false)];
 
@@ -351,9 +575,7 @@ mod windows {
 
         let owner_sid = security_info.owner().ok_or(Error::WrongOwner)?;
 
-        if !is_well_known_sid(owner_sid, WinLocalSystemSid)
-            && !is_well_known_sid(owner_sid, WinBuiltinAdministratorsSid)
-        {
+        if !is_admin_sid(owner_sid) {
             return Err(Error::WrongOwner);
         }
 
@@ -399,9 +621,54 @@ mod windows {
         result
     }
 
+    /// Returns whether the file at `path` is owned by SYSTEM or Built-in Administrators, and
+    /// can't be modified by anyone else.
+    pub fn is_admin_only_writable(path: &Path) -> Result<bool, io::Error> {
+        let security_info = SecurityInformation::from_file(
+            path,
+            OWNER_SECURITY_INFORMATION | DACL_SECURITY_INFORMATION,
+        )?;
+
+        match security_info.owner() {
+            Some(owner_sid) if is_admin_sid(owner_sid) => (),
+            _ => return Ok(false),
+        }
+        // A missing DACL grants everyone full access.
+        let dacl = match security_info.dacl() {
+            Some(dacl) => dacl,
+            None => return Ok(false),
+        };
+
+        for index in 0..DWORD::from(dacl.AceCount) {
+            let mut ace = ptr::null_mut();
+            if unsafe { GetAce(dacl as *const ACL as *mut _, index, &mut ace) } == FALSE {
+                return Err(io::Error::last_os_error());
+            }
+            let header = unsafe { &*(ace as *const ACE_HEADER) };
+            if header.AceFlags & INHERIT_ONLY_ACE != 0 {
+                continue;
+            }
+            match header.AceType {
+                ACCESS_DENIED_ACE_TYPE => (),
+                ACCESS_ALLOWED_ACE_TYPE => {
+                    let ace = unsafe { &*(ace as *const ACCESS_ALLOWED_ACE) };
+                    let sid = unsafe { &*(&ace.SidStart as *const DWORD as *const SID) };
+                    if ace.Mask & WRITE_ACCESS_MASK != 0 && !is_admin_sid(sid) {
+                        return Ok(false);
+                    }
+                }
+                // Other kinds of entries are not expected on files, so don't trust them.
+                _ => return Ok(false),
+            }
+        }
+
+        Ok(true)
+    }
+
     struct SecurityInformation {
         security_descriptor: *mut SECURITY_DESCRIPTOR,
         owner: PSID,
+        dacl: PACL,
     }
 
     impl SecurityInformation {
@@ -422,6 +689,7 @@ mod windows {
 
             let mut security_descriptor = ptr::null_mut();
             let mut owner = ptr::null_mut();
+            let mut dacl = ptr::null_mut();
 
             let status = unsafe {
                 GetNamedSecurityInfoW(
@@ -430,7 +698,7 @@ mod windows {
                     security_information,
                     &mut owner,
                     ptr::null_mut(),
-                    ptr::null_mut(),
+                    &mut dacl,
                     ptr::null_mut(),
                     &mut security_descriptor,
                 )
@@ -443,6 +711,7 @@ mod windows {
             Ok(SecurityInformation {
                 security_descriptor: security_descriptor as *mut _,
                 owner,
+                dacl,
             })
         }
 
@@ -450,7 +719,12 @@ mod windows {
             unsafe { (self.owner as *const SID).as_ref() }
         }
 
-        // TODO: Can be expanded with `group()`, `dacl()`, and `sacl()`.
+        /// Returns the DACL, if it was requested and the object has one.
+        pub fn dacl(&self) -> Option<&ACL> {
+            unsafe { (self.dacl as *const ACL).as_ref() }
+        }
+
+        // TODO: Can be expanded with `group()` and `sacl()`.
     }
 
     impl Drop for SecurityInformation {
@@ -459,6 +733,11 @@ mod windows {
         }
     }
 
+    fn is_admin_sid(sid: &SID) -> bool {
+        is_well_known_sid(sid, WinLocalSystemSid)
+            || is_well_known_sid(sid, WinBuiltinAdministratorsSid)
+    }
+
     fn is_well_known_sid(sid: &SID, well_known_sid_type: WELL_KNOWN_SID_TYPE) -> bool {
         unsafe { IsWellKnownSid(sid as *const SID as *mut _, well_known_sid_type) == TRUE }
     }
@@ -0,0 +1,606 @@
+//! A bounded journal of the changes made to the settings, used for showing the history of the
+//! settings and for rolling them back.
+//!
+//! The journal is stored in the settings directory with one JSON entry per line. Each entry
+//! describes one revision of the settings: when it was made, by which client, and the old and
+//! new values of every field that changed. Fields are named by their dot separated path in
+//! `settings.json`, like in the settings policy.
+//!
+//! The account token is never written to the journal, so rolling back the settings leaves the
+//! current account token in place. Other secrets, such as proxy credentials, are redacted. Rolling
+//! back restores them from the current settings where they still exist.
+
+use crate::diagnostics::{REDACTED_SECRET, SECRET_FIELDS};
+use chrono::{DateTime, Utc};
+use mullvad_types::settings::Settings;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+use talpid_types::ErrorExt;
+
+const HISTORY_FILE: &str = "settings-history.jsonl";
+
+/// The maximum number of revisions kept in the journal.
+const MAX_ENTRIES: usize = 100;
+
+/// Fields that are left out of the journal because they are secret.
+const UNJOURNALED_FIELDS: &[&str] = &["account_token"];
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Unable to write settings history to {}", _0)]
+    WriteError(String, #[error(source)] io::Error),
+
+    #[error(display = "Unable to remove settings history file {}", _0)]
+    DeleteError(String, #[error(source)] io::Error),
+
+    #[error(display = "Unable to serialize settings history")]
+    SerializeError(#[error(source)] serde_json::Error),
+
+    #[error(display = "Revision {} is not in the settings history", _0)]
+    UnknownRevision(u64),
+
+    #[error(display = "The settings history does not match the settings")]
+    InconsistentHistory,
+
+    #[error(display = "The rolled back settings are invalid")]
+    InvalidSettings(#[error(source)] serde_json::Error),
+}
+
+/// A revision of the settings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub revision: u64,
+    pub timestamp: DateTime<Utc>,
+    /// Describes the client that made the change.
+    pub client: String,
+    pub changes: Vec<FieldChange>,
+}
+
+/// A change to a single settings field. A missing value means that the field did not exist, which
+/// happens when the variant of an enum changes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub field: String,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub old_value: Option<Value>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub new_value: Option<Value>,
+}
+
+/// Deserializes a present value, including `null`, as `Some`.
+fn deserialize_present<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug)]
+pub struct SettingsHistory {
+    entries: VecDeque<HistoryEntry>,
+    path: PathBuf,
+}
+
+impl SettingsHistory {
+    /// Loads the journal from the settings directory. Entries that can't be parsed are skipped.
+    pub fn load(settings_dir: &Path) -> Self {
+        let path = settings_dir.join(HISTORY_FILE);
+        let entries = match File::open(&path) {
+            Ok(file) => BufReader::new(file)
+                .lines()
+                .filter_map(|line| {
+                    let line = line.ok()?;
+                    serde_json::from_str(&line)
+                        .map(|mut entry: HistoryEntry| {
+                            // Older journals may contain secrets
+                            entry.changes.iter_mut().for_each(redact_change);
+                            entry
+                        })
+                        .map_err(|error| {
+                            log::warn!(
+                                "{}",
+                                error.display_chain_with_msg(
+                                    "Skipping invalid settings history entry"
+                                )
+                            )
+                        })
+                        .ok()
+                })
+                .collect(),
+            Err(error) => {
+                if error.kind() != io::ErrorKind::NotFound {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Unable to read settings history")
+                    );
+                }
+                VecDeque::new()
+            }
+        };
+        SettingsHistory { entries, path }
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &HistoryEntry> {
+        self.entries.iter()
+    }
+
+    /// Returns the revision of the current settings.
+    pub fn current_revision(&self) -> u64 {
+        self.entries.back().map(|entry| entry.revision).unwrap_or(0)
+    }
+
+    /// Appends the changes between `old_settings` and `new_settings` to the journal as a new
+    /// revision. The oldest revisions are removed when the journal is full.
+    pub fn record(
+        &mut self,
+        old_settings: &Settings,
+        new_settings: &Settings,
+        client: &str,
+    ) -> Result<(), Error> {
+        let mut changes = diff(old_settings, new_settings)?;
+        changes.iter_mut().for_each(redact_change);
+        if changes.is_empty() {
+            return Ok(());
+        }
+        let entry = HistoryEntry {
+            revision: self.current_revision() + 1,
+            timestamp: Utc::now(),
+            client: client.to_owned(),
+            changes,
+        };
+        let line = serde_json::to_string(&entry).map_err(Error::SerializeError)?;
+        self.entries.push_back(entry);
+
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.pop_front();
+            self.rewrite()
+        } else {
+            self.append(&line)
+        }
+    }
+
+    fn append(&self, line: &str) -> Result<(), Error> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| Error::WriteError(self.path.display().to_string(), e))?;
+        writeln!(file, "{}", line)
+            .and_then(|_| file.sync_all())
+            .map_err(|e| Error::WriteError(self.path.display().to_string(), e))
+    }
+
+    /// Replaces the journal with the current entries. The entries are written to a temporary
+    /// file that is renamed over the journal, so that it is never left partially written.
+    fn rewrite(&self) -> Result<(), Error> {
+        let mut contents = String::new();
+        for entry in &self.entries {
+            contents += &serde_json::to_string(entry).map_err(Error::SerializeError)?;
+            contents.push('\n');
+        }
+        let temp_path = self.path.with_extension("jsonl.tmp");
+        let write_error = |e| Error::WriteError(self.path.display().to_string(), e);
+
+        let mut file = File::create(&temp_path).map_err(write_error)?;
+        file.write_all(contents.as_bytes())
+            .and_then(|_| file.sync_all())
+            .map_err(write_error)?;
+        fs::rename(&temp_path, &self.path).map_err(write_error)
+    }
+
+    /// Removes all revisions.
+    #[cfg(not(target_os = "android"))]
+    pub fn clear(&mut self) -> Result<(), Error> {
+        self.entries.clear();
+        match fs::remove_file(&self.path) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => {
+                Err(Error::DeleteError(self.path.display().to_string(), error))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the settings as they were at `revision`, by undoing the changes of all later
+    /// revisions on `settings`.
+    pub fn settings_at(&self, settings: &Settings, revision: u64) -> Result<Settings, Error> {
+        let oldest_revision = self
+            .entries
+            .front()
+            .map(|entry| entry.revision - 1)
+            .unwrap_or(0);
+        if revision < oldest_revision || revision > self.current_revision() {
+            return Err(Error::UnknownRevision(revision));
+        }
+
+        let current_value = serde_json::to_value(settings).map_err(Error::SerializeError)?;
+        let mut settings_value = current_value.clone();
+        for entry in self
+            .entries
+            .iter()
+            .rev()
+            .take_while(|entry| entry.revision > revision)
+        {
+            for change in entry.changes.iter().rev() {
+                set_field(&mut settings_value, &change.field, change.old_value.clone())?;
+            }
+        }
+        restore_secrets(&mut settings_value, Some(&current_value));
+        serde_json::from_value(settings_value).map_err(Error::InvalidSettings)
+    }
+}
+
+/// Returns the fields that differ between `old_settings` and `new_settings`.
+fn diff(old_settings: &Settings, new_settings: &Settings) -> Result<Vec<FieldChange>, Error> {
+    let old_value = serde_json::to_value(old_settings).map_err(Error::SerializeError)?;
+    let new_value = serde_json::to_value(new_settings).map_err(Error::SerializeError)?;
+    let mut changes = Vec::new();
+    diff_values("", Some(&old_value), Some(&new_value), &mut changes);
+    Ok(changes)
+}
+
+fn diff_values(
+    field: &str,
+    old_value: Option<&Value>,
+    new_value: Option<&Value>,
+    changes: &mut Vec<FieldChange>,
+) {
+    if UNJOURNALED_FIELDS.contains(&field) {
+        return;
+    }
+    match (old_value, new_value) {
+        (Some(Value::Object(old_object)), Some(Value::Object(new_object))) => {
+            let new_keys = new_object
+                .keys()
+                .filter(|key| !old_object.contains_key(key.as_str()));
+            for key in old_object.keys().chain(new_keys) {
+                let child_field = if field.is_empty() {
+                    key.to_owned()
+                } else {
+                    format!("{}.{}", field, key)
+                };
+                diff_values(
+                    &child_field,
+                    old_object.get(key),
+                    new_object.get(key),
+                    changes,
+                );
+            }
+        }
+        (old_value, new_value) if old_value != new_value => changes.push(FieldChange {
+            field: field.to_owned(),
+            old_value: old_value.cloned(),
+            new_value: new_value.cloned(),
+        }),
+        _ => (),
+    }
+}
+
+/// Redacts the secrets in the values of `change`. The structure of the values is kept, so that
+/// later changes to fields within a secret can still be undone.
+fn redact_change(change: &mut FieldChange) {
+    let is_secret = change
+        .field
+        .split('.')
+        .any(|key| SECRET_FIELDS.contains(&key));
+    if let Some(value) = &mut change.old_value {
+        redact_value(value, is_secret);
+    }
+    if let Some(value) = &mut change.new_value {
+        redact_value(value, is_secret);
+    }
+}
+
+fn redact_value(value: &mut Value, is_secret: bool) {
+    match value {
+        Value::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                redact_value(field, is_secret || SECRET_FIELDS.contains(&key.as_str()));
+            }
+        }
+        Value::Array(values) => values
+            .iter_mut()
+            .for_each(|value| redact_value(value, is_secret)),
+        Value::Null => (),
+        _ if is_secret => *value = Value::from(REDACTED_SECRET),
+        _ => (),
+    }
+}
+
+/// Replaces every secret field in `value` that contains redacted values with the field at the
+/// same path in `current`, or with `null` if it can't be restored. Secrets are only restored if
+/// the other fields of the object are unchanged, so that the credentials of one proxy are never
+/// given to another.
+fn restore_secrets(value: &mut Value, current: Option<&Value>) {
+    match value {
+        Value::Object(fields) => {
+            let current_fields = current.and_then(Value::as_object);
+            let is_same_object = current_fields
+                .map(|current_fields| {
+                    fields
+                        .iter()
+                        .filter(|(key, _)| !SECRET_FIELDS.contains(&key.as_str()))
+                        .all(|(key, field)| current_fields.get(key) == Some(field))
+                })
+                .unwrap_or(false);
+            for (key, field) in fields.iter_mut() {
+                let current_field = current_fields.and_then(|current| current.get(key));
+                if !SECRET_FIELDS.contains(&key.as_str()) {
+                    restore_secrets(field, current_field);
+                } else if is_redacted(field) {
+                    *field = current_field
+                        .filter(|_| is_same_object)
+                        .cloned()
+                        .unwrap_or(Value::Null);
+                }
+            }
+        }
+        Value::Array(values) => {
+            for (index, element) in values.iter_mut().enumerate() {
+                restore_secrets(element, current.and_then(|current| current.get(index)));
+            }
+        }
+        _ => (),
+    }
+}
+
+fn is_redacted(value: &Value) -> bool {
+    match value {
+        Value::Object(fields) => fields.values().any(is_redacted),
+        Value::Array(values) => values.iter().any(is_redacted),
+        value => value.as_str() == Some(REDACTED_SECRET),
+    }
+}
+
+/// Sets the field at the dot separated path `field` to `value`, or removes it if `value` is
+/// `None`.
+fn set_field(settings: &mut Value, field: &str, value: Option<Value>) -> Result<(), Error> {
+    let mut keys: Vec<&str> = field.split('.').collect();
+    let last_key = keys.pop().ok_or(Error::InconsistentHistory)?;
+    let mut parent = settings;
+    for key in keys {
+        parent = parent.get_mut(key).ok_or(Error::InconsistentHistory)?;
+    }
+    let object = parent.as_object_mut().ok_or(Error::InconsistentHistory)?;
+    match value {
+        Some(value) => {
+            object.insert(last_key.to_owned(), value);
+        }
+        None => {
+            object.remove(last_key);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_types::relay_constraints::{
+        BridgeSettings, Constraint, LocationConstraint, RelayConstraintsUpdate, RelaySettingsUpdate,
+    };
+    use talpid_types::net::openvpn::{
+        LocalProxySettings, ProxyAuth, ProxySettings, Socks5ProxySettings,
+    };
+
+    #[test]
+    fn test_journal_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = SettingsHistory::load(dir.path());
+
+        let old_settings = Settings::default();
+        let mut new_settings = old_settings.clone();
+        new_settings.allow_lan = true;
+        new_settings.tunnel_options.openvpn.mssfix = Some(1400);
+        history
+            .record(&old_settings, &new_settings, "uid 1000")
+            .unwrap();
+        history
+            .record(&new_settings, &new_settings, "uid 1000")
+            .unwrap();
+
+        let contents = fs::read_to_string(dir.path().join(HISTORY_FILE)).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let entry: Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(entry["revision"], 1);
+        assert_eq!(entry["client"], "uid 1000");
+        assert_eq!(
+            entry["changes"],
+            serde_json::json!([
+                { "field": "allow_lan", "old_value": false, "new_value": true },
+                { "field": "tunnel_options.openvpn.mssfix", "old_value": null, "new_value": 1400 },
+            ])
+        );
+
+        let loaded_history = SettingsHistory::load(dir.path());
+        assert_eq!(loaded_history.entries, history.entries);
+        assert_eq!(loaded_history.current_revision(), 1);
+    }
+
+    #[test]
+    fn test_journal_is_bounded() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = SettingsHistory::load(dir.path());
+
+        let mut settings = Settings::default();
+        for _ in 0..MAX_ENTRIES + 5 {
+            let old_settings = settings.clone();
+            settings.allow_lan = !settings.allow_lan;
+            history.record(&old_settings, &settings, "daemon").unwrap();
+        }
+
+        let loaded_history = SettingsHistory::load(dir.path());
+        assert_eq!(loaded_history.entries.len(), MAX_ENTRIES);
+        assert_eq!(loaded_history.entries[0].revision, 6);
+        assert_eq!(loaded_history.current_revision(), MAX_ENTRIES as u64 + 5);
+        assert!(matches!(
+            loaded_history.settings_at(&settings, 4),
+            Err(Error::UnknownRevision(4))
+        ));
+    }
+
+    #[test]
+    fn test_rollback() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = SettingsHistory::load(dir.path());
+
+        let original_settings = Settings::default();
+        let changes: Vec<Box<dyn Fn(&mut Settings)>> = vec![
+            Box::new(|settings| settings.allow_lan = true),
+            Box::new(|settings| settings.block_when_disconnected = true),
+            Box::new(|settings| {
+                settings.update_relay_settings(RelaySettingsUpdate::Normal(
+                    RelayConstraintsUpdate {
+                        location: Some(Constraint::Only(LocationConstraint::Country(
+                            "de".to_owned(),
+                        ))),
+                        ..Default::default()
+                    },
+                ));
+            }),
+            Box::new(|settings| {
+                settings.bridge_settings =
+                    BridgeSettings::Custom(ProxySettings::Local(LocalProxySettings {
+                        port: 1080,
+                        peer: "192.0.2.1:1080".parse().unwrap(),
+                    }))
+            }),
+            Box::new(|settings| settings.allow_lan = false),
+        ];
+
+        let mut revisions = vec![original_settings.clone()];
+        let mut settings = original_settings;
+        for change in changes {
+            let old_settings = settings.clone();
+            change(&mut settings);
+            history.record(&old_settings, &settings, "daemon").unwrap();
+            revisions.push(settings.clone());
+        }
+        assert_eq!(history.current_revision(), 5);
+
+        for (revision, expected_settings) in revisions.iter().enumerate() {
+            assert_eq!(
+                &history.settings_at(&settings, revision as u64).unwrap(),
+                expected_settings
+            );
+        }
+        assert!(matches!(
+            history.settings_at(&settings, 6),
+            Err(Error::UnknownRevision(6))
+        ));
+    }
+
+    #[test]
+    fn test_account_token_is_not_journaled() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = SettingsHistory::load(dir.path());
+
+        let original_settings = Settings::default();
+        let mut settings = original_settings.clone();
+        settings.set_account_token(Some("1234".to_owned()));
+        history
+            .record(&original_settings, &settings, "daemon")
+            .unwrap();
+        assert_eq!(history.current_revision(), 0);
+
+        let old_settings = settings.clone();
+        settings.allow_lan = true;
+        history.record(&old_settings, &settings, "daemon").unwrap();
+        assert_eq!(history.current_revision(), 1);
+
+        let contents = fs::read_to_string(dir.path().join(HISTORY_FILE)).unwrap();
+        assert!(!contents.contains("account_token"));
+        assert!(!contents.contains("1234"));
+
+        let rolled_back_settings = history.settings_at(&settings, 0).unwrap();
+        assert!(!rolled_back_settings.allow_lan);
+        assert_eq!(
+            rolled_back_settings.get_account_token(),
+            Some("1234".to_owned())
+        );
+    }
+
+    fn socks5_bridge(password: &str) -> BridgeSettings {
+        BridgeSettings::Custom(ProxySettings::Socks5(Socks5ProxySettings {
+            address: "192.0.2.1:1080".parse().unwrap(),
+            auth: Some(ProxyAuth {
+                username: "secret-username".to_owned(),
+                password: password.to_owned(),
+            }),
+        }))
+    }
+
+    #[test]
+    fn test_secrets_are_not_journaled() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = SettingsHistory::load(dir.path());
+
+        let original_settings = Settings::default();
+        let mut settings = original_settings.clone();
+        settings.bridge_settings = socks5_bridge("secret-password-1");
+        history
+            .record(&original_settings, &settings, "daemon")
+            .unwrap();
+        let first_settings = settings.clone();
+        settings.bridge_settings = socks5_bridge("secret-password-2");
+        history
+            .record(&first_settings, &settings, "daemon")
+            .unwrap();
+        assert_eq!(history.current_revision(), 2);
+
+        let contents = fs::read_to_string(dir.path().join(HISTORY_FILE)).unwrap();
+        assert!(!contents.contains("secret-"));
+        assert!(!format!("{:?}", history.entries).contains("secret-"));
+        assert!(!format!("{:?}", SettingsHistory::load(dir.path()).entries).contains("secret-"));
+
+        // Secrets that still exist are kept
+        assert_eq!(history.settings_at(&settings, 1).unwrap(), settings);
+        assert_eq!(
+            history.settings_at(&settings, 0).unwrap(),
+            original_settings
+        );
+
+        // Secrets are not given to another proxy
+        let mut other_proxy = settings.clone();
+        other_proxy.bridge_settings =
+            BridgeSettings::Custom(ProxySettings::Socks5(Socks5ProxySettings {
+                address: "192.0.2.2:1080".parse().unwrap(),
+                auth: None,
+            }));
+        history.record(&settings, &other_proxy, "daemon").unwrap();
+        let rolled_back_settings = history.settings_at(&other_proxy, 2).unwrap();
+        match rolled_back_settings.bridge_settings {
+            BridgeSettings::Custom(ProxySettings::Socks5(proxy)) => {
+                assert_eq!(proxy.address.to_string(), "192.0.2.1:1080");
+                assert!(proxy.auth.is_none());
+            }
+            bridge_settings => panic!("unexpected bridge settings: {:?}", bridge_settings),
+        }
+
+        // Secrets that no longer exist can't be restored
+        let mut without_bridge = other_proxy.clone();
+        without_bridge.bridge_settings = original_settings.bridge_settings;
+        history
+            .record(&other_proxy, &without_bridge, "daemon")
+            .unwrap();
+        let rolled_back_settings = history.settings_at(&without_bridge, 1).unwrap();
+        match rolled_back_settings.bridge_settings {
+            BridgeSettings::Custom(ProxySettings::Socks5(proxy)) => assert!(proxy.auth.is_none()),
+            bridge_settings => panic!("unexpected bridge settings: {:?}", bridge_settings),
+        }
+    }
+}
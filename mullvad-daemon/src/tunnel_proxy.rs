@@ -0,0 +1,529 @@
+//! A SOCKS5 proxy hosted by the daemon on a loopback address. It lets applications opt in to
+//! having their traffic sent through the tunnel, which is useful when most traffic is excluded
+//! from the tunnel through split tunneling.
+//!
+//! The proxy only listens while the tunnel is in the connected state. Outgoing connections are
+//! bound to the tunnel interface, so that they cannot leave the computer any other way, even if
+//! the firewall allows other traffic such as LAN traffic. The listener may only use a loopback
+//! address, and loopback traffic is allowed by every firewall implementation in every state.
+
+use futures::{
+    channel::oneshot,
+    future::{self, Either, Shared},
+    FutureExt, TryFutureExt,
+};
+use mullvad_types::settings::TunnelProxySettings;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+use talpid_types::{net::TunnelInterface, tunnel::TunnelStateTransition, ErrorExt};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+const SOCKS_VERSION: u8 = 0x05;
+
+const METHOD_NO_AUTHENTICATION: u8 = 0x00;
+const METHOD_NO_ACCEPTABLE: u8 = 0xff;
+
+const COMMAND_CONNECT: u8 = 0x01;
+
+const ADDRESS_TYPE_IPV4: u8 = 0x01;
+const ADDRESS_TYPE_DOMAIN: u8 = 0x03;
+const ADDRESS_TYPE_IPV6: u8 = 0x04;
+
+const REPLY_SUCCEEDED: u8 = 0x00;
+const REPLY_GENERAL_FAILURE: u8 = 0x01;
+const REPLY_HOST_UNREACHABLE: u8 = 0x04;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const REPLY_ADDRESS_TYPE_NOT_SUPPORTED: u8 = 0x08;
+
+/// Resolves once the proxy should stop. Dropping the sender half also resolves it.
+type CloseSignal = Shared<oneshot::Receiver<()>>;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Refusing to listen on non-loopback address {}", _0)]
+    NonLocalAddress(SocketAddr),
+
+    #[error(display = "Failed to listen on {}", _0)]
+    Bind(SocketAddr, #[error(source)] io::Error),
+}
+
+/// Runs the proxy listener whenever the proxy is enabled and the tunnel is connected.
+pub struct TunnelProxy {
+    settings: TunnelProxySettings,
+    tunnel_interface: Option<Arc<TunnelInterface>>,
+    listener: Option<ListenerHandle>,
+}
+
+struct ListenerHandle {
+    close_tx: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl TunnelProxy {
+    pub fn new(settings: TunnelProxySettings) -> Self {
+        TunnelProxy {
+            settings,
+            tunnel_interface: None,
+            listener: None,
+        }
+    }
+
+    /// Starts or stops the proxy depending on the new tunnel state.
+    pub async fn handle_tunnel_state_transition(&mut self, transition: &TunnelStateTransition) {
+        let tunnel_interface = match transition {
+            TunnelStateTransition::Connected(_, tunnel_interface) => Some(tunnel_interface),
+            _ => None,
+        };
+        if tunnel_interface != self.tunnel_interface.as_deref() {
+            self.tunnel_interface = tunnel_interface.cloned().map(Arc::new);
+            self.restart().await;
+        }
+    }
+
+    /// Applies new settings, restarting the listener if it is running.
+    pub async fn set_settings(&mut self, settings: TunnelProxySettings) {
+        if settings != self.settings {
+            self.settings = settings;
+            self.restart().await;
+        }
+    }
+
+    async fn restart(&mut self) {
+        self.stop().await;
+        if !self.settings.enabled {
+            return;
+        }
+        if let Some(tunnel_interface) = self.tunnel_interface.clone() {
+            if let Err(error) = self.start(tunnel_interface).await {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to start tunnel proxy")
+                );
+            }
+        }
+    }
+
+    async fn start(&mut self, tunnel_interface: Arc<TunnelInterface>) -> Result<(), Error> {
+        let address = self.settings.bind_address;
+        if !address.ip().is_loopback() {
+            return Err(Error::NonLocalAddress(address));
+        }
+        let listener = TcpListener::bind(address)
+            .await
+            .map_err(|error| Error::Bind(address, error))?;
+
+        let (close_tx, close_rx) = oneshot::channel();
+        let task = tokio::spawn(accept_connections(
+            listener,
+            tunnel_interface,
+            close_rx.shared(),
+        ));
+        self.listener = Some(ListenerHandle { close_tx, task });
+
+        log::info!("Tunnel proxy listening on {}", address);
+        Ok(())
+    }
+
+    /// Stops accepting connections and closes all open connections. Waits for the listener to
+    /// be closed, so that the address can be reused immediately.
+    async fn stop(&mut self) {
+        if let Some(listener) = self.listener.take() {
+            log::info!("Stopping tunnel proxy");
+            let _ = listener.close_tx.send(());
+            if let Err(error) = listener.task.await {
+                log::error!("Tunnel proxy listener panicked: {}", error);
+            }
+        }
+    }
+}
+
+async fn accept_connections(
+    mut listener: TcpListener,
+    tunnel_interface: Arc<TunnelInterface>,
+    close_signal: CloseSignal,
+) {
+    loop {
+        let accept = Box::pin(listener.accept());
+        let (client, peer) = match future::select(accept, close_signal.clone()).await {
+            Either::Left((Ok(connection), _)) => connection,
+            Either::Left((Err(error), _)) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to accept tunnel proxy connection")
+                );
+                continue;
+            }
+            Either::Right(_) => break,
+        };
+        log::trace!("Accepted tunnel proxy connection from {}", peer);
+
+        let connection = Box::pin(handle_client(client, tunnel_interface.clone()).map_err(
+            move |error| {
+                log::debug!(
+                    "{}",
+                    error.display_chain_with_msg(&format!("Tunnel proxy connection from {}", peer))
+                );
+            },
+        ));
+        tokio::spawn(future::select(connection, close_signal.clone()).map(|_| ()));
+    }
+}
+
+/// Serves a single SOCKS5 client. Only unauthenticated `CONNECT` requests are supported.
+async fn handle_client(
+    mut client: TcpStream,
+    tunnel_interface: Arc<TunnelInterface>,
+) -> io::Result<()> {
+    let mut header = [0u8; 2];
+    client.read_exact(&mut header).await?;
+    check_version(header[0])?;
+    let mut methods = vec![0u8; usize::from(header[1])];
+    client.read_exact(&mut methods).await?;
+    if !methods.contains(&METHOD_NO_AUTHENTICATION) {
+        client
+            .write_all(&[SOCKS_VERSION, METHOD_NO_ACCEPTABLE])
+            .await?;
+        return Err(invalid_data("No acceptable authentication method"));
+    }
+    client
+        .write_all(&[SOCKS_VERSION, METHOD_NO_AUTHENTICATION])
+        .await?;
+
+    let mut request = [0u8; 4];
+    client.read_exact(&mut request).await?;
+    check_version(request[0])?;
+    if request[1] != COMMAND_CONNECT {
+        send_reply(&mut client, REPLY_COMMAND_NOT_SUPPORTED, None).await?;
+        return Err(invalid_data("Unsupported command"));
+    }
+    let destination = match read_destination(&mut client, request[3]).await? {
+        Some(destination) => destination,
+        None => {
+            send_reply(&mut client, REPLY_ADDRESS_TYPE_NOT_SUPPORTED, None).await?;
+            return Err(invalid_data("Unsupported address type"));
+        }
+    };
+    let destinations = match destination.resolve().await {
+        Ok(destinations) => destinations,
+        Err(error) => {
+            send_reply(&mut client, REPLY_HOST_UNREACHABLE, None).await?;
+            return Err(error);
+        }
+    };
+
+    let mut server = match connect(&destinations, &tunnel_interface).await {
+        Ok(server) => server,
+        Err(error) => {
+            let reply = match error.kind() {
+                io::ErrorKind::NotFound => REPLY_HOST_UNREACHABLE,
+                _ => REPLY_GENERAL_FAILURE,
+            };
+            send_reply(&mut client, reply, None).await?;
+            return Err(error);
+        }
+    };
+    send_reply(&mut client, REPLY_SUCCEEDED, server.local_addr().ok()).await?;
+
+    let (mut client_read, mut client_write) = client.split();
+    let (mut server_read, mut server_write) = server.split();
+    let upload = async {
+        tokio::io::copy(&mut client_read, &mut server_write).await?;
+        server_write.shutdown().await
+    };
+    let download = async {
+        tokio::io::copy(&mut server_read, &mut client_write).await?;
+        client_write.shutdown().await
+    };
+    future::try_join(upload, download).await.map(|_| ())
+}
+
+fn check_version(version: u8) -> io::Result<()> {
+    if version != SOCKS_VERSION {
+        return Err(invalid_data("Unsupported SOCKS version"));
+    }
+    Ok(())
+}
+
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The destination of a `CONNECT` request.
+enum Destination {
+    Address(SocketAddr),
+    Domain(String, u16),
+}
+
+impl Destination {
+    async fn resolve(self) -> io::Result<Vec<SocketAddr>> {
+        match self {
+            Destination::Address(address) => Ok(vec![address]),
+            Destination::Domain(domain, port) => {
+                let addresses = tokio::net::lookup_host((domain.as_str(), port)).await?;
+                Ok(addresses.collect())
+            }
+        }
+    }
+}
+
+/// Reads the destination of a request. Returns `None` if the address type is unknown.
+async fn read_destination(
+    client: &mut TcpStream,
+    address_type: u8,
+) -> io::Result<Option<Destination>> {
+    let ip = match address_type {
+        ADDRESS_TYPE_IPV4 => {
+            let mut octets = [0u8; 4];
+            client.read_exact(&mut octets).await?;
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        ADDRESS_TYPE_IPV6 => {
+            let mut octets = [0u8; 16];
+            client.read_exact(&mut octets).await?;
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        ADDRESS_TYPE_DOMAIN => {
+            let length = client.read_u8().await?;
+            let mut domain = vec![0u8; usize::from(length)];
+            client.read_exact(&mut domain).await?;
+            let domain =
+                String::from_utf8(domain).map_err(|_| invalid_data("Invalid domain name"))?;
+            let port = client.read_u16().await?;
+            return Ok(Some(Destination::Domain(domain, port)));
+        }
+        _ => return Ok(None),
+    };
+    let port = client.read_u16().await?;
+    Ok(Some(Destination::Address(SocketAddr::new(ip, port))))
+}
+
+async fn connect(
+    destinations: &[SocketAddr],
+    tunnel_interface: &TunnelInterface,
+) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "No addresses to connect to");
+    for destination in destinations {
+        match connect_through_tunnel(*destination, tunnel_interface).await {
+            Ok(stream) => return Ok(stream),
+            Err(error) => last_error = error,
+        }
+    }
+    Err(last_error)
+}
+
+/// Connects to `destination` using a socket that is bound to the tunnel interface.
+async fn connect_through_tunnel(
+    destination: SocketAddr,
+    tunnel_interface: &TunnelInterface,
+) -> io::Result<TcpStream> {
+    let domain = match destination {
+        SocketAddr::V4(_) => Domain::ipv4(),
+        SocketAddr::V6(_) => Domain::ipv6(),
+    };
+    let socket = Socket::new(domain, Type::stream(), Some(Protocol::tcp()))?;
+    bind_to_interface(&socket, destination, tunnel_interface)?;
+    TcpStream::connect_std(socket.into_tcp_stream(), &destination).await
+}
+
+#[cfg(target_os = "linux")]
+fn bind_to_interface(
+    socket: &Socket,
+    _destination: SocketAddr,
+    tunnel_interface: &TunnelInterface,
+) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            tunnel_interface.name.as_ptr() as *const libc::c_void,
+            tunnel_interface.name.len() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn bind_to_interface(
+    socket: &Socket,
+    destination: SocketAddr,
+    tunnel_interface: &TunnelInterface,
+) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // Not exposed by the `libc` crate.
+    const IP_BOUND_IF: libc::c_int = 25;
+    const IPV6_BOUND_IF: libc::c_int = 125;
+
+    let interface_name = std::ffi::CString::new(tunnel_interface.name.as_str())
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+    let index = unsafe { libc::if_nametoindex(interface_name.as_ptr()) };
+    if index == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let (level, option) = match destination {
+        SocketAddr::V4(_) => (libc::IPPROTO_IP, IP_BOUND_IF),
+        SocketAddr::V6(_) => (libc::IPPROTO_IPV6, IPV6_BOUND_IF),
+    };
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            option,
+            &index as *const _ as *const libc::c_void,
+            std::mem::size_of_val(&index) as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Windows uses the strong host model for sending, so binding to an address on the tunnel
+/// interface means that packets are sent on the tunnel interface.
+#[cfg(windows)]
+fn bind_to_interface(
+    socket: &Socket,
+    destination: SocketAddr,
+    tunnel_interface: &TunnelInterface,
+) -> io::Result<()> {
+    let tunnel_ip = tunnel_interface
+        .ips
+        .iter()
+        .find(|ip| ip.is_ipv4() == destination.is_ipv4())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                "The tunnel interface has no address of the same family as the destination",
+            )
+        })?;
+    socket.bind(&SocketAddr::new(*tunnel_ip, 0).into())
+}
+
+// Sockets created by the app are routed through the tunnel on Android.
+#[cfg(target_os = "android")]
+fn bind_to_interface(
+    _socket: &Socket,
+    _destination: SocketAddr,
+    _tunnel_interface: &TunnelInterface,
+) -> io::Result<()> {
+    Ok(())
+}
+
+async fn send_reply(
+    client: &mut TcpStream,
+    reply: u8,
+    bound_address: Option<SocketAddr>,
+) -> io::Result<()> {
+    let bound_address =
+        bound_address.unwrap_or_else(|| SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0));
+    let mut response = vec![SOCKS_VERSION, reply, 0x00];
+    match bound_address.ip() {
+        IpAddr::V4(ip) => {
+            response.push(ADDRESS_TYPE_IPV4);
+            response.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            response.push(ADDRESS_TYPE_IPV6);
+            response.extend_from_slice(&ip.octets());
+        }
+    }
+    response.extend_from_slice(&bound_address.port().to_be_bytes());
+    client.write_all(&response).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn loopback_interface() -> Arc<TunnelInterface> {
+        let name = if cfg!(target_os = "macos") {
+            "lo0"
+        } else {
+            "lo"
+        };
+        Arc::new(TunnelInterface {
+            name: name.to_string(),
+            ips: vec![Ipv4Addr::LOCALHOST.into()],
+        })
+    }
+
+    #[test]
+    fn test_connect_request() {
+        let mut runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
+        runtime.block_on(async {
+            let mut echo_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let echo_address = echo_listener.local_addr().unwrap();
+            tokio::spawn(async move {
+                let (mut stream, _) = echo_listener.accept().await.unwrap();
+                let (mut read, mut write) = stream.split();
+                let _ = tokio::io::copy(&mut read, &mut write).await;
+            });
+
+            let mut proxy_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let proxy_address = proxy_listener.local_addr().unwrap();
+            tokio::spawn(async move {
+                let (client, _) = proxy_listener.accept().await.unwrap();
+                let _ = handle_client(client, loopback_interface()).await;
+            });
+
+            let mut client = TcpStream::connect(proxy_address).await.unwrap();
+            client
+                .write_all(&[SOCKS_VERSION, 1, METHOD_NO_AUTHENTICATION])
+                .await
+                .unwrap();
+            let mut method_reply = [0u8; 2];
+            client.read_exact(&mut method_reply).await.unwrap();
+            assert_eq!(method_reply, [SOCKS_VERSION, METHOD_NO_AUTHENTICATION]);
+
+            let mut request = vec![SOCKS_VERSION, COMMAND_CONNECT, 0x00, ADDRESS_TYPE_IPV4];
+            request.extend_from_slice(&[127, 0, 0, 1]);
+            request.extend_from_slice(&echo_address.port().to_be_bytes());
+            client.write_all(&request).await.unwrap();
+            let mut reply = [0u8; 10];
+            client.read_exact(&mut reply).await.unwrap();
+            assert_eq!(&reply[..2], &[SOCKS_VERSION, REPLY_SUCCEEDED]);
+
+            client.write_all(b"ping").await.unwrap();
+            let mut echo = [0u8; 4];
+            client.read_exact(&mut echo).await.unwrap();
+            assert_eq!(&echo, b"ping");
+        });
+    }
+
+    #[test]
+    fn test_rejects_authentication_only_clients() {
+        let mut runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
+        runtime.block_on(async {
+            let mut proxy_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let proxy_address = proxy_listener.local_addr().unwrap();
+            let server = tokio::spawn(async move {
+                let (client, _) = proxy_listener.accept().await.unwrap();
+                handle_client(client, loopback_interface()).await
+            });
+
+            let mut client = TcpStream::connect(proxy_address).await.unwrap();
+            // Offer only username/password authentication
+            client.write_all(&[SOCKS_VERSION, 1, 0x02]).await.unwrap();
+            let mut method_reply = [0u8; 2];
+            client.read_exact(&mut method_reply).await.unwrap();
+            assert_eq!(method_reply, [SOCKS_VERSION, METHOD_NO_ACCEPTABLE]);
+            assert!(server.await.unwrap().is_err());
+        });
+    }
+}
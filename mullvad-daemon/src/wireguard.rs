@@ -29,6 +29,8 @@ const ROTATION_START_DELAY: Duration = Duration::from_secs(60 * 3);
 /// How often to check whether the key has expired.
 /// A short interval is used in case the computer is ever suspended.
 const KEY_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// How often to check whether the key has been revoked from another device.
+const REVOCATION_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -36,6 +38,12 @@ pub enum Error {
     RestError(#[error(source)] mullvad_rpc::rest::Error),
     #[error(display = "Account already has maximum number of keys")]
     TooManyKeys,
+    #[error(display = "No account token is set")]
+    NoAccountToken,
+    #[error(display = "The key is in use by this device")]
+    RevokeCurrentKey,
+    #[error(display = "Failed to update account history")]
+    AccountHistoryError(#[error(source)] crate::account_history::Error),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -47,6 +55,7 @@ pub struct KeyManager {
 
     abort_scheduler_tx: Option<AbortHandle>,
     auto_rotation_interval: Duration,
+    abort_revocation_check: Option<AbortHandle>,
 }
 
 impl KeyManager {
@@ -57,6 +66,7 @@ impl KeyManager {
             current_job: None,
             abort_scheduler_tx: None,
             auto_rotation_interval: Duration::new(0, 0),
+            abort_revocation_check: None,
         }
     }
 
@@ -73,6 +83,7 @@ impl KeyManager {
             .map(|entry| entry.map(|entry| entry.wireguard.map(|wg| wg.get_public_key())))
         {
             Ok(Some(Some(public_key))) => {
+                self.run_revocation_check(account_token.clone(), public_key.key.clone());
                 self.run_automatic_rotation(account_token, public_key).await
             }
             Ok(Some(None)) => {
@@ -148,6 +159,99 @@ impl KeyManager {
         }
     }
 
+    /// Lists all keys registered on the account. If `current_key` is missing from the list, the
+    /// daemon is notified that the key has been revoked.
+    pub fn list_keys(
+        &self,
+        account: AccountToken,
+        current_key: Option<talpid_types::net::wireguard::PublicKey>,
+        current_label: Option<String>,
+    ) -> impl Future<Output = Result<Vec<RegisteredKey>>> {
+        let mut rpc = mullvad_rpc::WireguardKeyProxy::new(self.http_handle.clone());
+        let daemon_tx = self.daemon_tx.clone();
+        async move {
+            let mut keys = rpc
+                .list_wireguard_keys(account.clone())
+                .await
+                .map_err(Self::map_rpc_error)?;
+            if let Some(current_key) = current_key {
+                match keys.iter_mut().find(|key| key.pubkey == current_key) {
+                    Some(key) => key.label = current_label,
+                    None => {
+                        let _ =
+                            daemon_tx.send(InternalDaemonEvent::WgKeyRevoked(account, current_key));
+                    }
+                }
+            }
+            Ok(keys)
+        }
+    }
+
+    /// Removes a key from the account.
+    pub fn revoke_key(
+        &self,
+        account: AccountToken,
+        key: talpid_types::net::wireguard::PublicKey,
+    ) -> impl Future<Output = Result<()>> {
+        let mut rpc = mullvad_rpc::WireguardKeyProxy::new(self.http_handle.clone());
+        async move {
+            rpc.remove_wireguard_key(account, &key)
+                .await
+                .map_err(Self::map_rpc_error)
+        }
+    }
+
+    /// Periodically checks that `public_key` is still registered on the account, and notifies the
+    /// daemon if it has been removed, e.g. from another device. Any previous check is cancelled.
+    pub fn run_revocation_check(
+        &mut self,
+        account: AccountToken,
+        public_key: talpid_types::net::wireguard::PublicKey,
+    ) {
+        self.stop_revocation_check();
+
+        let mut rpc = mullvad_rpc::WireguardKeyProxy::new(self.http_handle.clone());
+        let daemon_tx = self.daemon_tx.clone();
+        let fut = async move {
+            let mut interval = tokio::time::interval_at(
+                (Instant::now() + REVOCATION_CHECK_INTERVAL).into(),
+                REVOCATION_CHECK_INTERVAL,
+            );
+            loop {
+                interval.tick().await;
+                match rpc.get_wireguard_key(account.clone(), &public_key).await {
+                    Ok(_) => (),
+                    Err(RestError::ApiError(status, _code))
+                        if status == mullvad_rpc::StatusCode::NOT_FOUND =>
+                    {
+                        let _ = daemon_tx.send(InternalDaemonEvent::WgKeyRevoked(
+                            account.clone(),
+                            public_key.clone(),
+                        ));
+                        return;
+                    }
+                    Err(err) => {
+                        log::debug!(
+                            "{}",
+                            err.display_chain_with_msg("Failed to check whether key was revoked")
+                        );
+                    }
+                }
+            }
+        };
+        let (request, abort_handle) = abortable(Box::pin(fut));
+
+        tokio::spawn(request);
+        self.abort_revocation_check = Some(abort_handle);
+    }
+
+    /// Stops checking whether the current key has been revoked, e.g. when the account changes.
+    pub fn stop_revocation_check(&mut self) {
+        if let Some(abort_handle) = self.abort_revocation_check.take() {
+            abort_handle.abort();
+        }
+    }
+
 
     /// Generate a new private key asynchronously. The new keys will be sent to the daemon channel.
     pub async fn spawn_key_generation_task(
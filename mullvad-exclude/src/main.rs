@@ -12,7 +12,7 @@ use std::{
 };
 
 #[cfg(target_os = "linux")]
-use talpid_types::cgroup::{find_net_cls_mount, SPLIT_TUNNEL_CGROUP_NAME};
+use talpid_types::cgroup::find_split_tunnel_hierarchy;
 
 #[cfg(target_os = "linux")]
 const PROGRAM_NAME: &str = "mullvad-exclude";
@@ -39,11 +39,11 @@ enum Error {
     #[error(display = "An argument contains interior nul bytes")]
     ArgumentNulError(#[error(source)] NulError),
 
-    #[error(display = "Failed to find net_cls controller")]
-    FindNetClsController(#[error(source)] io::Error),
+    #[error(display = "Failed to find cgroup hierarchy")]
+    FindCGroupHierarchy(#[error(source)] io::Error),
 
-    #[error(display = "No net_cls controller")]
-    NoNetClsController,
+    #[error(display = "No net_cls controller or cgroup v2 hierarchy")]
+    NoCGroupHierarchy,
 }
 
 fn main() {
@@ -83,13 +83,11 @@ fn run() -> Result<Infallible, Error> {
         .map_err(Error::ArgumentNulError)?;
     let args: Vec<&CStr> = args.iter().map(|arg| &**arg).collect();
 
-    let cgroup_dir = find_net_cls_mount()
-        .map_err(Error::FindNetClsController)?
-        .ok_or(Error::NoNetClsController)?;
+    let hierarchy = find_split_tunnel_hierarchy()
+        .map_err(Error::FindCGroupHierarchy)?
+        .ok_or(Error::NoCGroupHierarchy)?;
 
-    let procs_path = cgroup_dir
-        .join(SPLIT_TUNNEL_CGROUP_NAME)
-        .join("cgroup.procs");
+    let procs_path = hierarchy.exclusions_path().join("cgroup.procs");
 
     let file = fs::OpenOptions::new()
         .write(true)
@@ -56,6 +56,7 @@ pub const CLASSES: &[&str] = &[
     "net/mullvad/talpid/tunnel/ErrorStateCause$TunnelParameterError",
     "net/mullvad/talpid/tunnel/ErrorStateCause$IsOffline",
     "net/mullvad/talpid/tunnel/ErrorStateCause$TapAdapterProblem",
+    "net/mullvad/talpid/tunnel/ErrorStateCause$AccountExpired",
     "net/mullvad/talpid/tunnel/ErrorStateCause$VpnPermissionDenied",
     "net/mullvad/talpid/tunnel/ParameterGenerationError",
     "net/mullvad/talpid/ConnectivityListener",
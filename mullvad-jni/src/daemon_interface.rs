@@ -24,6 +24,9 @@ pub enum Error {
 
     #[error(display = "Error performing RPC with the remote API")]
     RpcError(#[error(source)] mullvad_rpc::rest::Error),
+
+    #[error(display = "Failed to update settings")]
+    SettingsError,
 }
 
 type Result<T> = std::result::Result<T, Error>;
@@ -181,7 +184,9 @@ impl DaemonInterface {
 
         self.send_command(DaemonCommand::SetAccount(tx, account_token))?;
 
-        block_on(rx).map_err(|_| Error::NoResponse)
+        block_on(rx)
+            .map_err(|_| Error::NoResponse)?
+            .map_err(|_| Error::SettingsError)
     }
 
     pub fn set_allow_lan(&self, allow_lan: bool) -> Result<()> {
@@ -189,7 +194,9 @@ impl DaemonInterface {
 
         self.send_command(DaemonCommand::SetAllowLan(tx, allow_lan))?;
 
-        block_on(rx).map_err(|_| Error::NoResponse)
+        block_on(rx)
+            .map_err(|_| Error::NoResponse)?
+            .map_err(|_| Error::SettingsError)
     }
 
     pub fn set_auto_connect(&self, auto_connect: bool) -> Result<()> {
@@ -197,7 +204,9 @@ impl DaemonInterface {
 
         self.send_command(DaemonCommand::SetAutoConnect(tx, auto_connect))?;
 
-        block_on(rx).map_err(|_| Error::NoResponse)
+        block_on(rx)
+            .map_err(|_| Error::NoResponse)?
+            .map_err(|_| Error::SettingsError)
     }
 
     pub fn set_wireguard_mtu(&self, wireguard_mtu: Option<u16>) -> Result<()> {
@@ -205,7 +214,9 @@ impl DaemonInterface {
 
         self.send_command(DaemonCommand::SetWireguardMtu(tx, wireguard_mtu))?;
 
-        block_on(rx).map_err(|_| Error::NoResponse)
+        block_on(rx)
+            .map_err(|_| Error::NoResponse)?
+            .map_err(|_| Error::SettingsError)
     }
 
     pub fn shutdown(&self) -> Result<()> {
@@ -227,7 +238,9 @@ impl DaemonInterface {
 
         self.send_command(DaemonCommand::UpdateRelaySettings(tx, update))?;
 
-        block_on(rx).map_err(|_| Error::NoResponse)
+        block_on(rx)
+            .map_err(|_| Error::NoResponse)?
+            .map_err(|_| Error::SettingsError)
     }
 
     fn send_command(&self, command: DaemonCommand) -> Result<()> {
@@ -18,7 +18,8 @@ use jnix::{
     FromJava, IntoJava, JnixEnv,
 };
 use mullvad_daemon::{
-    exception_logging, logging, runtime::new_runtime_builder, version, Daemon, DaemonCommandChannel,
+    exception_logging, logging, policy::SettingsPolicy, runtime::new_runtime_builder, version,
+    Daemon, DaemonCommandChannel,
 };
 use mullvad_rpc::{rest::Error as RestError, StatusCode};
 use mullvad_types::account::{AccountData, VoucherSubmission};
@@ -148,8 +149,13 @@ fn start_logging(log_dir: &Path) -> Result<(), String> {
 fn initialize_logging(log_dir: &Path) -> Result<(), String> {
     let log_file = log_dir.join(LOG_FILENAME);
 
-    logging::init_logger(log::LevelFilter::Debug, Some(&log_file), true)
-        .map_err(|error| error.display_chain_with_msg("Failed to start logger"))?;
+    logging::init_logger(
+        log::LevelFilter::Debug,
+        Some(&log_file),
+        true,
+        logging::LogRotation::default(),
+    )
+    .map_err(|error| error.display_chain_with_msg("Failed to start logger"))?;
     exception_logging::enable();
     log_panics::init();
     version::log_version();
@@ -221,6 +227,7 @@ fn spawn_daemon(
             cache_dir,
             listener,
             command_channel,
+            SettingsPolicy::default(),
             android_context,
         ));
 
@@ -908,7 +915,13 @@ pub extern "system" fn Java_net_mullvad_mullvadvpn_dataproxy_MullvadProblemRepor
     let output_path_string = String::from_java(&env, outputPath);
     let output_path = Path::new(&output_path_string);
 
-    match mullvad_problem_report::collect_report(&[], output_path, Vec::new(), log_dir) {
+    match mullvad_problem_report::collect_report(
+        &[],
+        output_path,
+        mullvad_problem_report::redact::RedactionPipeline::with_defaults(Vec::new()),
+        mullvad_problem_report::ReportFormat::Text,
+        log_dir,
+    ) {
         Ok(()) => JNI_TRUE,
         Err(error) => {
             log::error!(
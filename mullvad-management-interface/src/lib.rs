@@ -4,16 +4,18 @@ pub mod types {
     pub use prost_types::Timestamp;
 }
 
+use futures::{
+    future::{self, Either},
+    FutureExt, Stream, StreamExt,
+};
 use parity_tokio_ipc::Endpoint as IpcEndpoint;
 #[cfg(unix)]
 use std::{fs, os::unix::fs::PermissionsExt};
-use std::{
-    io,
-    pin::Pin,
-    task::{Context, Poll},
-};
+use std::{io, net::SocketAddr};
 use tokio::io::{AsyncRead, AsyncWrite};
-use tonic::transport::{server::Connected, Endpoint, Server, Uri};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tonic::transport::{Certificate, Endpoint, Identity, Server, ServerTlsConfig, Uri};
 use tower::service_fn;
 
 pub use tonic::{async_trait, transport::Channel, Code, Request, Response, Status};
@@ -31,6 +33,9 @@ pub enum Error {
     #[error(display = "Failed to start IPC pipe/socket")]
     StartServerError(#[error(source)] io::Error),
 
+    #[error(display = "Failed to accept IPC connection")]
+    AcceptError(#[error(source)] io::Error),
+
     #[error(display = "Failed to initialize pipe/socket security attributes")]
     SecurityAttributes(#[error(source)] io::Error),
 
@@ -38,6 +43,9 @@ pub enum Error {
     PermissionsError(#[error(source)] io::Error),
 }
 
+mod peer_credentials;
+pub use peer_credentials::PeerCredentials;
+
 #[cfg(target_os = "windows")]
 mod windows_permissions;
 
@@ -55,26 +63,54 @@ pub async fn new_rpc_client() -> Result<ManagementServiceClient, Error> {
     Ok(ManagementServiceClient::new(channel))
 }
 
-pub async fn spawn_rpc_server<T: ManagementService>(
-    service: T,
+/// Serves the management interface on the IPC socket or pipe. Each connection is served by its
+/// own service, which `make_service` creates from the credentials of the client. The credentials
+/// are read from the connection when it is accepted, and are `None` if they are unavailable.
+pub async fn spawn_rpc_server<T, F>(
+    make_service: F,
     server_start_tx: std::sync::mpsc::Sender<()>,
     abort_rx: triggered::Listener,
-) -> std::result::Result<(), Error> {
+) -> std::result::Result<(), Error>
+where
+    T: ManagementService,
+    F: Fn(Option<PeerCredentials>) -> T,
+{
     use futures::stream::TryStreamExt;
-    use parity_tokio_ipc::SecurityAttributes;
 
     let socket_path = mullvad_paths::get_rpc_socket_path()
         .to_string_lossy()
         .to_string();
 
-    let mut endpoint = IpcEndpoint::new(socket_path.to_string());
-    endpoint.set_security_attributes(
-        SecurityAttributes::allow_everyone_create()
-            .map_err(Error::SecurityAttributes)?
-            .set_mode(777)
-            .map_err(Error::SecurityAttributes)?,
-    );
-    let incoming = endpoint.incoming().map_err(Error::StartServerError)?;
+    // The Unix socket is created directly, since the credentials of the peer can't be read from
+    // the connections returned by `parity_tokio_ipc`.
+    #[cfg(unix)]
+    let incoming = {
+        // A socket may be left behind if the daemon was not shut down cleanly. Another instance
+        // can't be using it, since the daemon checks for that before starting.
+        let _ = fs::remove_file(&socket_path);
+        UnixListener::bind(&socket_path)
+            .map_err(Error::StartServerError)?
+            .map_ok(|stream| {
+                let credentials = PeerCredentials::from_unix_stream(&stream).ok();
+                (stream, credentials)
+            })
+    };
+    #[cfg(windows)]
+    let incoming = {
+        use parity_tokio_ipc::SecurityAttributes;
+
+        let mut endpoint = IpcEndpoint::new(socket_path.to_string());
+        endpoint.set_security_attributes(
+            SecurityAttributes::allow_everyone_create()
+                .map_err(Error::SecurityAttributes)?
+                .set_mode(777)
+                .map_err(Error::SecurityAttributes)?,
+        );
+        endpoint
+            .incoming()
+            .map_err(Error::StartServerError)?
+            .map_ok(|stream| (stream, None))
+    };
 
     #[cfg(unix)]
     fs::set_permissions(&socket_path, PermissionsExt::from_mode(0o766))
@@ -85,39 +121,97 @@ pub async fn spawn_rpc_server<T: ManagementService>(
 
     let _ = server_start_tx.send(());
 
-    Server::builder()
-        .add_service(ManagementServiceServer::new(service))
-        .serve_with_incoming_shutdown(incoming.map_ok(StreamBox), abort_rx)
-        .await
-        .map_err(Error::GrpcTransportError)
+    serve_connections(incoming, make_service, abort_rx).await
 }
 
-#[derive(Debug)]
-struct StreamBox<T: AsyncRead + AsyncWrite>(pub T);
-impl<T: AsyncRead + AsyncWrite> Connected for StreamBox<T> {}
-impl<T: AsyncRead + AsyncWrite + Unpin> AsyncRead for StreamBox<T> {
-    fn poll_read(
-        mut self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        buf: &mut [u8],
-    ) -> Poll<std::io::Result<usize>> {
-        Pin::new(&mut self.0).poll_read(cx, buf)
+async fn serve_connections<I, S, T, F>(
+    incoming: I,
+    make_service: F,
+    abort_rx: triggered::Listener,
+) -> std::result::Result<(), Error>
+where
+    I: Stream<Item = io::Result<(S, Option<PeerCredentials>)>>,
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    T: ManagementService,
+    F: Fn(Option<PeerCredentials>) -> T,
+{
+    let mut http = hyper::server::conn::Http::new();
+    http.http2_only(true);
+
+    let mut incoming = Box::pin(incoming);
+    loop {
+        let (stream, credentials) = match future::select(incoming.next(), abort_rx.clone()).await {
+            Either::Left((Some(connection), _)) => connection.map_err(Error::AcceptError)?,
+            Either::Left((None, _)) | Either::Right(_) => break,
+        };
+        let service = ManagementServiceServer::new(make_service(credentials));
+        let connection = Box::pin(http.serve_connection(stream, service));
+        tokio::spawn(future::select(connection, abort_rx.clone()).map(|_| ()));
     }
+    Ok(())
 }
-impl<T: AsyncRead + AsyncWrite + Unpin> AsyncWrite for StreamBox<T> {
-    fn poll_write(
-        mut self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        buf: &[u8],
-    ) -> Poll<std::io::Result<usize>> {
-        Pin::new(&mut self.0).poll_write(cx, buf)
-    }
 
-    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
-        Pin::new(&mut self.0).poll_flush(cx)
-    }
+/// Configuration of a listener that serves the management interface over TCP, using TLS with
+/// client authentication.
+#[derive(Debug, Clone)]
+pub struct RemoteServerConfig {
+    /// Address to listen on.
+    pub address: SocketAddr,
+    /// PEM encoded certificate chain of the server.
+    pub certificate: Vec<u8>,
+    /// PEM encoded private key of the server.
+    pub private_key: Vec<u8>,
+    /// PEM encoded CA certificate that must have issued the client certificates.
+    pub client_ca: Vec<u8>,
+    /// SHA-256 fingerprints of the DER encoded client certificates that may connect.
+    pub allowed_clients: Vec<[u8; 32]>,
+}
+
+pub async fn spawn_remote_rpc_server<T: ManagementService>(
+    service: T,
+    config: RemoteServerConfig,
+    abort_rx: triggered::Listener,
+) -> std::result::Result<(), Error> {
+    let tls_config = ServerTlsConfig::new()
+        .identity(Identity::from_pem(&config.certificate, &config.private_key))
+        .client_ca_root(Certificate::from_pem(&config.client_ca));
+    let allowed_clients = config.allowed_clients;
+
+    Server::builder()
+        .tls_config(tls_config)
+        .add_service(ManagementServiceServer::with_interceptor(
+            service,
+            move |request: Request<()>| {
+                check_client_certificate(&request, &allowed_clients)?;
+                Ok(request)
+            },
+        ))
+        .serve_with_shutdown(config.address, abort_rx)
+        .await
+        .map_err(Error::GrpcTransportError)
+}
 
-    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
-        Pin::new(&mut self.0).poll_shutdown(cx)
+/// Fails unless the request was sent by a client that presented one of the allowed certificates.
+fn check_client_certificate<T>(
+    request: &Request<T>,
+    allowed_clients: &[[u8; 32]],
+) -> std::result::Result<(), Status> {
+    let certificates = request
+        .peer_certs()
+        .ok_or_else(|| Status::unauthenticated("A client certificate is required"))?;
+    // The end-entity certificate comes first in the chain
+    let certificate = certificates
+        .first()
+        .ok_or_else(|| Status::unauthenticated("A client certificate is required"))?;
+    let fingerprint = ring::digest::digest(&ring::digest::SHA256, certificate.get_ref());
+    if allowed_clients
+        .iter()
+        .any(|allowed| &allowed[..] == fingerprint.as_ref())
+    {
+        Ok(())
+    } else {
+        Err(Status::permission_denied(
+            "The client certificate is not allowed to manage the daemon",
+        ))
     }
 }
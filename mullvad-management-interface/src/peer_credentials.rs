@@ -0,0 +1,34 @@
+/// Credentials of the process on the other end of a management interface connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCredentials {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl PeerCredentials {
+    /// Reads the credentials of the peer of a Unix domain socket.
+    #[cfg(unix)]
+    pub fn from_unix_stream(stream: &tokio::net::UnixStream) -> std::io::Result<Self> {
+        let credentials = stream.peer_cred()?;
+        Ok(PeerCredentials {
+            uid: credentials.uid,
+            gid: credentials.gid,
+        })
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_socketpair_credentials() {
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let credentials = runtime.block_on(async {
+            let (stream, _peer) = tokio::net::UnixStream::pair().unwrap();
+            PeerCredentials::from_unix_stream(&stream).unwrap()
+        });
+        assert_eq!(credentials.uid, unsafe { libc::getuid() });
+        assert_eq!(credentials.gid, unsafe { libc::getgid() });
+    }
+}
@@ -1,9 +1,7 @@
 #![deny(rust_2018_idioms)]
 
-use lazy_static::lazy_static;
-use regex::Regex;
+use serde::Serialize;
 use std::{
-    borrow::Cow,
     cmp::min,
     collections::{BTreeMap, HashSet},
     ffi::OsStr,
@@ -15,6 +13,9 @@ use talpid_types::ErrorExt;
 
 
 pub mod metadata;
+pub mod redact;
+
+use redact::{RedactionPipeline, Redactions};
 
 /// Maximum number of bytes to read from each log file
 const LOG_MAX_READ_BYTES: usize = 128 * 1024;
@@ -69,6 +70,30 @@ pub enum Error {
 
     #[error(display = "Unable to spawn Tokio runtime")]
     CreateRuntime(#[error(source)] io::Error),
+
+    #[error(display = "JSON problem reports are for local use only and can not be sent")]
+    LocalOnlyReport,
+
+    #[error(display = "Failed to read redaction rules from {}", path)]
+    ReadRedactionRules {
+        path: String,
+        #[error(source)]
+        source: io::Error,
+    },
+
+    #[error(display = "Failed to parse redaction rules in {}", path)]
+    ParseRedactionRules {
+        path: String,
+        #[error(source)]
+        source: serde_json::Error,
+    },
+
+    #[error(display = "Invalid pattern in redaction rule \"{}\"", name)]
+    InvalidRedactionRule {
+        name: String,
+        #[error(source)]
+        source: regex::Error,
+    },
 }
 
 /// These are errors that can happen during problem report collection.
@@ -96,15 +121,88 @@ pub enum LogError {
     #[cfg(target_os = "windows")]
     #[error(display = "Missing %LOCALAPPDATA% environment variable")]
     NoLocalAppDataDir,
+
+    #[cfg(not(target_os = "android"))]
+    #[error(display = "Unable to spawn Tokio runtime")]
+    CreateRuntime(#[error(source)] io::Error),
+
+    #[cfg(not(target_os = "android"))]
+    #[error(display = "Failed to connect to the daemon")]
+    ConnectDaemon(#[error(source)] mullvad_management_interface::Error),
+
+    #[cfg(not(target_os = "android"))]
+    #[error(display = "Failed to get diagnostics from the daemon")]
+    GetDiagnostics(#[error(source)] mullvad_management_interface::Status),
+}
+
+/// The format of a collected problem report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// System information and logs as plain text. This is the format sent to support.
+    Text,
+    /// A JSON bundle that in addition to the logs contains diagnostics from the daemon, such as
+    /// the settings, tunnel state and routes. It is meant for local tooling and is never sent.
+    /// Text reports on Linux also include the system state part of the diagnostics.
+    Json,
+}
+
+/// Identifies the kind of a JSON report. It is written into every JSON report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ReportKind {
+    /// The report contains diagnostics that are meant for local tooling only.
+    LocalOnly,
 }
 
+/// Collects a problem report and writes it to `output_path`. All text in the report is passed
+/// through `redactors`.
 pub fn collect_report(
     extra_logs: &[&Path],
     output_path: &Path,
-    redact_custom_strings: Vec<String>,
+    redactors: RedactionPipeline,
+    format: ReportFormat,
     #[cfg(target_os = "android")] android_log_dir: &Path,
 ) -> Result<(), Error> {
-    let mut problem_report = ProblemReport::new(redact_custom_strings);
+    let problem_report = collect(
+        extra_logs,
+        redactors,
+        format,
+        #[cfg(target_os = "android")]
+        android_log_dir,
+    );
+
+    write_problem_report(&output_path, &problem_report, format).map_err(|source| {
+        Error::WriteReportError {
+            path: output_path.display().to_string(),
+            source,
+        }
+    })
+}
+
+/// Collects a problem report without writing it anywhere, and returns what was redacted from it.
+pub fn preview_redactions(
+    extra_logs: &[&Path],
+    redactors: RedactionPipeline,
+    format: ReportFormat,
+    #[cfg(target_os = "android")] android_log_dir: &Path,
+) -> Redactions {
+    collect(
+        extra_logs,
+        redactors,
+        format,
+        #[cfg(target_os = "android")]
+        android_log_dir,
+    )
+    .redactions
+}
+
+fn collect(
+    extra_logs: &[&Path],
+    redactors: RedactionPipeline,
+    format: ReportFormat,
+    #[cfg(target_os = "android")] android_log_dir: &Path,
+) -> ProblemReport {
+    let mut problem_report = ProblemReport::new(redactors);
 
     let daemon_logs_dir = {
         #[cfg(target_os = "android")]
@@ -163,9 +261,37 @@ pub fn collect_report(
 
     problem_report.add_logs(extra_logs);
 
-    write_problem_report(&output_path, &problem_report).map_err(|source| Error::WriteReportError {
-        path: output_path.display().to_string(),
-        source,
+    #[cfg(not(target_os = "android"))]
+    {
+        if format == ReportFormat::Json || cfg!(target_os = "linux") {
+            match collect_daemon_diagnostics() {
+                Ok(diagnostics) => problem_report.add_diagnostics(diagnostics),
+                Err(error) => problem_report
+                    .add_error("Failed to collect diagnostics from the daemon", &error),
+            }
+        }
+    }
+
+    problem_report
+}
+
+#[cfg(not(target_os = "android"))]
+fn collect_daemon_diagnostics() -> Result<mullvad_management_interface::types::Diagnostics, LogError>
+{
+    let mut runtime = tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_all()
+        .build()
+        .map_err(LogError::CreateRuntime)?;
+
+    runtime.block_on(async {
+        let mut rpc = mullvad_management_interface::new_rpc_client()
+            .await
+            .map_err(LogError::ConnectDaemon)?;
+        rpc.get_diagnostics(())
+            .await
+            .map(|response| response.into_inner())
+            .map_err(LogError::GetDiagnostics)
     })
 }
 
@@ -254,6 +380,12 @@ pub fn send_problem_report(
     user_message: &str,
     report_path: &Path,
 ) -> Result<(), Error> {
+    let is_json_report = File::open(report_path)
+        .map(|file| is_json_report(io::BufReader::new(file)))
+        .unwrap_or(false);
+    if is_json_report {
+        return Err(Error::LocalOnlyReport);
+    }
     let report_content = normalize_newlines(
         read_file_lossy(report_path, REPORT_MAX_SIZE).map_err(|source| {
             Error::ReadProblemReportError {
@@ -280,34 +412,80 @@ pub fn send_problem_report(
         .map_err(Error::SendRpcError)
 }
 
-fn write_problem_report(path: &Path, problem_report: &ProblemReport) -> io::Result<()> {
+/// Returns whether a report parses as a JSON object. Such reports are never sent, whatever their
+/// `kind` is, since they contain more than what is sent to support.
+fn is_json_report(report: impl io::Read) -> bool {
+    serde_json::from_reader::<_, serde_json::Map<String, serde::de::IgnoredAny>>(report).is_ok()
+}
+
+fn write_problem_report(
+    path: &Path,
+    problem_report: &ProblemReport,
+    format: ReportFormat,
+) -> io::Result<()> {
     let file = File::create(path)?;
     let mut permissions = file.metadata()?.permissions();
     permissions.set_readonly(true);
     file.set_permissions(permissions)?;
-    problem_report.write_to(BufWriter::new(file))?;
+    match format {
+        ReportFormat::Text => problem_report.write_to(BufWriter::new(file))?,
+        ReportFormat::Json => problem_report.write_json_to(BufWriter::new(file))?,
+    }
     Ok(())
 }
 
 #[derive(Debug)]
 struct ProblemReport {
     metadata: BTreeMap<String, String>,
-    logs: Vec<(String, String)>,
+    logs: Vec<LogEntry>,
     log_paths: HashSet<PathBuf>,
-    redact_custom_strings: Vec<String>,
+    #[cfg(not(target_os = "android"))]
+    diagnostics: Option<Diagnostics>,
+    redactors: RedactionPipeline,
+    redactions: Redactions,
+}
+
+#[derive(Debug, Serialize)]
+struct LogEntry {
+    /// Name of the log file, or `None` if the entry is an error message.
+    file_name: Option<String>,
+    label: String,
+    content: String,
+}
+
+/// Diagnostics from the daemon, as included in JSON reports.
+#[cfg(not(target_os = "android"))]
+#[derive(Debug, Serialize)]
+struct Diagnostics {
+    settings: serde_json::Value,
+    tunnel_state: serde_json::Value,
+    recent_events: Vec<serde_json::Value>,
+    dns_manager: Option<String>,
+    sections: Vec<DiagnosticsSection>,
+}
+
+/// A part of the system state collected by the daemon, such as the routing table.
+#[cfg(not(target_os = "android"))]
+#[derive(Debug, Serialize)]
+struct DiagnosticsSection {
+    name: String,
+    content: Option<String>,
+    /// Why the section could not be collected, if it failed.
+    error: Option<String>,
 }
 
 impl ProblemReport {
     /// Creates a new problem report with system information. Logs can be added with `add_log`.
-    /// Logs will have all strings in `redact_custom_strings` removed from them.
-    pub fn new(mut redact_custom_strings: Vec<String>) -> Self {
-        redact_custom_strings.retain(|redact| !redact.is_empty());
-
+    /// All text added to the report is passed through `redactors`.
+    pub fn new(redactors: RedactionPipeline) -> Self {
         ProblemReport {
             metadata: metadata::collect(),
             logs: Vec::new(),
             log_paths: HashSet::new(),
-            redact_custom_strings,
+            #[cfg(not(target_os = "android"))]
+            diagnostics: None,
+            redactors,
+            redactions: Redactions::default(),
         }
     }
 
@@ -337,7 +515,14 @@ impl ProblemReport {
                     ))
                 },
             ));
-            self.logs.push((redacted_path, content));
+            let file_name = path
+                .file_name()
+                .map(|file_name| self.redact(&file_name.to_string_lossy()));
+            self.logs.push(LogEntry {
+                file_name,
+                label: redacted_path,
+                content,
+            });
             println!("Adding {}", expanded_path.display());
         }
     }
@@ -345,54 +530,60 @@ impl ProblemReport {
     /// Attach an error to the report.
     pub fn add_error(&mut self, message: &'static str, error: &impl ErrorExt) {
         let redacted_error = self.redact(&error.display_chain());
-        self.logs.push((message.to_string(), redacted_error));
-    }
-
-    fn redact(&self, input: &str) -> String {
-        let out1 = Self::redact_account_number(input);
-        let out2 = Self::redact_home_dir(&out1);
-        let out3 = Self::redact_network_info(&out2);
-        self.redact_custom_strings(&out3).to_string()
+        self.logs.push(LogEntry {
+            file_name: None,
+            label: message.to_string(),
+            content: redacted_error,
+        });
     }
 
-    fn redact_account_number(input: &str) -> Cow<'_, str> {
-        lazy_static! {
-            static ref RE: Regex = Regex::new("\\d{16}").unwrap();
-        }
-        RE.replace_all(input, "[REDACTED ACCOUNT NUMBER]")
-    }
-
-    fn redact_home_dir(input: &str) -> Cow<'_, str> {
-        match dirs::home_dir() {
-            Some(home) => Cow::from(input.replace(home.to_string_lossy().as_ref(), "~")),
-            None => Cow::from(input),
-        }
+    /// Attach diagnostics from the daemon to the report. They are included in full in JSON reports.
+    /// Text reports only include the system state sections.
+    #[cfg(not(target_os = "android"))]
+    pub fn add_diagnostics(
+        &mut self,
+        diagnostics: mullvad_management_interface::types::Diagnostics,
+    ) {
+        let recent_events = diagnostics
+            .recent_events
+            .iter()
+            .map(|event| self.redact_json(event))
+            .collect();
+        let dns_manager = Some(diagnostics.dns_manager).filter(|manager| !manager.is_empty());
+        let sections = diagnostics
+            .sections
+            .iter()
+            .map(|section| {
+                let (content, error) = if section.error.is_empty() {
+                    (Some(self.redact(&section.content)), None)
+                } else {
+                    (None, Some(self.redact(&section.error)))
+                };
+                DiagnosticsSection {
+                    name: section.name.clone(),
+                    content,
+                    error,
+                }
+            })
+            .collect();
+        self.diagnostics = Some(Diagnostics {
+            settings: self.redact_json(&diagnostics.settings),
+            tunnel_state: self.redact_json(&diagnostics.tunnel_state),
+            recent_events,
+            dns_manager,
+            sections,
+        });
     }
 
-    fn redact_network_info(input: &str) -> Cow<'_, str> {
-        lazy_static! {
-            static ref RE: Regex = {
-                let boundary = "[^0-9a-zA-Z.:]";
-                let combined_pattern = format!(
-                    "(?P<start>^|{})(?:{}|{}|{})",
-                    boundary,
-                    build_ipv4_regex(),
-                    build_ipv6_regex(),
-                    build_mac_regex(),
-                );
-                Regex::new(&combined_pattern).unwrap()
-            };
-        }
-        RE.replace_all(input, "$start[REDACTED]")
+    /// Redacts a JSON document. The document is kept as a string if redaction made it invalid.
+    #[cfg(not(target_os = "android"))]
+    fn redact_json(&mut self, input: &str) -> serde_json::Value {
+        let redacted = self.redact(input);
+        serde_json::from_str(&redacted).unwrap_or_else(|_| serde_json::Value::String(redacted))
     }
 
-    fn redact_custom_strings<'a>(&self, input: &'a str) -> Cow<'a, str> {
-        // Can probably me made a lot faster with aho-corasick if optimization is ever needed.
-        let mut out = Cow::from(input);
-        for redact in &self.redact_custom_strings {
-            out = out.replace(redact, "[REDACTED]").into()
-        }
-        out
+    fn redact(&mut self, input: &str) -> String {
+        self.redactors.redact(input, &mut self.redactions)
     }
 
     fn write_to<W: Write>(&self, mut output: W) -> io::Result<()> {
@@ -403,13 +594,52 @@ impl ProblemReport {
         }
         // Write empty line to separate metadata from first log
         write_line!(output)?;
-        for &(ref label, ref content) in &self.logs {
+        for log in &self.logs {
             write_line!(output, "{}", LOG_DELIMITER)?;
-            write_line!(output, "Log: {}", label)?;
+            write_line!(output, "Log: {}", log.label)?;
             write_line!(output, "{}", LOG_DELIMITER)?;
-            output.write_all(content.as_bytes())?;
+            output.write_all(log.content.as_bytes())?;
             write_line!(output)?;
         }
+        #[cfg(not(target_os = "android"))]
+        {
+            let sections = self
+                .diagnostics
+                .iter()
+                .flat_map(|diagnostics| diagnostics.sections.iter());
+            for section in sections {
+                write_line!(output, "{}", LOG_DELIMITER)?;
+                write_line!(output, "System state: {}", section.name)?;
+                write_line!(output, "{}", LOG_DELIMITER)?;
+                match (&section.content, &section.error) {
+                    (_, Some(error)) => write_line!(output, "Failed to collect: {}", error)?,
+                    (Some(content), None) => output.write_all(content.as_bytes())?,
+                    (None, None) => (),
+                }
+                write_line!(output)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_json_to<W: Write>(&self, output: W) -> io::Result<()> {
+        #[derive(Serialize)]
+        struct JsonReport<'a> {
+            kind: ReportKind,
+            metadata: &'a BTreeMap<String, String>,
+            logs: &'a [LogEntry],
+            #[cfg(not(target_os = "android"))]
+            diagnostics: &'a Option<Diagnostics>,
+        }
+
+        let report = JsonReport {
+            kind: ReportKind::LocalOnly,
+            metadata: &self.metadata,
+            logs: &self.logs,
+            #[cfg(not(target_os = "android"))]
+            diagnostics: &self.diagnostics,
+        };
+        serde_json::to_writer_pretty(output, &report)?;
         Ok(())
     }
 
@@ -438,76 +668,6 @@ impl ProblemReport {
     }
 }
 
-fn build_mac_regex() -> String {
-    let octet = "[[:xdigit:]]{2}"; // 0 - ff
-
-    // five pairs of two hexadecimal chars followed by colon or dash
-    // followed by a pair of hexadecimal chars
-    format!("(?:{0}[:-]){{5}}({0})", octet)
-}
-
-fn build_ipv4_regex() -> String {
-    // regex adapted from  https://www.regular-expressions.info/ip.html
-
-    let above_250 = "25[0-5]";
-    let above_200 = "2[0-4][0-9]";
-    let above_100 = "1[0-9][0-9]";
-
-    // 100-119 | 120-126 | 128-129 | 130 - 199
-    let above_100_not_127 = "1(?:[01][0-9]|2[0-6]|2[89]|[3-9][0-9])";
-
-    let above_0 = "0?[0-9][0-9]?";
-
-    // matches 0-255, except 127
-    let first_octet = format!(
-        "(?:{}|{}|{}|{})",
-        above_250, above_200, above_100_not_127, above_0
-    );
-
-    // matches 0-255
-    let ip_octet = format!("(?:{}|{}|{}|{})", above_250, above_200, above_100, above_0);
-
-    format!("(?:{0}\\.{1}\\.{1}\\.{1})", first_octet, ip_octet)
-}
-
-fn build_ipv6_regex() -> String {
-    // Regular expression obtained from:
-    // https://stackoverflow.com/a/17871737
-    let ipv4_segment = "(25[0-5]|(2[0-4]|1{0,1}[0-9]){0,1}[0-9])";
-    let ipv4_address = format!("({0}\\.){{3,3}}{0}", ipv4_segment);
-
-    let ipv6_segment = "[0-9a-fA-F]{1,4}";
-
-    let long = format!("({0}:){{7,7}}{0}", ipv6_segment);
-    let compressed_1 = format!("({0}:){{1,7}}:", ipv6_segment);
-    let compressed_2 = format!("({0}:){{1,6}}:{0}", ipv6_segment);
-    let compressed_3 = format!("({0}:){{1,5}}(:{0}){{1,2}}", ipv6_segment);
-    let compressed_4 = format!("({0}:){{1,4}}(:{0}){{1,3}}", ipv6_segment);
-    let compressed_5 = format!("({0}:){{1,3}}(:{0}){{1,4}}", ipv6_segment);
-    let compressed_6 = format!("({0}:){{1,2}}(:{0}){{1,5}}", ipv6_segment);
-    let compressed_7 = format!("{0}:((:{0}){{1,6}})", ipv6_segment);
-    let compressed_8 = format!(":((:{0}){{1,7}}|:)", ipv6_segment);
-    let link_local = "[Ff][Ee]80:(:[0-9a-fA-F]{0,4}){0,4}%[0-9a-zA-Z]{1,}";
-    let ipv4_mapped = format!("::([fF]{{4}}(:0{{1,4}}){{0,1}}:){{0,1}}{}", ipv4_address);
-    let ipv4_embedded = format!("({0}:){{1,4}}:{1}", ipv6_segment, ipv4_address);
-
-    format!(
-        "{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}",
-        long,
-        link_local,
-        ipv4_mapped,
-        ipv4_embedded,
-        compressed_8,
-        compressed_7,
-        compressed_6,
-        compressed_5,
-        compressed_4,
-        compressed_3,
-        compressed_2,
-        compressed_1,
-    )
-}
-
 /// Helper to lossily read a file to a `String`. If the file size exceeds the given `max_bytes`,
 /// only the last `max_bytes` bytes of the file are read.
 fn read_file_lossy(path: &Path, max_bytes: usize) -> io::Result<String> {
@@ -549,7 +709,7 @@ mod tests {
     }
 
     fn assert_redacts_ipv4(input: &str) {
-        let report = ProblemReport::new(vec![]);
+        let mut report = ProblemReport::new(RedactionPipeline::with_defaults(vec![]));
         let actual = report.redact(&format!("pre {} post", input));
         assert_eq!("pre [REDACTED] post", actual);
     }
@@ -583,7 +743,7 @@ mod tests {
     }
 
     fn assert_redacts_ipv6(input: &str) {
-        let report = ProblemReport::new(vec![]);
+        let mut report = ProblemReport::new(RedactionPipeline::with_defaults(vec![]));
         let actual = report.redact(&format!("pre {} post", input));
         assert_eq!("pre [REDACTED] post", actual);
     }
@@ -594,14 +754,121 @@ mod tests {
     }
 
     fn assert_does_not_redact(input: &str) {
-        let report = ProblemReport::new(vec![]);
+        let mut report = ProblemReport::new(RedactionPipeline::with_defaults(vec![]));
         let res = report.redact(input);
         assert_eq!(input, res);
     }
 
+    #[test]
+    fn json_report() {
+        let mut report = ProblemReport::new(RedactionPipeline::with_defaults(Vec::new()));
+        report.add_error(
+            "Test error",
+            &LogError::ReadLogError {
+                path: "192.168.1.1".to_owned(),
+            },
+        );
+        let mut report_data = Vec::new();
+        report
+            .write_json_to(&mut report_data)
+            .expect("Unable to write report to vector");
+
+        let report_json: serde_json::Value =
+            serde_json::from_slice(&report_data).expect("Report is not valid JSON");
+        assert_eq!(
+            report_json["metadata"]["id"],
+            serde_json::Value::from(report.metadata["id"].clone())
+        );
+        let log = &report_json["logs"][0];
+        assert!(log["file_name"].is_null());
+        assert_eq!(log["label"], "Test error");
+        assert_eq!(
+            log["content"],
+            "Error: Error reading the contents of log file: [REDACTED]"
+        );
+    }
+
+    #[test]
+    #[cfg(not(target_os = "android"))]
+    fn text_report_includes_system_state() {
+        use mullvad_management_interface::types;
+
+        let mut report = ProblemReport::new(RedactionPipeline::with_defaults(Vec::new()));
+        report.add_diagnostics(types::Diagnostics {
+            sections: vec![
+                types::DiagnosticsSection {
+                    name: "routes".to_owned(),
+                    content: "default via 192.168.1.1 dev eth0 table main\n".to_owned(),
+                    error: String::new(),
+                },
+                types::DiagnosticsSection {
+                    name: "wireguard".to_owned(),
+                    content: String::new(),
+                    error: "Error: Failed to get the WireGuard device".to_owned(),
+                },
+            ],
+            ..types::Diagnostics::default()
+        });
+        let mut report_data = Vec::new();
+        report
+            .write_to(&mut report_data)
+            .expect("Unable to write report to vector");
+        let report_string = String::from_utf8(report_data).expect("Report is not correct UTF-8");
+
+        assert!(report_string.contains("System state: routes"));
+        assert!(report_string.contains("default via [REDACTED] dev eth0 table main"));
+        assert!(report_string.contains("System state: wireguard"));
+        assert!(
+            report_string.contains("Failed to collect: Error: Failed to get the WireGuard device")
+        );
+    }
+
+    #[test]
+    #[cfg(not(target_os = "android"))]
+    fn json_report_is_local_only_and_structured() {
+        use mullvad_management_interface::types;
+
+        let mut report = ProblemReport::new(RedactionPipeline::with_defaults(Vec::new()));
+        report.add_diagnostics(types::Diagnostics {
+            recent_events: vec![
+                r#"{"sequence_number":1,"event":{"connectivity_check_failure":{"relay":"10.0.16.1"}}}"#
+                    .to_owned(),
+            ],
+            ..types::Diagnostics::default()
+        });
+        let mut report_data = Vec::new();
+        report
+            .write_json_to(&mut report_data)
+            .expect("Unable to write report to vector");
+        let report: serde_json::Value =
+            serde_json::from_slice(&report_data).expect("Report is not valid JSON");
+
+        assert_eq!(report["kind"], "local_only");
+        let event = &report["diagnostics"]["recent_events"][0];
+        assert_eq!(event["sequence_number"], 1);
+        assert_eq!(
+            event["event"]["connectivity_check_failure"]["relay"],
+            "[REDACTED]"
+        );
+    }
+
+    #[test]
+    fn json_reports_are_not_sent() {
+        assert!(is_json_report(&br#"{"kind":"local_only","logs":[]}"#[..]));
+        assert!(is_json_report(&br#"{"logs":[]}"#[..]));
+        assert!(is_json_report(&b" {}\n"[..]));
+
+        let report = ProblemReport::new(RedactionPipeline::with_defaults(Vec::new()));
+        let mut report_data = Vec::new();
+        report
+            .write_to(&mut report_data)
+            .expect("Unable to write report to vector");
+        assert!(!is_json_report(&report_data[..]));
+    }
+
     #[test]
     fn parse_metadata() {
-        let report = ProblemReport::new(Vec::new());
+        let report = ProblemReport::new(RedactionPipeline::with_defaults(Vec::new()));
         let mut report_data = Vec::new();
         report
             .write_to(&mut report_data)
@@ -1,7 +1,10 @@
 #![deny(rust_2018_idioms)]
 
 use clap::{crate_authors, crate_name};
-use mullvad_problem_report::{collect_report, metadata, send_problem_report, Error};
+use mullvad_problem_report::{
+    collect_report, metadata, preview_redactions, redact::RedactionPipeline, send_problem_report,
+    Error, ReportFormat,
+};
 use std::{env, path::Path, process};
 use talpid_types::ErrorExt;
 
@@ -37,7 +40,7 @@ fn run() -> Result<(), Error> {
                         .short("o")
                         .value_name("PATH")
                         .takes_value(true)
-                        .required(true),
+                        .required_unless("preview"),
                 )
                 .arg(
                     clap::Arg::with_name("extra_logs")
@@ -54,6 +57,36 @@ fn run() -> Result<(), Error> {
                         .value_name("PHRASE")
                         .multiple(true)
                         .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::with_name("redaction_rules")
+                        .help(
+                            "Path to a JSON file with additional redaction rules. It contains a \
+                             list of objects with a \"pattern\" regex, and optionally a \"name\" \
+                             and a \"replacement\".",
+                        )
+                        .long("redaction-rules")
+                        .value_name("PATH")
+                        .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::with_name("preview")
+                        .help(
+                            "Show what would be redacted from the report instead of writing it. \
+                             The output contains the redacted information.",
+                        )
+                        .long("preview"),
+                )
+                .arg(
+                    clap::Arg::with_name("format")
+                        .help(
+                            "The format of the report. JSON reports also contain diagnostics \
+                             from the daemon, and can not be sent to support.",
+                        )
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["text", "json"])
+                        .default_value("text"),
                 ),
         )
         .subcommand(
@@ -95,8 +128,22 @@ fn run() -> Result<(), Error> {
             .values_of_os("extra_logs")
             .map(|os_values| os_values.map(Path::new).collect())
             .unwrap_or_else(Vec::new);
+        let format = match collect_matches.value_of("format").unwrap() {
+            "json" => ReportFormat::Json,
+            _ => ReportFormat::Text,
+        };
+        let mut redactors = RedactionPipeline::with_defaults(redact_custom_strings);
+        if let Some(rules_path) = collect_matches.value_of_os("redaction_rules") {
+            redactors.load_rules(Path::new(rules_path))?;
+        }
+
+        if collect_matches.is_present("preview") {
+            print!("{}", preview_redactions(&extra_logs, redactors, format));
+            return Ok(());
+        }
+
         let output_path = Path::new(collect_matches.value_of_os("output").unwrap());
-        collect_report(&extra_logs, output_path, redact_custom_strings)?;
+        collect_report(&extra_logs, output_path, redactors, format)?;
 
         let expanded_output_path = output_path
             .canonicalize()
@@ -105,6 +152,9 @@ fn run() -> Result<(), Error> {
             "Problem report written to {}",
             expanded_output_path.display()
         );
+        if format == ReportFormat::Json {
+            return Ok(());
+        }
         println!("");
         println!("Send the problem report to support via the send subcommand. See:");
         println!(" $ {} send --help", env::args().next().unwrap());
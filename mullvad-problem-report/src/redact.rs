@@ -0,0 +1,618 @@
+//! Removal of sensitive information from problem reports. Text added to a report is passed through
+//! a `RedactionPipeline`, which applies a list of `Redactor`s in order.
+
+use crate::Error;
+use regex::{Captures, Regex};
+use serde::Deserialize;
+use std::{borrow::Cow, collections::BTreeMap, fmt, fs, path::Path};
+
+/// Replacement used by custom strings and by rules that do not specify one.
+const DEFAULT_REPLACEMENT: &str = "[REDACTED]";
+
+/// Usernames that are not considered personal information. Redacting them would mostly remove
+/// unrelated words from the logs.
+const IGNORED_USERNAMES: &[&str] = &["root"];
+/// Usernames shorter than this are not redacted, for the same reason.
+const MIN_USERNAME_LEN: usize = 3;
+
+/// Removes one kind of sensitive information from text.
+pub trait Redactor {
+    /// Short description of what is redacted. Used when previewing redactions.
+    fn name(&self) -> &str;
+
+    /// Returns `input` with all sensitive information replaced. Each replaced string is passed to
+    /// `record`.
+    fn redact<'a>(&self, input: &'a str, record: &mut dyn FnMut(&str)) -> Cow<'a, str>;
+}
+
+/// Redactor that replaces all matches of a regular expression.
+///
+/// The replacement may refer to capture groups, as in `Regex::replace_all`. If the expression has
+/// a group named `value`, only that part of a match is recorded as redacted. This allows patterns
+/// to include surrounding context that is kept in the output.
+#[derive(Debug)]
+pub struct RegexRedactor {
+    name: String,
+    regex: Regex,
+    replacement: String,
+}
+
+impl RegexRedactor {
+    pub fn new(name: impl Into<String>, regex: Regex, replacement: impl Into<String>) -> Self {
+        RegexRedactor {
+            name: name.into(),
+            regex,
+            replacement: replacement.into(),
+        }
+    }
+
+    /// Returns a redactor that replaces the given strings, or `None` if there are no strings. If
+    /// `whole_words` is set, strings are only replaced where they are not part of a longer word.
+    pub fn literals(
+        name: impl Into<String>,
+        strings: impl IntoIterator<Item = String>,
+        replacement: impl Into<String>,
+        whole_words: bool,
+    ) -> Option<Self> {
+        let mut strings: Vec<String> = strings
+            .into_iter()
+            .filter(|string| !string.is_empty())
+            .collect();
+        if strings.is_empty() {
+            return None;
+        }
+        // Longer strings first, so that a string is not partially replaced by one of its prefixes.
+        strings.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        strings.dedup();
+
+        let alternatives = strings
+            .iter()
+            .map(|string| regex::escape(string))
+            .collect::<Vec<_>>()
+            .join("|");
+        let pattern = if whole_words {
+            format!(r"\b(?:{})\b", alternatives)
+        } else {
+            alternatives
+        };
+        let regex = Regex::new(&pattern).expect("Escaped strings should form a valid regex");
+        // Any `$` in a literal replacement must not be interpreted as a capture group.
+        let replacement = replacement.into().replace('$', "$$");
+        Some(Self::new(name, regex, replacement))
+    }
+}
+
+impl Redactor for RegexRedactor {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn redact<'a>(&self, input: &'a str, record: &mut dyn FnMut(&str)) -> Cow<'a, str> {
+        self.regex.replace_all(input, |captures: &Captures<'_>| {
+            let value = captures
+                .name("value")
+                .or_else(|| captures.get(0))
+                .map(|value| value.as_str())
+                .unwrap_or("");
+            record(value);
+            let mut replacement = String::new();
+            captures.expand(&self.replacement, &mut replacement);
+            replacement
+        })
+    }
+}
+
+/// Account numbers.
+pub fn account_number() -> RegexRedactor {
+    RegexRedactor::new(
+        "account number",
+        Regex::new(r"\d{16}").unwrap(),
+        "[REDACTED ACCOUNT NUMBER]",
+    )
+}
+
+/// The home directory of the current user. It is replaced with `~`.
+pub fn home_dir() -> Option<RegexRedactor> {
+    let home_dir = dirs::home_dir()?;
+    RegexRedactor::literals(
+        "home directory",
+        Some(home_dir.to_string_lossy().into_owned()),
+        "~",
+        false,
+    )
+}
+
+/// IPv4, IPv6 and MAC addresses, except for localhost.
+pub fn network_info() -> RegexRedactor {
+    let boundary = "[^0-9a-zA-Z.:]";
+    let pattern = format!(
+        "(?P<start>^|{})(?P<value>{}|{}|{})",
+        boundary,
+        build_ipv4_regex(),
+        build_ipv6_regex(),
+        build_mac_regex(),
+    );
+    RegexRedactor::new(
+        "network address",
+        Regex::new(&pattern).unwrap(),
+        "$start[REDACTED]",
+    )
+}
+
+/// WireGuard keys, which are 32 bytes encoded as base64.
+pub fn wireguard_keys() -> RegexRedactor {
+    RegexRedactor::new(
+        "WireGuard key",
+        Regex::new(r"(?P<start>^|[^0-9a-zA-Z+/])(?P<value>[0-9a-zA-Z+/]{42}[AEIMQUYcgkosw048]=)")
+            .unwrap(),
+        "$start[REDACTED WIREGUARD KEY]",
+    )
+}
+
+/// Email addresses.
+pub fn emails() -> RegexRedactor {
+    RegexRedactor::new(
+        "email address",
+        Regex::new(r"[0-9a-zA-Z._%+-]+@[0-9a-zA-Z-]+(?:\.[0-9a-zA-Z-]+)*\.[a-zA-Z]{2,}").unwrap(),
+        "[REDACTED EMAIL]",
+    )
+}
+
+/// Names of wireless networks, where they are labeled as such. For example `SSID: "Home"` or
+/// `ssid=Home`.
+pub fn ssids() -> RegexRedactor {
+    RegexRedactor::new(
+        "SSID",
+        Regex::new(
+            r#"(?i)(?P<start>\bssid["']?(?:\s*[:=]\s*|\s+))(?P<value>"[^"\n]*"|'[^'\n]*'|[^\s,;"']+)"#,
+        )
+        .unwrap(),
+        "$start[REDACTED SSID]",
+    )
+}
+
+/// The given usernames, where they appear as whole words.
+pub fn usernames(usernames: impl IntoIterator<Item = String>) -> Option<RegexRedactor> {
+    let usernames = usernames.into_iter().filter(|username| {
+        username.len() >= MIN_USERNAME_LEN && !IGNORED_USERNAMES.contains(&username.as_str())
+    });
+    RegexRedactor::literals("username", usernames, "[REDACTED USERNAME]", true)
+}
+
+/// Names of the current user, as given by the environment and the home directory.
+fn current_usernames() -> Vec<String> {
+    let mut usernames = Vec::new();
+    for variable in &["USER", "USERNAME"] {
+        if let Some(username) = std::env::var_os(variable) {
+            usernames.push(username.to_string_lossy().into_owned());
+        }
+    }
+    if let Some(home_dir) = dirs::home_dir() {
+        if let Some(username) = home_dir.file_name() {
+            usernames.push(username.to_string_lossy().into_owned());
+        }
+    }
+    usernames
+}
+
+/// Hostnames of the relays in the given relay list document.
+pub fn relay_hostnames(
+    relay_list: &[u8],
+) -> Result<Option<RegexRedactor>, mullvad_rpc::relay_list::Error> {
+    let relay_list = mullvad_rpc::relay_list::parse_relay_list(relay_list)?;
+    let hostnames = relay_list.relays().map(|relay| relay.hostname.clone());
+    Ok(RegexRedactor::literals(
+        "relay hostname",
+        hostnames,
+        "[REDACTED RELAY]",
+        true,
+    ))
+}
+
+/// Hostnames of the relays in the cached relay list, or in the one shipped with the app if there
+/// is no cache.
+fn cached_relay_hostnames() -> Option<RegexRedactor> {
+    let relay_list_paths = mullvad_paths::get_default_cache_dir()
+        .ok()
+        .into_iter()
+        .chain(Some(mullvad_paths::get_resource_dir()))
+        .map(|dir| dir.join("relays.json"));
+    for path in relay_list_paths {
+        if let Ok(Ok(redactor)) = fs::read(&path).map(|document| relay_hostnames(&document)) {
+            return redactor;
+        }
+    }
+    None
+}
+
+/// The given strings, which are replaced wherever they appear.
+pub fn custom_strings(strings: Vec<String>) -> Option<RegexRedactor> {
+    RegexRedactor::literals("custom string", strings, DEFAULT_REPLACEMENT, false)
+}
+
+/// A rule in a redaction rules file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RedactionRule {
+    /// Describes the rule in previews. Defaults to the pattern.
+    #[serde(default)]
+    name: Option<String>,
+    pattern: String,
+    #[serde(default)]
+    replacement: Option<String>,
+}
+
+/// Redactors that are applied in order.
+#[derive(Default)]
+pub struct RedactionPipeline {
+    redactors: Vec<Box<dyn Redactor>>,
+}
+
+impl fmt::Debug for RedactionPipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.redactors.iter().map(|redactor| redactor.name()))
+            .finish()
+    }
+}
+
+impl RedactionPipeline {
+    /// Returns a pipeline without any redactors.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a pipeline with all built-in redactors, followed by one for `custom_strings`.
+    pub fn with_defaults(custom_strings: Vec<String>) -> Self {
+        let mut pipeline = Self::new();
+        // WireGuard keys and emails go first, since other redactors would otherwise replace parts
+        // of them.
+        pipeline.push(wireguard_keys());
+        pipeline.push(account_number());
+        pipeline.push(emails());
+        pipeline.push_optional(home_dir());
+        pipeline.push_optional(usernames(current_usernames()));
+        pipeline.push_optional(cached_relay_hostnames());
+        pipeline.push(ssids());
+        pipeline.push(network_info());
+        pipeline.push_optional(self::custom_strings(custom_strings));
+        pipeline
+    }
+
+    pub fn push(&mut self, redactor: impl Redactor + 'static) {
+        self.redactors.push(Box::new(redactor));
+    }
+
+    fn push_optional(&mut self, redactor: Option<impl Redactor + 'static>) {
+        if let Some(redactor) = redactor {
+            self.push(redactor);
+        }
+    }
+
+    /// Appends the rules in a JSON file to the pipeline. The file contains a list of objects with a
+    /// `pattern` regex and optionally a `name` and a `replacement`, such as
+    /// `[{"name": "order number", "pattern": "ORD-\\d+", "replacement": "[ORDER]"}]`.
+    pub fn load_rules(&mut self, path: &Path) -> Result<(), Error> {
+        let rules = fs::read(path).map_err(|source| Error::ReadRedactionRules {
+            path: path.display().to_string(),
+            source,
+        })?;
+        let rules: Vec<RedactionRule> =
+            serde_json::from_slice(&rules).map_err(|source| Error::ParseRedactionRules {
+                path: path.display().to_string(),
+                source,
+            })?;
+        for rule in rules {
+            let name = rule.name.unwrap_or_else(|| rule.pattern.clone());
+            let regex =
+                Regex::new(&rule.pattern).map_err(|source| Error::InvalidRedactionRule {
+                    name: name.clone(),
+                    source,
+                })?;
+            let replacement = rule
+                .replacement
+                .unwrap_or_else(|| DEFAULT_REPLACEMENT.to_owned());
+            self.push(RegexRedactor::new(name, regex, replacement));
+        }
+        Ok(())
+    }
+
+    /// Applies all redactors to `input`, and records what they replaced in `redactions`.
+    pub fn redact(&self, input: &str, redactions: &mut Redactions) -> String {
+        let mut output = input.to_owned();
+        for redactor in &self.redactors {
+            let name = redactor.name();
+            output = redactor
+                .redact(&output, &mut |value| redactions.record(name, value))
+                .into_owned();
+        }
+        output
+    }
+}
+
+/// The strings that have been redacted, and how many times, grouped by redactor.
+#[derive(Debug, Default)]
+pub struct Redactions {
+    redacted: BTreeMap<String, BTreeMap<String, usize>>,
+}
+
+impl Redactions {
+    fn record(&mut self, redactor: &str, value: &str) {
+        *self
+            .redacted
+            .entry(redactor.to_owned())
+            .or_default()
+            .entry(value.to_owned())
+            .or_insert(0) += 1;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.redacted.is_empty()
+    }
+
+    /// Returns the strings replaced by the given redactor, and how many times each one was
+    /// replaced.
+    pub fn get(&self, redactor: &str) -> Option<&BTreeMap<String, usize>> {
+        self.redacted.get(redactor)
+    }
+}
+
+impl fmt::Display for Redactions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "Nothing was redacted");
+        }
+        for (redactor, values) in &self.redacted {
+            writeln!(f, "{}:", redactor)?;
+            for (value, count) in values {
+                writeln!(f, "    {} ({})", value, count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn build_mac_regex() -> String {
+    let octet = "[[:xdigit:]]{2}"; // 0 - ff
+
+    // five pairs of two hexadecimal chars followed by colon or dash
+    // followed by a pair of hexadecimal chars
+    format!("(?:{0}[:-]){{5}}({0})", octet)
+}
+
+fn build_ipv4_regex() -> String {
+    // regex adapted from  https://www.regular-expressions.info/ip.html
+
+    let above_250 = "25[0-5]";
+    let above_200 = "2[0-4][0-9]";
+    let above_100 = "1[0-9][0-9]";
+
+    // 100-119 | 120-126 | 128-129 | 130 - 199
+    let above_100_not_127 = "1(?:[01][0-9]|2[0-6]|2[89]|[3-9][0-9])";
+
+    let above_0 = "0?[0-9][0-9]?";
+
+    // matches 0-255, except 127
+    let first_octet = format!(
+        "(?:{}|{}|{}|{})",
+        above_250, above_200, above_100_not_127, above_0
+    );
+
+    // matches 0-255
+    let ip_octet = format!("(?:{}|{}|{}|{})", above_250, above_200, above_100, above_0);
+
+    format!("(?:{0}\\.{1}\\.{1}\\.{1})", first_octet, ip_octet)
+}
+
+fn build_ipv6_regex() -> String {
+    // Regular expression obtained from:
+    // https://stackoverflow.com/a/17871737
+    let ipv4_segment = "(25[0-5]|(2[0-4]|1{0,1}[0-9]){0,1}[0-9])";
+    let ipv4_address = format!("({0}\\.){{3,3}}{0}", ipv4_segment);
+
+    let ipv6_segment = "[0-9a-fA-F]{1,4}";
+    let long = format!("({0}:){{7,7}}{0}", ipv6_segment);
+    let compressed_1 = format!("({0}:){{1,7}}:", ipv6_segment);
+    let compressed_2 = format!("({0}:){{1,6}}:{0}", ipv6_segment);
+    let compressed_3 = format!("({0}:){{1,5}}(:{0}){{1,2}}", ipv6_segment);
+    let compressed_4 = format!("({0}:){{1,4}}(:{0}){{1,3}}", ipv6_segment);
+    let compressed_5 = format!("({0}:){{1,3}}(:{0}){{1,4}}", ipv6_segment);
+    let compressed_6 = format!("({0}:){{1,2}}(:{0}){{1,5}}", ipv6_segment);
+    let compressed_7 = format!("{0}:((:{0}){{1,6}})", ipv6_segment);
+    let compressed_8 = format!(":((:{0}){{1,7}}|:)", ipv6_segment);
+    let link_local = "[Ff][Ee]80:(:[0-9a-fA-F]{0,4}){0,4}%[0-9a-zA-Z]{1,}";
+    let ipv4_mapped = format!("::([fF]{{4}}(:0{{1,4}}){{0,1}}:){{0,1}}{}", ipv4_address);
+    let ipv4_embedded = format!("({0}:){{1,4}}:{1}", ipv6_segment, ipv4_address);
+
+    format!(
+        "{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}",
+        long,
+        link_local,
+        ipv4_mapped,
+        ipv4_embedded,
+        compressed_8,
+        compressed_7,
+        compressed_6,
+        compressed_5,
+        compressed_4,
+        compressed_3,
+        compressed_2,
+        compressed_1,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = include_str!("../test-redaction/input.log");
+    const EXPECTED: &str = include_str!("../test-redaction/expected.log");
+    const RELAY_LIST: &[u8] = include_bytes!("../test-redaction/relays.json");
+
+    fn test_data_path(file_name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("test-redaction")
+            .join(file_name)
+    }
+
+    /// Redacts the input fixture with a single redactor, and returns the output and the strings
+    /// the redactor recorded.
+    fn redact_input(redactor: impl Redactor + 'static) -> (String, Vec<String>) {
+        let name = redactor.name().to_owned();
+        let mut pipeline = RedactionPipeline::new();
+        pipeline.push(redactor);
+        let mut redactions = Redactions::default();
+        let output = pipeline.redact(INPUT, &mut redactions);
+        let values = redactions
+            .get(&name)
+            .map(|values| values.keys().cloned().collect())
+            .unwrap_or_default();
+        (output, values)
+    }
+
+    #[test]
+    fn test_account_number() {
+        let (output, values) = redact_input(account_number());
+        assert_eq!(values, vec!["1234567890123456"]);
+        assert!(output.contains("account [REDACTED ACCOUNT NUMBER]\n"));
+    }
+
+    #[test]
+    fn test_home_dir() {
+        let home_dir = match dirs::home_dir() {
+            Some(home_dir) => home_dir,
+            None => return,
+        };
+        let input = format!("Reading {}", home_dir.join("settings.json").display());
+        let mut pipeline = RedactionPipeline::new();
+        pipeline.push_optional(self::home_dir());
+        let output = pipeline.redact(&input, &mut Redactions::default());
+        assert_eq!(
+            output,
+            format!("Reading {}", Path::new("~").join("settings.json").display())
+        );
+    }
+
+    #[test]
+    fn test_network_info() {
+        let (output, values) = redact_input(network_info());
+        assert_eq!(
+            values,
+            vec![
+                "01:23:45:67:89:ab",
+                "10.64.0.1",
+                "185.213.154.68",
+                "fc00:bbbb:bbbb:bb01::1"
+            ]
+        );
+        assert!(output.contains("MAC [REDACTED] connected"));
+        assert!(output.contains("placed from 127.0.0.1"));
+        assert!(output.contains("[2020-11-02 09:47:59.123][mullvad_daemon]"));
+    }
+
+    #[test]
+    fn test_wireguard_keys() {
+        let (output, values) = redact_input(wireguard_keys());
+        assert_eq!(values, vec!["kRDrL9sjUldSmqK02V4a1rxUrG1Vk6zKLYdJxtjYUmU="]);
+        assert!(output.contains("Public key: [REDACTED WIREGUARD KEY]\n"));
+    }
+
+    #[test]
+    fn test_wireguard_keys_ignore_longer_base64() {
+        let input =
+            "Signature: kRDrL9sjUldSmqK02V4a1rxUrG1Vk6zKLYdJxtjYUmUkRDrL9sjUldSmqK02V4a1rx==";
+        let mut pipeline = RedactionPipeline::new();
+        pipeline.push(wireguard_keys());
+        let mut redactions = Redactions::default();
+        assert_eq!(pipeline.redact(input, &mut redactions), input);
+        assert!(redactions.is_empty());
+    }
+
+    #[test]
+    fn test_emails() {
+        let (output, values) = redact_input(emails());
+        assert_eq!(values, vec!["jane.doe@example.com"]);
+        assert!(output.contains("Reporter: [REDACTED EMAIL]\n"));
+    }
+
+    #[test]
+    fn test_ssids() {
+        let (output, values) = redact_input(ssids());
+        assert_eq!(values, vec!["\"Home Network\"", "CoffeeShop"]);
+        assert!(output.contains("connected to SSID [REDACTED SSID]\n"));
+        assert!(output.contains("wpa_supplicant: ssid=[REDACTED SSID]\n"));
+    }
+
+    #[test]
+    fn test_usernames() {
+        let (output, values) = redact_input(usernames(vec!["jdoe".to_owned()]).unwrap());
+        assert_eq!(values, vec!["jdoe"]);
+        assert!(output.contains("as [REDACTED USERNAME]\n"));
+        assert!(output.contains("/home/[REDACTED USERNAME]/.config"));
+        assert!(output.contains("jane.doe@example.com"));
+
+        assert!(usernames(vec!["root".to_owned(), "ab".to_owned()]).is_none());
+    }
+
+    #[test]
+    fn test_relay_hostnames() {
+        let redactor = relay_hostnames(RELAY_LIST)
+            .expect("Failed to parse relay list")
+            .unwrap();
+        let (output, values) = redact_input(redactor);
+        assert_eq!(values, vec!["se-got-wg-001"]);
+        assert!(output.contains("Selected relay [REDACTED RELAY] at"));
+    }
+
+    #[test]
+    fn test_custom_strings() {
+        let (output, values) = redact_input(custom_strings(vec!["Falcon".to_owned()]).unwrap());
+        assert_eq!(values, vec!["Falcon"]);
+        assert!(output.contains("for project [REDACTED]\n"));
+
+        assert!(custom_strings(vec![String::new()]).is_none());
+    }
+
+    #[test]
+    fn test_rules_file() {
+        let mut pipeline = RedactionPipeline::new();
+        pipeline
+            .load_rules(&test_data_path("rules.json"))
+            .expect("Failed to load rules");
+        let mut redactions = Redactions::default();
+        let output = pipeline.redact(INPUT, &mut redactions);
+        assert_eq!(redactions.get("order number").unwrap()["ORD-4711"], 1);
+        assert!(output.contains("Order [REDACTED ORDER] was placed"));
+
+        let mut pipeline = RedactionPipeline::new();
+        assert!(matches!(
+            pipeline.load_rules(&test_data_path("invalid-rules.json")),
+            Err(Error::InvalidRedactionRule { .. })
+        ));
+        assert!(matches!(
+            pipeline.load_rules(&test_data_path("relays.json")),
+            Err(Error::ParseRedactionRules { .. })
+        ));
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let mut pipeline = RedactionPipeline::new();
+        pipeline.push(wireguard_keys());
+        pipeline.push(account_number());
+        pipeline.push(emails());
+        pipeline.push_optional(usernames(vec!["jdoe".to_owned()]));
+        pipeline.push_optional(relay_hostnames(RELAY_LIST).unwrap());
+        pipeline.push(ssids());
+        pipeline.push(network_info());
+        pipeline.push_optional(custom_strings(vec!["Falcon".to_owned()]));
+        pipeline
+            .load_rules(&test_data_path("rules.json"))
+            .expect("Failed to load rules");
+
+        let mut redactions = Redactions::default();
+        assert_eq!(pipeline.redact(INPUT, &mut redactions), EXPECTED);
+        assert_eq!(redactions.get("username").unwrap()["jdoe"], 2);
+        assert!(redactions.to_string().contains("    CoffeeShop (1)\n"));
+    }
+}
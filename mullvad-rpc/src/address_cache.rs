@@ -41,6 +41,11 @@ impl AddressCache {
         Self::get_address_inner(&inner)
     }
 
+    /// Returns the current address without marking it as tried.
+    pub fn peek_address(&self) -> SocketAddr {
+        Self::get_address_inner(&self.inner.lock().unwrap())
+    }
+
     fn get_address_inner(inner: &AddressCacheInner) -> SocketAddr {
         if inner.addresses.is_empty() {
             return FALLBACK_API_ADDRESS.into();
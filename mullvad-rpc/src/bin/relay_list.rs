@@ -1,35 +1,66 @@
 /// Intended to be used to pre-load a relay list when creating an installer for the Mullvad VPN
-/// app.
-use mullvad_rpc::{rest::Error as RestError, MullvadRpcRuntime, RelayListProxy};
-use std::process;
+/// app. The relay list is written to stdout exactly as served by the API, since that is what the
+/// signature covers. If a path is given, the signature of the relay list is written to it.
+use mullvad_rpc::{relay_list, rest::Error as RestError, MullvadRpcRuntime, RelayListProxy};
+use std::{
+    env,
+    io::{self, Write},
+    process,
+};
 use talpid_types::ErrorExt;
 
 #[tokio::main]
 async fn main() {
+    let signature_path = env::args().nth(1);
     let mut runtime =
         MullvadRpcRuntime::new(tokio::runtime::Handle::current()).expect("Failed to load runtime");
 
     let relay_list_request = RelayListProxy::new(runtime.mullvad_rest_handle())
-        .relay_list()
+        .relay_list(None)
         .await;
 
     let relay_list = match relay_list_request {
-        Ok(relay_list) => relay_list,
+        Ok(Some(relay_list)) => relay_list,
+        Ok(None) => {
+            eprintln!("Relay list was not returned by the API");
+            process::exit(1);
+        }
         Err(RestError::TimeoutError(_)) => {
             eprintln!("Request timed out");
             process::exit(2);
         }
-        Err(e @ RestError::DeserializeError(_)) => {
-            eprintln!(
-                "{}",
-                e.display_chain_with_msg("Failed to deserialize relay list")
-            );
-            process::exit(3);
-        }
         Err(e) => {
             eprintln!("{}", e.display_chain_with_msg("Failed to fetch relay list"));
             process::exit(1);
         }
     };
-    println!("{}", serde_json::to_string_pretty(&relay_list).unwrap());
+
+    if let Err(e) = relay_list::parse_relay_list(&relay_list.document) {
+        eprintln!(
+            "{}",
+            e.display_chain_with_msg("Failed to deserialize relay list")
+        );
+        process::exit(3);
+    }
+
+    if let Some(signature_path) = signature_path {
+        let signature = match relay_list.signature {
+            Some(signature) => signature,
+            None => {
+                eprintln!("The relay list is not signed");
+                process::exit(4);
+            }
+        };
+        if let Err(e) = std::fs::write(&signature_path, format!("{}\n", signature)) {
+            eprintln!(
+                "{}",
+                e.display_chain_with_msg("Failed to write relay list signature")
+            );
+            process::exit(1);
+        }
+    }
+
+    io::stdout()
+        .write_all(&relay_list.document)
+        .expect("Failed to write relay list");
 }
@@ -0,0 +1,56 @@
+/// Signs relay lists with Ed25519, in the same way as the API does. Intended to be used to produce
+/// keys and signatures for test fixtures.
+///
+/// `sign_relay_list generate KEY_PATH` writes a new base64 encoded PKCS#8 private key to
+/// `KEY_PATH` and prints the base64 encoded public key, as expected in `relays.pub`.
+///
+/// `sign_relay_list sign KEY_PATH RELAY_LIST_PATH` prints the base64 encoded signature of the
+/// relay list, as expected in `relays.json.sig`.
+use ring::{
+    rand::SystemRandom,
+    signature::{Ed25519KeyPair, KeyPair},
+};
+use std::{env, fs, process};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        ["generate", key_path] => generate(key_path),
+        ["sign", key_path, relay_list_path] => sign(key_path, relay_list_path),
+        _ => {
+            eprintln!("Usage: sign_relay_list generate KEY_PATH");
+            eprintln!("       sign_relay_list sign KEY_PATH RELAY_LIST_PATH");
+            process::exit(2);
+        }
+    };
+
+    match result {
+        Ok(output) => println!("{}", output),
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    }
+}
+
+fn generate(key_path: &str) -> Result<String, String> {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+        .map_err(|_| "Failed to generate key".to_string())?;
+    let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+        .map_err(|_| "Failed to parse generated key".to_string())?;
+    fs::write(key_path, format!("{}\n", base64::encode(pkcs8.as_ref())))
+        .map_err(|error| format!("Failed to write {}: {}", key_path, error))?;
+    Ok(base64::encode(key_pair.public_key().as_ref()))
+}
+
+fn sign(key_path: &str, relay_list_path: &str) -> Result<String, String> {
+    let key = fs::read_to_string(key_path)
+        .map_err(|error| format!("Failed to read {}: {}", key_path, error))?;
+    let pkcs8 = base64::decode(key.trim()).map_err(|_| "Key is not valid base64".to_string())?;
+    let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&pkcs8)
+        .map_err(|_| "Key is not an Ed25519 PKCS#8 key".to_string())?;
+    let relay_list = fs::read(relay_list_path)
+        .map_err(|error| format!("Failed to read {}: {}", relay_list_path, error))?;
+    Ok(base64::encode(key_pair.sign(&relay_list).as_ref()))
+}
@@ -1,16 +1,24 @@
+use crate::proxy::{ApiConnectionMode, ApiConnectionModeHandle};
 use http::uri::Scheme;
 use hyper::{client::HttpConnector, service::Service, Uri};
 use hyper_rustls::MaybeHttpsStream;
 use std::{
+    error::Error as StdError,
     fmt,
     future::Future,
     io::{self, BufReader},
     pin::Pin,
     str,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     task::{Context, Poll},
+    time::SystemTime,
+};
+use tokio_rustls::rustls::{
+    self, Certificate, RootCertStore, ServerCertVerified, ServerCertVerifier, TLSError,
 };
-use tokio_rustls::rustls;
 use webpki::DNSNameRef;
 
 // Old LetsEncrypt root certificate
@@ -18,12 +26,52 @@ const OLD_ROOT_CERT: &[u8] = include_bytes!("../old_le_root_cert.pem");
 // New LetsEncrypt root certificate
 const NEW_ROOT_CERT: &[u8] = include_bytes!("../new_le_root_cert.pem");
 
+/// Base64 encoded SHA-256 digests of the SubjectPublicKeyInfo of the API certificate or of the
+/// intermediates that may sign it. At least one of them must be in the presented chain. The roots
+/// in the trust store are deliberately not pinned, since every chain they accept would match.
+const API_PINS: &[&str] = &[
+    // Let's Encrypt R3 intermediate
+    "jQJTbIh0grw0/1TkHSumWb+Fs0Ggogr621gT3PvPKG0=",
+    // Backup: Let's Encrypt R4 intermediate
+    "5VReIRNHJBiRxVSgOTTN6bdJZkpZ0m1hX+WPd5kPLQM=",
+    // Backup: Let's Encrypt E1 intermediate
+    "J2/oqMTsdhFWW/n85tys6b4yDBtb6idZayIEBx7QTxA=",
+    // Backup: Let's Encrypt E2 intermediate
+    "vZNucrIS7293MQLGt304+UKXMi78JTlrwyeUIuDIknA=",
+];
+
+/// The signature algorithms accepted when verifying the chain to a pinned key. These are the
+/// ones that rustls accepts.
+static SIGNATURE_ALGORITHMS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::ED25519,
+    &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+    &webpki::RSA_PKCS1_3072_8192_SHA384,
+];
+
+const DER_SEQUENCE: u8 = 0x30;
+
+/// Returned by the connector when the certificate chain of the pinned host has no pinned key.
+#[derive(err_derive::Error, Debug)]
+#[error(display = "No key in the verified certificate chain matches a pinned public key")]
+pub(crate) struct PinMismatch;
+
 /// A Connector for the `https` scheme.
 #[derive(Clone)]
 pub struct HttpsConnectorWithSni {
     sni_hostname: Option<String>,
+    connection_mode: Option<ApiConnectionModeHandle>,
     http: HttpConnector,
     tls: Arc<rustls::ClientConfig>,
+    pinned_keys: Option<Arc<PinnedKeys>>,
 }
 
 impl HttpsConnectorWithSni {
@@ -41,7 +89,9 @@ impl HttpsConnectorWithSni {
         config.enable_sni = true;
         config.root_store = Self::read_cert_store();
 
-        HttpsConnectorWithSni::from((http, config))
+        let mut connector = HttpsConnectorWithSni::from((http, config));
+        connector.pinned_keys = Some(Arc::new(PinnedKeys::new(crate::API_HOST, API_PINS)));
+        connector
     }
 
     fn read_cert_store() -> rustls::RootCertStore {
@@ -72,14 +122,22 @@ impl HttpsConnectorWithSni {
     pub fn set_sni_hostname(&mut self, hostname: Option<String>) {
         self.sni_hostname = hostname;
     }
+
+    /// Route connections according to the given connection mode. Connections are made directly
+    /// if no mode is set.
+    pub fn set_connection_mode(&mut self, connection_mode: Option<ApiConnectionModeHandle>) {
+        self.connection_mode = connection_mode;
+    }
 }
 
 impl From<(HttpConnector, rustls::ClientConfig)> for HttpsConnectorWithSni {
     fn from(args: (HttpConnector, rustls::ClientConfig)) -> HttpsConnectorWithSni {
         HttpsConnectorWithSni {
             sni_hostname: None,
+            connection_mode: None,
             http: args.0,
             tls: Arc::new(args.1),
+            pinned_keys: None,
         }
     }
 }
@@ -101,8 +159,29 @@ impl Service<Uri> for HttpsConnectorWithSni {
     }
 
     fn call(&mut self, uri: Uri) -> Self::Future {
-        let tls_connector: tokio_rustls::TlsConnector = self.tls.clone().into();
+        // Each connection gets its own verifier, so that a pin mismatch can be told apart from
+        // other TLS errors.
+        let pin_mismatch = Arc::new(AtomicBool::new(false));
+        let tls_config = match &self.pinned_keys {
+            Some(pinned_keys) => {
+                let mut config = (*self.tls).clone();
+                config
+                    .dangerous()
+                    .set_certificate_verifier(Arc::new(PinnedCertVerifier::new(
+                        pinned_keys.clone(),
+                        pin_mismatch.clone(),
+                    )));
+                Arc::new(config)
+            }
+            None => self.tls.clone(),
+        };
+        let tls_connector: tokio_rustls::TlsConnector = tls_config.into();
         let mut http = self.http.clone();
+        let connection_mode = self
+            .connection_mode
+            .as_ref()
+            .map(ApiConnectionModeHandle::get)
+            .unwrap_or(ApiConnectionMode::Direct);
         let sni_hostname = self
             .sni_hostname
             .clone()
@@ -122,11 +201,29 @@ impl Service<Uri> for HttpsConnectorWithSni {
             let hostname = sni_hostname?;
             let host = DNSNameRef::try_from_ascii_str(&hostname)
                 .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid hostname"))?;
-            let connection = http
-                .call(uri)
-                .await
-                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
-            let tls_connection = tls_connector.connect(host, connection).await?;
+            let target_host = uri.host().unwrap_or_default();
+            let target_port = uri.port_u16().unwrap_or(443);
+            let proxied_connection = connection_mode
+                .connect_proxied(target_host, target_port)
+                .await;
+            let connection = match proxied_connection {
+                Some(connection) => connection?,
+                None => http
+                    .call(uri)
+                    .await
+                    .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?,
+            };
+            let tls_connection =
+                tls_connector
+                    .connect(host, connection)
+                    .await
+                    .map_err(|error| {
+                        if pin_mismatch.load(Ordering::SeqCst) {
+                            io::Error::new(io::ErrorKind::InvalidData, PinMismatch)
+                        } else {
+                            error
+                        }
+                    })?;
 
             Ok(MaybeHttpsStream::Https(tls_connection))
         };
@@ -136,12 +233,316 @@ impl Service<Uri> for HttpsConnectorWithSni {
     }
 }
 
+
+/// Public keys of which at least one must be in the verified certificate chain of a host.
+struct PinnedKeys {
+    host: String,
+    digests: Vec<Vec<u8>>,
+}
+
+impl PinnedKeys {
+    fn new(host: &str, pins: &[&str]) -> Self {
+        PinnedKeys {
+            host: host.to_owned(),
+            digests: pins
+                .iter()
+                .map(|pin| base64::decode(pin).expect("Invalid public key pin"))
+                .collect(),
+        }
+    }
+
+    /// Returns whether a key is pinned. `spki` is the content of the SubjectPublicKeyInfo, as
+    /// stored in a trust anchor.
+    fn contains(&self, spki: &[u8]) -> bool {
+        let digest = ring::digest::digest(&ring::digest::SHA256, &der_sequence(spki));
+        self.digests
+            .iter()
+            .any(|pin| pin.as_slice() == digest.as_ref())
+    }
+}
+
+/// Verifies certificates like the default verifier. Certificates for the pinned host must in
+/// addition have a pinned key in their verified chain.
+struct PinnedCertVerifier {
+    inner: rustls::WebPKIVerifier,
+    pinned_keys: Arc<PinnedKeys>,
+    pin_mismatch: Arc<AtomicBool>,
+}
+
+impl PinnedCertVerifier {
+    fn new(pinned_keys: Arc<PinnedKeys>, pin_mismatch: Arc<AtomicBool>) -> Self {
+        PinnedCertVerifier {
+            inner: rustls::WebPKIVerifier::new(),
+            pinned_keys,
+            pin_mismatch,
+        }
+    }
+
+    /// Returns whether the end-entity certificate has a pinned key, or is signed through the
+    /// presented intermediates by an intermediate with a pinned key. A pinned certificate that is
+    /// presented but isn't part of the chain doesn't count, and neither do the roots.
+    fn has_pinned_chain(&self, presented_certs: &[Certificate]) -> bool {
+        let (end_entity, intermediates) = match presented_certs.split_first() {
+            Some(certs) => certs,
+            None => return false,
+        };
+        match webpki::trust_anchor_util::cert_der_as_trust_anchor(&end_entity.0) {
+            Ok(anchor) if self.pinned_keys.contains(anchor.spki) => return true,
+            _ => (),
+        }
+
+        let pinned_anchors: Vec<webpki::TrustAnchor<'_>> = intermediates
+            .iter()
+            .filter_map(|cert| webpki::trust_anchor_util::cert_der_as_trust_anchor(&cert.0).ok())
+            .filter(|anchor| self.pinned_keys.contains(anchor.spki))
+            .collect();
+        if pinned_anchors.is_empty() {
+            return false;
+        }
+
+        let end_entity = match webpki::EndEntityCert::from(&end_entity.0) {
+            Ok(cert) => cert,
+            Err(_) => return false,
+        };
+        let intermediates: Vec<&[u8]> =
+            intermediates.iter().map(|cert| cert.0.as_slice()).collect();
+        let now = match webpki::Time::try_from(SystemTime::now()) {
+            Ok(now) => now,
+            Err(_) => return false,
+        };
+        end_entity
+            .verify_is_valid_tls_server_cert(
+                SIGNATURE_ALGORITHMS,
+                &webpki::TLSServerTrustAnchors(&pinned_anchors),
+                &intermediates,
+                now,
+            )
+            .is_ok()
+    }
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        roots: &RootCertStore,
+        presented_certs: &[Certificate],
+        dns_name: DNSNameRef<'_>,
+        ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        let verified =
+            self.inner
+                .verify_server_cert(roots, presented_certs, dns_name, ocsp_response)?;
+
+        let hostname: &str = dns_name.into();
+        if hostname != self.pinned_keys.host || self.has_pinned_chain(presented_certs) {
+            Ok(verified)
+        } else {
+            log::error!("The certificate chain for {} has no pinned key", hostname);
+            self.pin_mismatch.store(true, Ordering::SeqCst);
+            Err(TLSError::General(PinMismatch.to_string()))
+        }
+    }
+}
+
+/// Returns true if the request failed because the certificate chain did not match the pin set.
+pub(crate) fn is_pin_mismatch(error: &hyper::Error) -> bool {
+    let mut source = error.source();
+    while let Some(error) = source {
+        let is_pin_mismatch = error
+            .downcast_ref::<io::Error>()
+            .and_then(io::Error::get_ref)
+            .map(|inner| inner.is::<PinMismatch>())
+            .unwrap_or(false);
+        if is_pin_mismatch {
+            return true;
+        }
+        source = error.source();
+    }
+    false
+}
+
+/// DER encodes a SEQUENCE with the given content.
+fn der_sequence(content: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![DER_SEQUENCE];
+    if content.len() < 0x80 {
+        encoded.push(content.len() as u8);
+    } else {
+        let length_bytes: Vec<u8> = content
+            .len()
+            .to_be_bytes()
+            .iter()
+            .skip_while(|byte| **byte == 0)
+            .copied()
+            .collect();
+        encoded.push(0x80 | length_bytes.len() as u8);
+        encoded.extend(length_bytes);
+    }
+    encoded.extend_from_slice(content);
+    encoded
+}
+
 #[cfg(test)]
 mod test {
-    use super::HttpsConnectorWithSni;
+    use super::*;
+
+    const TEST_ROOT_CERT: &[u8] = include_bytes!("../test-certs/root.pem");
+    const TEST_INTERMEDIATE_CERT: &[u8] = include_bytes!("../test-certs/intermediate.pem");
+    const TEST_OTHER_INTERMEDIATE_CERT: &[u8] =
+        include_bytes!("../test-certs/other-intermediate.pem");
+    const TEST_API_CERT: &[u8] = include_bytes!("../test-certs/api.pem");
+    const TEST_API_OTHER_INTERMEDIATE_CERT: &[u8] =
+        include_bytes!("../test-certs/api-other-intermediate.pem");
+    const TEST_OTHER_CERT: &[u8] = include_bytes!("../test-certs/other.pem");
+
+    const TEST_ROOT_PIN: &str = "FwcmjoP0xpdbMwHzbF86+hEAm0ApJ0my7Oi8CjMsooA=";
+    const TEST_INTERMEDIATE_PIN: &str = "DybO8Z/abrBcO5AzNJgq+HynMUo0l3ydxRG2KqAz0Zs=";
+    const TEST_OTHER_INTERMEDIATE_PIN: &str = "B8jvMKM90pA4LthVrYhGMFEnlnFkx19avkMLtxWwWXk=";
+    const TEST_API_PIN: &str = "AvedzYaf3QGLzkcra74ViNnrSORYUYUF+cueemduEls=";
+    const TEST_OTHER_PIN: &str = "UKwiALm1bHY9ALobW22c6QyWM93H1JqxJamN6dsY4T8=";
+
+    /// The API certificate and the intermediate that signs it.
+    const API_CHAIN: &[&[u8]] = &[TEST_API_CERT, TEST_INTERMEDIATE_CERT];
+    /// An API certificate that is valid under the root, signed by another intermediate.
+    const API_OTHER_CHAIN: &[&[u8]] = &[
+        TEST_API_OTHER_INTERMEDIATE_CERT,
+        TEST_OTHER_INTERMEDIATE_CERT,
+    ];
+
+    fn read_cert(pem: &[u8]) -> Certificate {
+        rustls::internal::pemfile::certs(&mut BufReader::new(pem))
+            .expect("Failed to parse certificate")
+            .remove(0)
+    }
+
+    fn test_roots() -> RootCertStore {
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(&read_cert(TEST_ROOT_CERT))
+            .expect("Failed to add test root");
+        roots
+    }
+
+    fn is_pinned(pins: &[&str], cert: &[u8]) -> bool {
+        let cert = read_cert(cert);
+        let anchor = webpki::trust_anchor_util::cert_der_as_trust_anchor(&cert.0).unwrap();
+        PinnedKeys::new("api.mullvad.net", pins).contains(anchor.spki)
+    }
+
+    fn verify_chain(
+        pins: &[&str],
+        certs: &[&[u8]],
+        hostname: &str,
+    ) -> (Result<ServerCertVerified, TLSError>, bool) {
+        let pin_mismatch = Arc::new(AtomicBool::new(false));
+        let verifier = PinnedCertVerifier::new(
+            Arc::new(PinnedKeys::new("api.mullvad.net", pins)),
+            pin_mismatch.clone(),
+        );
+        let dns_name = DNSNameRef::try_from_ascii_str(hostname).unwrap();
+        let certs: Vec<Certificate> = certs.iter().map(|cert| read_cert(cert)).collect();
+        let result = verifier.verify_server_cert(&test_roots(), &certs, dns_name, &[]);
+        (result, pin_mismatch.load(Ordering::SeqCst))
+    }
+
+    fn assert_pin_mismatch(pins: &[&str], certs: &[&[u8]]) {
+        let (result, pin_mismatch) = verify_chain(pins, certs, "api.mullvad.net");
+        assert!(result.is_err());
+        assert!(pin_mismatch);
+    }
 
     #[test]
     fn test_cert_loading() {
         let _certs = HttpsConnectorWithSni::read_cert_store();
     }
+
+    #[test]
+    fn test_shipped_roots_not_pinned() {
+        for root in &[OLD_ROOT_CERT, NEW_ROOT_CERT] {
+            assert!(!is_pinned(API_PINS, root));
+        }
+    }
+
+    #[test]
+    fn test_spki_pin() {
+        for (cert, pin) in &[
+            (TEST_ROOT_CERT, TEST_ROOT_PIN),
+            (TEST_INTERMEDIATE_CERT, TEST_INTERMEDIATE_PIN),
+            (TEST_OTHER_INTERMEDIATE_CERT, TEST_OTHER_INTERMEDIATE_PIN),
+            (TEST_API_CERT, TEST_API_PIN),
+            (TEST_OTHER_CERT, TEST_OTHER_PIN),
+        ] {
+            assert!(is_pinned(&[*pin], cert));
+        }
+    }
+
+    #[test]
+    fn test_pinned_key_accepted() {
+        assert!(verify_chain(&[TEST_API_PIN], API_CHAIN, "api.mullvad.net")
+            .0
+            .is_ok());
+        assert!(verify_chain(
+            &[TEST_OTHER_PIN, TEST_API_PIN],
+            API_CHAIN,
+            "api.mullvad.net"
+        )
+        .0
+        .is_ok());
+    }
+
+    #[test]
+    fn test_pinned_intermediate_accepted() {
+        assert!(
+            verify_chain(&[TEST_INTERMEDIATE_PIN], API_CHAIN, "api.mullvad.net")
+                .0
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_unpinned_intermediate_rejected() {
+        // The chain is valid under the trusted root, but neither the key of the certificate nor
+        // the intermediate is pinned
+        let certs: Vec<Certificate> = API_OTHER_CHAIN.iter().map(|cert| read_cert(cert)).collect();
+        let dns_name = DNSNameRef::try_from_ascii_str("api.mullvad.net").unwrap();
+        assert!(rustls::WebPKIVerifier::new()
+            .verify_server_cert(&test_roots(), &certs, dns_name, &[])
+            .is_ok());
+        assert_pin_mismatch(&[TEST_INTERMEDIATE_PIN, TEST_API_PIN], API_OTHER_CHAIN);
+    }
+
+    #[test]
+    fn test_pinned_root_not_sufficient() {
+        assert_pin_mismatch(&[TEST_ROOT_PIN], API_CHAIN);
+    }
+
+    #[test]
+    fn test_pinned_cert_outside_chain_rejected() {
+        // A pinned certificate that is presented but does not sign the chain is not enough
+        assert_pin_mismatch(
+            &[TEST_INTERMEDIATE_PIN],
+            &[
+                TEST_API_OTHER_INTERMEDIATE_CERT,
+                TEST_OTHER_INTERMEDIATE_CERT,
+                TEST_INTERMEDIATE_CERT,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_other_hosts_not_pinned() {
+        assert!(
+            verify_chain(&[TEST_API_PIN], &[TEST_OTHER_CERT], "example.com")
+                .0
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_invalid_chain_rejected() {
+        // A pinned key does not make an invalid certificate acceptable
+        let (result, pin_mismatch) =
+            verify_chain(&[TEST_OTHER_PIN], &[TEST_OTHER_CERT], "api.mullvad.net");
+        assert!(result.is_err());
+        assert!(!pin_mismatch);
+    }
 }
@@ -21,10 +21,12 @@ mod https_client_with_sni;
 use crate::https_client_with_sni::HttpsConnectorWithSni;
 
 mod address_cache;
-mod relay_list;
+mod proxy;
+pub mod relay_list;
 use address_cache::AddressCache;
 pub use hyper::StatusCode;
-pub use relay_list::RelayListProxy;
+pub use proxy::{ApiConnectionMode, ApiConnectionModeHandle, ProxyConfig};
+pub use relay_list::{RelayListDocument, RelayListProxy, RelayListPublicKey};
 
 /// Error code returned by the Mullvad API if the voucher has alreaby been used.
 pub const VOUCHER_USED: &str = "VOUCHER_USED";
@@ -42,6 +44,7 @@ pub struct MullvadRpcRuntime {
     https_connector: HttpsConnectorWithSni,
     handle: tokio::runtime::Handle,
     address_cache: AddressCache,
+    connection_mode: ApiConnectionModeHandle,
 }
 
 #[derive(err_derive::Error, Debug)]
@@ -53,10 +56,12 @@ pub enum Error {
 impl MullvadRpcRuntime {
     /// Create a new `MullvadRpcRuntime`.
     pub fn new(handle: tokio::runtime::Handle) -> Result<Self, Error> {
+        let address_cache = AddressCache::new();
         Ok(MullvadRpcRuntime {
             https_connector: HttpsConnectorWithSni::new(),
             handle,
-            address_cache: AddressCache::new(),
+            connection_mode: ApiConnectionModeHandle::new(address_cache.clone()),
+            address_cache,
         })
     }
 
@@ -73,19 +78,27 @@ impl MullvadRpcRuntime {
         Ok(MullvadRpcRuntime {
             https_connector,
             handle,
+            connection_mode: ApiConnectionModeHandle::new(address_cache.clone()),
             address_cache,
         })
     }
 
-    /// Creates a new request service and returns a handle to it.
-    fn new_request_service(&mut self, sni_hostname: Option<String>) -> rest::RequestServiceHandle {
+    /// Creates a new request service and returns a handle to it. If `connection_mode` is set,
+    /// connections are made according to it.
+    fn new_request_service(
+        &mut self,
+        sni_hostname: Option<String>,
+        connection_mode: Option<ApiConnectionModeHandle>,
+    ) -> rest::RequestServiceHandle {
         let mut https_connector = self.https_connector.clone();
         https_connector.set_sni_hostname(sni_hostname);
+        https_connector.set_connection_mode(connection_mode.clone());
 
         let service = rest::RequestService::new(
             https_connector,
             self.handle.clone(),
             self.address_cache.clone(),
+            connection_mode,
         );
         let handle = service.handle();
         self.handle.spawn(service.into_future());
@@ -94,7 +107,10 @@ impl MullvadRpcRuntime {
 
     /// Returns a request factory initialized to create requests for the master API
     pub fn mullvad_rest_handle(&mut self) -> rest::MullvadRestHandle {
-        let service = self.new_request_service(Some(API_HOST.to_owned()));
+        let service = self.new_request_service(
+            Some(API_HOST.to_owned()),
+            Some(self.connection_mode.clone()),
+        );
         let factory = rest::RequestFactory::new(
             API_HOST.to_owned(),
             Box::new(self.address_cache.clone()),
@@ -106,7 +122,12 @@ impl MullvadRpcRuntime {
 
     /// Returns a new request service handle
     pub fn rest_handle(&mut self) -> rest::RequestServiceHandle {
-        self.new_request_service(None)
+        self.new_request_service(None, None)
+    }
+
+    /// Returns a handle for changing how the API is reached.
+    pub fn connection_mode_handle(&self) -> ApiConnectionModeHandle {
+        self.connection_mode.clone()
     }
 
     pub fn handle(&mut self) -> &mut tokio::runtime::Handle {
@@ -397,6 +418,26 @@ impl WireguardKeyProxy {
         rest::deserialize_body(response).await
     }
 
+    /// Lists all WireGuard keys that are registered on the account.
+    pub async fn list_wireguard_keys(
+        &mut self,
+        account_token: AccountToken,
+    ) -> Result<Vec<mullvad_types::wireguard::RegisteredKey>, rest::Error> {
+        let service = self.handle.service.clone();
+
+        let response = rest::send_request(
+            &self.handle.factory,
+            service,
+            "/v1/wireguard-keys",
+            Method::GET,
+            Some(account_token),
+            StatusCode::OK,
+        )
+        .await?;
+
+        rest::deserialize_body(response).await
+    }
+
     pub async fn remove_wireguard_key(
         &mut self,
         account_token: AccountToken,
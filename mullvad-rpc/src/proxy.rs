@@ -0,0 +1,390 @@
+//! Support for reaching the API through proxies, for networks where it is blocked.
+
+use crate::address_cache::AddressCache;
+use std::{
+    fmt, io,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+};
+use talpid_types::net::openvpn::ProxyAuth;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+const SOCKS5_VERSION: u8 = 5;
+const SOCKS5_AUTH_NONE: u8 = 0;
+const SOCKS5_AUTH_USERNAME_PASSWORD: u8 = 2;
+const SOCKS5_USERNAME_PASSWORD_VERSION: u8 = 1;
+const SOCKS5_CMD_CONNECT: u8 = 1;
+const SOCKS5_ATYP_IPV4: u8 = 1;
+const SOCKS5_ATYP_DOMAIN: u8 = 3;
+const SOCKS5_ATYP_IPV6: u8 = 4;
+const SOCKS5_REPLY_SUCCEEDED: u8 = 0;
+
+/// Upper limit on the size of the response header of an HTTP proxy.
+const MAX_HTTP_RESPONSE_HEADER_SIZE: usize = 8 * 1024;
+
+/// How connections to the API are made.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiConnectionMode {
+    /// Connect directly to the API.
+    Direct,
+    /// Connect through a SOCKS5 proxy.
+    Socks5(ProxyConfig),
+    /// Connect through an HTTP proxy, using the `CONNECT` method.
+    Http(ProxyConfig),
+}
+
+impl fmt::Display for ApiConnectionMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiConnectionMode::Direct => write!(f, "direct"),
+            ApiConnectionMode::Socks5(proxy) => write!(f, "SOCKS5 proxy at {}", proxy.address),
+            ApiConnectionMode::Http(proxy) => write!(f, "HTTP proxy at {}", proxy.address),
+        }
+    }
+}
+
+/// Address and credentials of a proxy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyConfig {
+    pub address: SocketAddr,
+    pub auth: Option<ProxyAuth>,
+}
+
+impl ApiConnectionMode {
+    /// Opens a TCP connection to `host` through the proxy. Returns `None` for `Direct`, which is
+    /// handled by the regular HTTP connector.
+    pub(crate) async fn connect_proxied(
+        &self,
+        host: &str,
+        port: u16,
+    ) -> Option<io::Result<TcpStream>> {
+        // IPv6 addresses in URIs are enclosed in brackets
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        match self {
+            ApiConnectionMode::Direct => None,
+            ApiConnectionMode::Socks5(proxy) => Some(socks5_connect(proxy, host, port).await),
+            ApiConnectionMode::Http(proxy) => Some(http_connect(proxy, host, port).await),
+        }
+    }
+}
+
+/// A handle to the connection mode that is shared by all API clients of a `MullvadRpcRuntime`.
+/// It also keeps track of consecutive failed requests, so that its owner can switch to another
+/// mode when the current one doesn't work.
+#[derive(Clone)]
+pub struct ApiConnectionModeHandle {
+    state: Arc<Mutex<ConnectionModeState>>,
+    address_cache: AddressCache,
+}
+
+struct ConnectionModeState {
+    mode: ApiConnectionMode,
+    consecutive_failures: u32,
+    failure_listener: Option<Arc<dyn Fn(u32) + Send + Sync>>,
+}
+
+impl ApiConnectionModeHandle {
+    pub(crate) fn new(address_cache: AddressCache) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(ConnectionModeState {
+                mode: ApiConnectionMode::Direct,
+                consecutive_failures: 0,
+                failure_listener: None,
+            })),
+            address_cache,
+        }
+    }
+
+    pub fn get(&self) -> ApiConnectionMode {
+        self.state.lock().unwrap().mode.clone()
+    }
+
+    /// Changes how new connections are made and resets the failure count.
+    pub fn set(&self, mode: ApiConnectionMode) {
+        let mut state = self.state.lock().unwrap();
+        if state.mode != mode {
+            log::info!("Connecting to the API using mode: {}", mode);
+            state.mode = mode;
+        }
+        state.consecutive_failures = 0;
+    }
+
+    /// Sets a callback that is called with the number of consecutive failed requests after each
+    /// request that fails to reach the API.
+    pub fn set_failure_listener(&self, listener: impl Fn(u32) + Send + Sync + 'static) {
+        self.state.lock().unwrap().failure_listener = Some(Arc::new(listener));
+    }
+
+    /// Returns the API address that the next request will be sent to.
+    pub fn api_address(&self) -> SocketAddr {
+        self.address_cache.peek_address()
+    }
+
+    pub(crate) fn register_success(&self) {
+        self.state.lock().unwrap().consecutive_failures = 0;
+    }
+
+    pub(crate) fn register_failure(&self) {
+        let (consecutive_failures, listener) = {
+            let mut state = self.state.lock().unwrap();
+            state.consecutive_failures += 1;
+            (state.consecutive_failures, state.failure_listener.clone())
+        };
+        if let Some(listener) = listener {
+            listener(consecutive_failures);
+        }
+    }
+}
+
+async fn socks5_connect(proxy: &ProxyConfig, host: &str, port: u16) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(proxy.address).await?;
+
+    let auth_method = if proxy.auth.is_some() {
+        SOCKS5_AUTH_USERNAME_PASSWORD
+    } else {
+        SOCKS5_AUTH_NONE
+    };
+    stream.write_all(&[SOCKS5_VERSION, 1, auth_method]).await?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply != [SOCKS5_VERSION, auth_method] {
+        return Err(proxy_error(
+            "The SOCKS5 proxy does not accept the authentication method",
+        ));
+    }
+
+    if let Some(auth) = &proxy.auth {
+        let mut request = vec![SOCKS5_USERNAME_PASSWORD_VERSION];
+        push_length_prefixed(&mut request, &auth.username)?;
+        push_length_prefixed(&mut request, &auth.password)?;
+        stream.write_all(&request).await?;
+        stream.read_exact(&mut reply).await?;
+        if reply[1] != SOCKS5_REPLY_SUCCEEDED {
+            return Err(proxy_error("The SOCKS5 proxy rejected the credentials"));
+        }
+    }
+
+    let mut request = vec![SOCKS5_VERSION, SOCKS5_CMD_CONNECT, 0];
+    match host.parse() {
+        Ok(IpAddr::V4(address)) => {
+            request.push(SOCKS5_ATYP_IPV4);
+            request.extend_from_slice(&address.octets());
+        }
+        Ok(IpAddr::V6(address)) => {
+            request.push(SOCKS5_ATYP_IPV6);
+            request.extend_from_slice(&address.octets());
+        }
+        Err(_) => {
+            request.push(SOCKS5_ATYP_DOMAIN);
+            push_length_prefixed(&mut request, host)?;
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply_header = [0u8; 4];
+    stream.read_exact(&mut reply_header).await?;
+    if reply_header[1] != SOCKS5_REPLY_SUCCEEDED {
+        return Err(proxy_error(&format!(
+            "The SOCKS5 proxy failed to connect to the API, reply code {}",
+            reply_header[1]
+        )));
+    }
+    // Skip the address that the proxy bound to, and its port
+    let address_length = match reply_header[3] {
+        SOCKS5_ATYP_IPV4 => 4,
+        SOCKS5_ATYP_IPV6 => 16,
+        SOCKS5_ATYP_DOMAIN => {
+            let mut length = [0u8; 1];
+            stream.read_exact(&mut length).await?;
+            usize::from(length[0])
+        }
+        _ => return Err(proxy_error("Invalid address type in SOCKS5 reply")),
+    };
+    let mut bound_address = vec![0u8; address_length + 2];
+    stream.read_exact(&mut bound_address).await?;
+
+    Ok(stream)
+}
+
+async fn http_connect(proxy: &ProxyConfig, host: &str, port: u16) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(proxy.address).await?;
+
+    let authority = if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    };
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+    if let Some(auth) = &proxy.auth {
+        let credentials = base64::encode(format!("{}:{}", auth.username, auth.password));
+        request += &format!("Proxy-Authorization: Basic {}\r\n", credentials);
+    }
+    request += "\r\n";
+    stream.write_all(request.as_bytes()).await?;
+
+    // The header is read one byte at a time so that nothing after it is consumed
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= MAX_HTTP_RESPONSE_HEADER_SIZE {
+            return Err(proxy_error("The response of the HTTP proxy is too large"));
+        }
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte).await?;
+        response.push(byte[0]);
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    if status_line.split_whitespace().nth(1) != Some("200") {
+        return Err(proxy_error(&format!(
+            "The HTTP proxy failed to connect to the API: {}",
+            status_line
+        )));
+    }
+
+    Ok(stream)
+}
+
+fn push_length_prefixed(buffer: &mut Vec<u8>, value: &str) -> io::Result<()> {
+    if value.len() > usize::from(u8::MAX) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "SOCKS5 fields are limited to 255 bytes",
+        ));
+    }
+    buffer.push(value.len() as u8);
+    buffer.extend_from_slice(value.as_bytes());
+    Ok(())
+}
+
+fn proxy_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn proxy_config(address: SocketAddr) -> ProxyConfig {
+        ProxyConfig {
+            address,
+            auth: Some(ProxyAuth {
+                username: "user".to_owned(),
+                password: "secret".to_owned(),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_socks5_connect() {
+        let mut runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
+        runtime.block_on(async {
+            let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let proxy = proxy_config(listener.local_addr().unwrap());
+
+            let server = tokio::spawn(async move {
+                let (mut stream, _) = listener.accept().await.unwrap();
+
+                let mut greeting = [0u8; 3];
+                stream.read_exact(&mut greeting).await.unwrap();
+                assert_eq!(greeting, [5, 1, 2]);
+                stream.write_all(&[5, 2]).await.unwrap();
+
+                let mut auth = [0u8; 14];
+                stream.read_exact(&mut auth).await.unwrap();
+                assert_eq!(&auth, b"\x01\x04user\x06secret");
+                stream.write_all(&[1, 0]).await.unwrap();
+
+                let mut request = [0u8; 10];
+                stream.read_exact(&mut request).await.unwrap();
+                assert_eq!(request, [5, 1, 0, 1, 193, 138, 218, 78, 1, 187]);
+                stream
+                    .write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 80])
+                    .await
+                    .unwrap();
+                stream.write_all(b"payload").await.unwrap();
+            });
+
+            let mode = ApiConnectionMode::Socks5(proxy);
+            let mut stream = mode
+                .connect_proxied("193.138.218.78", 443)
+                .await
+                .unwrap()
+                .unwrap();
+            let mut payload = String::new();
+            stream.read_to_string(&mut payload).await.unwrap();
+            assert_eq!(payload, "payload");
+            server.await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_http_connect() {
+        let mut runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
+        runtime.block_on(async {
+            let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let proxy = proxy_config(listener.local_addr().unwrap());
+
+            let server = tokio::spawn(async move {
+                let (mut stream, _) = listener.accept().await.unwrap();
+
+                let mut request = Vec::new();
+                while !request.ends_with(b"\r\n\r\n") {
+                    let mut byte = [0u8; 1];
+                    stream.read_exact(&mut byte).await.unwrap();
+                    request.push(byte[0]);
+                }
+                assert_eq!(
+                    String::from_utf8(request).unwrap(),
+                    "CONNECT [2001:db8::1]:443 HTTP/1.1\r\nHost: [2001:db8::1]:443\r\n\
+                     Proxy-Authorization: Basic dXNlcjpzZWNyZXQ=\r\n\r\n"
+                );
+                stream
+                    .write_all(b"HTTP/1.1 200 Connection established\r\n\r\npayload")
+                    .await
+                    .unwrap();
+            });
+
+            let mode = ApiConnectionMode::Http(proxy);
+            let mut stream = mode
+                .connect_proxied("[2001:db8::1]", 443)
+                .await
+                .unwrap()
+                .unwrap();
+            let mut payload = String::new();
+            stream.read_to_string(&mut payload).await.unwrap();
+            assert_eq!(payload, "payload");
+            server.await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_http_connect_refused() {
+        let mut runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
+        runtime.block_on(async {
+            let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let proxy = proxy_config(listener.local_addr().unwrap());
+
+            tokio::spawn(async move {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = [0u8; 16];
+                let _ = stream.read(&mut request).await;
+                let _ = stream
+                    .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+                    .await;
+            });
+
+            let mode = ApiConnectionMode::Http(proxy);
+            assert!(mode
+                .connect_proxied("193.138.218.78", 443)
+                .await
+                .unwrap()
+                .is_err());
+        });
+    }
+}
@@ -1,8 +1,9 @@
 /// A module dedicated to retrieving the relay list from the master API.
 use crate::rest;
 
-use hyper::{Method, StatusCode};
+use hyper::{header, Method, StatusCode};
 use mullvad_types::{location, relay_list};
+use ring::signature::{UnparsedPublicKey, ED25519};
 use talpid_types::net::wireguard;
 
 use std::{
@@ -20,33 +21,140 @@ pub struct RelayListProxy {
 
 const RELAY_LIST_TIMEOUT: Duration = Duration::from_secs(15);
 
+/// Header containing the base64 encoded Ed25519 signature of the relay list document.
+const SIGNATURE_HEADER: &str = "x-relay-list-signature";
+
+const ED25519_PUBLIC_KEY_LEN: usize = 32;
+const ED25519_SIGNATURE_LEN: usize = 64;
+
+/// A relay list as served by the API. The document has to be kept as is, since that is what the
+/// signature covers.
+#[derive(Debug, Clone)]
+pub struct RelayListDocument {
+    /// The unparsed relay list.
+    pub document: Vec<u8>,
+    /// Base64 encoded Ed25519 signature of `document`, if the API sent one.
+    pub signature: Option<String>,
+    /// Identifies this version of the relay list.
+    pub etag: Option<String>,
+}
+
+impl RelayListDocument {
+    /// Verifies the signature of the document and parses it.
+    pub fn verify(&self, public_key: &RelayListPublicKey) -> Result<relay_list::RelayList, Error> {
+        let signature = self.signature.as_ref().ok_or(Error::MissingSignature)?;
+        public_key.verify(&self.document, signature)?;
+        parse_relay_list(&self.document)
+    }
+}
+
+/// Errors that can happen when verifying a relay list.
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    /// The relay list is not signed.
+    #[error(display = "The relay list is not signed")]
+    MissingSignature,
+
+    /// The relay list signature does not match the document or the key.
+    #[error(display = "The relay list signature is invalid")]
+    InvalidSignature,
+
+    /// The public key is not a base64 encoded Ed25519 key.
+    #[error(display = "Invalid relay list public key")]
+    InvalidPublicKey,
+
+    /// The relay list could not be parsed.
+    #[error(display = "Failed to parse the relay list")]
+    Parse(#[error(source)] serde_json::Error),
+}
+
+/// An Ed25519 public key that relay lists are signed with.
+#[derive(Debug, Clone)]
+pub struct RelayListPublicKey(Vec<u8>);
+
+impl RelayListPublicKey {
+    /// Decodes a base64 encoded Ed25519 public key.
+    pub fn from_base64(encoded: &str) -> Result<Self, Error> {
+        let key = base64::decode(encoded.trim()).map_err(|_| Error::InvalidPublicKey)?;
+        if key.len() != ED25519_PUBLIC_KEY_LEN {
+            return Err(Error::InvalidPublicKey);
+        }
+        Ok(RelayListPublicKey(key))
+    }
+
+    /// Checks that `signature` is a valid base64 encoded signature of `document`.
+    pub fn verify(&self, document: &[u8], signature: &str) -> Result<(), Error> {
+        let signature = base64::decode(signature.trim()).map_err(|_| Error::InvalidSignature)?;
+        if signature.len() != ED25519_SIGNATURE_LEN {
+            return Err(Error::InvalidSignature);
+        }
+        UnparsedPublicKey::new(&ED25519, &self.0)
+            .verify(document, &signature)
+            .map_err(|_| Error::InvalidSignature)
+    }
+}
+
+/// Parses a relay list document in the format served by the API.
+pub fn parse_relay_list(document: &[u8]) -> Result<relay_list::RelayList, Error> {
+    let relay_list: ServerRelayList = serde_json::from_slice(document).map_err(Error::Parse)?;
+    Ok(relay_list.into_relay_list())
+}
+
 impl RelayListProxy {
     /// Construct a new relay list rest client
     pub fn new(handle: rest::MullvadRestHandle) -> Self {
         Self { handle }
     }
 
-    /// Fetch the relay list
-    pub fn relay_list(&self) -> impl Future<Output = Result<relay_list::RelayList, rest::Error>> {
+    /// Fetch the relay list. If `etag` is given and matches the current relay list, `None` is
+    /// returned. The returned document is not verified.
+    pub fn relay_list(
+        &self,
+        etag: Option<String>,
+    ) -> impl Future<Output = Result<Option<RelayListDocument>, rest::Error>> {
         let service = self.handle.service.clone();
         let request = self.handle.factory.request("/v1/relays", Method::GET);
 
         let future = async move {
             let mut request = request?;
             request.set_timeout(RELAY_LIST_TIMEOUT);
+            if let Some(etag) = etag {
+                request.add_header(header::IF_NONE_MATCH, &etag)?;
+            }
 
             let response = service.request(request).await?;
+            if response.status() == StatusCode::NOT_MODIFIED {
+                return Ok(None);
+            }
             if response.status() != StatusCode::OK {
                 return rest::handle_error_response(response).await;
             }
-            Ok(rest::deserialize_body::<ServerRelayList>(response)
-                .await?
-                .into_relay_list())
+
+            let etag = header_value(&response, header::ETAG.as_str());
+            let signature = header_value(&response, SIGNATURE_HEADER);
+            let document = rest::read_body(response).await?;
+            Ok(Some(RelayListDocument {
+                document,
+                signature,
+                etag,
+            }))
         };
         future
     }
 }
 
+fn header_value(response: &rest::Response, name: &str) -> Option<String> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|value| match value.to_str() {
+            Ok(value) => Some(value.to_string()),
+            Err(_) => {
+                log::error!("Ignoring invalid {} header in relay list response", name);
+                None
+            }
+        })
+}
 
 #[derive(Debug, serde::Deserialize)]
 struct ServerRelayList {
@@ -345,3 +453,86 @@ struct Bridges {
     shadowsocks: Vec<relay_list::ShadowsocksEndpointData>,
     relays: Vec<Relay>,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ring::{
+        rand::SystemRandom,
+        signature::{Ed25519KeyPair, KeyPair},
+    };
+
+    const DOCUMENT: &[u8] = include_bytes!("../test-relay-list/relays.json");
+
+    /// Generates a new signing key, and returns it along with its base64 encoded public key.
+    fn generate_key() -> (Ed25519KeyPair, String) {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let public_key = base64::encode(key_pair.public_key().as_ref());
+        (key_pair, public_key)
+    }
+
+    fn sign(key_pair: &Ed25519KeyPair, document: &[u8]) -> String {
+        base64::encode(key_pair.sign(document).as_ref())
+    }
+
+    fn document(document: &[u8], signature: Option<&str>) -> RelayListDocument {
+        RelayListDocument {
+            document: document.to_vec(),
+            signature: signature.map(str::to_string),
+            etag: None,
+        }
+    }
+
+    #[test]
+    fn test_valid_signature() {
+        let (key_pair, public_key) = generate_key();
+        let key = RelayListPublicKey::from_base64(&public_key).unwrap();
+        let signature = sign(&key_pair, DOCUMENT);
+        let relay_list = document(DOCUMENT, Some(&signature)).verify(&key).unwrap();
+        assert_eq!(relay_list.countries.len(), 1);
+        assert_eq!(relay_list.countries[0].cities[0].relays.len(), 2);
+    }
+
+    #[test]
+    fn test_missing_signature() {
+        let (_, public_key) = generate_key();
+        let key = RelayListPublicKey::from_base64(&public_key).unwrap();
+        assert!(matches!(
+            document(DOCUMENT, None).verify(&key),
+            Err(Error::MissingSignature)
+        ));
+    }
+
+    #[test]
+    fn test_tampered_document() {
+        let (key_pair, public_key) = generate_key();
+        let key = RelayListPublicKey::from_base64(&public_key).unwrap();
+        let signature = sign(&key_pair, DOCUMENT);
+        let tampered = String::from_utf8(DOCUMENT.to_vec())
+            .unwrap()
+            .replace("se-got-wg-001", "se-got-wg-666");
+        assert!(matches!(
+            document(tampered.as_bytes(), Some(&signature)).verify(&key),
+            Err(Error::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn test_wrong_key() {
+        let (key_pair, _) = generate_key();
+        let (_, other_public_key) = generate_key();
+        let key = RelayListPublicKey::from_base64(&other_public_key).unwrap();
+        let signature = sign(&key_pair, DOCUMENT);
+        assert!(matches!(
+            document(DOCUMENT, Some(&signature)).verify(&key),
+            Err(Error::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_public_key() {
+        assert!(RelayListPublicKey::from_base64("not a key").is_err());
+        assert!(RelayListPublicKey::from_base64(&base64::encode([0u8; 16])).is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use crate::address_cache::AddressCache;
+use crate::{address_cache::AddressCache, https_client_with_sni, proxy::ApiConnectionModeHandle};
 use futures::{
     channel::{mpsc, oneshot},
     future::{abortable, AbortHandle, Aborted},
@@ -44,6 +44,10 @@ pub enum Error {
     #[error(display = "Hyper error")]
     HyperError(#[error(source)] hyper::Error),
 
+    /// The verified certificate chain of the API has no pinned public key.
+    #[error(display = "The API certificate chain does not match any pinned public key")]
+    PinMismatch,
+
     #[error(display = "Invalid header value")]
     InvalidHeaderError(#[error(source)] http::header::InvalidHeaderValue),
 
@@ -71,6 +75,16 @@ pub enum Error {
     UriError(#[error(source)] http::uri::InvalidUri),
 }
 
+impl Error {
+    fn from_hyper_error(error: hyper::Error) -> Self {
+        if https_client_with_sni::is_pin_mismatch(&error) {
+            Error::PinMismatch
+        } else {
+            Error::HyperError(error)
+        }
+    }
+}
+
 /// A service that executes HTTP requests, allowing for on-demand termination of all in-flight
 /// requests
 pub(crate) struct RequestService<C> {
@@ -82,11 +96,18 @@ pub(crate) struct RequestService<C> {
     next_id: u64,
     in_flight_requests: BTreeMap<u64, AbortHandle>,
     address_cache: AddressCache,
+    connection_mode: Option<ApiConnectionModeHandle>,
 }
 
 impl<C: Connect + Clone + Send + Sync + 'static> RequestService<C> {
-    /// Constructs a new request service.
-    pub fn new(connector: C, handle: Handle, address_cache: AddressCache) -> RequestService<C> {
+    /// Constructs a new request service. If a connection mode handle is given, it is notified
+    /// of requests that succeed and requests that fail to reach the server.
+    pub fn new(
+        connector: C,
+        handle: Handle,
+        address_cache: AddressCache,
+        connection_mode: Option<ApiConnectionModeHandle>,
+    ) -> RequestService<C> {
         let client = Self::new_client(connector.clone());
 
         let (command_tx, command_rx) = mpsc::channel(1);
@@ -99,6 +120,7 @@ impl<C: Connect + Clone + Send + Sync + 'static> RequestService<C> {
             connector,
             handle,
             address_cache,
+            connection_mode,
         }
     }
 
@@ -124,9 +146,13 @@ impl<C: Connect + Clone + Send + Sync + 'static> RequestService<C> {
                 let hyper_request = request.into_request();
                 let host_addr = get_request_socket_addr(&hyper_request);
 
-                let (request_future, abort_handle) =
-                    abortable(self.client.request(hyper_request).map_err(Error::from));
+                let (request_future, abort_handle) = abortable(
+                    self.client
+                        .request(hyper_request)
+                        .map_err(Error::from_hyper_error),
+                );
                 let address_cache = self.address_cache.clone();
+                let connection_mode = self.connection_mode.clone();
 
                 let future = async move {
                     let response =
@@ -145,6 +171,15 @@ impl<C: Connect + Clone + Send + Sync + 'static> RequestService<C> {
                             }
                         }
                     }
+                    if let Some(connection_mode) = connection_mode {
+                        match &response {
+                            Ok(_) => connection_mode.register_success(),
+                            Err(Error::HyperError(_))
+                            | Err(Error::TimeoutError(_))
+                            | Err(Error::PinMismatch) => connection_mode.register_failure(),
+                            Err(_) => (),
+                        }
+                    }
 
 
                     if completion_tx.send(response).is_err() {
@@ -296,6 +331,13 @@ impl RestRequest {
         Ok(())
     }
 
+    /// Adds a header to the request.
+    pub fn add_header<T: header::IntoHeaderName>(&mut self, key: T, value: &str) -> Result<()> {
+        let header_value = HeaderValue::from_str(value).map_err(Error::InvalidHeaderError)?;
+        self.request.headers_mut().insert(key, header_value);
+        Ok(())
+    }
+
     /// Sets timeout for the request.
     pub fn set_timeout(&mut self, timeout: Duration) {
         self.timeout = timeout;
@@ -504,7 +546,13 @@ pub fn post_request_with_json<B: serde::Serialize>(
 }
 
 
-pub async fn deserialize_body<T: serde::de::DeserializeOwned>(mut response: Response) -> Result<T> {
+pub async fn deserialize_body<T: serde::de::DeserializeOwned>(response: Response) -> Result<T> {
+    let body = read_body(response).await?;
+    serde_json::from_slice(&body).map_err(Error::DeserializeError)
+}
+
+/// Reads the whole body of a response.
+pub async fn read_body(mut response: Response) -> Result<Vec<u8>> {
     let body_length: usize = response
         .headers()
         .get(header::CONTENT_LENGTH)
@@ -516,8 +564,7 @@ pub async fn deserialize_body<T: serde::de::DeserializeOwned>(mut response: Resp
     while let Some(chunk) = response.body_mut().next().await {
         body.extend(&chunk?);
     }
-
-    serde_json::from_slice(&body).map_err(Error::DeserializeError)
+    Ok(body)
 }
 
 pub async fn parse_rest_response(
@@ -0,0 +1,45 @@
+#!/usr/bin/env bash
+# Generates the certificates used by the certificate pinning tests. The private keys are thrown
+# away. Requires OpenSSL 3.4 or later.
+#
+#   root.pem                      Test Root CA
+#   intermediate.pem              Test Intermediate CA, signed by the root
+#   other-intermediate.pem        Test Other Intermediate CA, signed by the root
+#   api.pem                       api.mullvad.net, signed by the intermediate
+#   api-other-intermediate.pem    api.mullvad.net, signed by the other intermediate
+#   other.pem                     example.com, signed by the root
+
+set -eu
+cd "$(dirname "$0")"
+
+KEYS=$(mktemp -d)
+trap 'rm -rf "$KEYS"' EXIT
+VALIDITY=(-not_before 20200101000000Z -not_after 21200101000000Z)
+
+function key {
+    openssl genpkey -algorithm EC -pkeyopt ec_paramgen_curve:P-256 -out "$KEYS/$1.key"
+}
+
+# Usage: cert <name> <subject> <issuer> <extensions>
+function cert {
+    key "$1"
+    printf "%b" "$4" > "$KEYS/$1.ext"
+    openssl req -new -key "$KEYS/$1.key" -subj "/CN=$2" -out "$KEYS/$1.csr"
+    openssl x509 -req -in "$KEYS/$1.csr" -CA "$3.pem" -CAkey "$KEYS/$3.key" \
+        -set_serial "0x$(openssl rand -hex 8)" "${VALIDITY[@]}" -sha256 \
+        -extfile "$KEYS/$1.ext" -out "$1.pem"
+}
+
+CA_EXT="basicConstraints=critical,CA:TRUE\nkeyUsage=critical,keyCertSign,cRLSign\n"
+LEAF_EXT="basicConstraints=critical,CA:FALSE\nextendedKeyUsage=serverAuth\nsubjectAltName="
+
+key root
+openssl req -x509 -new -key "$KEYS/root.key" -subj "/CN=Test Root CA" "${VALIDITY[@]}" \
+    -sha256 -addext "basicConstraints=critical,CA:TRUE" \
+    -addext "keyUsage=critical,keyCertSign,cRLSign" -out root.pem
+
+cert intermediate "Test Intermediate CA" root "$CA_EXT"
+cert other-intermediate "Test Other Intermediate CA" root "$CA_EXT"
+cert api api.mullvad.net intermediate "${LEAF_EXT}DNS:api.mullvad.net\n"
+cert api-other-intermediate api.mullvad.net other-intermediate "${LEAF_EXT}DNS:api.mullvad.net\n"
+cert other example.com root "${LEAF_EXT}DNS:example.com\n"
@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, net::SocketAddr};
+use talpid_types::net::openvpn::ProxyAuth;
+
+/// A way of reaching the API. The daemon tries the configured methods in order, and moves on to
+/// the next one when requests keep failing.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessMethod {
+    /// Connect directly to the API.
+    Direct,
+    /// Connect through a Shadowsocks bridge picked from the relay list.
+    Bridges,
+    /// Connect through a SOCKS5 proxy.
+    Socks5(CustomProxy),
+    /// Connect through an HTTP proxy that supports the `CONNECT` method.
+    Http(CustomProxy),
+}
+
+impl AccessMethod {
+    /// The methods that are used if none are configured.
+    pub fn defaults() -> Vec<AccessMethod> {
+        vec![AccessMethod::Direct, AccessMethod::Bridges]
+    }
+}
+
+impl fmt::Display for AccessMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessMethod::Direct => write!(f, "direct"),
+            AccessMethod::Bridges => write!(f, "Shadowsocks bridges"),
+            AccessMethod::Socks5(proxy) => write!(f, "SOCKS5 proxy at {}", proxy),
+            AccessMethod::Http(proxy) => write!(f, "HTTP proxy at {}", proxy),
+        }
+    }
+}
+
+/// A proxy configured by the user.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub struct CustomProxy {
+    pub address: SocketAddr,
+    pub auth: Option<ProxyAuth>,
+}
+
+impl fmt::Display for CustomProxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.auth {
+            Some(auth) => write!(f, "{} as {}", self.address, auth.username),
+            None => write!(f, "{}", self.address),
+        }
+    }
+}
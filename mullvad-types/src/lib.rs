@@ -1,11 +1,13 @@
 #![deny(rust_2018_idioms)]
 
 pub mod account;
+pub mod api_access;
 pub mod auth_failed;
 pub mod endpoint;
 pub mod location;
 pub mod relay_constraints;
 pub mod relay_list;
+pub mod schedule;
 pub mod settings;
 pub mod states;
 pub mod version;
@@ -6,6 +6,7 @@ use crate::{
 use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::{BTreeMap, HashSet},
     fmt,
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
 };
@@ -30,6 +31,108 @@ impl RelayList {
             countries: Vec::new(),
         }
     }
+
+    /// Adds the countries, cities and relays of `other` to this list. Relays in `other` replace
+    /// relays with the same hostname.
+    pub fn merge(&mut self, other: RelayList) {
+        let hostnames: HashSet<String> =
+            other.relays().map(|relay| relay.hostname.clone()).collect();
+        for city in self
+            .countries
+            .iter_mut()
+            .flat_map(|country| country.cities.iter_mut())
+        {
+            city.relays
+                .retain(|relay| !hostnames.contains(&relay.hostname));
+        }
+
+        for other_country in other.countries {
+            let country = match self
+                .countries
+                .iter_mut()
+                .find(|country| country.code == other_country.code)
+            {
+                Some(country) => country,
+                None => {
+                    self.countries.push(other_country);
+                    continue;
+                }
+            };
+            for other_city in other_country.cities {
+                match country
+                    .cities
+                    .iter_mut()
+                    .find(|city| city.code == other_city.code)
+                {
+                    Some(city) => city.relays.extend(other_city.relays),
+                    None => country.cities.push(other_city),
+                }
+            }
+        }
+    }
+
+    /// Returns an iterator over all relays in the list.
+    pub fn relays(&self) -> impl Iterator<Item = &Relay> {
+        self.countries
+            .iter()
+            .flat_map(|country| country.cities.iter())
+            .flat_map(|city| city.relays.iter())
+    }
+}
+
+/// The relays that changed between two versions of a [`RelayList`], identified by hostname.
+#[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize, Serialize)]
+pub struct RelayListDiff {
+    /// Relays that are only in the new list.
+    pub added: Vec<String>,
+    /// Relays that are only in the old list.
+    pub removed: Vec<String>,
+    /// Relays that were active in the old list but are inactive in the new one.
+    pub deactivated: Vec<String>,
+}
+
+impl RelayListDiff {
+    pub fn new(old: &RelayList, new: &RelayList) -> Self {
+        let old_relays: BTreeMap<&str, &Relay> = old
+            .relays()
+            .map(|relay| (relay.hostname.as_str(), relay))
+            .collect();
+        let new_relays: BTreeMap<&str, &Relay> = new
+            .relays()
+            .map(|relay| (relay.hostname.as_str(), relay))
+            .collect();
+
+        let mut diff = Self::default();
+        for (hostname, new_relay) in &new_relays {
+            match old_relays.get(hostname) {
+                Some(old_relay) => {
+                    if old_relay.active && !new_relay.active {
+                        diff.deactivated.push(hostname.to_string());
+                    }
+                }
+                None => diff.added.push(hostname.to_string()),
+            }
+        }
+        diff.removed = old_relays
+            .keys()
+            .filter(|hostname| !new_relays.contains_key(*hostname))
+            .map(|hostname| hostname.to_string())
+            .collect();
+        diff
+    }
+
+    /// Returns true if no relays were added, removed or deactivated.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.deactivated.is_empty()
+    }
+
+    /// Returns true if the relay with the given hostname was removed or deactivated.
+    pub fn is_unavailable(&self, hostname: &str) -> bool {
+        self.removed
+            .iter()
+            .chain(self.deactivated.iter())
+            .any(|unavailable| unavailable == hostname)
+    }
 }
 
 /// A list of [`RelayListCity`]s within a country. Used by [`RelayList`].
@@ -193,3 +296,128 @@ impl ShadowsocksEndpointData {
         })
     }
 }
+
+/// Helpers for building relay lists in tests, also used by the tests of other crates.
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support {
+    use super::*;
+
+    /// Returns an active relay without any tunnel endpoints.
+    pub fn relay(hostname: &str) -> Relay {
+        Relay {
+            hostname: hostname.to_string(),
+            ipv4_addr_in: Ipv4Addr::new(10, 0, 0, 1),
+            ipv6_addr_in: None,
+            include_in_country: true,
+            active: true,
+            owned: true,
+            provider: String::new(),
+            weight: 1,
+            tunnels: RelayTunnels::default(),
+            bridges: RelayBridges::default(),
+            location: None,
+        }
+    }
+
+    /// Returns a relay list with all relays in Gothenburg, Sweden.
+    pub fn relay_list(relays: Vec<Relay>) -> RelayList {
+        RelayList {
+            countries: vec![RelayListCountry {
+                name: "Sweden".to_string(),
+                code: "se".to_string(),
+                cities: vec![city("Gothenburg", "got", relays)],
+            }],
+        }
+    }
+
+    pub fn city(name: &str, code: &str, relays: Vec<Relay>) -> RelayListCity {
+        RelayListCity {
+            name: name.to_string(),
+            code: code.to_string(),
+            latitude: 57.7,
+            longitude: 11.97,
+            relays,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        test_support::{city, relay_list},
+        *,
+    };
+
+    fn relay(hostname: &str, active: bool) -> Relay {
+        Relay {
+            active,
+            ..test_support::relay(hostname)
+        }
+    }
+
+    fn hostnames(relay_list: &RelayList) -> Vec<&str> {
+        relay_list
+            .relays()
+            .map(|relay| relay.hostname.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_relay_list_merge() {
+        let mut merged = relay_list(vec![
+            relay("se1-wireguard", true),
+            relay("se2-wireguard", true),
+        ]);
+        let mut replacement = relay("se2-wireguard", true);
+        replacement.provider = "lab".to_string();
+        let mut other = relay_list(vec![replacement, relay("lab1", true)]);
+        other.countries[0]
+            .cities
+            .push(city("Malmö", "mma", vec![relay("lab2", true)]));
+        other.countries.push(RelayListCountry {
+            name: "Norway".to_string(),
+            code: "no".to_string(),
+            cities: vec![city("Oslo", "osl", vec![relay("lab3", true)])],
+        });
+
+        merged.merge(other);
+
+        assert_eq!(merged.countries.len(), 2);
+        assert_eq!(merged.countries[0].cities.len(), 2);
+        assert_eq!(
+            hostnames(&merged),
+            vec!["se1-wireguard", "se2-wireguard", "lab1", "lab2", "lab3"]
+        );
+        let replaced = merged
+            .relays()
+            .find(|relay| relay.hostname == "se2-wireguard")
+            .unwrap();
+        assert_eq!(replaced.provider, "lab");
+    }
+
+    #[test]
+    fn test_relay_list_diff() {
+        let old = relay_list(vec![
+            relay("se1-wireguard", true),
+            relay("se2-wireguard", true),
+            relay("se3-wireguard", true),
+            relay("se4-wireguard", false),
+        ]);
+        let new = relay_list(vec![
+            relay("se1-wireguard", true),
+            relay("se3-wireguard", false),
+            relay("se4-wireguard", true),
+            relay("se5-wireguard", true),
+        ]);
+
+        let diff = RelayListDiff::new(&old, &new);
+        assert_eq!(diff.added, vec!["se5-wireguard".to_string()]);
+        assert_eq!(diff.removed, vec!["se2-wireguard".to_string()]);
+        assert_eq!(diff.deactivated, vec!["se3-wireguard".to_string()]);
+        assert!(diff.is_unavailable("se2-wireguard"));
+        assert!(diff.is_unavailable("se3-wireguard"));
+        assert!(!diff.is_unavailable("se1-wireguard"));
+
+        assert!(RelayListDiff::new(&new, &new).is_empty());
+    }
+}
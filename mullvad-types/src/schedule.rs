@@ -0,0 +1,71 @@
+use crate::relay_constraints::LocationConstraint;
+use chrono::{NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A rule that makes the daemon perform an action at a given local time of day.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+pub struct ScheduleRule {
+    /// Identifies the rule so that it can be removed. Assigned by the daemon.
+    pub id: u32,
+    /// The days of the week on which the rule applies. An empty list means every day.
+    pub weekdays: Vec<Weekday>,
+    /// The local time of day at which the action is performed.
+    pub time: NaiveTime,
+    pub action: ScheduleAction,
+}
+
+impl ScheduleRule {
+    /// Returns whether the rule applies on the given day of the week.
+    pub fn applies_on(&self, weekday: Weekday) -> bool {
+        self.weekdays.is_empty() || self.weekdays.contains(&weekday)
+    }
+}
+
+impl fmt::Display for ScheduleRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} at {}",
+            self.id,
+            self.action,
+            self.time.format("%H:%M")
+        )?;
+        if self.weekdays.is_empty() {
+            write!(f, " every day")
+        } else {
+            let weekdays: Vec<String> = self.weekdays.iter().map(|day| day.to_string()).collect();
+            write!(f, " on {}", weekdays.join(", "))
+        }
+    }
+}
+
+/// What a [`ScheduleRule`] does when it is triggered.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleAction {
+    /// Secure the device and connect the tunnel.
+    Connect,
+    /// Disconnect the tunnel.
+    Disconnect,
+    /// Change the location constraint of the relay settings.
+    SetLocation(LocationConstraint),
+}
+
+impl fmt::Display for ScheduleAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScheduleAction::Connect => write!(f, "connect"),
+            ScheduleAction::Disconnect => write!(f, "disconnect"),
+            ScheduleAction::SetLocation(LocationConstraint::Country(country)) => {
+                write!(f, "set location to {}", country)
+            }
+            ScheduleAction::SetLocation(LocationConstraint::City(country, city)) => {
+                write!(f, "set location to {} {}", country, city)
+            }
+            ScheduleAction::SetLocation(LocationConstraint::Hostname(country, city, hostname)) => {
+                write!(f, "set location to {} {} {}", country, city, hostname)
+            }
+        }
+    }
+}
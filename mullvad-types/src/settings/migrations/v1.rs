@@ -69,6 +69,11 @@ impl super::SettingsMigration for Migration {
                 auto_connect: old.auto_connect,
                 tunnel_options: old.tunnel_options,
                 show_beta_releases: false,
+                tunnel_proxy: Default::default(),
+                schedule: Vec::new(),
+                api_access_methods: crate::api_access::AccessMethod::defaults(),
+                account_expiry_notifications: crate::settings::DEFAULT_ACCOUNT_EXPIRY_NOTIFICATIONS
+                    .to_vec(),
                 settings_version: super::SettingsVersion::V2,
             }),
             VersionedSettings::V2(new) => VersionedSettings::V2(new),
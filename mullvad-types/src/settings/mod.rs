@@ -1,16 +1,24 @@
-use crate::relay_constraints::{
-    BridgeConstraints, BridgeSettings, BridgeState, Constraint, LocationConstraint,
-    RelayConstraints, RelaySettings, RelaySettingsUpdate,
+use crate::{
+    api_access::AccessMethod,
+    relay_constraints::{
+        BridgeConstraints, BridgeSettings, BridgeState, Constraint, LocationConstraint,
+        RelayConstraints, RelaySettings, RelaySettingsUpdate,
+    },
+    schedule::ScheduleRule,
 };
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use log::{debug, info};
 use serde::{Deserialize, Serialize};
 use serde_json;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use talpid_types::net::{openvpn, wireguard, GenericTunnelOptions};
 
 mod migrations;
 
+/// Hours before the account expires at which clients are notified by default.
+pub const DEFAULT_ACCOUNT_EXPIRY_NOTIFICATIONS: [u32; 2] = [72, 24];
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(err_derive::Error, Debug)]
@@ -50,6 +58,19 @@ pub struct Settings {
     /// Whether to notify users of beta updates.
     #[serde(deserialize_with = "deserialize_show_beta_releases")]
     pub show_beta_releases: bool,
+    /// Settings for the local SOCKS5 proxy that forwards traffic through the tunnel.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub tunnel_proxy: TunnelProxySettings,
+    /// Rules for connecting, disconnecting and changing location at given times.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub schedule: Vec<ScheduleRule>,
+    /// The ways of reaching the API, in the order they are tried.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub api_access_methods: Vec<AccessMethod>,
+    /// Hours before the account expires at which clients are notified, in descending order.
+    /// Clients are always notified once the account has expired.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub account_expiry_notifications: Vec<u32>,
     /// Specifies settings schema version
     #[cfg_attr(target_os = "android", jnix(skip))]
     settings_version: migrations::SettingsVersion,
@@ -70,6 +91,10 @@ impl Default for Settings {
             auto_connect: false,
             tunnel_options: TunnelOptions::default(),
             show_beta_releases: false,
+            tunnel_proxy: TunnelProxySettings::default(),
+            schedule: Vec::new(),
+            api_access_methods: AccessMethod::defaults(),
+            account_expiry_notifications: DEFAULT_ACCOUNT_EXPIRY_NOTIFICATIONS.to_vec(),
             settings_version: migrations::SettingsVersion::V2,
         }
     }
@@ -164,6 +189,10 @@ pub struct TunnelOptions {
     /// Contains generic tunnel options that may apply to more than a single tunnel type.
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub generic: GenericTunnelOptions,
+    /// Interval, in minutes, after which the daemon reconnects to a different relay matching the
+    /// same constraints. Relays are not rotated if this is `None`.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub relay_rotation_interval: Option<u32>,
 }
 
 impl Default for TunnelOptions {
@@ -173,11 +202,42 @@ impl Default for TunnelOptions {
             wireguard: wireguard::TunnelOptions {
                 mtu: None,
                 automatic_rotation: None,
+                quantum_resistant: false,
+                connectivity_check: Default::default(),
             },
             generic: GenericTunnelOptions {
                 // Enable IPv6 be default on Android
                 enable_ipv6: cfg!(target_os = "android"),
             },
+            relay_rotation_interval: None,
+        }
+    }
+}
+
+/// Settings for the SOCKS5 proxy hosted by the daemon. The proxy only accepts connections while
+/// the tunnel is up, and connections made through it are routed through the tunnel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TunnelProxySettings {
+    /// Whether the proxy should be running while the tunnel is up.
+    pub enabled: bool,
+    /// The local address that the proxy listens on. Must be a loopback address.
+    pub bind_address: SocketAddr,
+}
+
+impl TunnelProxySettings {
+    /// Returns whether the settings are acceptable, i.e. if the proxy would only be reachable
+    /// from the local machine.
+    pub fn is_valid(&self) -> bool {
+        self.bind_address.ip().is_loopback() && self.bind_address.port() != 0
+    }
+}
+
+impl Default for TunnelProxySettings {
+    fn default() -> Self {
+        TunnelProxySettings {
+            enabled: false,
+            bind_address: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 1080),
         }
     }
 }
@@ -242,6 +302,22 @@ mod test {
               "show_beta_releases": null
         }"#;
 
-        let _ = Settings::load_from_bytes(old_settings).unwrap();
+        let settings = Settings::load_from_bytes(old_settings).unwrap();
+        assert_eq!(settings.tunnel_proxy, TunnelProxySettings::default());
+    }
+
+    #[test]
+    fn test_tunnel_proxy_must_be_local() {
+        let mut settings = TunnelProxySettings::default();
+        assert!(settings.is_valid());
+
+        settings.bind_address = "0.0.0.0:1080".parse().unwrap();
+        assert!(!settings.is_valid());
+
+        settings.bind_address = "[::1]:0".parse().unwrap();
+        assert!(!settings.is_valid());
+
+        settings.bind_address = "[::1]:9050".parse().unwrap();
+        assert!(settings.is_valid());
     }
 }
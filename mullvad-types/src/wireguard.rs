@@ -43,6 +43,16 @@ pub struct AssociatedAddresses {
     pub ipv6_address: ipnetwork::Ipv6Network,
 }
 
+/// A WireGuard key that is registered on an account, possibly by another device.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RegisteredKey {
+    pub pubkey: wireguard::PublicKey,
+    pub ipv4_address: ipnetwork::Ipv4Network,
+    pub ipv6_address: ipnetwork::Ipv6Network,
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
 /// Event that is emitted when the daemon has finished generating a key.
 #[serde(rename_all = "snake_case")]
 #[derive(Clone, Debug, Deserialize, Serialize)]
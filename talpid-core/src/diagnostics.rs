@@ -0,0 +1,390 @@
+use crate::{firewall, split_tunnel, tunnel::wireguard::wireguard_kernel};
+use futures::{StreamExt, TryStreamExt};
+use netlink_packet_route::{
+    constants::*,
+    link::{nlas::Nla as LinkNla, LinkMessage},
+    route::{nlas::Nla as RouteNla, RouteMessage},
+    rule::{nlas::Nla as RuleNla, RuleHeader, RuleMessage},
+    NetlinkMessage, NetlinkPayload, RtnlMessage,
+};
+use rtnetlink::{Handle, IpVersion};
+use std::{collections::BTreeMap, fmt::Write, fs, io, net::IpAddr, path::Path};
+use talpid_types::cgroup::{find_split_tunnel_hierarchy, SplitTunnelHierarchy};
+
+const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+
+/// Errors that can happen while collecting a diagnostics section.
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Failed to open a netlink connection.
+    #[error(display = "Failed to open a netlink connection")]
+    NetlinkConnectError(#[error(source)] io::Error),
+
+    /// A netlink request failed.
+    #[error(display = "Netlink request failed")]
+    NetlinkError(#[error(source)] rtnetlink::Error),
+
+    /// Failed to list the netfilter tables.
+    #[error(display = "Failed to list the firewall tables")]
+    FirewallError(#[error(source)] firewall::Error),
+
+    /// Failed to read a file.
+    #[error(display = "Failed to read {}", _0)]
+    ReadFileError(String, #[error(source)] io::Error),
+
+    /// Failed to look up the cgroup hierarchy used for split tunneling.
+    #[error(display = "Failed to find the cgroup hierarchy")]
+    FindCgroupError(#[error(source)] io::Error),
+
+    /// Failed to query the WireGuard device.
+    #[error(display = "Failed to get the WireGuard device")]
+    WireguardError(#[error(source)] wireguard_kernel::Error),
+
+    /// A blocking task did not run to completion.
+    #[error(display = "The collecting task was aborted")]
+    TaskAborted,
+}
+
+/// A named part of the system diagnostics. Sections are collected independently, so a failure to
+/// collect one of them does not affect the others.
+#[derive(Debug)]
+pub struct Section {
+    /// Name of the section.
+    pub name: &'static str,
+    /// The collected information, or the reason it could not be collected.
+    pub content: Result<String, Error>,
+}
+
+/// Collects the routing, firewall, DNS, split tunneling and WireGuard state of the system.
+/// `dns_manager` is the name of the DNS monitor in use, if any, as reported by the tunnel state
+/// machine.
+pub async fn collect(dns_manager: Option<String>) -> Vec<Section> {
+    vec![
+        Section {
+            name: "routes",
+            content: routes().await,
+        },
+        Section {
+            name: "rules",
+            content: rules().await,
+        },
+        Section {
+            name: "firewall",
+            content: firewall_tables().await,
+        },
+        Section {
+            name: "dns",
+            content: dns(dns_manager),
+        },
+        Section {
+            name: "cgroup",
+            content: cgroup(),
+        },
+        Section {
+            name: "wireguard",
+            content: wireguard_device().await,
+        },
+    ]
+}
+
+fn connect() -> Result<Handle, Error> {
+    let (connection, handle, _) =
+        rtnetlink::new_connection().map_err(Error::NetlinkConnectError)?;
+    tokio::spawn(connection);
+    Ok(handle)
+}
+
+/// Equivalent of `ip route show table all` for both IP versions.
+async fn routes() -> Result<String, Error> {
+    let handle = connect()?;
+    let links = link_names(&handle).await?;
+
+    let mut output = String::new();
+    for ip_version in vec![IpVersion::V4, IpVersion::V6] {
+        let mut request = handle.route().get(ip_version).execute();
+        while let Some(route) = request.try_next().await.map_err(Error::NetlinkError)? {
+            output.push_str(&format_route(route, &links));
+            output.push('\n');
+        }
+    }
+    Ok(output)
+}
+
+/// Equivalent of `ip rule show` for both IP versions.
+async fn rules() -> Result<String, Error> {
+    let mut handle = connect()?;
+
+    let mut output = String::new();
+    for family in &[libc::AF_INET, libc::AF_INET6] {
+        let mut request = NetlinkMessage::from(RtnlMessage::GetRule(RuleMessage {
+            header: RuleHeader {
+                family: *family as u8,
+                ..RuleHeader::default()
+            },
+            nlas: vec![],
+        }));
+        request.header.flags = NLM_F_REQUEST | NLM_F_DUMP;
+
+        let mut response = handle.request(request).map_err(Error::NetlinkError)?;
+        while let Some(message) = response.next().await {
+            match message.payload {
+                NetlinkPayload::InnerMessage(RtnlMessage::NewRule(rule)) => {
+                    output.push_str(&format_rule(rule));
+                    output.push('\n');
+                }
+                NetlinkPayload::Error(error) => {
+                    return Err(Error::NetlinkError(rtnetlink::Error::NetlinkError(error)));
+                }
+                _ => (),
+            }
+        }
+    }
+    Ok(output)
+}
+
+async fn link_names(handle: &Handle) -> Result<BTreeMap<u32, String>, Error> {
+    let mut links = BTreeMap::new();
+    let mut request = handle.link().get().execute();
+    while let Some(link) = request.try_next().await.map_err(Error::NetlinkError)? {
+        if let Some((index, name)) = link_name(link) {
+            links.insert(index, name);
+        }
+    }
+    Ok(links)
+}
+
+fn link_name(link: LinkMessage) -> Option<(u32, String)> {
+    let index = link.header.index;
+    link.nlas.into_iter().find_map(|nla| match nla {
+        LinkNla::IfName(name) => Some((index, name)),
+        _ => None,
+    })
+}
+
+fn format_route(route: RouteMessage, links: &BTreeMap<u32, String>) -> String {
+    let mut destination = None;
+    let mut table = u32::from(route.header.table);
+    let mut attributes = String::new();
+
+    for nla in route.nlas {
+        match nla {
+            RouteNla::Destination(bytes) => destination = parse_ip(&bytes),
+            RouteNla::Table(id) => table = id,
+            RouteNla::Gateway(bytes) => {
+                if let Some(gateway) = parse_ip(&bytes) {
+                    let _ = write!(attributes, " via {}", gateway);
+                }
+            }
+            RouteNla::Oif(index) => match links.get(&index) {
+                Some(name) => {
+                    let _ = write!(attributes, " dev {}", name);
+                }
+                None => {
+                    let _ = write!(attributes, " dev #{}", index);
+                }
+            },
+            RouteNla::Priority(metric) => {
+                let _ = write!(attributes, " metric {}", metric);
+            }
+            _ => (),
+        }
+    }
+
+    let destination = match destination {
+        Some(address) => format!("{}/{}", address, route.header.destination_prefix_length),
+        None => "default".to_owned(),
+    };
+    format!("{}{} table {}", destination, attributes, table_name(table))
+}
+
+fn format_rule(rule: RuleMessage) -> String {
+    let mut priority = 0;
+    let mut source = None;
+    let mut destination = None;
+    let mut table = u32::from(rule.header.table);
+    let mut attributes = String::new();
+
+    for nla in rule.nlas {
+        match nla {
+            RuleNla::Priority(value) => priority = value,
+            RuleNla::Source(bytes) => source = parse_ip(&bytes),
+            RuleNla::Destination(bytes) => destination = parse_ip(&bytes),
+            RuleNla::Table(id) => table = id,
+            RuleNla::FwMark(mark) => {
+                let _ = write!(attributes, " fwmark {:#x}", mark);
+            }
+            RuleNla::FwMask(mask) => {
+                let _ = write!(attributes, "/{:#x}", mask);
+            }
+            RuleNla::Iifname(name) => {
+                let _ = write!(attributes, " iif {}", name);
+            }
+            RuleNla::OifName(name) => {
+                let _ = write!(attributes, " oif {}", name);
+            }
+            other => {
+                let _ = write!(attributes, " {:?}", other);
+            }
+        }
+    }
+
+    let source = match source {
+        Some(address) => format!("{}/{}", address, rule.header.src_len),
+        None => "all".to_owned(),
+    };
+    let mut output = format!("{}:\tfrom {}", priority, source);
+    if let Some(address) = destination {
+        let _ = write!(output, " to {}/{}", address, rule.header.dst_len);
+    }
+    output.push_str(&attributes);
+    let _ = write!(output, " lookup {}", table_name(table));
+    if rule.header.action != FR_ACT_TO_TBL {
+        let _ = write!(output, " action {}", rule.header.action);
+    }
+    output
+}
+
+fn table_name(id: u32) -> String {
+    match id {
+        id if id == u32::from(RT_TABLE_LOCAL) => "local".to_owned(),
+        id if id == u32::from(RT_TABLE_MAIN) => "main".to_owned(),
+        id if id == u32::from(RT_TABLE_DEFAULT) => "default".to_owned(),
+        id => id.to_string(),
+    }
+}
+
+fn parse_ip(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => {
+            let mut octets = [0u8; 4];
+            octets.copy_from_slice(bytes);
+            Some(IpAddr::from(octets))
+        }
+        16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(bytes);
+            Some(IpAddr::from(octets))
+        }
+        _ => None,
+    }
+}
+
+async fn firewall_tables() -> Result<String, Error> {
+    tokio::task::spawn_blocking(firewall::list_tables)
+        .await
+        .map_err(|_| Error::TaskAborted)?
+        .map_err(Error::FirewallError)
+}
+
+/// Describes the DNS monitor in use and the contents of `/etc/resolv.conf`.
+fn dns(dns_manager: Option<String>) -> Result<String, Error> {
+    let mut output = format!(
+        "DNS manager: {}\n",
+        dns_manager.as_deref().unwrap_or("none")
+    );
+
+    if let Ok(target) = fs::read_link(RESOLV_CONF_PATH) {
+        let _ = writeln!(output, "{} -> {}", RESOLV_CONF_PATH, target.display());
+    }
+    output.push('\n');
+    output.push_str(&read_file(Path::new(RESOLV_CONF_PATH))?);
+    Ok(output)
+}
+
+/// Describes the cgroup used to exclude processes from the tunnel, and the processes in it.
+fn cgroup() -> Result<String, Error> {
+    let hierarchy = match find_split_tunnel_hierarchy().map_err(Error::FindCgroupError)? {
+        Some(hierarchy) => hierarchy,
+        None => return Ok("No cgroup hierarchy for split tunneling is mounted\n".to_owned()),
+    };
+
+    let mut output = match &hierarchy {
+        SplitTunnelHierarchy::NetCls(path) => format!("net_cls hierarchy: {}\n", path.display()),
+        SplitTunnelHierarchy::Unified(path) => format!("unified hierarchy: {}\n", path.display()),
+    };
+
+    let exclusions_path = hierarchy.exclusions_path();
+    if !exclusions_path.exists() {
+        let _ = writeln!(output, "{} does not exist", exclusions_path.display());
+        return Ok(output);
+    }
+
+    if let SplitTunnelHierarchy::NetCls(_) = hierarchy {
+        let classid = read_file(&exclusions_path.join("net_cls.classid"))?;
+        let _ = writeln!(
+            output,
+            "net_cls.classid: {} (expected {})",
+            classid.trim(),
+            split_tunnel::NET_CLS_CLASSID
+        );
+    }
+
+    let pids = read_file(&exclusions_path.join("cgroup.procs"))?;
+    let pids: Vec<&str> = pids.lines().collect();
+    let _ = writeln!(output, "cgroup.procs: {}", pids.join(" "));
+    Ok(output)
+}
+
+async fn wireguard_device() -> Result<String, Error> {
+    match wireguard_kernel::device_status()
+        .await
+        .map_err(Error::WireguardError)?
+    {
+        Some(device) => Ok(device),
+        None => Ok("No kernel WireGuard device\n".to_owned()),
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, Error> {
+    fs::read_to_string(path)
+        .map_err(|error| Error::ReadFileError(path.display().to_string(), error))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_format_route() {
+        let mut route = RouteMessage::default();
+        route.header.table = RT_TABLE_MAIN;
+        route.header.destination_prefix_length = 24;
+        route.nlas = vec![
+            RouteNla::Destination(vec![10, 64, 0, 0]),
+            RouteNla::Gateway(vec![10, 64, 0, 1]),
+            RouteNla::Oif(3),
+            RouteNla::Priority(100),
+        ];
+        let mut links = BTreeMap::new();
+        links.insert(3, "wg-mullvad".to_owned());
+
+        assert_eq!(
+            format_route(route, &links),
+            "10.64.0.0/24 via 10.64.0.1 dev wg-mullvad metric 100 table main"
+        );
+    }
+
+    #[test]
+    fn test_format_rule() {
+        let rule = RuleMessage {
+            header: RuleHeader {
+                family: libc::AF_INET as u8,
+                action: FR_ACT_TO_TBL,
+                ..RuleHeader::default()
+            },
+            nlas: vec![
+                RuleNla::Priority(32765),
+                RuleNla::Source(Ipv4Addr::new(0, 0, 0, 0).octets().to_vec()),
+                RuleNla::FwMark(0x6d6f6c65),
+                RuleNla::Table(1),
+            ],
+        };
+
+        assert_eq!(
+            format_rule(rule),
+            "32765:\tfrom 0.0.0.0/0 fwmark 0x6d6f6c65 lookup 1"
+        );
+    }
+}
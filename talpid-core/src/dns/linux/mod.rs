@@ -64,6 +64,10 @@ impl super::DnsMonitorT for DnsMonitor {
         }
         Ok(())
     }
+
+    fn manager(&self) -> Option<String> {
+        self.inner.as_ref().map(ToString::to_string)
+    }
 }
 
 pub enum DnsMonitorHolder {
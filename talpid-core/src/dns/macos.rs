@@ -200,6 +200,13 @@ impl super::DnsMonitorT for DnsMonitor {
         }
         Ok(())
     }
+
+    fn manager(&self) -> Option<String> {
+        self.state
+            .lock()
+            .as_ref()
+            .map(|_| "SystemConfiguration".to_string())
+    }
 }
 
 impl DnsMonitor {
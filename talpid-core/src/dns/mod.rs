@@ -49,6 +49,11 @@ impl DnsMonitor {
         log::info!("Resetting DNS");
         self.inner.reset()
     }
+
+    /// Returns the name of the mechanism used to manage DNS, if DNS is currently being managed.
+    pub fn manager(&self) -> Option<String> {
+        self.inner.manager()
+    }
 }
 
 trait DnsMonitorT: Sized {
@@ -59,4 +64,6 @@ trait DnsMonitorT: Sized {
     fn set(&mut self, interface: &str, servers: &[IpAddr]) -> Result<(), Self::Error>;
 
     fn reset(&mut self) -> Result<(), Self::Error>;
+
+    fn manager(&self) -> Option<String>;
 }
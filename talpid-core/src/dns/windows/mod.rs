@@ -83,6 +83,10 @@ impl super::DnsMonitorT for DnsMonitor {
     fn reset(&mut self) -> Result<(), Error> {
         Ok(())
     }
+
+    fn manager(&self) -> Option<String> {
+        Some("WinDns".to_string())
+    }
 }
 
 fn ip_to_widestring(ip: &IpAddr) -> WideCString {
@@ -1,4 +1,4 @@
-use super::{FirewallArguments, FirewallPolicy, FirewallT};
+use super::{AllowedListener, FirewallArguments, FirewallPolicy, FirewallT};
 use crate::{split_tunnel, tunnel};
 use ipnetwork::IpNetwork;
 use lazy_static::lazy_static;
@@ -9,6 +9,7 @@ use nftnl::{
     nft_expr, table, Batch, Chain, FinalizedBatch, ProtoFamily, Rule, Table,
 };
 use std::{
+    collections::HashSet,
     env,
     ffi::{CStr, CString},
     io,
@@ -52,6 +53,10 @@ pub enum Error {
         _0
     )]
     LookupIfaceIndexError(String, #[error(source)] crate::linux::IfaceIndexLookupError),
+
+    /// Unable to run `nft` to list the firewall tables.
+    #[error(display = "Failed to run nft")]
+    RunNft(#[error(source)] io::Error),
 }
 
 lazy_static! {
@@ -89,7 +94,9 @@ enum End {
 }
 
 /// The Linux implementation for the firewall and DNS.
-pub struct Firewall(());
+pub struct Firewall {
+    allowed_listener: Option<AllowedListener>,
+}
 
 struct FirewallTables {
     main: Table,
@@ -100,8 +107,10 @@ struct FirewallTables {
 impl FirewallT for Firewall {
     type Error = Error;
 
-    fn new(_args: FirewallArguments) -> Result<Self> {
-        Ok(Firewall(()))
+    fn new(args: FirewallArguments) -> Result<Self> {
+        Ok(Firewall {
+            allowed_listener: args.allowed_listener,
+        })
     }
 
     fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<()> {
@@ -110,7 +119,7 @@ impl FirewallT for Firewall {
             mangle_v4: Table::new(&*MANGLE_TABLE_NAME_V4, ProtoFamily::Ipv4),
             mangle_v6: Table::new(&*MANGLE_TABLE_NAME_V6, ProtoFamily::Ipv6),
         };
-        let batch = PolicyBatch::new(&tables).finalize(&policy)?;
+        let batch = PolicyBatch::new(&tables).finalize(&policy, self.allowed_listener.as_ref())?;
         self.send_and_process(&batch)?;
         self.verify_tables(&[&TABLE_NAME, &MANGLE_TABLE_NAME_V4, &MANGLE_TABLE_NAME_V6])
     }
@@ -158,6 +167,21 @@ impl Firewall {
     }
 
     fn verify_tables(&self, expected_tables: &[&CStr]) -> Result<()> {
+        let table_set = Self::get_tables()?;
+
+        for expected_table in expected_tables {
+            if !table_set.contains(*expected_table) {
+                log::error!(
+                    "Expected '{}' netfilter table to be set, but it is not",
+                    expected_table.to_string_lossy()
+                );
+                return Err(Error::NetfilterTableNotSetError);
+            }
+        }
+        Ok(())
+    }
+
+    fn get_tables() -> Result<HashSet<CString>> {
         let socket = mnl::Socket::new(mnl::Bus::Netfilter).map_err(Error::NetlinkOpenError)?;
         let portid = socket.portid();
         let seq = 0;
@@ -167,7 +191,7 @@ impl Firewall {
             .send(&get_tables_msg)
             .map_err(Error::NetlinkSendError)?;
 
-        let mut table_set = std::collections::HashSet::new();
+        let mut table_set = HashSet::new();
         let mut msg_buffer = vec![0; nftnl::nft_nlmsg_maxsize() as usize];
 
         while let Some(message) = Self::socket_recv(&socket, &mut msg_buffer)? {
@@ -181,17 +205,7 @@ impl Firewall {
                 mnl::CbResult::Ok => log::trace!("cb_run OK"),
             }
         }
-
-        for expected_table in expected_tables {
-            if !table_set.contains(*expected_table) {
-                log::error!(
-                    "Expected '{}' netfilter table to be set, but it is not",
-                    expected_table.to_string_lossy()
-                );
-                return Err(Error::NetfilterTableNotSetError);
-            }
-        }
-        Ok(())
+        Ok(table_set)
     }
 
     fn socket_recv<'a>(socket: &mnl::Socket, buf: &'a mut [u8]) -> Result<Option<&'a [u8]>> {
@@ -205,6 +219,45 @@ impl Firewall {
     }
 }
 
+/// Returns the contents of the netfilter tables managed by the firewall, as printed by
+/// `nft list table`. Tables that are not currently set are noted as such.
+pub fn list_tables() -> Result<String> {
+    let existing_tables = Firewall::get_tables()?;
+    let tables: [(&str, &CStr); 3] = [
+        ("inet", &TABLE_NAME),
+        ("ip", &MANGLE_TABLE_NAME_V4),
+        ("ip6", &MANGLE_TABLE_NAME_V6),
+    ];
+
+    let mut output = String::new();
+    for (family, name) in tables.iter() {
+        let is_set = existing_tables.contains(*name);
+        let name = name.to_string_lossy();
+        if !is_set {
+            output.push_str(&format!("# table {} {} is not set\n", family, name));
+            continue;
+        }
+
+        let result = duct::cmd!("nft", "list", "table", *family, &*name)
+            .stdout_capture()
+            .stderr_capture()
+            .unchecked()
+            .run()
+            .map_err(Error::RunNft)?;
+        if result.status.success() {
+            output.push_str(&String::from_utf8_lossy(&result.stdout));
+        } else {
+            output.push_str(&format!(
+                "# failed to list table {} {}: {}\n",
+                family,
+                name,
+                String::from_utf8_lossy(&result.stderr).trim()
+            ));
+        }
+    }
+    Ok(output)
+}
+
 struct PolicyBatch<'a> {
     batch: Batch,
     in_chain: Chain<'a>,
@@ -278,15 +331,40 @@ impl<'a> PolicyBatch<'a> {
 
     /// Finalize the nftnl message batch by adding every firewall rule needed to satisfy the given
     /// policy.
-    pub fn finalize(mut self, policy: &FirewallPolicy) -> Result<FinalizedBatch> {
+    pub fn finalize(
+        mut self,
+        policy: &FirewallPolicy,
+        allowed_listener: Option<&AllowedListener>,
+    ) -> Result<FinalizedBatch> {
         self.add_loopback_rules()?;
         self.add_split_tunneling_rules()?;
         self.add_dhcp_client_rules();
+        if let Some(listener) = allowed_listener {
+            self.add_allowed_listener_rules(listener);
+        }
         self.add_policy_specific_rules(policy)?;
 
         Ok(self.batch.finalize())
     }
 
+    /// Allows the given networks to connect to a local TCP listener.
+    fn add_allowed_listener_rules(&mut self, listener: &AllowedListener) {
+        use TransportProtocol::Tcp;
+        for net in &listener.networks {
+            let mut in_rule = Rule::new(&self.in_chain);
+            check_net(&mut in_rule, End::Src, *net);
+            check_port(&mut in_rule, Tcp, End::Dst, listener.port);
+            add_verdict(&mut in_rule, &Verdict::Accept);
+            self.batch.add(&in_rule, nftnl::MsgType::Add);
+
+            let mut out_rule = Rule::new(&self.out_chain);
+            check_net(&mut out_rule, End::Dst, *net);
+            check_port(&mut out_rule, Tcp, End::Src, listener.port);
+            add_verdict(&mut out_rule, &Verdict::Accept);
+            self.batch.add(&out_rule, nftnl::MsgType::Add);
+        }
+    }
+
     fn add_split_tunneling_rules(&mut self) -> Result<()> {
         let mangle_chains = [&self.mangle_chain_v4, &self.mangle_chain_v6];
         for chain in &mangle_chains {
@@ -297,6 +375,17 @@ impl<'a> PolicyBatch<'a> {
             rule.add_expr(&nft_expr!(ct mark set));
             rule.add_expr(&nft_expr!(meta mark set));
             self.batch.add(&rule, nftnl::MsgType::Add);
+
+            // Processes are excluded using a cgroup v2 on systems without the net_cls controller
+            if let Some(cgroup_id) = split_tunnel::unified_exclusions_cgroup_id() {
+                let mut rule = Rule::new(chain);
+                rule.add_expr(&SocketCgroupV2 { level: 1 });
+                rule.add_expr(&nft_expr!(cmp == &cgroup_id.to_ne_bytes()[..]));
+                rule.add_expr(&nft_expr!(immediate data split_tunnel::MARK));
+                rule.add_expr(&nft_expr!(ct mark set));
+                rule.add_expr(&nft_expr!(meta mark set));
+                self.batch.add(&rule, nftnl::MsgType::Add);
+            }
         }
 
         let mut rule = Rule::new(&self.in_chain);
@@ -458,9 +547,14 @@ impl<'a> PolicyBatch<'a> {
                 pingable_hosts,
                 allow_lan,
                 use_fwmark,
+                psk_exchange_endpoint,
             } => {
                 self.add_allow_icmp_pingable_hosts(&pingable_hosts);
                 self.add_allow_endpoint_rules(peer_endpoint, *use_fwmark);
+                if let Some(psk_exchange_endpoint) = psk_exchange_endpoint {
+                    // Sockets used for the key exchange are not marked, so match on root UID
+                    self.add_allow_endpoint_rules(psk_exchange_endpoint, false);
+                }
 
                 // Important to block DNS after allow relay rule (so the relay can operate
                 // over port 53) but before allow LAN (so DNS does not leak to the LAN)
@@ -485,7 +579,13 @@ impl<'a> PolicyBatch<'a> {
                 }
                 *allow_lan
             }
-            FirewallPolicy::Blocked { allow_lan } => {
+            FirewallPolicy::Blocked {
+                allow_lan,
+                allowed_endpoint,
+            } => {
+                if let Some(allowed_endpoint) = allowed_endpoint {
+                    self.add_allow_endpoint_rules(&allowed_endpoint.endpoint, false);
+                }
                 // Important to drop DNS before allowing LAN (to stop DNS leaking to the LAN)
                 self.add_drop_dns_rule();
                 *allow_lan
@@ -789,3 +889,32 @@ fn add_verdict(rule: &mut Rule<'_>, verdict: &expr::Verdict) {
     }
     rule.add_expr(verdict);
 }
+
+/// Loads the ID of the cgroup v2 ancestor at the given level of the socket that a packet belongs
+/// to into register 1. Equivalent to `socket cgroupv2 level <level>` in nft.
+/// This is not provided by nftnl, and requires Linux 5.13 or later.
+struct SocketCgroupV2 {
+    level: u32,
+}
+
+impl expr::Expression for SocketCgroupV2 {
+    fn to_expr(&self, _rule: &Rule<'_>) -> *mut nftnl::nftnl_sys::nftnl_expr {
+        // Attributes from libnftnl/expr.h and linux/netfilter/nf_tables.h
+        const NFTNL_EXPR_SOCKET_KEY: u16 = 1;
+        const NFTNL_EXPR_SOCKET_DREG: u16 = 2;
+        const NFTNL_EXPR_SOCKET_LEVEL: u16 = 3;
+        const NFT_SOCKET_CGROUPV2: u32 = 3;
+        const NFT_REG_1: u32 = 1;
+
+        unsafe {
+            let expr = nftnl::nftnl_sys::nftnl_expr_alloc(b"socket\0" as *const _ as *const _);
+            if expr.is_null() {
+                panic!("Failed to allocate socket expression");
+            }
+            nftnl::nftnl_sys::nftnl_expr_set_u32(expr, NFTNL_EXPR_SOCKET_KEY, NFT_SOCKET_CGROUPV2);
+            nftnl::nftnl_sys::nftnl_expr_set_u32(expr, NFTNL_EXPR_SOCKET_DREG, NFT_REG_1);
+            nftnl::nftnl_sys::nftnl_expr_set_u32(expr, NFTNL_EXPR_SOCKET_LEVEL, self.level);
+            expr
+        }
+    }
+}
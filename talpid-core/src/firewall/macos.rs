@@ -99,9 +99,13 @@ impl Firewall {
                 peer_endpoint,
                 allow_lan,
                 pingable_hosts,
+                psk_exchange_endpoint,
             } => {
                 let mut rules = vec![self.get_allow_relay_rule(peer_endpoint)?];
                 rules.extend(self.get_allow_pingable_hosts(&pingable_hosts)?);
+                if let Some(psk_exchange_endpoint) = psk_exchange_endpoint {
+                    rules.push(self.get_allow_relay_rule(psk_exchange_endpoint)?);
+                }
                 if allow_lan {
                     // Important to block DNS after allow relay rule (so the relay can operate
                     // over port 53) but before allow LAN (so DNS does not leak to the LAN)
@@ -174,8 +178,14 @@ impl Firewall {
 
                 Ok(rules)
             }
-            FirewallPolicy::Blocked { allow_lan } => {
+            FirewallPolicy::Blocked {
+                allow_lan,
+                allowed_endpoint,
+            } => {
                 let mut rules = Vec::new();
+                if let Some(allowed_endpoint) = allowed_endpoint {
+                    rules.push(self.get_allow_relay_rule(allowed_endpoint.endpoint)?);
+                }
                 if allow_lan {
                     // Important to block DNS before allow LAN (so DNS does not leak to the LAN)
                     rules.append(&mut self.get_block_dns_rules()?);
@@ -30,6 +30,9 @@ mod imp;
 
 pub use self::imp::Error;
 
+#[cfg(target_os = "linux")]
+pub(crate) use self::imp::list_tables;
+
 #[cfg(unix)]
 lazy_static! {
     /// When "allow local network" is enabled the app will allow traffic to and from these networks.
@@ -101,6 +104,10 @@ pub enum FirewallPolicy {
         /// root UID.
         #[cfg(target_os = "linux")]
         use_fwmark: bool,
+        /// In-tunnel endpoint that the daemon may connect to in order to negotiate a
+        /// quantum-resistant pre-shared key with the relay.
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        psk_exchange_endpoint: Option<Endpoint>,
     },
 
     /// Allow traffic only to server and over tunnel interface
@@ -124,6 +131,9 @@ pub enum FirewallPolicy {
     Blocked {
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
+        /// Endpoint that the daemon may use to reach the API, directly or through a proxy.
+        #[cfg(not(target_os = "android"))]
+        allowed_endpoint: Option<AllowedEndpoint>,
     },
 }
 
@@ -166,7 +176,7 @@ impl fmt::Display for FirewallPolicy {
                 tunnel.ipv6_gateway,
                 if *allow_lan { "Allowing" } else { "Blocking" }
             ),
-            FirewallPolicy::Blocked { allow_lan } => write!(
+            FirewallPolicy::Blocked { allow_lan, .. } => write!(
                 f,
                 "Blocked, {} LAN",
                 if *allow_lan { "Allowing" } else { "Blocking" }
@@ -181,12 +191,36 @@ pub struct Firewall {
     inner: imp::Firewall,
 }
 
+/// A local TCP listener that should be reachable from the given networks regardless of the
+/// firewall policy.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AllowedListener {
+    /// The port that the listener is bound to.
+    pub port: u16,
+    /// Networks that are allowed to connect to the listener.
+    pub networks: Vec<IpNetwork>,
+}
+
+/// An endpoint that is reachable in the blocked policy.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AllowedEndpoint {
+    /// The endpoint that may be reached.
+    pub endpoint: Endpoint,
+    /// The only process that may send packets to the endpoint.
+    #[cfg(windows)]
+    pub client: PathBuf,
+}
+
 /// Arguments required when first initializing the firewall.
 pub struct FirewallArguments {
     /// Determines whether the firewall should atomically enter the blocked state during init.
     pub initialize_blocked: bool,
     /// This argument is required for the blocked state to configure the firewall correctly.
     pub allow_lan: bool,
+    /// A listener that is reachable in every policy, including the blocked state.
+    #[cfg(target_os = "linux")]
+    pub allowed_listener: Option<AllowedListener>,
 }
 
 impl Firewall {
@@ -3,7 +3,7 @@ use crate::logging::windows::log_sink;
 use std::{net::IpAddr, path::Path, ptr};
 
 use self::winfw::*;
-use super::{FirewallArguments, FirewallPolicy, FirewallT};
+use super::{AllowedEndpoint, FirewallArguments, FirewallPolicy, FirewallT};
 use crate::winnet;
 use log::{debug, error, trace};
 use std::os::windows::ffi::OsStrExt;
@@ -104,9 +104,12 @@ impl FirewallT for Firewall {
                 let cfg = &WinFwSettings::new(allow_lan);
                 self.set_connected_state(&peer_endpoint, &cfg, &tunnel, &relay_client)
             }
-            FirewallPolicy::Blocked { allow_lan } => {
+            FirewallPolicy::Blocked {
+                allow_lan,
+                allowed_endpoint,
+            } => {
                 let cfg = &WinFwSettings::new(allow_lan);
-                self.set_blocked_state(&cfg)
+                self.set_blocked_state(&cfg, allowed_endpoint.as_ref())
             }
         }
     }
@@ -242,13 +245,33 @@ impl Firewall {
         }
     }
 
-    fn set_blocked_state(&mut self, winfw_settings: &WinFwSettings) -> Result<(), Error> {
+    fn set_blocked_state(
+        &mut self,
+        winfw_settings: &WinFwSettings,
+        allowed_endpoint: Option<&AllowedEndpoint>,
+    ) -> Result<(), Error> {
         trace!("Applying 'blocked' firewall policy");
-        unsafe {
-            WinFw_ApplyPolicyBlocked(winfw_settings)
-                .into_result()
-                .map_err(Error::ApplyingBlockedPolicy)
-        }
+        let status = match allowed_endpoint {
+            Some(allowed_endpoint) => {
+                // ip_str and client have to outlive winfw_endpoint
+                let ip_str = Self::widestring_ip(allowed_endpoint.endpoint.address.ip());
+                let winfw_endpoint = WinFwRelay {
+                    ip: ip_str.as_ptr(),
+                    port: allowed_endpoint.endpoint.address.port(),
+                    protocol: WinFwProt::from(allowed_endpoint.endpoint.protocol),
+                };
+
+                let mut client: Vec<u16> =
+                    allowed_endpoint.client.as_os_str().encode_wide().collect();
+                client.push(0u16);
+
+                unsafe {
+                    WinFw_ApplyPolicyBlocked(winfw_settings, &winfw_endpoint, client.as_ptr())
+                }
+            }
+            None => unsafe { WinFw_ApplyPolicyBlocked(winfw_settings, ptr::null(), ptr::null()) },
+        };
+        status.into_result().map_err(Error::ApplyingBlockedPolicy)
     }
 }
 
@@ -395,7 +418,11 @@ mod winfw {
         ) -> WinFwPolicyStatus;
 
         #[link_name = "WinFw_ApplyPolicyBlocked"]
-        pub fn WinFw_ApplyPolicyBlocked(settings: &WinFwSettings) -> WinFwPolicyStatus;
+        pub fn WinFw_ApplyPolicyBlocked(
+            settings: &WinFwSettings,
+            allowed_endpoint: *const WinFwRelay,
+            allowed_endpoint_client: *const libc::wchar_t,
+        ) -> WinFwPolicyStatus;
 
         #[link_name = "WinFw_Reset"]
         pub fn WinFw_Reset() -> WinFwPolicyStatus;
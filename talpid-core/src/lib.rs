@@ -49,8 +49,8 @@ pub mod tunnel_state_machine;
 pub mod future_retry;
 
 #[cfg(not(target_os = "android"))]
-/// Internal code for managing bundled proxy software.
-mod proxy;
+/// Code for managing bundled proxy software.
+pub mod proxy;
 
 #[cfg(not(target_os = "android"))]
 mod mktemp;
@@ -59,5 +59,9 @@ mod mktemp;
 #[cfg(target_os = "linux")]
 mod linux;
 
+/// Collection of system state for troubleshooting.
+#[cfg(target_os = "linux")]
+pub mod diagnostics;
+
 /// A pair of functions to monitor and establish connectivity with ICMP
 mod ping_monitor;
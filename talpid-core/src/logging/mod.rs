@@ -1,4 +1,7 @@
-use std::{fs, io, path::Path};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
 
 /// Types/implementations for logging through a callback.
 #[cfg(windows)]
@@ -14,16 +17,82 @@ pub struct RotateLogError(#[error(source)] io::Error);
 /// A new log file is created with the given file name, but if a file with that name already exists
 /// it is backed up with the extension changed to `.old.log`.
 pub fn rotate_log(file: &Path) -> Result<(), RotateLogError> {
-    let backup = file.with_extension("old.log");
-    if let Err(error) = fs::rename(file, &backup) {
+    rotate_log_with_retention(file, 1)
+}
+
+/// Create a new log file while keeping up to `retention` backups of previous versions of it.
+///
+/// The most recent backup has the extension `.old.log`, like with `rotate_log`. Older backups are
+/// numbered: `.old.2.log`, `.old.3.log` and so on. Backups beyond `retention` are removed.
+///
+/// This is called by the logger itself when a log file grows too large, so it must never log
+/// anything: that would deadlock. Failing to move or remove a backup is reported on stderr.
+pub fn rotate_log_with_retention(file: &Path, retention: usize) -> Result<(), RotateLogError> {
+    if retention == 0 {
+        remove_log(file);
+    } else {
+        remove_log(&backup_path(file, retention));
+        for index in (1..retention).rev() {
+            rename_log(&backup_path(file, index), &backup_path(file, index + 1));
+        }
+        rename_log(file, &backup_path(file, 1));
+    }
+
+    fs::File::create(file).map(|_| ()).map_err(RotateLogError)
+}
+
+/// Returns the path of the `index`th most recent backup of `file`, starting from 1.
+fn backup_path(file: &Path, index: usize) -> PathBuf {
+    if index == 1 {
+        file.with_extension("old.log")
+    } else {
+        file.with_extension(format!("old.{}.log", index))
+    }
+}
+
+fn rename_log(from: &Path, to: &Path) {
+    if let Err(error) = fs::rename(from, to) {
         if error.kind() != io::ErrorKind::NotFound {
-            log::warn!(
-                "Failed to rotate log file to {}: {}",
-                backup.display(),
+            eprintln!("Failed to rotate log file to {}: {}", to.display(), error);
+        }
+    }
+}
+
+fn remove_log(file: &Path) {
+    if let Err(error) = fs::remove_file(file) {
+        if error.kind() != io::ErrorKind::NotFound {
+            eprintln!(
+                "Failed to remove old log file {}: {}",
+                file.display(),
                 error
             );
         }
     }
+}
 
-    fs::File::create(file).map(|_| ()).map_err(RotateLogError)
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rotate_log_with_retention() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("daemon.log");
+
+        for content in &["1", "2", "3", "4"] {
+            rotate_log_with_retention(&log, 2).unwrap();
+            fs::write(&log, content).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&log).unwrap(), "4");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("daemon.old.log")).unwrap(),
+            "3"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("daemon.old.2.log")).unwrap(),
+            "2"
+        );
+        assert!(!dir.path().join("daemon.old.3.log").exists());
+    }
 }
@@ -7,6 +7,8 @@ mod imp;
 #[path = "win.rs"]
 mod imp;
 
+use std::time::Duration;
+
 pub use imp::Error;
 
 pub trait Pinger: Send {
@@ -14,6 +16,51 @@ pub trait Pinger: Send {
     fn send_icmp(&mut self) -> Result<(), Error>;
     /// Clears all resources used by the pinger.
     fn reset(&mut self) {}
+    /// Returns statistics about the echo replies received since the pinger was last reset, if the
+    /// pinger keeps track of them.
+    fn stats(&mut self) -> Option<PingStats> {
+        None
+    }
+}
+
+/// Statistics about the echo requests sent by a [`Pinger`].
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PingStats {
+    /// Number of echo requests sent
+    pub sent: u32,
+    /// Number of echo replies received
+    pub received: u32,
+    /// Number of echo requests that were not answered in time
+    pub lost: u32,
+    /// Round-trip time of the most recent reply
+    pub last_rtt: Option<Duration>,
+    total_rtt: Duration,
+}
+
+impl PingStats {
+    #[cfg(unix)]
+    fn add_reply(&mut self, rtt: Duration) {
+        self.received += 1;
+        self.last_rtt = Some(rtt);
+        self.total_rtt += rtt;
+    }
+
+    /// Average round-trip time of all replies
+    pub fn average_rtt(&self) -> Option<Duration> {
+        if self.received == 0 {
+            return None;
+        }
+        Some(self.total_rtt / self.received)
+    }
+
+    /// Fraction of the requests that have been answered or timed out that were lost
+    pub fn loss(&self) -> f32 {
+        let completed = self.received + self.lost;
+        if completed == 0 {
+            return 0.0;
+        }
+        self.lost as f32 / completed as f32
+    }
 }
 
 pub fn new_pinger(
@@ -1,109 +1,416 @@
-use std::{io, net::Ipv4Addr};
+use super::PingStats;
+use pnet_packet::{
+    icmp::{
+        self,
+        echo_reply::EchoReplyPacket,
+        echo_request::{EchoRequestPacket, MutableEchoRequestPacket},
+        IcmpCode, IcmpPacket, IcmpTypes,
+    },
+    ipv4::Ipv4Packet,
+    Packet,
+};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+/// Echo requests that haven't been answered within this time are considered lost.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+const ICMP_HEADER_LENGTH: usize = 8;
+const ICMP_PAYLOAD_LENGTH: usize = 56;
+const RECEIVE_BUFFER_LENGTH: usize = 1024;
 
 #[derive(err_derive::Error, Debug)]
+#[error(no_from)]
 pub enum Error {
-    #[error(display = "Failed to run ping command")]
-    PingError(#[error(source)] io::Error),
+    /// Failed to open ICMP socket
+    #[error(display = "Failed to open ICMP socket")]
+    OpenError(#[error(source)] io::Error),
+
+    /// Failed to bind the socket to the tunnel interface
+    #[error(display = "Failed to bind ICMP socket to interface {}", _0)]
+    BindDeviceError(String, #[error(source)] io::Error),
+
+    /// Failed to read from ICMP socket
+    #[error(display = "Failed to read from ICMP socket")]
+    ReadError(#[error(source)] io::Error),
 
-    #[error(display = "Ping timed out")]
-    TimeoutError,
+    /// Failed to write to ICMP socket
+    #[error(display = "Failed to write to ICMP socket")]
+    WriteError(#[error(source)] io::Error),
 }
 
-/// A pinger that sends ICMP requests without waiting for responses
+type Result<T> = std::result::Result<T, Error>;
+
+/// A pinger that sends ICMP echo requests through a socket bound to a specific interface, and
+/// keeps track of the replies.
+///
+/// A raw socket is used if the process is allowed to open one. Otherwise, an unprivileged
+/// datagram ICMP socket is used, in which case the kernel manages the identifier of the requests.
 pub struct Pinger {
+    sock: Socket,
+    raw_socket: bool,
     addr: Ipv4Addr,
-    interface_name: String,
-    processes: Vec<duct::Handle>,
+    id: u16,
+    seq: u16,
+    tracker: ReplyTracker,
 }
 
 impl Pinger {
-    pub fn new(addr: Ipv4Addr, interface_name: String) -> Result<Self, Error> {
+    pub fn new(addr: Ipv4Addr, interface_name: String) -> Result<Self> {
+        let (sock, raw_socket) =
+            match Socket::new(Domain::ipv4(), Type::raw(), Some(Protocol::icmpv4())) {
+                Ok(sock) => (sock, true),
+                Err(_) => (
+                    Socket::new(Domain::ipv4(), Type::dgram(), Some(Protocol::icmpv4()))
+                        .map_err(Error::OpenError)?,
+                    false,
+                ),
+            };
+        sock.set_nonblocking(true).map_err(Error::OpenError)?;
+        bind_to_interface(&sock, &interface_name)
+            .map_err(|error| Error::BindDeviceError(interface_name, error))?;
+
         Ok(Self {
-            processes: vec![],
+            sock,
+            raw_socket,
             addr,
-            interface_name,
+            id: rand::random(),
+            seq: 0,
+            tracker: ReplyTracker::default(),
         })
     }
 
+    /// returns the next ping packet
+    fn next_ping_request(&mut self) -> EchoRequestPacket<'static> {
+        use rand::Rng;
+        const ICMP_PACKET_LENGTH: usize = ICMP_HEADER_LENGTH + ICMP_PAYLOAD_LENGTH;
+        let mut payload = [0u8; ICMP_PAYLOAD_LENGTH];
+        rand::thread_rng().fill(&mut payload[..]);
+        let mut packet = MutableEchoRequestPacket::owned(vec![0u8; ICMP_PACKET_LENGTH])
+            .expect("Failed to construct an empty packet");
+        packet.set_icmp_type(IcmpTypes::EchoRequest);
+        packet.set_icmp_code(IcmpCode::new(0));
+        packet.set_sequence_number(self.next_seq());
+        packet.set_identifier(self.id);
+        packet.set_payload(&payload);
+        packet.set_checksum(icmp::checksum(&IcmpPacket::new(&packet.packet()).unwrap()));
+        packet.consume_to_immutable()
+    }
+
+    fn next_seq(&mut self) -> u16 {
+        let seq = self.seq;
+        self.seq = self.seq.wrapping_add(1);
+        seq
+    }
 
-    fn try_deplete_process_list(&mut self) {
-        self.processes.retain(|child| {
-            match child.try_wait() {
-                // child has terminated, doesn't have to be retained
-                Ok(Some(_)) => false,
-                _ => true,
+    /// Reads all pending echo replies from the socket and updates the statistics.
+    fn receive_replies(&mut self) -> Result<()> {
+        let mut buffer = [0u8; RECEIVE_BUFFER_LENGTH];
+        // The kernel replaces the identifier of requests sent over datagram sockets, and only
+        // delivers matching replies to the socket
+        let identifier = if self.raw_socket { Some(self.id) } else { None };
+        loop {
+            let length = match self.sock.recv(&mut buffer) {
+                Ok(length) => length,
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(error) => return Err(Error::ReadError(error)),
+            };
+            if let Some(seq) = parse_reply(&buffer[..length], self.addr, identifier) {
+                self.tracker.reply_received(seq, Instant::now());
             }
+        }
+        self.tracker.expire_requests(Instant::now());
+        Ok(())
+    }
+}
+
+/// Returns the sequence number of `packet` if it is an echo reply from `source`. If `identifier`
+/// is set, replies with any other identifier are ignored.
+fn parse_reply(packet: &[u8], source: Ipv4Addr, identifier: Option<u16>) -> Option<u16> {
+    // Raw sockets, and datagram sockets on macOS, include the IP header
+    let icmp_packet = if packet.first().map(|byte| byte >> 4) == Some(4) {
+        let ip_packet = Ipv4Packet::new(packet)?;
+        if ip_packet.get_source() != source {
+            return None;
+        }
+        let header_length = usize::from(ip_packet.get_header_length()) * 4;
+        packet.get(header_length..)?
+    } else {
+        packet
+    };
+
+    let reply = EchoReplyPacket::new(icmp_packet)?;
+    if reply.get_icmp_type() != IcmpTypes::EchoReply {
+        return None;
+    }
+    if let Some(identifier) = identifier {
+        if reply.get_identifier() != identifier {
+            return None;
+        }
+    }
+    Some(reply.get_sequence_number())
+}
+
+/// Keeps track of the echo requests that are waiting for a reply, and the statistics of the
+/// requests that have been answered or timed out.
+#[derive(Default)]
+struct ReplyTracker {
+    in_flight: HashMap<u16, Instant>,
+    stats: PingStats,
+}
+
+impl ReplyTracker {
+    fn request_sent(&mut self, seq: u16, now: Instant) {
+        self.in_flight.insert(seq, now);
+        self.stats.sent += 1;
+    }
+
+    /// Records the round-trip time of the request with sequence number `seq`. Replies to unknown
+    /// or already expired requests are ignored.
+    fn reply_received(&mut self, seq: u16, now: Instant) {
+        if let Some(sent_at) = self.in_flight.remove(&seq) {
+            self.stats.add_reply(now.saturating_duration_since(sent_at));
+        }
+    }
+
+    /// Counts requests that have not been answered within `REPLY_TIMEOUT` as lost.
+    fn expire_requests(&mut self, now: Instant) {
+        let stats = &mut self.stats;
+        self.in_flight.retain(|_, sent_at| {
+            let lost = now.saturating_duration_since(*sent_at) >= REPLY_TIMEOUT;
+            if lost {
+                stats.lost += 1;
+            }
+            !lost
         });
     }
+
+    fn reset(&mut self) {
+        self.in_flight.clear();
+        self.stats = PingStats::default();
+    }
 }
 
 impl super::Pinger for Pinger {
     // Send an ICMP packet without waiting for a reply
-    fn send_icmp(&mut self) -> Result<(), Error> {
-        self.try_deplete_process_list();
+    fn send_icmp(&mut self) -> Result<()> {
+        self.receive_replies()?;
 
-        let cmd = ping_cmd(self.addr, 1, &self.interface_name);
-        let handle = cmd.start().map_err(Error::PingError)?;
-        self.processes.push(handle);
+        let request = self.next_ping_request();
+        let destination = SocketAddr::new(IpAddr::from(self.addr), 0);
+        self.sock
+            .send_to(request.packet(), &destination.into())
+            .map_err(Error::WriteError)?;
+        self.tracker
+            .request_sent(request.get_sequence_number(), Instant::now());
         Ok(())
     }
 
     fn reset(&mut self) {
-        let processes = std::mem::replace(&mut self.processes, vec![]);
-        for proc in processes {
-            if proc
-                .try_wait()
-                .map(|maybe_stopped| maybe_stopped.is_none())
-                .unwrap_or(false)
-            {
-                if let Err(err) = proc.kill() {
-                    log::error!("Failed to kill ping process - {}", err);
-                }
-            }
+        self.tracker.reset();
+    }
+
+    fn stats(&mut self) -> Option<PingStats> {
+        if let Err(error) = self.receive_replies() {
+            log::error!("Failed to read ICMP replies: {}", error);
         }
+        Some(self.tracker.stats.clone())
     }
 }
 
-impl Drop for Pinger {
-    fn drop(&mut self) {
-        for child in self.processes.iter_mut() {
-            if let Err(e) = child.kill() {
-                log::error!("Failed to kill ping process - {}", e);
-            }
-        }
+#[cfg(target_os = "linux")]
+fn bind_to_interface(sock: &Socket, interface_name: &str) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let result = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            interface_name.as_ptr() as *const libc::c_void,
+            interface_name.len() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
     }
+    Ok(())
 }
 
+// Binding to an interface requires root on Android. Sockets created by the app are routed
+// through the tunnel anyway.
+#[cfg(target_os = "android")]
+fn bind_to_interface(_sock: &Socket, _interface_name: &str) -> io::Result<()> {
+    Ok(())
+}
 
-fn ping_cmd(ip: Ipv4Addr, timeout_secs: u16, interface: &str) -> duct::Expression {
-    let mut args = vec!["-n", "-i", "1"];
+#[cfg(target_os = "macos")]
+fn bind_to_interface(sock: &Socket, interface_name: &str) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
 
-    let timeout_flag = if cfg!(target_os = "linux") || cfg!(target_os = "android") {
-        "-w"
-    } else {
-        "-t"
+    // Not exposed by the `libc` crate.
+    const IP_BOUND_IF: libc::c_int = 25;
+
+    let interface_name = std::ffi::CString::new(interface_name)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+    let index = unsafe { libc::if_nametoindex(interface_name.as_ptr()) };
+    if index == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let result = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::IPPROTO_IP,
+            IP_BOUND_IF,
+            &index as *const _ as *const libc::c_void,
+            std::mem::size_of_val(&index) as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ping_monitor::Pinger as _;
+    use pnet_packet::{
+        icmp::{echo_reply::MutableEchoReplyPacket, IcmpType},
+        ipv4::MutableIpv4Packet,
     };
-    let timeout_secs = timeout_secs.to_string();
+    use std::thread;
 
-    args.extend_from_slice(&[timeout_flag, &timeout_secs]);
+    const TARGET: Ipv4Addr = Ipv4Addr::new(10, 64, 0, 1);
 
-    let interface_flag = if cfg!(target_os = "linux") {
-        Some("-I")
-    } else if cfg!(target_os = "macos") {
-        Some("-b")
+    const LOOPBACK_INTERFACE: &str = if cfg!(target_os = "macos") {
+        "lo0"
     } else {
-        None
+        "lo"
     };
 
-    if let Some(interface_flag) = interface_flag {
-        args.extend_from_slice(&[interface_flag, interface]);
+    fn echo_reply(icmp_type: IcmpType, identifier: u16, seq: u16) -> Vec<u8> {
+        let mut packet = MutableEchoReplyPacket::owned(vec![0u8; ICMP_HEADER_LENGTH]).unwrap();
+        packet.set_icmp_type(icmp_type);
+        packet.set_identifier(identifier);
+        packet.set_sequence_number(seq);
+        packet.packet().to_vec()
+    }
+
+    fn with_ip_header(source: Ipv4Addr, icmp_packet: &[u8]) -> Vec<u8> {
+        const IP_HEADER_LENGTH: usize = 20;
+        let mut packet =
+            MutableIpv4Packet::owned(vec![0u8; IP_HEADER_LENGTH + icmp_packet.len()]).unwrap();
+        packet.set_version(4);
+        packet.set_header_length((IP_HEADER_LENGTH / 4) as u8);
+        packet.set_source(source);
+        packet.set_payload(icmp_packet);
+        packet.packet().to_vec()
+    }
+
+    #[test]
+    fn test_parse_reply() {
+        let reply = echo_reply(IcmpTypes::EchoReply, 1, 7);
+        assert_eq!(parse_reply(&reply, TARGET, None), Some(7));
+        assert_eq!(parse_reply(&reply, TARGET, Some(1)), Some(7));
+        assert_eq!(parse_reply(&reply, TARGET, Some(2)), None);
+
+        let unreachable = echo_reply(IcmpTypes::DestinationUnreachable, 1, 7);
+        assert_eq!(parse_reply(&unreachable, TARGET, None), None);
+
+        assert_eq!(parse_reply(&[], TARGET, None), None);
+    }
+
+    #[test]
+    fn test_parse_reply_with_ip_header() {
+        let reply = echo_reply(IcmpTypes::EchoReply, 1, 7);
+        let packet = with_ip_header(TARGET, &reply);
+        assert_eq!(parse_reply(&packet, TARGET, Some(1)), Some(7));
+
+        let other_source = with_ip_header(Ipv4Addr::new(10, 64, 0, 2), &reply);
+        assert_eq!(parse_reply(&other_source, TARGET, Some(1)), None);
+
+        // Truncated packets are not parsed
+        assert_eq!(parse_reply(&packet[..10], TARGET, Some(1)), None);
+    }
+
+    #[test]
+    fn test_tracker_records_replies() {
+        let start = Instant::now();
+        let mut tracker = ReplyTracker::default();
+        tracker.request_sent(0, start);
+        tracker.request_sent(1, start);
+
+        tracker.reply_received(1, start + Duration::from_millis(300));
+        tracker.reply_received(0, start + Duration::from_millis(100));
+        // Duplicate and unsolicited replies are ignored
+        tracker.reply_received(1, start + Duration::from_millis(400));
+        tracker.reply_received(5, start + Duration::from_millis(400));
+
+        assert_eq!(tracker.stats.sent, 2);
+        assert_eq!(tracker.stats.received, 2);
+        assert_eq!(tracker.stats.lost, 0);
+        assert_eq!(tracker.stats.last_rtt, Some(Duration::from_millis(100)));
+        assert_eq!(
+            tracker.stats.average_rtt(),
+            Some(Duration::from_millis(200))
+        );
+        assert!(tracker.in_flight.is_empty());
+    }
+
+    #[test]
+    fn test_tracker_counts_lost_requests() {
+        let start = Instant::now();
+        let mut tracker = ReplyTracker::default();
+        tracker.request_sent(0, start);
+        tracker.request_sent(1, start + Duration::from_secs(2));
+
+        tracker.expire_requests(start + REPLY_TIMEOUT - Duration::from_millis(1));
+        assert_eq!(tracker.stats.lost, 0);
+
+        tracker.expire_requests(start + REPLY_TIMEOUT);
+        assert_eq!(tracker.stats.lost, 1);
+        assert_eq!(tracker.in_flight.len(), 1);
+
+        // Late replies to expired requests are not counted
+        tracker.reply_received(0, start + REPLY_TIMEOUT);
+        tracker.reply_received(1, start + REPLY_TIMEOUT);
+        assert_eq!(tracker.stats.received, 1);
+        assert_eq!(tracker.stats.loss(), 0.5);
+
+        tracker.reset();
+        assert_eq!(tracker.stats, PingStats::default());
+        assert!(tracker.in_flight.is_empty());
     }
 
-    let ip = ip.to_string();
-    args.push(&ip);
+    #[test]
+    #[ignore = "requires permission to open ICMP sockets"]
+    fn test_ping_loopback() {
+        let mut pinger = Pinger::new(Ipv4Addr::LOCALHOST, LOOPBACK_INTERFACE.to_string())
+            .expect("Failed to create pinger");
 
-    duct::cmd("ping", args)
-        .stdin_null()
-        .stdout_null()
-        .unchecked()
+        for _ in 0..3 {
+            pinger.send_icmp().unwrap();
+        }
+        let start = Instant::now();
+        let mut stats = pinger.stats().unwrap();
+        while stats.received < 3 && start.elapsed() < Duration::from_secs(2) {
+            thread::sleep(Duration::from_millis(10));
+            stats = pinger.stats().unwrap();
+        }
+
+        assert_eq!(stats.sent, 3);
+        assert_eq!(stats.received, 3);
+        assert_eq!(stats.lost, 0);
+        assert!(stats.last_rtt.is_some());
+        assert_eq!(stats.loss(), 0.0);
+
+        pinger.reset();
+        assert_eq!(pinger.stats().unwrap().sent, 0);
+    }
 }
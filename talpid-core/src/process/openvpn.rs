@@ -332,6 +332,24 @@ impl OpenVpnCommand {
                 args.push("255.255.255.255".to_owned());
                 args.push("net_gateway".to_owned());
             }
+            Some(net::openvpn::ProxySettings::Socks5(ref socks5_proxy)) => {
+                args.push("--socks-proxy".to_owned());
+                args.push(socks5_proxy.address.ip().to_string());
+                args.push(socks5_proxy.address.port().to_string());
+
+                if socks5_proxy.auth.is_some() {
+                    if let Some(ref auth_file) = self.proxy_auth_path {
+                        args.push(auth_file.to_string_lossy().to_string());
+                    } else {
+                        log::error!("Proxy credentials present but credentials file missing");
+                    }
+                }
+
+                args.push("--route".to_owned());
+                args.push(socks5_proxy.address.ip().to_string());
+                args.push("255.255.255.255".to_owned());
+                args.push("net_gateway".to_owned());
+            }
             Some(net::openvpn::ProxySettings::Shadowsocks(ref ss)) => {
                 args.push("--socks-proxy".to_owned());
                 args.push("127.0.0.1".to_owned());
@@ -426,8 +444,11 @@ impl StoppableProcess for OpenVpnProcHandle {
 #[cfg(test)]
 mod tests {
     use super::OpenVpnCommand;
-    use std::{ffi::OsString, net::Ipv4Addr};
-    use talpid_types::net::{Endpoint, TransportProtocol};
+    use std::{
+        ffi::OsString,
+        net::{Ipv4Addr, SocketAddr},
+    };
+    use talpid_types::net::{openvpn, Endpoint, TransportProtocol};
 
     #[test]
     fn passes_one_remote() {
@@ -454,4 +475,29 @@ mod tests {
         assert!(testee_args.contains(&OsString::from("123")));
         assert!(testee_args.contains(&OsString::from("cde")));
     }
+
+    #[test]
+    fn passes_socks5_proxy() {
+        let address = SocketAddr::new(Ipv4Addr::new(10, 0, 0, 1).into(), 1080);
+        let proxy = openvpn::ProxySettings::Socks5(openvpn::Socks5ProxySettings {
+            address,
+            auth: Some(openvpn::ProxyAuth {
+                username: String::from("user"),
+                password: String::from("pass"),
+            }),
+        });
+
+        let testee_args = OpenVpnCommand::new("")
+            .proxy_settings(proxy)
+            .proxy_auth("./auth/file")
+            .get_arguments();
+
+        let socks_index = testee_args
+            .iter()
+            .position(|arg| arg == &OsString::from("--socks-proxy"))
+            .expect("missing --socks-proxy argument");
+        assert_eq!(testee_args[socks_index + 1], OsString::from("10.0.0.1"));
+        assert_eq!(testee_args[socks_index + 2], OsString::from("1080"));
+        assert_eq!(testee_args[socks_index + 3], OsString::from("./auth/file"));
+    }
 }
@@ -6,11 +6,15 @@ use self::shadowsocks::ShadowsocksProxyMonitor;
 use std::{fmt, path::PathBuf, sync::mpsc};
 use talpid_types::net::openvpn;
 
+/// The way a proxy service stopped.
 pub enum WaitResult {
+    /// The proxy exited on its own. Contains a description of the exit status.
     UnexpectedExit(String),
+    /// The proxy was shut down through its close handle.
     ProperShutdown,
 }
 
+/// Monitors a running proxy service.
 pub trait ProxyMonitor: Send {
     /// Create a handle than can be used to ask the proxy service to shut down.
     fn close_handle(&mut self) -> Box<dyn ProxyMonitorCloseHandle>;
@@ -28,7 +32,9 @@ impl fmt::Debug for dyn ProxyMonitor {
     }
 }
 
+/// Handle used to shut down a proxy service.
 pub trait ProxyMonitorCloseHandle: Send {
+    /// Ask the proxy service to shut down.
     fn close(self: Box<Self>) -> Result<()>;
 }
 
@@ -77,10 +83,14 @@ impl ProxyMonitorCloseHandle for NoopProxyMonitorCloseHandle {
 /// proxy implementations find their way around.
 /// TODO: Move struct to wider scope and use more generic name.
 pub struct ProxyResourceData {
+    /// Directory containing the proxy binaries.
     pub resource_dir: PathBuf,
+    /// Directory to store the proxy log in. Uses a temporary directory if `None`.
     pub log_dir: Option<PathBuf>,
 }
 
+/// Start the proxy described by `settings`. Only Shadowsocks proxies are managed by us, for any
+/// other proxy this returns a monitor that only keeps track of the port.
 pub fn start_proxy(
     settings: &openvpn::ProxySettings,
     resource_data: &ProxyResourceData,
@@ -96,6 +106,12 @@ pub fn start_proxy(
                 remote_settings.address.port(),
             )?))
         }
+        openvpn::ProxySettings::Socks5(socks5_settings) => {
+            // These are generic proxy settings with the proxy client not managed by us.
+            Ok(Box::new(NoopProxyMonitor::start(
+                socks5_settings.address.port(),
+            )?))
+        }
         openvpn::ProxySettings::Shadowsocks(ss_settings) => Ok(Box::new(
             ShadowsocksProxyMonitor::start(ss_settings, resource_data)?,
         )),
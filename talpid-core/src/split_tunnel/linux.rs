@@ -1,9 +1,10 @@
 use std::{
     env, fs,
     io::{self, BufRead, BufReader, BufWriter, Write},
+    os::unix::fs::MetadataExt,
     path::PathBuf,
 };
-use talpid_types::cgroup::{find_net_cls_mount, SPLIT_TUNNEL_CGROUP_NAME};
+use talpid_types::cgroup::{find_split_tunnel_hierarchy, SplitTunnelHierarchy};
 
 const DEFAULT_NET_CLS_DIR: &str = "/sys/fs/cgroup/net_cls";
 const NET_CLS_DIR_OVERRIDE_ENV_VAR: &str = "TALPID_NET_CLS_MOUNT_DIR";
@@ -48,25 +49,45 @@ pub enum Error {
     ListMounts(#[error(source)] io::Error),
 }
 
+/// Returns the ID of the cgroup v2 that contains excluded processes, if the unified hierarchy is
+/// used for split tunneling. The ID is needed to match the cgroup in nftables.
+pub fn unified_exclusions_cgroup_id() -> Option<u64> {
+    match find_split_tunnel_hierarchy() {
+        Ok(Some(hierarchy @ SplitTunnelHierarchy::Unified(_))) => {
+            // On 64-bit systems, the cgroup ID is the inode number of the cgroup directory
+            fs::metadata(hierarchy.exclusions_path())
+                .map(|metadata| metadata.ino())
+                .ok()
+        }
+        _ => None,
+    }
+}
+
 /// Manages PIDs to exclude from the tunnel.
 pub struct PidManager {
-    net_cls_path: PathBuf,
+    hierarchy: SplitTunnelHierarchy,
 }
 
 impl PidManager {
     /// Create object to manage split-tunnel PIDs.
     pub fn new() -> Result<PidManager, Error> {
         let manager = PidManager {
-            net_cls_path: Self::create_cgroup()?,
+            hierarchy: Self::create_cgroup()?,
         };
+        log::debug!(
+            "Using cgroup hierarchy for split tunneling: {:?}",
+            manager.hierarchy
+        );
         manager.setup_exclusion_group()?;
         Ok(manager)
     }
 
-    /// Set up cgroup used to track PIDs for split tunneling.
-    fn create_cgroup() -> Result<PathBuf, Error> {
-        if let Some(net_cls_path) = find_net_cls_mount().map_err(Error::ListMounts)? {
-            return Ok(net_cls_path);
+    /// Set up cgroup used to track PIDs for split tunneling. The net_cls controller is used if
+    /// available. Otherwise, the cgroup v2 hierarchy is used if it is mounted. If neither is
+    /// available, the net_cls controller is mounted.
+    fn create_cgroup() -> Result<SplitTunnelHierarchy, Error> {
+        if let Some(hierarchy) = find_split_tunnel_hierarchy().map_err(Error::ListMounts)? {
+            return Ok(hierarchy);
         }
 
         let net_cls_dir = env::var(NET_CLS_DIR_OVERRIDE_ENV_VAR)
@@ -88,18 +109,24 @@ impl PidManager {
         .map_err(Error::InitNetClsCGroup)?;
 
 
-        Ok(net_cls_dir)
+        Ok(SplitTunnelHierarchy::NetCls(net_cls_dir))
     }
 
     fn setup_exclusion_group(&self) -> Result<(), Error> {
-        let exclusions_dir = self.net_cls_path.join(SPLIT_TUNNEL_CGROUP_NAME);
+        let exclusions_dir = self.hierarchy.exclusions_path();
         if !exclusions_dir.exists() {
             fs::create_dir(exclusions_dir.clone()).map_err(Error::CreateCGroup)?;
         }
 
-        let classid_path = exclusions_dir.join("net_cls.classid");
-        fs::write(classid_path, NET_CLS_CLASSID.to_string().as_bytes())
-            .map_err(Error::SetCGroupClassId)
+        match self.hierarchy {
+            SplitTunnelHierarchy::NetCls(_) => {
+                let classid_path = exclusions_dir.join("net_cls.classid");
+                fs::write(classid_path, NET_CLS_CLASSID.to_string().as_bytes())
+                    .map_err(Error::SetCGroupClassId)
+            }
+            // Sockets in the cgroup are matched by the ID of the cgroup instead
+            SplitTunnelHierarchy::Unified(_) => Ok(()),
+        }
     }
 
     /// Add a PID to exclude from the tunnel.
@@ -109,10 +136,7 @@ impl PidManager {
 
     /// Add PIDs to exclude from the tunnel.
     pub fn add_list<T: Into<i32> + ToString>(&self, pids: &[T]) -> Result<(), Error> {
-        let exclusions_path = self
-            .net_cls_path
-            .join(SPLIT_TUNNEL_CGROUP_NAME)
-            .join("cgroup.procs");
+        let exclusions_path = self.hierarchy.exclusions_path().join("cgroup.procs");
 
         let file = fs::OpenOptions::new()
             .write(true)
@@ -135,7 +159,7 @@ impl PidManager {
     pub fn remove(&self, pid: i32) -> Result<(), Error> {
         // FIXME: We remove PIDs from our cgroup here by adding
         //        them to the parent cgroup. This seems wrong.
-        let exclusions_path = self.net_cls_path.join("cgroup.procs");
+        let exclusions_path = self.hierarchy.mount_path().join("cgroup.procs");
 
         let mut file = fs::OpenOptions::new()
             .write(true)
@@ -149,10 +173,7 @@ impl PidManager {
 
     /// Return a list of PIDs that are excluded from the tunnel.
     pub fn list(&self) -> Result<Vec<i32>, Error> {
-        let exclusions_path = self
-            .net_cls_path
-            .join(SPLIT_TUNNEL_CGROUP_NAME)
-            .join("cgroup.procs");
+        let exclusions_path = self.hierarchy.exclusions_path().join("cgroup.procs");
 
         let file = fs::File::open(exclusions_path).map_err(Error::ListCGroupPids)?;
 
@@ -444,13 +444,16 @@ impl<C: OpenVpnBuilder + 'static> OpenVpnMonitor<C> {
     fn create_proxy_auth_file(
         proxy_settings: &Option<openvpn::ProxySettings>,
     ) -> std::result::Result<Option<mktemp::TempFile>, io::Error> {
-        if let Some(openvpn::ProxySettings::Remote(ref remote_proxy)) = proxy_settings {
-            if let Some(ref proxy_auth) = remote_proxy.auth {
-                return Ok(Some(Self::create_credentials_file(
-                    &proxy_auth.username,
-                    &proxy_auth.password,
-                )?));
-            }
+        let proxy_auth = match proxy_settings {
+            Some(openvpn::ProxySettings::Remote(ref remote_proxy)) => remote_proxy.auth.as_ref(),
+            Some(openvpn::ProxySettings::Socks5(ref socks5_proxy)) => socks5_proxy.auth.as_ref(),
+            _ => None,
+        };
+        if let Some(proxy_auth) = proxy_auth {
+            return Ok(Some(Self::create_credentials_file(
+                &proxy_auth.username,
+                &proxy_auth.password,
+            )?));
         }
         Ok(None)
     }
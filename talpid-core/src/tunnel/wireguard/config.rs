@@ -6,6 +6,7 @@ use std::{
 use talpid_types::net::{wireguard, GenericTunnelOptions};
 
 /// Config required to set up a single WireGuard tunnel
+#[derive(Clone)]
 pub struct Config {
     /// Contains tunnel endpoint specific config
     pub tunnel: wireguard::TunnelConfig,
@@ -20,10 +21,18 @@ pub struct Config {
     /// Firewall mark
     #[cfg(target_os = "linux")]
     pub fwmark: u32,
+    /// Whether a quantum-resistant pre-shared key should be negotiated once the tunnel is up
+    pub quantum_resistant: bool,
+    /// Thresholds used by the connectivity monitor
+    pub connectivity_check: wireguard::ConnectivityCheckOptions,
 }
 
 const DEFAULT_MTU: u16 = 1380;
 
+/// The pre-shared key negotiation requires the firewall to allow traffic to the relay inside the
+/// tunnel before it is up, which is only implemented on some platforms.
+pub const QUANTUM_RESISTANCE_SUPPORTED: bool = cfg!(any(target_os = "linux", target_os = "macos"));
+
 /// Configuration errors
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -87,6 +96,11 @@ impl Config {
             .filter(|ip| ip.is_ipv4() || generic_options.enable_ipv6)
             .collect();
 
+        let quantum_resistant = wg_options.quantum_resistant && QUANTUM_RESISTANCE_SUPPORTED;
+        if wg_options.quantum_resistant && !QUANTUM_RESISTANCE_SUPPORTED {
+            log::warn!("Quantum-resistant tunnels are not supported on this platform");
+        }
+
         let ipv6_gateway = if generic_options.enable_ipv6 {
             connection_config.ipv6_gateway
         } else {
@@ -101,6 +115,8 @@ impl Config {
             mtu,
             #[cfg(target_os = "linux")]
             fwmark: crate::linux::TUNNEL_FW_MARK,
+            quantum_resistant,
+            connectivity_check: wg_options.connectivity_check,
         })
     }
 
@@ -123,6 +139,9 @@ impl Config {
                 .add("public_key", peer.public_key.as_bytes().as_ref())
                 .add("endpoint", peer.endpoint.to_string().as_str())
                 .add("replace_allowed_ips", "true");
+            if let Some(psk) = &peer.psk {
+                wg_conf.add("preshared_key", psk.as_bytes().as_ref());
+            }
             for addr in &peer.allowed_ips {
                 wg_conf.add("allowed_ip", addr.to_string().as_str());
             }
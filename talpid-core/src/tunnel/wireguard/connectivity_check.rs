@@ -7,6 +7,7 @@ use std::{
     sync::{mpsc, Mutex, Weak},
     time::{Duration, Instant},
 };
+use talpid_types::net::wireguard::ConnectivityCheckOptions;
 
 use super::{Tunnel, TunnelError};
 
@@ -15,20 +16,6 @@ const DELAY_ON_INITIAL_SETUP: Duration = Duration::from_millis(50);
 /// Sleep time used when checking if an established connection is still working.
 const REGULAR_LOOP_SLEEP: Duration = Duration::from_secs(1);
 
-
-/// Timeout for waiting on receiving traffic after sending outgoing traffic.  Once this timeout is
-/// hit, a ping will be sent every `SECONDS_PER_PING` until `PING_TIMEOUT` is reached, or traffic
-/// is received.
-const BYTES_RX_TIMEOUT: Duration = Duration::from_secs(5);
-/// Timeout for waiting on receiving or sending any traffic.  Once this timeout is hit, a ping will
-/// be sent every `SECONDS_PER_PING` until `PING_TIMEOUT` is reached or traffic is received.
-const TRAFFIC_TIMEOUT: Duration = Duration::from_secs(120);
-/// Timeout for waiting on receiving traffic after sending the first ICMP packet.  Once this
-/// timeout is reached, it is assumed that the connection is lost.
-const PING_TIMEOUT: Duration = Duration::from_secs(15);
-/// Number of seconds to wait between sending ICMP packets
-const SECONDS_PER_PING: Duration = Duration::from_secs(3);
-
 /// Connectivity monitor errors
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -42,6 +29,34 @@ pub enum Error {
 }
 
 
+/// Timeouts used by the connectivity monitor, derived from [`ConnectivityCheckOptions`].
+#[derive(Debug, Clone, Copy)]
+struct Timeouts {
+    /// Timeout for waiting on receiving traffic after sending outgoing traffic.  Once this timeout
+    /// is hit, a ping will be sent every `ping_interval` until `ping` is reached, or traffic is
+    /// received.
+    bytes_rx: Duration,
+    /// Timeout for waiting on receiving or sending any traffic.  Once this timeout is hit, a ping
+    /// will be sent every `ping_interval` until `ping` is reached or traffic is received.
+    traffic: Duration,
+    /// Timeout for waiting on receiving traffic after sending the first ICMP packet.  Once this
+    /// timeout is reached, it is assumed that the connection is lost.
+    ping: Duration,
+    /// Time to wait between sending ICMP packets
+    ping_interval: Duration,
+}
+
+impl From<&ConnectivityCheckOptions> for Timeouts {
+    fn from(options: &ConnectivityCheckOptions) -> Self {
+        Self {
+            bytes_rx: Duration::from_secs(options.rx_timeout.into()),
+            traffic: Duration::from_secs(options.traffic_timeout.into()),
+            ping: Duration::from_secs(options.ping_timeout.into()),
+            ping_interval: Duration::from_secs(options.ping_interval.into()),
+        }
+    }
+}
+
 /// Verifies if a connection to a tunnel is working.
 /// The connectivity monitor is biased to receiving traffic - it is expected that all outgoing
 /// traffic will be answered with a response.
@@ -55,18 +70,20 @@ pub enum Error {
 /// timeout. A connection is considered to be established the first time an increase in incoming
 /// traffic is observed.
 ///
-/// The connectivity monitor will start sending pings and start the countdown to `PING_TIMEOUT` in
+/// The connectivity monitor will start sending pings and start the countdown to the ping timeout in
 /// the following cases:
 /// - In case that we have observed a bump in the outgoing traffic but no coressponding incoming
-/// traffic for longer than `BYTES_RX_TIMEOUT`, then the monitor will start pinging.
+/// traffic for longer than the RX timeout, then the monitor will start pinging.
 /// - In case that no increase in outgoing or incoming traffic has been observed for longer than
-/// `TRAFFIC_TIMEOUT`, then the monitor will start pinging as well.
+/// the traffic timeout, then the monitor will start pinging as well.
 ///
 /// Once a connection established, a connection is only considered broken once the connectivity
-/// monitor has started pinging and no traffic has been received for a duration of `PING_TIMEOUT`.
+/// monitor has started pinging and no traffic has been received for the duration of the ping
+/// timeout. The timeouts are configured through [`ConnectivityCheckOptions`].
 pub struct ConnectivityMonitor {
     tunnel_handle: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
     conn_state: ConnState,
+    timeouts: Timeouts,
     initial_ping_timestamp: Option<Instant>,
     num_pings_sent: u32,
     pinger: Box<dyn Pinger>,
@@ -75,19 +92,23 @@ pub struct ConnectivityMonitor {
 
 
 impl ConnectivityMonitor {
+    /// Creates a monitor that pings `options.ping_target` if set, and `addr` otherwise.
     pub(super) fn new(
         addr: Ipv4Addr,
         interface: String,
+        options: &ConnectivityCheckOptions,
         tunnel_handle: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
         close_receiver: mpsc::Receiver<()>,
     ) -> Result<Self, Error> {
-        let pinger = new_pinger(addr, interface).map_err(Error::PingError)?;
+        let ping_target = options.ping_target.unwrap_or(addr);
+        let pinger = new_pinger(ping_target, interface).map_err(Error::PingError)?;
 
         let now = Instant::now();
 
         Ok(Self {
             tunnel_handle,
             conn_state: ConnState::new(now, Default::default()),
+            timeouts: Timeouts::from(options),
             initial_ping_timestamp: None,
             num_pings_sent: 0,
             pinger,
@@ -103,7 +124,7 @@ impl ConnectivityMonitor {
         }
 
         let start = Instant::now();
-        while start.elapsed() < PING_TIMEOUT {
+        while start.elapsed() < self.timeouts.ping {
             if self.check_connectivity(Instant::now())? {
                 return Ok(true);
             }
@@ -114,6 +135,13 @@ impl ConnectivityMonitor {
         Ok(false)
     }
 
+    /// Forgets that connectivity has been established, e.g. after the tunnel has been
+    /// reconfigured and its traffic counters have been reset.
+    pub(super) fn reset(&mut self) {
+        self.conn_state = ConnState::new(Instant::now(), Default::default());
+        self.reset_pinger();
+    }
+
     pub(super) fn run(&mut self) -> Result<(), Error> {
         self.wait_loop(REGULAR_LOOP_SLEEP)
     }
@@ -182,13 +210,15 @@ impl ConnectivityMonitor {
 
     fn maybe_send_ping(&mut self, now: Instant) -> Result<(), Error> {
         // Only send out a ping if we haven't received a byte in a while or no traffic has flowed
-        // in the last 2 minutes, but if a ping already has been sent out, only send one out every
-        // 3 seconds.
-        if (self.conn_state.rx_timed_out() || self.conn_state.traffic_timed_out())
+        // for the traffic timeout, but if a ping already has been sent out, only send one out
+        // every ping interval.
+        if (self.conn_state.rx_timed_out(&self.timeouts)
+            || self.conn_state.traffic_timed_out(&self.timeouts))
             && self
                 .initial_ping_timestamp
                 .map(|initial_ping_timestamp| {
-                    initial_ping_timestamp.elapsed() / self.num_pings_sent < SECONDS_PER_PING
+                    initial_ping_timestamp.elapsed() / self.num_pings_sent
+                        < self.timeouts.ping_interval
                 })
                 .unwrap_or(true)
         {
@@ -203,12 +233,23 @@ impl ConnectivityMonitor {
 
     fn ping_timed_out(&self) -> bool {
         self.initial_ping_timestamp
-            .map(|initial_ping_timestamp| initial_ping_timestamp.elapsed() > PING_TIMEOUT)
+            .map(|initial_ping_timestamp| initial_ping_timestamp.elapsed() > self.timeouts.ping)
             .unwrap_or(false)
     }
 
     /// Reset timeouts - assume that the last time bytes were received is now.
     fn reset_pinger(&mut self) {
+        if self.num_pings_sent > 0 {
+            if let Some(stats) = self.pinger.stats() {
+                log::debug!(
+                    "Ping stats: {} sent, {} received, {:.0}% loss, average RTT: {:?}",
+                    stats.sent,
+                    stats.received,
+                    stats.loss() * 100.0,
+                    stats.average_rtt(),
+                );
+            }
+        }
         self.initial_ping_timestamp = None;
         self.num_pings_sent = 0;
         self.pinger.reset();
@@ -301,9 +342,9 @@ impl ConnState {
     }
 
     // check if last time data was received is too long ago
-    pub fn rx_timed_out(&self) -> bool {
+    fn rx_timed_out(&self, timeouts: &Timeouts) -> bool {
         match self {
-            ConnState::Connecting { start, .. } => start.elapsed() >= BYTES_RX_TIMEOUT,
+            ConnState::Connecting { start, .. } => start.elapsed() >= timeouts.bytes_rx,
             ConnState::Connected {
                 rx_timestamp,
                 tx_timestamp,
@@ -311,23 +352,23 @@ impl ConnState {
             } => {
                 // if last sent bytes were sent after or at the same time as last received bytes
                 tx_timestamp >= rx_timestamp &&
-                    // and the response hasn't been seen for BYTES_RX_TIMEOUT
-                    rx_timestamp.elapsed() >= BYTES_RX_TIMEOUT
+                    // and the response hasn't been seen for the RX timeout
+                    rx_timestamp.elapsed() >= timeouts.bytes_rx
             }
         }
     }
 
     // check if no bytes have been sent or received in a while
-    pub fn traffic_timed_out(&self) -> bool {
+    fn traffic_timed_out(&self, timeouts: &Timeouts) -> bool {
         match self {
-            ConnState::Connecting { .. } => self.rx_timed_out(),
+            ConnState::Connecting { .. } => self.rx_timed_out(timeouts),
             ConnState::Connected {
                 rx_timestamp,
                 tx_timestamp,
                 ..
             } => {
-                rx_timestamp.elapsed() >= TRAFFIC_TIMEOUT
-                    || tx_timestamp.elapsed() >= TRAFFIC_TIMEOUT
+                rx_timestamp.elapsed() >= timeouts.traffic
+                    || tx_timestamp.elapsed() >= timeouts.traffic
             }
         }
     }
@@ -343,7 +384,7 @@ impl ConnState {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::tunnel::wireguard::{stats, TunnelError};
+    use crate::tunnel::wireguard::{stats, Config, TunnelError};
     use std::{
         sync::{
             atomic::{AtomicBool, Ordering},
@@ -359,20 +400,22 @@ mod test {
         let conn_state = ConnState::new(now, Default::default());
 
         assert!(!conn_state.connected());
-        assert!(!conn_state.rx_timed_out());
-        assert!(!conn_state.traffic_timed_out());
+        assert!(!conn_state.rx_timed_out(&default_timeouts()));
+        assert!(!conn_state.traffic_timed_out(&default_timeouts()));
     }
 
     /// Test if ConnState::Connecting will timeout after not receiving any traffic after
-    /// BYTES_RX_TIMEOUT
+    /// the default RX timeout
     #[test]
     fn test_conn_state_timeout_after_rx_timeout() {
-        let now = Instant::now().checked_sub(BYTES_RX_TIMEOUT).unwrap();
+        let now = Instant::now()
+            .checked_sub(default_timeouts().bytes_rx)
+            .unwrap();
         let conn_state = ConnState::new(now, Default::default());
 
         assert!(!conn_state.connected());
-        assert!(conn_state.rx_timed_out());
-        assert!(conn_state.traffic_timed_out());
+        assert!(conn_state.rx_timed_out(&default_timeouts()));
+        assert!(conn_state.traffic_timed_out(&default_timeouts()));
     }
 
     /// Test if ConnState::Connecting correctly transitions into ConnState::Connected if traffic is
@@ -390,20 +433,22 @@ mod test {
         );
 
         assert!(conn_state.connected());
-        assert!(!conn_state.rx_timed_out());
-        assert!(!conn_state.traffic_timed_out());
+        assert!(!conn_state.rx_timed_out(&default_timeouts()));
+        assert!(!conn_state.traffic_timed_out(&default_timeouts()));
     }
 
-    /// Test if ConnState::Connected correctly times out after TRAFFIC_TIMEOUT when no traffic is
-    /// observed
+    /// Test if ConnState::Connected correctly times out after the default traffic timeout when no
+    /// traffic is observed
     #[test]
     fn test_conn_state_traffic_times_out_after_connecting() {
         let start = Instant::now()
-            .checked_sub(TRAFFIC_TIMEOUT + Duration::from_secs(1))
+            .checked_sub(default_timeouts().traffic + Duration::from_secs(1))
             .unwrap();
         let mut conn_state = ConnState::new(start, Default::default());
 
-        let connect_time = Instant::now().checked_sub(TRAFFIC_TIMEOUT).unwrap();
+        let connect_time = Instant::now()
+            .checked_sub(default_timeouts().traffic)
+            .unwrap();
         conn_state.update(
             connect_time,
             Stats {
@@ -413,16 +458,16 @@ mod test {
         );
 
         assert!(conn_state.connected());
-        assert!(!conn_state.rx_timed_out());
-        assert!(conn_state.traffic_timed_out());
+        assert!(!conn_state.rx_timed_out(&default_timeouts()));
+        assert!(conn_state.traffic_timed_out(&default_timeouts()));
     }
 
-    /// Test if ConnState::Connected correctly times out after BYTES_RX_TIMEOUT when no incoming
-    /// traffic is observed
+    /// Test if ConnState::Connected correctly times out after the default RX timeout when no
+    /// incoming traffic is observed
     #[test]
     fn test_conn_state_rx_times_out_after_connecting() {
         let start = Instant::now()
-            .checked_sub(BYTES_RX_TIMEOUT + Duration::from_secs(1))
+            .checked_sub(default_timeouts().bytes_rx + Duration::from_secs(1))
             .unwrap();
         let mut conn_state = ConnState::new(start, Default::default());
 
@@ -434,7 +479,9 @@ mod test {
             },
         );
 
-        let update_time = Instant::now().checked_sub(BYTES_RX_TIMEOUT).unwrap();
+        let update_time = Instant::now()
+            .checked_sub(default_timeouts().bytes_rx)
+            .unwrap();
         conn_state.update(
             update_time,
             Stats {
@@ -444,8 +491,8 @@ mod test {
         );
 
         assert!(conn_state.connected());
-        assert!(conn_state.rx_timed_out());
-        assert!(!conn_state.traffic_timed_out());
+        assert!(conn_state.rx_timed_out(&default_timeouts()));
+        assert!(!conn_state.traffic_timed_out(&default_timeouts()));
     }
 
     #[derive(Default)]
@@ -525,6 +572,14 @@ mod test {
         fn get_tunnel_stats(&self) -> Result<stats::Stats, TunnelError> {
             (self.on_get_stats)()
         }
+
+        fn set_config(&self, _config: &Config) -> Result<(), TunnelError> {
+            Ok(())
+        }
+    }
+
+    fn default_timeouts() -> Timeouts {
+        Timeouts::from(&ConnectivityCheckOptions::default())
     }
 
     fn mock_monitor(
@@ -535,6 +590,7 @@ mod test {
     ) -> ConnectivityMonitor {
         ConnectivityMonitor {
             conn_state: ConnState::new(now, Default::default()),
+            timeouts: default_timeouts(),
             initial_ping_timestamp: None,
             num_pings_sent: 0,
             pinger,
@@ -557,13 +613,14 @@ mod test {
 
     #[test]
     /// Verify that `check_connectivity()` returns `false` if the tunnel is connected and traffic is
-    /// not flowing after `BYTES_RX_TIMEOUT` and `PING_TIMEOUT`.
+    /// not flowing after the RX timeout and ping timeout.
     fn test_ping_times_out() {
         let (_tunnel_anchor, tunnel) = MockTunnel::never_incrementing().into_locked();
         let (_tx, rx) = mpsc::channel();
         let pinger = MockPinger::default();
         let now = Instant::now();
-        let start = now - (BYTES_RX_TIMEOUT + PING_TIMEOUT + Duration::from_secs(10));
+        let start =
+            now - (default_timeouts().bytes_rx + default_timeouts().ping + Duration::from_secs(10));
         let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, rx);
 
         // Mock the state - connectivity has been established
@@ -633,7 +690,7 @@ mod test {
 
     #[test]
     /// Verify that the connectivity monitor detects the tunnel timing out after no longer than
-    /// `BYTES_RX_TIMEOUT` and `PING_TIMEOUT` combined.
+    /// the RX timeout and ping timeout combined.
     fn test_wait_loop_timeout() {
         let should_stop = Arc::new(AtomicBool::new(false));
         let should_stop_inner = should_stop.clone();
@@ -672,8 +729,68 @@ mod test {
             .unwrap());
         should_stop.store(true, Ordering::SeqCst);
         assert!(result_rx
-            .recv_timeout(BYTES_RX_TIMEOUT + PING_TIMEOUT + Duration::from_secs(2))
+            .recv_timeout(
+                default_timeouts().bytes_rx + default_timeouts().ping + Duration::from_secs(2)
+            )
             .unwrap()
             .is_ok());
     }
+
+    #[test]
+    /// Verify that a custom RX timeout is respected by `ConnState`.
+    fn test_conn_state_custom_rx_timeout() {
+        let timeouts = Timeouts::from(&ConnectivityCheckOptions {
+            rx_timeout: 1,
+            ..ConnectivityCheckOptions::default()
+        });
+        let start = Instant::now().checked_sub(Duration::from_secs(2)).unwrap();
+        let conn_state = ConnState::new(start, Default::default());
+
+        assert!(conn_state.rx_timed_out(&timeouts));
+        assert!(!conn_state.rx_timed_out(&default_timeouts()));
+    }
+
+    #[test]
+    /// Verify that `check_connectivity()` uses the configured ping timeout, and that pings are
+    /// sent once the configured RX timeout has passed.
+    fn test_custom_ping_timeout() {
+        let pings_sent = Arc::new(Mutex::new(0));
+        let pings_sent_inner = pings_sent.clone();
+        let pinger = MockPinger {
+            on_send_ping: Some(Box::new(move || *pings_sent_inner.lock().unwrap() += 1)),
+        };
+        let (_tunnel_anchor, tunnel) = MockTunnel::never_incrementing().into_locked();
+        let (_tx, rx) = mpsc::channel();
+        let now = Instant::now();
+        let start = now - Duration::from_secs(5);
+        let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, rx);
+        monitor.timeouts = Timeouts::from(&ConnectivityCheckOptions {
+            rx_timeout: 1,
+            ping_timeout: 2,
+            ping_interval: 1,
+            ..ConnectivityCheckOptions::default()
+        });
+
+        // Mock the state - connectivity has been established, but nothing has been received since
+        monitor.conn_state = connected_state(start);
+        monitor.maybe_send_ping(start).unwrap();
+        assert_eq!(*pings_sent.lock().unwrap(), 1);
+        assert!(!monitor.check_connectivity(now).unwrap());
+    }
+
+    #[test]
+    /// Verify that the default ping timeout is not hit in the same situation as in
+    /// `test_custom_ping_timeout`.
+    fn test_default_ping_timeout_not_reached() {
+        let (_tunnel_anchor, tunnel) = MockTunnel::never_incrementing().into_locked();
+        let (_tx, rx) = mpsc::channel();
+        let pinger = MockPinger::default();
+        let now = Instant::now();
+        let start = now - Duration::from_secs(5);
+        let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, rx);
+
+        monitor.conn_state = connected_state(start);
+        monitor.maybe_send_ping(start).unwrap();
+        assert!(monitor.check_connectivity(now).unwrap());
+    }
 }
@@ -9,19 +9,22 @@ use lazy_static::lazy_static;
 use std::env;
 use std::{
     collections::HashSet,
+    net::SocketAddr,
     path::Path,
-    sync::{mpsc, Arc, Mutex},
+    sync::{mpsc, Arc, Mutex, Weak},
 };
-use talpid_types::ErrorExt;
+use talpid_types::{net::wireguard::PrivateKey, ErrorExt};
 
 /// WireGuard config data-types
 pub mod config;
 mod connectivity_check;
 mod logging;
+/// Negotiation of quantum-resistant pre-shared keys
+pub mod psk_exchange;
 mod stats;
 mod wireguard_go;
 #[cfg(target_os = "linux")]
-mod wireguard_kernel;
+pub(crate) mod wireguard_kernel;
 
 use self::wireguard_go::WgGoTunnel;
 
@@ -45,6 +48,10 @@ pub enum Error {
     /// Failed to setup connectivity monitor
     #[error(display = "Connectivity monitor failed")]
     ConnectivityMonitorError(#[error(source)] connectivity_check::Error),
+
+    /// Failed to negotiate a quantum-resistant pre-shared key
+    #[error(display = "Failed to negotiate a pre-shared key with the relay")]
+    PskNegotiationError(#[error(source)] psk_exchange::Error),
 }
 
 
@@ -109,12 +116,27 @@ impl WireguardMonitor {
         let mut connectivity_monitor = connectivity_check::ConnectivityMonitor::new(
             gateway,
             iface_name.to_string(),
+            &config.connectivity_check,
             Arc::downgrade(&monitor.tunnel),
             pinger_rx,
         )?;
 
+        let psk_negotiation = if config.quantum_resistant {
+            let negotiator = psk_exchange::RelayPskNegotiator::new(SocketAddr::new(
+                config.ipv4_gateway.into(),
+                psk_exchange::PSK_EXCHANGE_PORT,
+            ));
+            Some(PskNegotiation {
+                config: config.clone(),
+                tunnel: Arc::downgrade(&monitor.tunnel),
+                negotiator: Box::new(negotiator),
+            })
+        } else {
+            None
+        };
+
         std::thread::spawn(move || {
-            match connectivity_monitor.establish_connectivity() {
+            match Self::establish_connectivity(&mut connectivity_monitor, psk_negotiation) {
                 Ok(true) => {
                     (on_event)(TunnelEvent::Up(metadata));
 
@@ -140,6 +162,27 @@ impl WireguardMonitor {
         Ok(monitor)
     }
 
+    /// Waits for the tunnel to become functional. If requested, a pre-shared key is then
+    /// negotiated with the relay and the tunnel is reconfigured to use it, after which
+    /// connectivity has to be established again.
+    fn establish_connectivity(
+        connectivity_monitor: &mut connectivity_check::ConnectivityMonitor,
+        psk_negotiation: Option<PskNegotiation>,
+    ) -> Result<bool> {
+        if !connectivity_monitor.establish_connectivity()? {
+            return Ok(false);
+        }
+        if let Some(mut psk_negotiation) = psk_negotiation {
+            log::debug!("Negotiating a quantum-resistant pre-shared key");
+            if !psk_negotiation.reconfigure_tunnel()? {
+                return Ok(false);
+            }
+            connectivity_monitor.reset();
+            return Ok(connectivity_monitor.establish_connectivity()?);
+        }
+        Ok(true)
+    }
+
     #[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
     fn open_tunnel(
         config: &Config,
@@ -257,6 +300,44 @@ impl WireguardMonitor {
     }
 }
 
+/// State required to upgrade a running tunnel with a negotiated pre-shared key.
+struct PskNegotiation {
+    config: Config,
+    tunnel: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
+    negotiator: Box<dyn psk_exchange::PskNegotiator>,
+}
+
+impl PskNegotiation {
+    /// Negotiates a pre-shared key for a new ephemeral key and applies both to the tunnel.
+    /// Returns false if the tunnel has already been closed.
+    fn reconfigure_tunnel(&mut self) -> Result<bool> {
+        let ephemeral_key = PrivateKey::new_from_random();
+        let psk = self.negotiator.negotiate(
+            &self.config.tunnel.private_key.public_key(),
+            &ephemeral_key.public_key(),
+        )?;
+
+        let mut config = self.config.clone();
+        config.tunnel.private_key = ephemeral_key;
+        for peer in &mut config.peers {
+            peer.psk = Some(psk.clone());
+        }
+
+        let tunnel = match self.tunnel.upgrade() {
+            Some(tunnel) => tunnel,
+            None => return Ok(false),
+        };
+        let tunnel = tunnel.lock().expect("Tunnel lock poisoned");
+        match tunnel.as_ref() {
+            Some(tunnel) => {
+                tunnel.set_config(&config)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
 enum CloseMsg {
     Stop,
     PingErr,
@@ -281,6 +362,7 @@ pub(crate) trait Tunnel: Send {
     fn get_interface_name(&self) -> String;
     fn stop(self: Box<Self>) -> std::result::Result<(), TunnelError>;
     fn get_tunnel_stats(&self) -> std::result::Result<stats::Stats, TunnelError>;
+    fn set_config(&self, config: &Config) -> std::result::Result<(), TunnelError>;
 }
 
 /// Errors to be returned from WireGuard implementations, namely implementers of the Tunnel trait
@@ -317,6 +399,10 @@ pub enum TunnelError {
     #[error(display = "Failed to get config of WireGuard tunnel")]
     GetConfigError,
 
+    /// Error whilst trying to apply a new config to a running WireGuard tunnel
+    #[error(display = "Failed to set config of WireGuard tunnel")]
+    SetConfigError,
+
     /// Failed to duplicate tunnel file descriptor for wireguard-go
     #[cfg(any(target_os = "linux", target_os = "macos", target_os = "android"))]
     #[error(display = "Failed to duplicate tunnel file descriptor for wireguard-go")]
@@ -345,3 +431,101 @@ pub enum TunnelError {
     #[error(display = "Failed to set up logging")]
     LoggingError(#[error(source)] logging::Error),
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use talpid_types::net::wireguard::{PeerConfig, PresharedKey, PublicKey, TunnelConfig};
+
+    struct MockNegotiator(PresharedKey);
+
+    impl psk_exchange::PskNegotiator for MockNegotiator {
+        fn negotiate(
+            &mut self,
+            _current_pubkey: &PublicKey,
+            _ephemeral_pubkey: &PublicKey,
+        ) -> std::result::Result<PresharedKey, psk_exchange::Error> {
+            Ok(self.0.clone())
+        }
+    }
+
+    /// Records the configs applied to it
+    struct MockTunnel(Arc<Mutex<Vec<Config>>>);
+
+    impl Tunnel for MockTunnel {
+        fn get_interface_name(&self) -> String {
+            "mock-tunnel".to_string()
+        }
+
+        fn stop(self: Box<Self>) -> std::result::Result<(), TunnelError> {
+            Ok(())
+        }
+
+        fn get_tunnel_stats(&self) -> std::result::Result<stats::Stats, TunnelError> {
+            Ok(Default::default())
+        }
+
+        fn set_config(&self, config: &Config) -> std::result::Result<(), TunnelError> {
+            self.0.lock().unwrap().push(config.clone());
+            Ok(())
+        }
+    }
+
+    fn mock_config() -> Config {
+        Config {
+            tunnel: TunnelConfig {
+                private_key: PrivateKey::new_from_random(),
+                addresses: vec![Ipv4Addr::new(10, 64, 0, 2).into()],
+            },
+            peers: vec![PeerConfig {
+                public_key: PrivateKey::new_from_random().public_key(),
+                allowed_ips: vec!["0.0.0.0/0".parse().unwrap()],
+                endpoint: "1.2.3.4:51820".parse().unwrap(),
+                psk: None,
+            }],
+            ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+            ipv6_gateway: None,
+            mtu: 1380,
+            #[cfg(target_os = "linux")]
+            fwmark: 0,
+            quantum_resistant: true,
+            connectivity_check: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_reconfigure_with_negotiated_psk() {
+        let applied_configs = Arc::new(Mutex::new(vec![]));
+        let tunnel: Arc<Mutex<Option<Box<dyn Tunnel>>>> = Arc::new(Mutex::new(Some(Box::new(
+            MockTunnel(applied_configs.clone()),
+        ))));
+        let config = mock_config();
+        let psk = PresharedKey::from([1u8; 32]);
+
+        let mut psk_negotiation = PskNegotiation {
+            config: config.clone(),
+            tunnel: Arc::downgrade(&tunnel),
+            negotiator: Box::new(MockNegotiator(psk.clone())),
+        };
+        assert!(psk_negotiation.reconfigure_tunnel().unwrap());
+
+        let applied_configs = applied_configs.lock().unwrap();
+        assert_eq!(applied_configs.len(), 1);
+        let new_config = &applied_configs[0];
+        assert_ne!(new_config.tunnel.private_key, config.tunnel.private_key);
+        assert_eq!(new_config.peers[0].psk, Some(psk));
+        assert_eq!(new_config.peers[0].public_key, config.peers[0].public_key);
+    }
+
+    #[test]
+    fn test_reconfigure_closed_tunnel() {
+        let tunnel: Arc<Mutex<Option<Box<dyn Tunnel>>>> = Arc::new(Mutex::new(None));
+        let mut psk_negotiation = PskNegotiation {
+            config: mock_config(),
+            tunnel: Arc::downgrade(&tunnel),
+            negotiator: Box::new(MockNegotiator(PresharedKey::from([1u8; 32]))),
+        };
+        assert!(!psk_negotiation.reconfigure_tunnel().unwrap());
+    }
+}
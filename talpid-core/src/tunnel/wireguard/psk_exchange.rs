@@ -0,0 +1,208 @@
+//! Negotiation of a quantum-resistant pre-shared key with a relay.
+//!
+//! Once an ephemeral tunnel is up, the client generates a KEM key pair and sends the public half
+//! to the relay, along with its current WireGuard public key and a newly generated ephemeral
+//! WireGuard public key. The relay encapsulates a shared secret to the KEM public key and returns
+//! the ciphertext. Both sides then use the shared secret as the pre-shared key for the ephemeral
+//! WireGuard key, and the tunnel is reconfigured to use it.
+//!
+//! Request format:
+//! `version (u8) | current pubkey (32) | ephemeral pubkey (32) | kem (u8) | len (u16) | kem pubkey`
+//!
+//! Response format:
+//! `status (u8) | len (u16) | ciphertext`
+
+use pqcrypto_kyber::kyber1024;
+use pqcrypto_traits::kem::{Ciphertext as _, PublicKey as _, SharedSecret as _};
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
+use talpid_types::net::wireguard::{PresharedKey, PublicKey};
+
+/// Port that relays listen on for key exchange requests. Only reachable through the tunnel.
+pub const PSK_EXCHANGE_PORT: u16 = 1337;
+
+const PROTOCOL_VERSION: u8 = 1;
+const KEM_KYBER1024: u8 = 1;
+const STATUS_OK: u8 = 0;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Errors that can occur when negotiating a pre-shared key.
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Failed to connect to the key exchange service
+    #[error(display = "Failed to connect to the key exchange service at {}", _0)]
+    ConnectError(SocketAddr, #[error(source)] io::Error),
+
+    /// Failed to send or receive a key exchange message
+    #[error(display = "Failed to exchange messages with the key exchange service")]
+    IoError(#[error(source)] io::Error),
+
+    /// The relay rejected the request
+    #[error(display = "The key exchange request was rejected with status {}", _0)]
+    Rejected(u8),
+
+    /// The relay returned a ciphertext that could not be decapsulated
+    #[error(display = "Received an invalid ciphertext")]
+    InvalidCiphertext,
+}
+
+/// Negotiates a pre-shared key to be used together with an ephemeral WireGuard key.
+pub trait PskNegotiator: Send {
+    /// Returns a pre-shared key that the peer associates with `ephemeral_pubkey`.
+    fn negotiate(
+        &mut self,
+        current_pubkey: &PublicKey,
+        ephemeral_pubkey: &PublicKey,
+    ) -> Result<PresharedKey, Error>;
+}
+
+/// Negotiates a pre-shared key with the key exchange service on a relay, using Kyber1024.
+pub struct RelayPskNegotiator {
+    service_address: SocketAddr,
+}
+
+impl RelayPskNegotiator {
+    /// Creates a negotiator that talks to the key exchange service at the given address.
+    pub fn new(service_address: SocketAddr) -> Self {
+        Self { service_address }
+    }
+
+    fn connect(&self) -> Result<TcpStream, Error> {
+        let stream = TcpStream::connect_timeout(&self.service_address, CONNECT_TIMEOUT)
+            .map_err(|error| Error::ConnectError(self.service_address, error))?;
+        stream
+            .set_read_timeout(Some(IO_TIMEOUT))
+            .and_then(|_| stream.set_write_timeout(Some(IO_TIMEOUT)))
+            .map_err(Error::IoError)?;
+        Ok(stream)
+    }
+}
+
+impl PskNegotiator for RelayPskNegotiator {
+    fn negotiate(
+        &mut self,
+        current_pubkey: &PublicKey,
+        ephemeral_pubkey: &PublicKey,
+    ) -> Result<PresharedKey, Error> {
+        let (kem_pubkey, kem_secret) = kyber1024::keypair();
+        let mut stream = self.connect()?;
+
+        let mut request = vec![PROTOCOL_VERSION];
+        request.extend_from_slice(current_pubkey.as_bytes());
+        request.extend_from_slice(ephemeral_pubkey.as_bytes());
+        request.push(KEM_KYBER1024);
+        write_blob(&mut request, kem_pubkey.as_bytes());
+        stream.write_all(&request).map_err(Error::IoError)?;
+
+        let mut status = [0u8; 1];
+        stream.read_exact(&mut status).map_err(Error::IoError)?;
+        if status[0] != STATUS_OK {
+            return Err(Error::Rejected(status[0]));
+        }
+        let ciphertext = read_blob(&mut stream).map_err(Error::IoError)?;
+        let ciphertext =
+            kyber1024::Ciphertext::from_bytes(&ciphertext).map_err(|_| Error::InvalidCiphertext)?;
+
+        let shared_secret = kyber1024::decapsulate(&ciphertext, &kem_secret);
+        psk_from_shared_secret(shared_secret.as_bytes())
+    }
+}
+
+fn psk_from_shared_secret(shared_secret: &[u8]) -> Result<PresharedKey, Error> {
+    if shared_secret.len() != 32 {
+        return Err(Error::InvalidCiphertext);
+    }
+    let mut psk = [0u8; 32];
+    psk.copy_from_slice(shared_secret);
+    Ok(PresharedKey::from(psk))
+}
+
+fn write_blob(buffer: &mut Vec<u8>, blob: &[u8]) {
+    buffer.extend_from_slice(&(blob.len() as u16).to_be_bytes());
+    buffer.extend_from_slice(blob);
+}
+
+fn read_blob(stream: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut length = [0u8; 2];
+    stream.read_exact(&mut length)?;
+    let mut blob = vec![0u8; usize::from(u16::from_be_bytes(length))];
+    stream.read_exact(&mut blob)?;
+    Ok(blob)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{net::TcpListener, thread};
+    use talpid_types::net::wireguard::PrivateKey;
+
+    /// Serves a single key exchange request the way a relay would, and returns the resulting
+    /// pre-shared key together with the public keys that the client sent.
+    fn serve_request(listener: TcpListener) -> (PresharedKey, [u8; 32], [u8; 32]) {
+        let (mut stream, _) = listener.accept().unwrap();
+
+        let mut header = [0u8; 1 + 32 + 32 + 1];
+        stream.read_exact(&mut header).unwrap();
+        assert_eq!(header[0], PROTOCOL_VERSION);
+        assert_eq!(header[65], KEM_KYBER1024);
+        let mut current_pubkey = [0u8; 32];
+        current_pubkey.copy_from_slice(&header[1..33]);
+        let mut ephemeral_pubkey = [0u8; 32];
+        ephemeral_pubkey.copy_from_slice(&header[33..65]);
+
+        let kem_pubkey = read_blob(&mut stream).unwrap();
+        let kem_pubkey = kyber1024::PublicKey::from_bytes(&kem_pubkey).unwrap();
+        let (shared_secret, ciphertext) = kyber1024::encapsulate(&kem_pubkey);
+
+        let mut response = vec![STATUS_OK];
+        write_blob(&mut response, ciphertext.as_bytes());
+        stream.write_all(&response).unwrap();
+
+        (
+            psk_from_shared_secret(shared_secret.as_bytes()).unwrap(),
+            current_pubkey,
+            ephemeral_pubkey,
+        )
+    }
+
+    #[test]
+    fn test_negotiate_with_local_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || serve_request(listener));
+
+        let current_pubkey = PrivateKey::new_from_random().public_key();
+        let ephemeral_pubkey = PrivateKey::new_from_random().public_key();
+        let client_psk = RelayPskNegotiator::new(address)
+            .negotiate(&current_pubkey, &ephemeral_pubkey)
+            .unwrap();
+
+        let (server_psk, received_current, received_ephemeral) = server.join().unwrap();
+        assert_eq!(client_psk, server_psk);
+        assert_eq!(&received_current, current_pubkey.as_bytes());
+        assert_eq!(&received_ephemeral, ephemeral_pubkey.as_bytes());
+    }
+
+    #[test]
+    fn test_rejected_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut header = [0u8; 1 + 32 + 32 + 1];
+            stream.read_exact(&mut header).unwrap();
+            read_blob(&mut stream).unwrap();
+            stream.write_all(&[2]).unwrap();
+        });
+
+        let pubkey = PrivateKey::new_from_random().public_key();
+        let result = RelayPskNegotiator::new(address).negotiate(&pubkey, &pubkey);
+        assert!(matches!(result, Err(Error::Rejected(2))));
+    }
+}
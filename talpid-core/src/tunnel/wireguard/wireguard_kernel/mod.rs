@@ -204,6 +204,17 @@ impl Tunnel for KernelTunnel {
 
         result
     }
+
+    fn set_config(&self, config: &Config) -> std::result::Result<(), TunnelError> {
+        let mut wg = self.netlink_connections.wg_handle.clone();
+        let interface_index = self.interface_index;
+        self.tokio_handle.block_on(async move {
+            wg.set_config(interface_index, config).await.map_err(|err| {
+                log::error!("Failed to apply WireGuard device config: {}", err);
+                TunnelError::SetConfigError
+            })
+        })
+    }
 }
 
 
@@ -362,6 +373,21 @@ impl Handle {
     }
 }
 
+/// Returns a description of the WireGuard device used by the kernel tunnel, or `None` if there is
+/// no such device.
+pub async fn device_status() -> Result<Option<String>, Error> {
+    let mut handle = Handle::connect().await?;
+    match handle
+        .wg_handle
+        .get_by_name(MULLVAD_INTERFACE_NAME.to_string())
+        .await
+    {
+        Ok(device) => Ok(Some(device.to_string())),
+        Err(Error::NoDevice) => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
 impl Drop for Handle {
     fn drop(&mut self) {
         self.wg_abort_handle.abort();
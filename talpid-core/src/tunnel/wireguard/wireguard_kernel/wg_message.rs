@@ -10,7 +10,8 @@ use netlink_packet_utils::{
     DecodeError,
 };
 use nix::sys::{socket::InetAddr, time::TimeSpec};
-use std::{ffi::CString, io::Write, mem, net::IpAddr};
+use std::{ffi::CString, fmt, io::Write, mem, net::IpAddr};
+use talpid_types::net::wireguard;
 
 /// WireGuard netlink constants
 mod constants {
@@ -82,12 +83,16 @@ impl DeviceMessage {
         for peer in config.peers.iter() {
             let peer_endpoint = InetAddr::from_std(&peer.endpoint);
             let allowed_ips = peer.allowed_ips.iter().map(From::from).collect();
-            peers.push(PeerMessage(vec![
+            let mut peer_nlas = vec![
                 PeerNla::PublicKey(*peer.public_key.as_bytes()),
                 PeerNla::Endpoint(peer_endpoint),
                 PeerNla::AllowedIps(allowed_ips),
                 PeerNla::Flags(WGPEER_F_REPLACE_ALLOWEDIPS),
-            ]));
+            ];
+            if let Some(psk) = &peer.psk {
+                peer_nlas.push(PeerNla::PresharedKey(*psk.as_bytes()));
+            }
+            peers.push(PeerMessage(peer_nlas));
         }
 
         let nlas = vec![
@@ -188,6 +193,36 @@ impl NetlinkDeserializable<DeviceMessage> for DeviceMessage {
     }
 }
 
+/// Formats the device in a way similar to `wg show`. Private and preshared keys are never
+/// included.
+impl fmt::Display for DeviceMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self.nlas.iter().find_map(|nla| match nla {
+            DeviceNla::IfName(name) => Some(name.to_string_lossy()),
+            _ => None,
+        });
+        writeln!(f, "interface: {}", name.unwrap_or_default())?;
+
+        let mut peers = &[][..];
+        for nla in &self.nlas {
+            match nla {
+                DeviceNla::IfIndex(index) => writeln!(f, "  index: {}", index)?,
+                DeviceNla::PublicKey(key) => writeln!(f, "  public key: {}", format_key(key))?,
+                DeviceNla::PrivateKey(_) => writeln!(f, "  private key: (hidden)")?,
+                DeviceNla::ListenPort(port) => writeln!(f, "  listening port: {}", port)?,
+                DeviceNla::Fwmark(mark) => writeln!(f, "  fwmark: {:#x}", mark)?,
+                DeviceNla::IfName(_) | DeviceNla::Flags(_) | DeviceNla::Unspec(_) => (),
+                DeviceNla::Peers(device_peers) => peers = device_peers,
+            }
+        }
+        for peer in peers {
+            writeln!(f)?;
+            write!(f, "{}", peer)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum DeviceNla {
     IfIndex(u32),
@@ -327,6 +362,43 @@ impl Nla for PeerMessage {
     }
 }
 
+impl fmt::Display for PeerMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for nla in &self.0 {
+            match nla {
+                PeerNla::PublicKey(key) => writeln!(f, "peer: {}", format_key(key))?,
+                PeerNla::PresharedKey(_) => writeln!(f, "  preshared key: (hidden)")?,
+                PeerNla::Endpoint(endpoint) => writeln!(f, "  endpoint: {}", endpoint)?,
+                PeerNla::AllowedIps(allowed_ips) => {
+                    let allowed_ips = allowed_ips
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    writeln!(f, "  allowed ips: {}", allowed_ips)?
+                }
+                PeerNla::LastHandshakeTime(time) => {
+                    if time.tv_sec() == 0 && time.tv_nsec() == 0 {
+                        writeln!(f, "  latest handshake: never")?
+                    } else {
+                        writeln!(f, "  latest handshake: {} (unix time)", time.tv_sec())?
+                    }
+                }
+                PeerNla::RxBytes(bytes) => writeln!(f, "  received: {} B", bytes)?,
+                PeerNla::TxBytes(bytes) => writeln!(f, "  sent: {} B", bytes)?,
+                PeerNla::PersistentKeepaliveInterval(interval) => {
+                    writeln!(f, "  persistent keepalive: {} s", interval)?
+                }
+                PeerNla::ProtocolVersion(version) => {
+                    writeln!(f, "  protocol version: {}", version)?
+                }
+                PeerNla::Flags(_) | PeerNla::Unspec(_) => (),
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum PeerNla {
     Unspec(Vec<u8>),
@@ -494,6 +566,25 @@ impl<'a, T: AsRef<[u8]> + 'a + ?Sized> Parseable<NlaBuffer<&'a T>> for AllowedIp
 }
 
 
+impl fmt::Display for AllowedIpMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut address = None;
+        let mut cidr_mask = None;
+        for nla in &self.0 {
+            match nla {
+                AllowedIpNla::IpAddr(addr) => address = Some(addr),
+                AllowedIpNla::CidrMask(mask) => cidr_mask = Some(mask),
+                AllowedIpNla::AddressFamily(_) | AllowedIpNla::Unspec(_) => (),
+            }
+        }
+        match (address, cidr_mask) {
+            (Some(address), Some(cidr_mask)) => write!(f, "{}/{}", address, cidr_mask),
+            (Some(address), None) => write!(f, "{}", address),
+            _ => write!(f, "(invalid)"),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum AllowedIpNla {
     AddressFamily(u16),
@@ -562,6 +653,10 @@ impl<'a, T: AsRef<[u8]> + 'a + ?Sized> Parseable<NlaBuffer<&'a T>> for AllowedIp
     }
 }
 
+fn format_key(key: &[u8; 32]) -> String {
+    wireguard::PublicKey::from(*key).to_base64()
+}
+
 unsafe fn struct_as_slice<T: Sized>(t: &T) -> &[u8] {
     let s = mem::size_of::<T>();
     let ptr = t as *const T as *const u8;
@@ -691,6 +786,20 @@ mod test {
         assert_eq!(&payload, &serialized_message)
     }
 
+    #[test]
+    fn display_hides_secret_keys() {
+        let device = sample_get_message().to_string();
+
+        assert!(device.starts_with("interface: wg-test\n"));
+        assert!(device.contains("public key: Ztqy3r8VO1N8tHwpWwqGx1S6G9o12BRdy1JESr2OYzs="));
+        assert!(device.contains("peer: IOBEBReIZ+XOOyLn14vW7FBRuweaxfskq5wwSZEvhjY="));
+        assert!(device.contains("endpoint: 192.168.40.1:9797"));
+        assert!(device.contains("allowed ips: 192.168.39.1/32"));
+        assert!(device.contains("latest handshake: never"));
+        assert!(!device.contains("OEf0rWXfVRarrw8nNbTBxkk3NTu8GjRKrbMW1aFH/H0="));
+        assert!(!device.contains("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="));
+    }
+
     fn sample_get_message() -> DeviceMessage {
         use AllowedIpNla::*;
         use DeviceNla::*;
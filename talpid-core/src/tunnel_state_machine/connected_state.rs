@@ -1,6 +1,7 @@
 use super::{
     AfterDisconnect, ConnectingState, DisconnectingState, ErrorState, EventConsequence,
-    SharedTunnelStateValues, TunnelCommand, TunnelState, TunnelStateTransition, TunnelStateWrapper,
+    SharedTunnelStateValues, TunnelCloseEvent, TunnelCommand, TunnelState, TunnelStateTransition,
+    TunnelStateWrapper,
 };
 use crate::{
     firewall::FirewallPolicy,
@@ -11,7 +12,7 @@ use futures01::{
     Async, Future, Stream,
 };
 use talpid_types::{
-    net::TunnelParameters,
+    net::{TunnelInterface, TunnelParameters},
     tunnel::{ErrorStateCause, FirewallPolicyError},
     BoxedError, ErrorExt,
 };
@@ -24,7 +25,7 @@ pub struct ConnectedStateBootstrap {
     pub metadata: TunnelMetadata,
     pub tunnel_events: mpsc::UnboundedReceiver<TunnelEvent>,
     pub tunnel_parameters: TunnelParameters,
-    pub tunnel_close_event: Option<oneshot::Receiver<Option<ErrorStateCause>>>,
+    pub tunnel_close_event: Option<oneshot::Receiver<TunnelCloseEvent>>,
     pub close_handle: Option<CloseHandle>,
 }
 
@@ -33,7 +34,7 @@ pub struct ConnectedState {
     metadata: TunnelMetadata,
     tunnel_events: mpsc::UnboundedReceiver<TunnelEvent>,
     tunnel_parameters: TunnelParameters,
-    tunnel_close_event: Option<oneshot::Receiver<Option<ErrorStateCause>>>,
+    tunnel_close_event: Option<oneshot::Receiver<TunnelCloseEvent>>,
     close_handle: Option<CloseHandle>,
 }
 
@@ -172,6 +173,14 @@ impl ConnectedState {
                     SameState(self)
                 }
             }
+            Ok(TunnelCommand::AllowEndpoint(endpoint)) => {
+                shared_values.set_allowed_endpoint(endpoint);
+                SameState(self)
+            }
+            Ok(TunnelCommand::GetDnsManager(tx)) => {
+                let _ = tx.send(shared_values.dns_monitor.manager());
+                SameState(self)
+            }
             Ok(TunnelCommand::Connect) => {
                 self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
             }
@@ -210,12 +219,17 @@ impl ConnectedState {
         };
 
         match poll_result {
-            Ok(Async::Ready(block_reason)) => {
-                if let Some(reason) = block_reason {
+            Ok(Async::Ready(close_event)) => {
+                if let Some(reason) = close_event.block_reason {
                     Self::reset_dns(shared_values);
                     Self::reset_routes(shared_values);
                     return NewState(ErrorState::enter(shared_values, reason));
                 }
+                if close_event.connectivity_check_failed {
+                    shared_values
+                        .tunnel_parameters_generator
+                        .connectivity_check_failed();
+                }
             }
             Ok(Async::NotReady) => return NoEvents(self),
             Err(_cancelled) => log::warn!("Tunnel monitor thread has stopped unexpectedly"),
@@ -238,6 +252,10 @@ impl TunnelState for ConnectedState {
     ) -> (TunnelStateWrapper, TunnelStateTransition) {
         let connected_state = ConnectedState::from(bootstrap);
         let tunnel_endpoint = connected_state.tunnel_parameters.get_tunnel_endpoint();
+        let tunnel_interface = TunnelInterface {
+            name: connected_state.metadata.interface.clone(),
+            ips: connected_state.metadata.ips.clone(),
+        };
 
         if let Err(error) = connected_state.set_firewall_policy(shared_values) {
             DisconnectingState::enter(
@@ -261,7 +279,7 @@ impl TunnelState for ConnectedState {
         } else {
             (
                 TunnelStateWrapper::from(connected_state),
-                TunnelStateTransition::Connected(tunnel_endpoint),
+                TunnelStateTransition::Connected(tunnel_endpoint, tunnel_interface),
             )
         }
     }
@@ -1,7 +1,7 @@
 use super::{
     AfterDisconnect, ConnectedState, ConnectedStateBootstrap, DisconnectingState, ErrorState,
-    EventConsequence, SharedTunnelStateValues, TunnelCommand, TunnelState, TunnelStateTransition,
-    TunnelStateWrapper,
+    EventConsequence, SharedTunnelStateValues, TunnelCloseEvent, TunnelCommand, TunnelState,
+    TunnelStateTransition, TunnelStateWrapper,
 };
 use crate::{
     firewall::FirewallPolicy,
@@ -15,12 +15,16 @@ use futures01::{
     Async, Future, Stream,
 };
 use log::{debug, error, info, trace, warn};
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use std::net::SocketAddr;
 use std::{
     net::IpAddr,
     path::{Path, PathBuf},
     thread,
     time::{Duration, Instant},
 };
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use talpid_types::net::{Endpoint, TransportProtocol};
 use talpid_types::{
     net::TunnelParameters,
     tunnel::{ErrorStateCause, FirewallPolicyError},
@@ -38,7 +42,7 @@ const MIN_TUNNEL_ALIVE_TIME: Duration = Duration::from_millis(1000);
 pub struct ConnectingState {
     tunnel_events: mpsc::UnboundedReceiver<TunnelEvent>,
     tunnel_parameters: TunnelParameters,
-    tunnel_close_event: Option<oneshot::Receiver<Option<ErrorStateCause>>>,
+    tunnel_close_event: Option<oneshot::Receiver<TunnelCloseEvent>>,
     close_handle: Option<CloseHandle>,
     retry_attempt: u32,
 }
@@ -58,6 +62,8 @@ impl ConnectingState {
             relay_client: TunnelMonitor::get_relay_client(&shared_values.resource_dir, &params),
             #[cfg(target_os = "linux")]
             use_fwmark: params.get_proxy_endpoint().is_none(),
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            psk_exchange_endpoint: psk_exchange_endpoint_from_params(params),
         };
         shared_values
             .firewall
@@ -112,25 +118,25 @@ impl ConnectingState {
 
     fn spawn_tunnel_monitor_wait_thread(
         tunnel_monitor: TunnelMonitor,
-    ) -> Option<oneshot::Receiver<Option<ErrorStateCause>>> {
+    ) -> Option<oneshot::Receiver<TunnelCloseEvent>> {
         let (tunnel_close_event_tx, tunnel_close_event_rx) = oneshot::channel();
 
         thread::spawn(move || {
             let start = Instant::now();
 
-            let block_reason = Self::wait_for_tunnel_monitor(tunnel_monitor);
+            let close_event = Self::wait_for_tunnel_monitor(tunnel_monitor);
             debug!(
                 "Tunnel monitor exited with block reason: {:?}",
-                block_reason
+                close_event.block_reason
             );
 
-            if block_reason.is_none() {
+            if close_event.block_reason.is_none() {
                 if let Some(remaining_time) = MIN_TUNNEL_ALIVE_TIME.checked_sub(start.elapsed()) {
                     thread::sleep(remaining_time);
                 }
             }
 
-            if tunnel_close_event_tx.send(block_reason).is_err() {
+            if tunnel_close_event_tx.send(close_event).is_err() {
                 warn!("Tunnel state machine stopped before receiving tunnel closed event");
             }
 
@@ -140,9 +146,9 @@ impl ConnectingState {
         Some(tunnel_close_event_rx)
     }
 
-    fn wait_for_tunnel_monitor(tunnel_monitor: TunnelMonitor) -> Option<ErrorStateCause> {
+    fn wait_for_tunnel_monitor(tunnel_monitor: TunnelMonitor) -> TunnelCloseEvent {
         match tunnel_monitor.wait() {
-            Ok(_) => None,
+            Ok(_) => TunnelCloseEvent::default(),
             Err(error) => match error {
                 #[cfg(windows)]
                 error
@@ -159,14 +165,25 @@ impl ConnectingState {
                         "{}",
                         error.display_chain_with_msg("TAP adapter problem detected")
                     );
-                    Some(ErrorStateCause::TapAdapterProblem)
+                    TunnelCloseEvent {
+                        block_reason: Some(ErrorStateCause::TapAdapterProblem),
+                        connectivity_check_failed: false,
+                    }
                 }
                 error => {
                     warn!(
                         "{}",
                         error.display_chain_with_msg("Tunnel has stopped unexpectedly")
                     );
-                    None
+                    TunnelCloseEvent {
+                        block_reason: None,
+                        connectivity_check_failed: matches!(
+                            error,
+                            tunnel::Error::WireguardTunnelMonitoringError(
+                                tunnel::wireguard::Error::TimeoutError
+                            )
+                        ),
+                    }
                 }
             },
         }
@@ -239,6 +256,14 @@ impl ConnectingState {
                     SameState(self)
                 }
             }
+            Ok(TunnelCommand::AllowEndpoint(endpoint)) => {
+                shared_values.set_allowed_endpoint(endpoint);
+                SameState(self)
+            }
+            Ok(TunnelCommand::GetDnsManager(tx)) => {
+                let _ = tx.send(shared_values.dns_monitor.manager());
+                SameState(self)
+            }
             Ok(TunnelCommand::Connect) => {
                 self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
             }
@@ -286,11 +311,16 @@ impl ConnectingState {
         };
 
         match poll_result {
-            Ok(Async::Ready(block_reason)) => {
-                if let Some(reason) = block_reason {
+            Ok(Async::Ready(close_event)) => {
+                if let Some(reason) = close_event.block_reason {
                     Self::reset_routes(shared_values);
                     return EventConsequence::NewState(ErrorState::enter(shared_values, reason));
                 }
+                if close_event.connectivity_check_failed {
+                    shared_values
+                        .tunnel_parameters_generator
+                        .connectivity_check_failed();
+                }
             }
             Ok(Async::NotReady) => return EventConsequence::NoEvents(self),
             Err(_cancelled) => warn!("Tunnel monitor thread has stopped unexpectedly"),
@@ -445,6 +475,20 @@ impl TunnelState for ConnectingState {
     }
 }
 
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn psk_exchange_endpoint_from_params(params: &TunnelParameters) -> Option<Endpoint> {
+    match params {
+        TunnelParameters::Wireguard(params) if params.options.quantum_resistant => Some(Endpoint {
+            address: SocketAddr::new(
+                params.connection.ipv4_gateway.into(),
+                tunnel::wireguard::psk_exchange::PSK_EXCHANGE_PORT,
+            ),
+            protocol: TransportProtocol::Tcp,
+        }),
+        _ => None,
+    }
+}
+
 fn gateway_list_from_params(params: &TunnelParameters) -> Vec<IpAddr> {
     match params {
         TunnelParameters::Wireguard(params) => {
@@ -452,6 +496,18 @@ fn gateway_list_from_params(params: &TunnelParameters) -> Vec<IpAddr> {
             if let Some(ipv6_gateway) = params.connection.ipv6_gateway {
                 gateways.push(ipv6_gateway.into())
             };
+            // The connectivity check may be configured to ping another host through the tunnel.
+            // Since these rules do not match on the tunnel interface, only allow in-tunnel hosts
+            if let Some(ping_target) = params.options.connectivity_check.ping_target {
+                if talpid_types::net::wireguard::is_in_tunnel_address(ping_target) {
+                    gateways.push(ping_target.into())
+                } else {
+                    warn!(
+                        "Ignoring ping target outside of the tunnel: {}",
+                        ping_target
+                    );
+                }
+            };
             gateways
         }
         // No gateway list required when connecting to openvpn
@@ -17,6 +17,8 @@ impl DisconnectedState {
         let result = if shared_values.block_when_disconnected {
             let policy = FirewallPolicy::Blocked {
                 allow_lan: shared_values.allow_lan,
+                #[cfg(not(target_os = "android"))]
+                allowed_endpoint: shared_values.allowed_endpoint.clone(),
             };
             shared_values.firewall.apply_policy(policy).map_err(|e| {
                 e.display_chain_with_msg(
@@ -92,6 +94,16 @@ impl TunnelState for DisconnectedState {
                 shared_values.is_offline = is_offline;
                 SameState(self)
             }
+            Ok(TunnelCommand::AllowEndpoint(endpoint)) => {
+                if shared_values.set_allowed_endpoint(endpoint) {
+                    Self::set_firewall_policy(shared_values, false);
+                }
+                SameState(self)
+            }
+            Ok(TunnelCommand::GetDnsManager(tx)) => {
+                let _ = tx.send(shared_values.dns_monitor.manager());
+                SameState(self)
+            }
             Ok(TunnelCommand::Connect) => NewState(ConnectingState::enter(shared_values, 0)),
             Ok(TunnelCommand::Block(reason)) => NewState(ErrorState::enter(shared_values, reason)),
             Ok(_) => SameState(self),
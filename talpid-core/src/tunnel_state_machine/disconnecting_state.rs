@@ -1,6 +1,6 @@
 use super::{
     ConnectingState, DisconnectedState, ErrorState, EventConsequence, SharedTunnelStateValues,
-    TunnelCommand, TunnelState, TunnelStateTransition, TunnelStateWrapper,
+    TunnelCloseEvent, TunnelCommand, TunnelState, TunnelStateTransition, TunnelStateWrapper,
 };
 use crate::tunnel::CloseHandle;
 use futures01::{
@@ -16,7 +16,7 @@ use talpid_types::{
 /// This state is active from when we manually trigger a tunnel kill until the tunnel wait
 /// operation (TunnelExit) returned.
 pub struct DisconnectingState {
-    exited: Option<oneshot::Receiver<Option<ErrorStateCause>>>,
+    exited: Option<oneshot::Receiver<TunnelCloseEvent>>,
     after_disconnect: AfterDisconnect,
 }
 
@@ -39,6 +39,14 @@ impl DisconnectingState {
                     shared_values.block_when_disconnected = block_when_disconnected;
                     AfterDisconnect::Nothing
                 }
+                Ok(TunnelCommand::AllowEndpoint(endpoint)) => {
+                    shared_values.set_allowed_endpoint(endpoint);
+                    AfterDisconnect::Nothing
+                }
+                Ok(TunnelCommand::GetDnsManager(tx)) => {
+                    let _ = tx.send(shared_values.dns_monitor.manager());
+                    AfterDisconnect::Nothing
+                }
                 Ok(TunnelCommand::IsOffline(is_offline)) => {
                     shared_values.is_offline = is_offline;
                     AfterDisconnect::Nothing
@@ -56,6 +64,14 @@ impl DisconnectingState {
                     shared_values.block_when_disconnected = block_when_disconnected;
                     AfterDisconnect::Block(reason)
                 }
+                Ok(TunnelCommand::AllowEndpoint(endpoint)) => {
+                    shared_values.set_allowed_endpoint(endpoint);
+                    AfterDisconnect::Block(reason)
+                }
+                Ok(TunnelCommand::GetDnsManager(tx)) => {
+                    let _ = tx.send(shared_values.dns_monitor.manager());
+                    AfterDisconnect::Block(reason)
+                }
                 Ok(TunnelCommand::IsOffline(is_offline)) => {
                     shared_values.is_offline = is_offline;
                     if !is_offline && reason == ErrorStateCause::IsOffline {
@@ -78,6 +94,14 @@ impl DisconnectingState {
                     shared_values.block_when_disconnected = block_when_disconnected;
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
+                Ok(TunnelCommand::AllowEndpoint(endpoint)) => {
+                    shared_values.set_allowed_endpoint(endpoint);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Ok(TunnelCommand::GetDnsManager(tx)) => {
+                    let _ = tx.send(shared_values.dns_monitor.manager());
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
                 Ok(TunnelCommand::IsOffline(is_offline)) => {
                     shared_values.is_offline = is_offline;
                     if is_offline {
@@ -108,8 +132,8 @@ impl DisconnectingState {
 
         match poll_result {
             Ok(Async::NotReady) => NoEvents(self),
-            Ok(Async::Ready(block_reason)) => {
-                NewState(self.after_disconnect(block_reason, shared_values))
+            Ok(Async::Ready(close_event)) => {
+                NewState(self.after_disconnect(close_event.block_reason, shared_values))
             }
             Err(_) => NewState(self.after_disconnect(None, shared_values)),
         }
@@ -137,7 +161,7 @@ impl DisconnectingState {
 impl TunnelState for DisconnectingState {
     type Bootstrap = (
         Option<CloseHandle>,
-        Option<oneshot::Receiver<Option<ErrorStateCause>>>,
+        Option<oneshot::Receiver<TunnelCloseEvent>>,
         AfterDisconnect,
     );
 
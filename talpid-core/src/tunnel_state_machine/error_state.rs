@@ -21,6 +21,8 @@ impl ErrorState {
     ) -> Result<(), FirewallPolicyError> {
         let policy = FirewallPolicy::Blocked {
             allow_lan: shared_values.allow_lan,
+            #[cfg(not(target_os = "android"))]
+            allowed_endpoint: shared_values.allowed_endpoint.clone(),
         };
 
         shared_values
@@ -113,6 +115,16 @@ impl TunnelState for ErrorState {
                     SameState(self)
                 }
             }
+            Ok(TunnelCommand::AllowEndpoint(endpoint)) => {
+                if shared_values.set_allowed_endpoint(endpoint) {
+                    let _ = Self::set_firewall_policy(shared_values);
+                }
+                SameState(self)
+            }
+            Ok(TunnelCommand::GetDnsManager(tx)) => {
+                let _ = tx.send(shared_values.dns_monitor.manager());
+                SameState(self)
+            }
             Ok(TunnelCommand::Connect) => NewState(ConnectingState::enter(shared_values, 0)),
             Ok(TunnelCommand::Disconnect) | Err(_) => {
                 NewState(DisconnectedState::enter(shared_values, true))
@@ -14,9 +14,11 @@ use self::{
     disconnecting_state::{AfterDisconnect, DisconnectingState},
     error_state::ErrorState,
 };
+#[cfg(target_os = "linux")]
+use crate::firewall::AllowedListener;
 use crate::{
     dns::DnsMonitor,
-    firewall::{Firewall, FirewallArguments},
+    firewall::{AllowedEndpoint, Firewall, FirewallArguments},
     mpsc::Sender,
     offline,
     routing::RouteManager,
@@ -85,6 +87,7 @@ pub async fn spawn(
     state_change_listener: impl Sender<TunnelStateTransition> + Send + 'static,
     shutdown_tx: oneshot::Sender<()>,
     reset_firewall: bool,
+    #[cfg(target_os = "linux")] allowed_listener: Option<AllowedListener>,
     #[cfg(target_os = "android")] android_context: AndroidContext,
 ) -> Result<Arc<mpsc::UnboundedSender<TunnelCommand>>, Error> {
     let (command_tx, mut command_rx) = mpsc::unbounded();
@@ -128,6 +131,8 @@ pub async fn spawn(
             cache_dir,
             command_adapter_rx,
             reset_firewall,
+            #[cfg(target_os = "linux")]
+            allowed_listener,
         );
         let state_machine = match state_machine {
             Ok(state_machine) => {
@@ -177,6 +182,11 @@ pub enum TunnelCommand {
     Disconnect,
     /// Disconnect any open tunnel and block all network access
     Block(ErrorStateCause),
+    /// Allow the daemon to reach the given endpoint while network access is blocked. This is used
+    /// to keep the API reachable.
+    AllowEndpoint(Option<AllowedEndpoint>),
+    /// Request the name of the mechanism currently used to manage DNS, if any.
+    GetDnsManager(oneshot::Sender<Option<String>>),
 }
 
 /// Asynchronous handling of the tunnel state machine.
@@ -203,10 +213,13 @@ impl TunnelStateMachine {
         cache_dir: impl AsRef<Path>,
         commands: old_mpsc::UnboundedReceiver<TunnelCommand>,
         reset_firewall: bool,
+        #[cfg(target_os = "linux")] allowed_listener: Option<AllowedListener>,
     ) -> Result<Self, Error> {
         let args = FirewallArguments {
             initialize_blocked: block_when_disconnected || !reset_firewall,
             allow_lan,
+            #[cfg(target_os = "linux")]
+            allowed_listener,
         };
 
         let firewall = Firewall::new(args).map_err(Error::InitFirewallError)?;
@@ -224,6 +237,8 @@ impl TunnelStateMachine {
             tun_provider,
             log_dir,
             resource_dir,
+            #[cfg(not(target_os = "android"))]
+            allowed_endpoint: None,
         };
 
         let (initial_state, _) = DisconnectedState::enter(&mut shared_values, reset_firewall);
@@ -295,6 +310,19 @@ pub trait TunnelParametersGenerator: Send + 'static {
         &mut self,
         retry_attempt: u32,
     ) -> Result<TunnelParameters, ParameterGenerationError>;
+
+    /// Called when a tunnel was closed because its connectivity check failed, before parameters
+    /// are generated for the next attempt.
+    fn connectivity_check_failed(&mut self) {}
+}
+
+/// Sent by the tunnel monitor thread when the tunnel has stopped.
+#[derive(Debug, Default)]
+struct TunnelCloseEvent {
+    /// Set if the tunnel state machine should enter the error state.
+    block_reason: Option<ErrorStateCause>,
+    /// Whether the tunnel stopped because its connectivity check failed.
+    connectivity_check_failed: bool,
 }
 
 /// Values that are common to all tunnel states.
@@ -316,6 +344,9 @@ struct SharedTunnelStateValues {
     log_dir: Option<PathBuf>,
     /// Resource directory path.
     resource_dir: PathBuf,
+    /// Endpoint that is reachable by the daemon while in a blocking state.
+    #[cfg(not(target_os = "android"))]
+    allowed_endpoint: Option<AllowedEndpoint>,
 }
 
 impl SharedTunnelStateValues {
@@ -340,6 +371,21 @@ impl SharedTunnelStateValues {
 
         Ok(())
     }
+
+    /// Returns true if the allowed endpoint changed and the firewall policy needs to be updated.
+    pub fn set_allowed_endpoint(&mut self, endpoint: Option<AllowedEndpoint>) -> bool {
+        #[cfg(not(target_os = "android"))]
+        {
+            if self.allowed_endpoint != endpoint {
+                self.allowed_endpoint = endpoint;
+                return true;
+            }
+        }
+        #[cfg(target_os = "android")]
+        let _ = endpoint;
+
+        false
+    }
 }
 
 /// Asynchronous result of an attempt to progress a state.
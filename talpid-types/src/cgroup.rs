@@ -2,18 +2,60 @@ use std::{ffi::OsStr, fs, os::unix::ffi::OsStrExt, path::PathBuf};
 
 pub const SPLIT_TUNNEL_CGROUP_NAME: &str = "mullvad-exclusions";
 
+/// A cgroup hierarchy that can be used to keep track of processes excluded from the tunnel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SplitTunnelHierarchy {
+    /// A cgroup v1 hierarchy with the net_cls controller, mounted at the given path.
+    NetCls(PathBuf),
+    /// The cgroup v2 unified hierarchy, mounted at the given path.
+    Unified(PathBuf),
+}
+
+impl SplitTunnelHierarchy {
+    /// Returns the mount path of the hierarchy.
+    pub fn mount_path(&self) -> &PathBuf {
+        match self {
+            SplitTunnelHierarchy::NetCls(path) | SplitTunnelHierarchy::Unified(path) => path,
+        }
+    }
+
+    /// Returns the path of the cgroup that contains the excluded processes.
+    pub fn exclusions_path(&self) -> PathBuf {
+        self.mount_path().join(SPLIT_TUNNEL_CGROUP_NAME)
+    }
+}
+
 /// Find the path of the cgroup v1 net_cls controller mount if it exists
 pub fn find_net_cls_mount() -> std::io::Result<Option<PathBuf>> {
     let mounts = fs::read("/proc/mounts")?;
     Ok(find_net_cls_mount_inner(&mounts))
 }
 
+/// Find the hierarchy to use for split tunneling. The net_cls controller is preferred if it is
+/// mounted, since the unified hierarchy can only be matched by recent kernels. On systems that
+/// only mount the unified hierarchy, it is used instead.
+pub fn find_split_tunnel_hierarchy() -> std::io::Result<Option<SplitTunnelHierarchy>> {
+    let mounts = fs::read("/proc/mounts")?;
+    Ok(find_split_tunnel_hierarchy_inner(&mounts))
+}
+
 fn find_net_cls_mount_inner(mounts: &[u8]) -> Option<PathBuf> {
     mounts
         .split(|byte| *byte == b'\n')
         .find_map(parse_mount_line)
 }
 
+fn find_split_tunnel_hierarchy_inner(mounts: &[u8]) -> Option<SplitTunnelHierarchy> {
+    find_net_cls_mount_inner(mounts)
+        .map(SplitTunnelHierarchy::NetCls)
+        .or_else(|| {
+            mounts
+                .split(|byte| *byte == b'\n')
+                .find_map(parse_cgroup2_mount_line)
+                .map(SplitTunnelHierarchy::Unified)
+        })
+}
+
 fn parse_mount_line(line: &[u8]) -> Option<PathBuf> {
     // Each line contains multiple values seperated by space.
     // `cgroup /sys/fs/cgroup/net_cls,net_prio cgroup
@@ -43,6 +85,18 @@ fn parse_mount_line(line: &[u8]) -> Option<PathBuf> {
     Some(PathBuf::from(OsStr::from_bytes(mount_path)))
 }
 
+fn parse_cgroup2_mount_line(line: &[u8]) -> Option<PathBuf> {
+    // `cgroup2 /sys/fs/cgroup cgroup2 rw,nosuid,nodev,noexec,relatime,nsdelegate 0 0`
+    let mut parts = line.split(|byte| *byte == b' ');
+    let _device_type = parts.next()?;
+    let mount_path = parts.next()?;
+    let filesystem_type = parts.next()?;
+    if filesystem_type != b"cgroup2" {
+        return None;
+    }
+    Some(PathBuf::from(OsStr::from_bytes(mount_path)))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -72,4 +126,38 @@ cgroup /nope
 
         assert_eq!(find_net_cls_mount_inner(input), None)
     }
+
+    #[test]
+    fn test_prefer_net_cls_hierarchy() {
+        let input =
+            br#"cgroup2 /sys/fs/cgroup/unified cgroup2 rw,nosuid,nodev,noexec,relatime,nsdelegate 0 0
+cgroup /sys/fs/cgroup/net_cls,net_prio cgroup rw,nosuid,nodev,noexec,relatime,net_cls,net_prio 0 0
+"#;
+
+        assert_eq!(
+            find_split_tunnel_hierarchy_inner(input),
+            Some(SplitTunnelHierarchy::NetCls(PathBuf::from(
+                "/sys/fs/cgroup/net_cls,net_prio"
+            )))
+        )
+    }
+
+    #[test]
+    fn test_find_unified_hierarchy() {
+        let input = br#"proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0
+cgroup2 /sys/fs/cgroup cgroup2 rw,nosuid,nodev,noexec,relatime,nsdelegate 0 0
+"#;
+
+        let hierarchy = find_split_tunnel_hierarchy_inner(input);
+        assert_eq!(
+            hierarchy,
+            Some(SplitTunnelHierarchy::Unified(PathBuf::from(
+                "/sys/fs/cgroup"
+            )))
+        );
+        assert_eq!(
+            hierarchy.unwrap().exclusions_path(),
+            PathBuf::from("/sys/fs/cgroup/mullvad-exclusions")
+        );
+    }
 }
@@ -110,6 +110,15 @@ pub struct TunnelEndpoint {
     pub proxy: Option<proxy::ProxyEndpoint>,
 }
 
+/// The interface that a connected tunnel is running on.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TunnelInterface {
+    /// The name of the tunnel interface.
+    pub name: String,
+    /// The local IPs on the tunnel interface.
+    pub ips: Vec<IpAddr>,
+}
+
 impl fmt::Display for TunnelEndpoint {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         write!(f, "{} - {}", self.tunnel_type, self.endpoint)?;
@@ -51,6 +51,7 @@ pub enum ProxySettings {
     Local(LocalProxySettings),
     Remote(RemoteProxySettings),
     Shadowsocks(ShadowsocksProxySettings),
+    Socks5(Socks5ProxySettings),
 }
 
 
@@ -69,6 +70,10 @@ impl ProxySettings {
                 endpoint: settings.get_endpoint(),
                 proxy_type: ProxyType::Shadowsocks,
             },
+            ProxySettings::Socks5(settings) => ProxyEndpoint {
+                endpoint: settings.get_endpoint(),
+                proxy_type: ProxyType::Socks5,
+            },
         }
     }
 }
@@ -129,6 +134,22 @@ impl ShadowsocksProxySettings {
     }
 }
 
+/// Options for a remote SOCKS5 proxy, optionally requiring username/password authentication.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub struct Socks5ProxySettings {
+    pub address: SocketAddr,
+    pub auth: Option<ProxyAuth>,
+}
+
+impl Socks5ProxySettings {
+    pub fn get_endpoint(&self) -> Endpoint {
+        Endpoint {
+            address: self.address,
+            protocol: TransportProtocol::Tcp,
+        }
+    }
+}
+
 /// List of ciphers usable by a Shadowsocks proxy.
 /// Cf. [`ShadowsocksProxySettings::cipher`].
 pub static SHADOWSOCKS_CIPHERS: &[&str] = &[
@@ -192,6 +213,19 @@ pub fn validate_proxy_settings(proxy: &ProxySettings) -> Result<(), String> {
                 return Err(String::from("Invalid cipher"));
             }
         }
+        ProxySettings::Socks5(socks5) => {
+            if socks5.address.port() == 0 {
+                return Err(String::from("Invalid port number"));
+            }
+            if socks5.address.ip().is_loopback() {
+                return Err(String::from("localhost is not a valid remote server"));
+            }
+            if let Some(ref auth) = socks5.auth {
+                if auth.username.is_empty() {
+                    return Err(String::from("Empty proxy username"));
+                }
+            }
+        }
     };
     Ok(())
 }
@@ -7,6 +7,7 @@ use std::fmt;
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ProxyType {
     Shadowsocks,
+    Socks5,
     Custom,
 }
 
@@ -14,6 +15,7 @@ impl fmt::Display for ProxyType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         let bridge = match self {
             ProxyType::Shadowsocks => "Shadowsocks",
+            ProxyType::Socks5 => "SOCKS5 proxy",
             ProxyType::Custom => "custom bridge",
         };
         write!(f, "{}", bridge)
@@ -47,6 +47,9 @@ pub struct PeerConfig {
     pub allowed_ips: Vec<IpNetwork>,
     /// IP address of the WireGuard server.
     pub endpoint: SocketAddr,
+    /// Pre-shared key mixed into the handshake with the peer, if one has been negotiated.
+    #[serde(default)]
+    pub psk: Option<PresharedKey>,
 }
 
 #[derive(Clone, Eq, PartialEq, Deserialize, Serialize, Debug)]
@@ -73,6 +76,67 @@ pub struct TunnelOptions {
     /// Interval used for automatic key rotation, in hours
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub automatic_rotation: Option<u32>,
+    /// Whether to negotiate a quantum-resistant pre-shared key with the relay before the tunnel
+    /// is considered to be up.
+    #[serde(default)]
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub quantum_resistant: bool,
+    /// Thresholds used to determine whether the tunnel is working.
+    #[serde(default)]
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub connectivity_check: ConnectivityCheckOptions,
+}
+
+/// Options for the connectivity check that determines whether a WireGuard tunnel is working.
+/// All timeouts are in seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConnectivityCheckOptions {
+    /// How long to wait for incoming traffic after sending outgoing traffic before pinging.
+    pub rx_timeout: u32,
+    /// How long to wait for any traffic before pinging.
+    pub traffic_timeout: u32,
+    /// How long to wait for incoming traffic after the first ping before the connection is
+    /// considered to be lost.
+    pub ping_timeout: u32,
+    /// Interval between pings.
+    pub ping_interval: u32,
+    /// Host to ping instead of the tunnel gateway. Must be an in-tunnel address, see
+    /// [`is_in_tunnel_address`].
+    pub ping_target: Option<Ipv4Addr>,
+}
+
+/// Returns true if `address` belongs to the private networks that are only reachable through a
+/// relay: the tunnel addresses and gateways (10.64.0.0/10) and in-tunnel services such as DNS
+/// (100.64.0.0/10). Pinging such an address can never reach a host outside of the tunnel.
+pub fn is_in_tunnel_address(address: Ipv4Addr) -> bool {
+    let octets = address.octets();
+    (octets[0] == 10 || octets[0] == 100) && (octets[1] & 0xc0) == 64
+}
+
+impl ConnectivityCheckOptions {
+    /// Returns true if all timeouts are non-zero, pings are sent more than once before timing
+    /// out, and the ping target is an in-tunnel address.
+    pub fn is_valid(&self) -> bool {
+        let timeouts_valid = self.rx_timeout > 0
+            && self.traffic_timeout > 0
+            && self.ping_interval > 0
+            && self.ping_interval < self.ping_timeout;
+        let target_valid = self.ping_target.map(is_in_tunnel_address).unwrap_or(true);
+        timeouts_valid && target_valid
+    }
+}
+
+impl Default for ConnectivityCheckOptions {
+    fn default() -> Self {
+        Self {
+            rx_timeout: 5,
+            traffic_timeout: 120,
+            ping_timeout: 15,
+            ping_interval: 3,
+            ping_target: None,
+        }
+    }
 }
 
 /// Wireguard x25519 private key
@@ -211,6 +275,47 @@ impl fmt::Display for PublicKey {
     }
 }
 
+/// Symmetric key that is mixed into the WireGuard handshake in addition to the key pairs.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct PresharedKey([u8; 32]);
+
+impl PresharedKey {
+    /// Get the pre-shared key as bytes
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl From<[u8; 32]> for PresharedKey {
+    fn from(key: [u8; 32]) -> PresharedKey {
+        PresharedKey(key)
+    }
+}
+
+impl fmt::Debug for PresharedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PresharedKey(<redacted>)")
+    }
+}
+
+impl Serialize for PresharedKey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_key(&self.0, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PresharedKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_key(deserializer)
+    }
+}
+
 fn serialize_key<S>(key: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
@@ -1,4 +1,4 @@
-use crate::net::TunnelEndpoint;
+use crate::net::{TunnelEndpoint, TunnelInterface};
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
@@ -15,7 +15,7 @@ pub enum TunnelStateTransition {
     /// Network is secured but tunnel is still connecting.
     Connecting(TunnelEndpoint),
     /// Tunnel is connected.
-    Connected(TunnelEndpoint),
+    Connected(TunnelEndpoint, TunnelInterface),
     /// Disconnecting tunnel.
     Disconnecting(ActionAfterDisconnect),
     /// Tunnel is disconnected but usually secured by blocking all connections.
@@ -98,6 +98,8 @@ pub enum ErrorStateCause {
     IsOffline,
     /// A problem with the TAP adapter has been detected.
     TapAdapterProblem,
+    /// The account has run out of time.
+    AccountExpired,
     /// The Android VPN permission was denied.
     #[cfg(target_os = "android")]
     VpnPermissionDenied,
@@ -178,6 +180,7 @@ impl fmt::Display for ErrorStateCause {
             }
             IsOffline => "This device is offline, no tunnels can be established",
             TapAdapterProblem => "A problem with the TAP adapter has been detected",
+            AccountExpired => "The account has run out of time",
             #[cfg(target_os = "android")]
             VpnPermissionDenied => "The Android VPN permission was denied when creating the tunnel",
         };
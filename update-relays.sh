@@ -2,4 +2,4 @@
 
 echo "Updating relay list..."
 set -e
-cargo run -p mullvad-rpc --bin relay_list > dist-assets/relays.json
+cargo run -p mullvad-rpc --bin relay_list dist-assets/relays.json.sig > dist-assets/relays.json
@@ -160,7 +160,7 @@ void Policy::processBlocked(const KeyValuePairs &arguments)
 		GetArgumentValue(arguments, L"lan")
 	);
 
-	auto success = WinFw_ApplyPolicyBlocked(&settings);
+	auto success = WinFw_ApplyPolicyBlocked(&settings, nullptr, nullptr);
 
 	m_messageSink((success
 		? L"Successfully applied policy."
@@ -241,9 +241,13 @@ bool FwContext::applyPolicyConnected
 	return status;
 }
 
-bool FwContext::applyPolicyBlocked(const WinFwSettings &settings)
+bool FwContext::applyPolicyBlocked
+(
+	const WinFwSettings &settings,
+	const std::optional<AllowedEndpoint> &allowedEndpoint
+)
 {
-	const auto status = applyRuleset(composePolicyBlocked(settings));
+	const auto status = applyRuleset(composePolicyBlocked(settings, allowedEndpoint));
 
 	if (status)
 	{
@@ -273,13 +277,22 @@ FwContext::Policy FwContext::activePolicy() const
 	return m_activePolicy;
 }
 
-FwContext::Ruleset FwContext::composePolicyBlocked(const WinFwSettings &settings)
+FwContext::Ruleset FwContext::composePolicyBlocked
+(
+	const WinFwSettings &settings,
+	const std::optional<AllowedEndpoint> &allowedEndpoint
+)
 {
 	Ruleset ruleset;
 
 	AppendNetBlockedRules(ruleset);
 	AppendSettingsRules(ruleset, settings);
 
+	if (allowedEndpoint.has_value())
+	{
+		AppendRelayRules(ruleset, allowedEndpoint->endpoint, allowedEndpoint->client);
+	}
+
 	return ruleset;
 }
 
@@ -307,7 +320,7 @@ bool FwContext::applyBlockedBaseConfiguration(const WinFwSettings &settings, uin
 		//
 		checkpoint = controller.peekCheckpoint();
 
-		return applyRulesetDirectly(composePolicyBlocked(settings), controller);
+		return applyRulesetDirectly(composePolicyBlocked(settings, {}), controller);
 	});
 }
 
@@ -29,6 +29,12 @@ public:
 		std::vector<wfp::IpAddress> hosts;
 	};
 
+	struct AllowedEndpoint
+	{
+		WinFwRelay endpoint;
+		std::wstring client;
+	};
+
 	bool applyPolicyConnecting
 	(
 		const WinFwSettings &settings,
@@ -46,7 +52,11 @@ public:
 		const std::vector<wfp::IpAddress> &tunnelDnsServers
 	);
 
-	bool applyPolicyBlocked(const WinFwSettings &settings);
+	bool applyPolicyBlocked
+	(
+		const WinFwSettings &settings,
+		const std::optional<AllowedEndpoint> &allowedEndpoint
+	);
 
 	bool reset();
 
@@ -67,7 +77,11 @@ private:
 	FwContext(const FwContext &) = delete;
 	FwContext &operator=(const FwContext &) = delete;
 
-	Ruleset composePolicyBlocked(const WinFwSettings &settings);
+	Ruleset composePolicyBlocked
+	(
+		const WinFwSettings &settings,
+		const std::optional<AllowedEndpoint> &allowedEndpoint
+	);
 
 	bool applyBaseConfiguration();
 	bool applyBlockedBaseConfiguration(const WinFwSettings &settings, uint32_t &checkpoint);
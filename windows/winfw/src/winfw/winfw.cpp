@@ -332,7 +332,9 @@ WINFW_LINKAGE
 WINFW_POLICY_STATUS
 WINFW_API
 WinFw_ApplyPolicyBlocked(
-	const WinFwSettings *settings
+	const WinFwSettings *settings,
+	const WinFwRelay *allowedEndpoint,
+	const wchar_t *allowedEndpointClient
 )
 {
 	if (nullptr == g_fwContext)
@@ -347,7 +349,19 @@ WinFw_ApplyPolicyBlocked(
 			THROW_ERROR("Invalid argument: settings");
 		}
 
-		return g_fwContext->applyPolicyBlocked(*settings)
+		std::optional<FwContext::AllowedEndpoint> endpoint;
+
+		if (nullptr != allowedEndpoint)
+		{
+			if (nullptr == allowedEndpointClient)
+			{
+				THROW_ERROR("Invalid argument: allowedEndpointClient");
+			}
+
+			endpoint = FwContext::AllowedEndpoint{ *allowedEndpoint, allowedEndpointClient };
+		}
+
+		return g_fwContext->applyPolicyBlocked(*settings, endpoint)
 			? WINFW_POLICY_STATUS_SUCCESS
 			: WINFW_POLICY_STATUS_GENERAL_FAILURE;
 	}
@@ -192,13 +192,24 @@ WinFw_ApplyPolicyConnected(
 //
 // Apply restrictions in the firewall that block all traffic, except:
 // - What is specified by settings
+// - Communication with the allowed endpoint, if one is specified
+//
+// Parameters:
+//
+// allowedEndpoint:
+//   Optional endpoint that may be reached, e.g. to keep the API reachable.
+// allowedEndpointClient:
+//   Path of the only process that may communicate with the allowed endpoint.
+//   Must be specified if allowedEndpoint is specified.
 //
 extern "C"
 WINFW_LINKAGE
 WINFW_POLICY_STATUS
 WINFW_API
 WinFw_ApplyPolicyBlocked(
-	const WinFwSettings *settings
+	const WinFwSettings *settings,
+	const WinFwRelay *allowedEndpoint,
+	const wchar_t *allowedEndpointClient
 );
 
 //